@@ -1,5 +1,12 @@
 fn main()
 {
-	println!("cargo:rustc-link-lib=X11");
-	println!("cargo:rustc-link-lib=Xtst");
+	// the x11 crate's xlib, xtest and xrandr bindings need linking against, and
+	// libXext for grabbing the screen with the shared memory extension
+	if std::env::var_os("CARGO_FEATURE_X11").is_some()
+	{
+		println!("cargo:rustc-link-lib=X11");
+		println!("cargo:rustc-link-lib=Xtst");
+		println!("cargo:rustc-link-lib=Xrandr");
+		println!("cargo:rustc-link-lib=Xext");
+	}
 }
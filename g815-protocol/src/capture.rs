@@ -0,0 +1,369 @@
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use log::warn;
+use serde::{Serialize, Deserialize};
+
+use super::{CommandError, CommandResult, DeviceEvent};
+use super::g815::G815Keyboard;
+use super::transport::Transport;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction
+{
+	/// a command sent to the keyboard
+	Out,
+	/// a response or interrupt from the keyboard
+	In
+}
+
+/// One report sent or received, a line of a capture file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRecord
+{
+	/// seconds since the capture started
+	pub time: f64,
+	/// the serial number of the keyboard, so captures of several can be told apart
+	pub device: String,
+	pub direction: Direction,
+	/// the report's bytes in hex, i.e. "11 ff 0a 00"
+	pub report: String
+}
+
+impl CaptureRecord
+{
+	fn bytes(&self) -> Vec<u8>
+	{
+		self.report
+			.split_whitespace()
+			.filter_map(|byte| u8::from_str_radix(byte, 16).ok())
+			.collect()
+	}
+}
+
+/// Where --capture writes every report sent to or read from the keyboards, as
+/// a line of json each. Clones write to the same file.
+#[derive(Clone)]
+pub struct Capture
+{
+	file: Arc<Mutex<File>>,
+	started: Instant
+}
+
+impl Capture
+{
+	pub fn create(path: &Path) -> std::io::Result<Self>
+	{
+		Ok(Self
+		{
+			file: Arc::new(Mutex::new(File::create(path)?)),
+			started: Instant::now()
+		})
+	}
+
+	/// A transport that records everything that goes through `transport`
+	pub fn wrap(&self, transport: Box<dyn Transport>) -> Box<dyn Transport>
+	{
+		let device = transport.serial_number().unwrap_or_default();
+		Box::new(CaptureTransport { capture: self.clone(), device, transport })
+	}
+
+	fn record(&self, device: &str, direction: Direction, report: &[u8])
+	{
+		let record = CaptureRecord
+		{
+			time: self.started.elapsed().as_secs_f64(),
+			device: device.to_string(),
+			direction,
+			report: report
+				.iter()
+				.map(|byte| format!("{:02x}", byte))
+				.collect::<Vec<String>>()
+				.join(" ")
+		};
+
+		let line = serde_json::to_string(&record).unwrap();
+
+		if let Err(error) = writeln!(self.file.lock().unwrap(), "{}", line)
+		{
+			warn!("unable to write to the capture: {}", error);
+		}
+	}
+}
+
+struct CaptureTransport
+{
+	capture: Capture,
+	device: String,
+	transport: Box<dyn Transport>
+}
+
+impl Transport for CaptureTransport
+{
+	fn write(&mut self, data: &[u8]) -> CommandResult<usize>
+	{
+		self.capture.record(&self.device, Direction::Out, data);
+		self.transport.write(data)
+	}
+
+	fn read_timeout(&mut self, buffer: &mut [u8], timeout: i32) -> CommandResult<usize>
+	{
+		let bytes_read = self.transport.read_timeout(buffer, timeout)?;
+
+		if bytes_read > 0
+		{
+			self.capture.record(&self.device, Direction::In, &buffer[..bytes_read]);
+		}
+
+		Ok(bytes_read)
+	}
+
+	fn product_string(&self) -> Option<String>
+	{
+		self.transport.product_string()
+	}
+
+	fn serial_number(&self) -> Option<String>
+	{
+		self.transport.serial_number()
+	}
+}
+
+pub fn load(path: &Path) -> Result<Vec<CaptureRecord>, String>
+{
+	let file = File::open(path).map_err(|error| error.to_string())?;
+
+	BufReader::new(file)
+		.lines()
+		.enumerate()
+		.filter(|(_number, line)| line.as_ref().map(|line| !line.trim().is_empty()).unwrap_or(true))
+		.map(|(number, line)|
+		{
+			let line = line.map_err(|error| error.to_string())?;
+			serde_json::from_str(&line).map_err(|error| format!("line {}: {}", number + 1, error))
+		})
+		.collect()
+}
+
+/// Plays a capture back to a G815Keyboard: commands are answered with the
+/// responses that were recorded for them, and once it stops sending them the
+/// rest of what the keyboard sent is read back as if it had just arrived.
+/// Clones share their place in the capture.
+#[derive(Clone)]
+pub struct ReplayTransport
+{
+	inner: Arc<Mutex<ReplayState>>
+}
+
+struct ReplayState
+{
+	device: String,
+	records: VecDeque<CaptureRecord>,
+	// the start of the last command sent, whose response is still to be read
+	awaiting: Option<Vec<u8>>,
+	// the start of the last command in the capture, whose response isn't an interrupt
+	last_command: Vec<u8>,
+	time: f64
+}
+
+impl ReplayTransport
+{
+	pub fn new(device: String, records: Vec<CaptureRecord>) -> Self
+	{
+		Self
+		{
+			inner: Arc::new(Mutex::new(ReplayState
+			{
+				device,
+				records: records.into(),
+				awaiting: None,
+				last_command: Vec::new(),
+				time: 0.0
+			}))
+		}
+	}
+
+	/// Whether anything the keyboard sent is still to be read
+	pub fn finished(&self) -> bool
+	{
+		!self.state().records.iter().any(|record| record.direction == Direction::In)
+	}
+
+	/// When the last report read was captured
+	pub fn time(&self) -> f64
+	{
+		self.state().time
+	}
+
+	fn state(&self) -> MutexGuard<'_, ReplayState>
+	{
+		self.inner.lock().unwrap()
+	}
+}
+
+impl Transport for ReplayTransport
+{
+	fn write(&mut self, data: &[u8]) -> CommandResult<usize>
+	{
+		let mut state = self.state();
+
+		match state.records.iter().any(|record| record.direction == Direction::In)
+		{
+			true =>
+			{
+				state.awaiting = Some(data[..4].to_vec());
+				Ok(data.len())
+			},
+			false => Err(CommandError::LogicError("the capture has ended".to_string()))
+		}
+	}
+
+	fn read_timeout(&mut self, buffer: &mut [u8], _timeout: i32) -> CommandResult<usize>
+	{
+		let mut state = self.state();
+
+		while let Some(record) = state.records.pop_front()
+		{
+			let report = record.bytes();
+
+			match record.direction
+			{
+				Direction::Out =>
+				{
+					state.last_command = report.into_iter().take(4).collect();
+					continue
+				},
+				// whilst nothing is waiting on it, the response to a command in
+				// the capture is skipped, as the driver would have read it then
+				Direction::In if state.awaiting.is_none() && answers(&report, &state.last_command) => continue,
+				Direction::In => ()
+			}
+
+			if state.awaiting.as_deref().map(|awaiting| answers(&report, awaiting)).unwrap_or(false)
+			{
+				state.awaiting = None;
+			}
+
+			state.time = record.time;
+
+			let length = report.len().min(buffer.len());
+			buffer[..length].copy_from_slice(&report[..length]);
+			return Ok(length)
+		}
+
+		match state.awaiting.take()
+		{
+			Some(_awaiting) => Err(CommandError::LogicError("the capture has ended".to_string())),
+			None => Ok(0)
+		}
+	}
+
+	fn product_string(&self) -> Option<String>
+	{
+		Some("G815 (replayed)".to_string())
+	}
+
+	fn serial_number(&self) -> Option<String>
+	{
+		Some(self.state().device.clone())
+	}
+}
+
+/// Whether a report is the response to a command (starting `command`), or the
+/// error the keyboard sends in its place
+fn answers(report: &[u8], command: &[u8]) -> bool
+{
+	command.len() == 4 && (report.starts_with(command)
+		|| report.len() >= 5 && report[..2] == command[..2] && report[2] == 0xff && report[3..5] == command[2..4])
+}
+
+/// The events a keyboard's part of a capture would have produced, with when
+/// each was read
+fn replayed_events(device: String, records: Vec<CaptureRecord>) -> (String, Vec<(f64, DeviceEvent)>)
+{
+	let transport = ReplayTransport::new(device, records);
+	let mut keyboard = G815Keyboard::init(Box::new(transport.clone()));
+	let mut events = Vec::new();
+
+	loop
+	{
+		events.extend(keyboard
+			.get_events()
+			.into_iter()
+			.map(|event| (transport.time(), event)));
+
+		if transport.finished()
+		{
+			return (keyboard.to_string(), events)
+		}
+	}
+}
+
+/// Feeds a capture (--replay) back through a G815Keyboard's event parsing,
+/// printing the events each keyboard in it would have produced
+pub fn replay(records: Vec<CaptureRecord>)
+{
+	let mut devices: Vec<String> = Vec::new();
+
+	for record in records.iter()
+	{
+		if !devices.contains(&record.device)
+		{
+			devices.push(record.device.clone());
+		}
+	}
+
+	for device in devices
+	{
+		let records = records
+			.iter()
+			.filter(|record| record.device == device)
+			.cloned()
+			.collect();
+
+		let (keyboard, events) = replayed_events(device, records);
+		println!("{}", keyboard);
+
+		for (time, event) in events
+		{
+			println!("{:>10.3}s  {:?}", time, event);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::KeyType;
+	use crate::mock::MockTransport;
+
+	#[test]
+	fn captures_replay_to_the_same_events()
+	{
+		let path = std::env::temp_dir().join(format!("g815d-capture-test-{}.jsonl", std::process::id()));
+		let capture = Capture::create(&path).unwrap();
+		let transport = MockTransport::g815();
+		let mut keyboard = G815Keyboard::init(capture.wrap(Box::new(transport.clone())));
+
+		keyboard.set_gkeys_software_mode(true).unwrap();
+		transport.interrupt(&[0x11, 0xff, 0x0a, 0x00, 0b010]);
+		keyboard.set_mode_leds(0b001).unwrap();
+		transport.interrupt(&[0x11, 0xff, 0x0a, 0x00, 0b000]);
+
+		let live: Vec<DeviceEvent> = keyboard.get_events();
+		let records = load(&path).unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		let (_keyboard, replayed) = replayed_events("mock".to_string(), records);
+		let replayed: Vec<DeviceEvent> = replayed.into_iter().map(|(_time, event)| event).collect();
+
+		assert!(matches!(live[..], [DeviceEvent::KeyDown(KeyType::GKey, 2), DeviceEvent::KeyUp(KeyType::GKey, 2)]));
+		assert_eq!(format!("{:?}", replayed), format!("{:?}", live));
+	}
+}
@@ -0,0 +1,593 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap};
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::Mutex;
+
+use hsl::HSL;
+use serde::{Serialize, Deserialize, Serializer, Deserializer};
+use serde::de::{Visitor, Error};
+
+pub type NamedColors = HashMap<String, Color>;
+
+thread_local!
+{
+	/// the named colors that color values can refer to whilst a configuration
+	/// is being deserialized
+	static NAMED_COLORS: RefCell<Option<NamedColors>> = const { RefCell::new(None) };
+	/// whether colors are written as they were in the config whilst it's being
+	/// serialized, rather than as hex codes
+	static WRITE_SPELLINGS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// How colors were written in the config, kept for the lifetime of the driver
+/// so colors can stay `Copy`. Only colors parsed whilst a config is being
+/// deserialized are kept, so colors sent at runtime (i.e. over dbus) can't grow it.
+static SPELLINGS: Mutex<BTreeSet<&'static str>> = Mutex::new(BTreeSet::new());
+
+#[derive(Copy, Clone)]
+pub struct Color
+{
+	pub r: u8,
+	pub g: u8,
+	pub b: u8,
+	/// how the color was written, when it wasn't a hex code (i.e. `red` or
+	/// `accent @ 40%`), so saving the config doesn't replace it with one
+	spelling: Option<&'static str>
+}
+
+impl Color
+{
+	const GAMMA: f64 = 2.2;
+	/// the color temperature (in kelvin) of a white that's left as it is
+	pub const DAYLIGHT: u16 = 6500;
+
+	// bit-reversed order so that consecutive frames spread the lit frames out evenly
+	const DITHER_THRESHOLDS: [f64; 8] = [
+		0.5 / 8.0, 4.5 / 8.0, 2.5 / 8.0, 6.5 / 8.0,
+		1.5 / 8.0, 5.5 / 8.0, 3.5 / 8.0, 7.5 / 8.0
+	];
+
+	pub const fn new(r: u8, g: u8, b: u8) -> Self
+	{
+		Self { r, g, b, spelling: None }
+	}
+
+	pub fn black() -> Self
+	{
+		Self::new(0, 0, 0)
+	}
+
+	/// Linearly interpolates between this color (at 0.0) and another (at 1.0)
+	pub fn mix(&self, other: Color, amount: f64) -> Self
+	{
+		let amount = amount.clamp(0_f64, 1_f64);
+		let mix = |from: u8, to: u8| (from as f64 + (to as f64 - from as f64) * amount).round() as u8;
+		Self::new(mix(self.r, other.r), mix(self.g, other.g), mix(self.b, other.b))
+	}
+
+	/// How colorful this color is (0.0 - 1.0), regardless of how bright it is
+	pub fn chroma(&self) -> f64
+	{
+		let max = self.r.max(self.g).max(self.b);
+		let min = self.r.min(self.g).min(self.b);
+		(max - min) as f64 / 255_f64
+	}
+
+	/// A color that stands out against this one: the opposite hue at full
+	/// saturation, or for grays, white against dark keys and red against light ones
+	pub fn contrasting(&self) -> Self
+	{
+		let hsl = HSL::from_rgb(&[self.r, self.g, self.b]);
+
+		match (self.chroma() < 0.2, hsl.l < 0.5)
+		{
+			(true, true) => Self::new(255, 255, 255),
+			(true, false) => Self::new(255, 0, 0),
+			(false, _) => HSL { h: (hsl.h + 180_f64) % 360_f64, s: 1_f64, l: 0.5_f64 }.into()
+		}
+	}
+
+	/// Runs a closure (i.e. deserializing a configuration) during which colors can
+	/// be given by any of the names in `colors` instead of a hex code
+	pub fn with_named_colors<T, F>(colors: &NamedColors, f: F) -> T
+	where
+		F: FnOnce() -> T
+	{
+		let _restore = NamedColorsGuard(NAMED_COLORS.with(|named| named.replace(Some(colors.clone()))));
+		f()
+	}
+
+	/// Runs a closure (i.e. serializing a configuration to save it) during which
+	/// colors are written as they were in the config, so named colors and
+	/// brightness modifiers aren't replaced by the hex codes they stand for
+	pub fn with_spellings<T, F>(f: F) -> T
+	where
+		F: FnOnce() -> T
+	{
+		let _restore = SpellingsGuard(WRITE_SPELLINGS.with(|write| write.replace(true)));
+		f()
+	}
+
+	/// Parses a color as written in the config: a hex code, `rgb(r, g, b)`,
+	/// `hsl(h, s%, l%)` or a color name, optionally followed by a brightness
+	/// modifier such as `ff0000 @ 40%`, which scales each channel
+	pub fn parse(value: &str) -> Result<Self, String>
+	{
+		let value = value.trim();
+		let mut parts = value.splitn(2, '@');
+		let color = parts.next().unwrap_or_default().trim();
+		let brightness = parts
+			.next()
+			.map(|brightness| Self::parse_percentage(brightness.trim()))
+			.transpose()?;
+
+		let color = match color.strip_suffix(')')
+		{
+			Some(function) if function.starts_with("rgb(") =>
+				Self::parse_rgb(&function["rgb(".len()..])?,
+			Some(function) if function.starts_with("hsl(") =>
+				Self::parse_hsl(&function["hsl(".len()..])?,
+			_ if color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()) => color
+				.try_into()
+				.map_err(|e: std::num::ParseIntError| format!(
+					"parse error for hex code {} - {}",
+					color,
+					e.to_string()))?,
+			_ => match Self::named(color)
+			{
+				Some(Some(color)) => color,
+				Some(None) => return Err(format!("unknown color name: {}", color)),
+				None => return Err(format!("invalid hex color code: {}", color))
+			}
+		};
+
+		// linearly, unlike lighting.brightness, so `ff0000 @ 40%` is 660000
+		let color = match brightness
+		{
+			Some(brightness) => color.mix(Self::black(), 1_f64 - brightness),
+			None => color
+		};
+
+		let in_config = NAMED_COLORS.with(|named| named.borrow().is_some());
+
+		Ok(match in_config && value != format!("{:x}", color)
+		{
+			true => Self { spelling: Some(Self::intern(value)), ..color },
+			false => color
+		})
+	}
+
+	fn intern(spelling: &str) -> &'static str
+	{
+		let mut spellings = SPELLINGS.lock().unwrap();
+
+		match spellings.get(spelling)
+		{
+			Some(interned) => interned,
+			None =>
+			{
+				let interned: &'static str = Box::leak(spelling.to_string().into_boxed_str());
+				spellings.insert(interned);
+				interned
+			}
+		}
+	}
+
+	/// Splits the arguments of a color function, checking there are 3 of them
+	fn function_arguments(arguments: &str) -> Result<Vec<&str>, String>
+	{
+		let arguments: Vec<&str> = arguments.split(',').map(|argument| argument.trim()).collect();
+
+		match arguments.len()
+		{
+			3 => Ok(arguments),
+			count => Err(format!("color functions take 3 arguments, {} given", count))
+		}
+	}
+
+	fn parse_rgb(arguments: &str) -> Result<Self, String>
+	{
+		let channels = Self::function_arguments(arguments)?
+			.iter()
+			.map(|channel| channel
+				.parse::<u8>()
+				.map_err(|_| format!("invalid rgb channel (0 - 255): {}", channel)))
+			.collect::<Result<Vec<u8>, String>>()?;
+
+		Ok(Self::new(channels[0], channels[1], channels[2]))
+	}
+
+	fn parse_hsl(arguments: &str) -> Result<Self, String>
+	{
+		let arguments = Self::function_arguments(arguments)?;
+		// NaN and infinity parse, but aren't angles
+		let hue = arguments[0]
+			.trim_end_matches("deg")
+			.parse::<f64>()
+			.ok()
+			.filter(|hue| hue.is_finite())
+			.ok_or_else(|| format!("invalid hue: {}", arguments[0]))?;
+
+		Ok(HSL
+		{
+			h: hue.rem_euclid(360_f64),
+			s: Self::parse_percentage(arguments[1])?,
+			l: Self::parse_percentage(arguments[2])?
+		}.into())
+	}
+
+	/// Parses a percentage (`40%`) into a fraction between 0.0 and 1.0
+	fn parse_percentage(value: &str) -> Result<f64, String>
+	{
+		value
+			.strip_suffix('%')
+			.and_then(|percentage| percentage.trim().parse::<f64>().ok())
+			.filter(|percentage| (0_f64..=100_f64).contains(percentage))
+			.map(|percentage| percentage / 100_f64)
+			.ok_or_else(|| format!("invalid percentage (0% - 100%): {}", value))
+	}
+
+	/// Looks up a named color. Returns None if no named colors are in scope.
+	fn named(name: &str) -> Option<Option<Self>>
+	{
+		NAMED_COLORS.with(|named| named
+			.borrow()
+			.as_ref()
+			.map(|colors| colors.get(name).copied()))
+	}
+
+	/// Scales this color to a perceptual brightness level (0.0 - 1.0). The keyboard
+	/// leds are linear, so the brightness is gamma corrected before being applied
+	/// to each channel. The result is left unrounded so that it can be dithered.
+	pub fn scale(&self, brightness: f64) -> (f64, f64, f64)
+	{
+		let factor = brightness.clamp(0_f64, 1_f64).powf(Self::GAMMA);
+		(self.r as f64 * factor, self.g as f64 * factor, self.b as f64 * factor)
+	}
+
+	/// Scales this color (see `scale()`) and quantizes it back to 8 bits per channel.
+	/// If a dither frame is given, fractional channel values are spread across
+	/// consecutive frames instead of being rounded, so that dim colors keep their
+	/// hue rather than collapsing into a handful of blotchy colors.
+	pub fn scaled(&self, brightness: f64, dither_frame: Option<u8>) -> Self
+	{
+		let quantize = |value: f64| match dither_frame
+		{
+			Some(frame) =>
+			{
+				let threshold = Self::DITHER_THRESHOLDS[frame as usize % Self::DITHER_THRESHOLDS.len()];
+				(value.floor() + if value.fract() > threshold { 1_f64 } else { 0_f64 }) as u8
+			},
+			None => value.round() as u8
+		};
+
+		let (r, g, b) = self.scale(brightness);
+		Self::new(quantize(r), quantize(g), quantize(b))
+	}
+
+	/// Scales this color down (keeping its hue) so the sum of its channels is
+	/// at most `max_sum`, which limits how much current the key's led can draw
+	pub fn capped(&self, max_sum: u16) -> Self
+	{
+		let sum = self.r as u16 + self.g as u16 + self.b as u16;
+
+		match sum > max_sum
+		{
+			true =>
+			{
+				let factor = max_sum as f64 / sum as f64;
+				let cap = |channel: u8| (channel as f64 * factor).floor() as u8;
+				Self::new(cap(self.r), cap(self.g), cap(self.b))
+			},
+			false => *self
+		}
+	}
+
+	/// Shifts this color towards the color of light at a temperature (in
+	/// kelvin), as redshift does for screens. 6500K, daylight, leaves it as it is.
+	pub fn warmed(&self, temperature: u16) -> Self
+	{
+		let (r, g, b) = Self::white_point(temperature as f64);
+		let (daylight_r, daylight_g, daylight_b) = Self::white_point(Self::DAYLIGHT as f64);
+		let warm = |channel: u8, white: f64, daylight: f64| (channel as f64 * (white / daylight).min(1_f64)).round() as u8;
+
+		Self::new(warm(self.r, r, daylight_r), warm(self.g, g, daylight_g), warm(self.b, b, daylight_b))
+	}
+
+	/// The color of a black body at a temperature, from Tanner Helland's fit
+	/// of the CIE 1964 color matching functions
+	fn white_point(temperature: f64) -> (f64, f64, f64)
+	{
+		let t = temperature.clamp(1000_f64, 40000_f64) / 100_f64;
+
+		let r = match t <= 66_f64
+		{
+			true => 255_f64,
+			false => 329.698727446 * (t - 60_f64).powf(-0.1332047592)
+		};
+
+		let g = match t <= 66_f64
+		{
+			true => 99.4708025861 * t.ln() - 161.1195681661,
+			false => 288.1221695283 * (t - 60_f64).powf(-0.0755148492)
+		};
+
+		let b = match t
+		{
+			t if t >= 66_f64 => 255_f64,
+			t if t <= 19_f64 => 0_f64,
+			t => 138.5177312231 * (t - 10_f64).ln() - 305.0447927307
+		};
+
+		let clamp = |channel: f64| channel.clamp(0_f64, 255_f64);
+		(clamp(r), clamp(g), clamp(b))
+	}
+}
+
+/// Puts back the named colors that were in scope before `with_named_colors`,
+/// even if its closure panics
+struct NamedColorsGuard(Option<NamedColors>);
+
+impl Drop for NamedColorsGuard
+{
+	fn drop(&mut self)
+	{
+		NAMED_COLORS.with(|named| named.replace(self.0.take()));
+	}
+}
+
+struct SpellingsGuard(bool);
+
+impl Drop for SpellingsGuard
+{
+	fn drop(&mut self)
+	{
+		WRITE_SPELLINGS.with(|write| write.set(self.0));
+	}
+}
+
+// the spelling doesn't make it a different color
+
+impl PartialEq for Color
+{
+	fn eq(&self, other: &Self) -> bool
+	{
+		(self.r, self.g, self.b) == (other.r, other.g, other.b)
+	}
+}
+
+impl Eq for Color {}
+
+impl Hash for Color
+{
+	fn hash<H: Hasher>(&self, state: &mut H)
+	{
+		(self.r, self.g, self.b).hash(state);
+	}
+}
+
+impl fmt::Debug for Color
+{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	{
+		f.debug_struct("Color").field("r", &self.r).field("g", &self.g).field("b", &self.b).finish()
+	}
+}
+
+impl Default for Color
+{
+	fn default() -> Self
+	{
+		Color::black()
+	}
+}
+
+impl From<HSL> for Color
+{
+	fn from(hsl: HSL) -> Self
+	{
+		let (r, g, b) = hsl.to_rgb();
+		Self::new(r, g, b)
+	}
+}
+
+impl From<u32> for Color
+{
+	fn from(color: u32) -> Self
+	{
+		Color::new(
+			(color >> 16 & 0xff) as u8,
+			(color >> 8 & 0xff) as u8,
+			color as u8)
+	}
+}
+
+impl TryFrom<&str> for Color
+{
+	type Error = std::num::ParseIntError;
+
+	fn try_from(string: &str) -> Result<Self, Self::Error>
+	{
+		u32::from_str_radix(string, 16)
+			.map(|color| color.into())
+	}
+}
+
+impl std::fmt::Display for Color
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error>
+	{
+		f.write_fmt(format_args!("rgb({}, {}, {})", self.r, self.g, self.b))
+	}
+}
+
+impl std::fmt::LowerHex for Color
+{
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> Result<(), std::fmt::Error>
+	{
+		f.write_fmt(format_args!("{:02x}{:02x}{:02x}", self.r, self.g, self.b))
+	}
+}
+
+impl Serialize for Color
+{
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer
+	{
+		match (self.spelling, WRITE_SPELLINGS.with(Cell::get))
+		{
+			(Some(spelling), true) => serializer.serialize_str(spelling),
+			_ => serializer.serialize_str(format!(
+				"{:02x}{:02x}{:02x}",
+				self.r,
+				self.g,
+				self.b).as_str())
+		}
+	}
+}
+
+struct ColorVisitor;
+
+impl<'de> Visitor<'de> for ColorVisitor
+{
+	type Value = Color;
+
+	fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result
+	{
+		formatter.write_str("a hex color code (00ff00), rgb(0, 255, 0), hsl(120, 100%, 50%) \
+			or a color name, optionally followed by a brightness: 00ff00 @ 40%")
+	}
+
+	fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+	where
+		E: Error
+	{
+		Color::parse(value).map_err(E::custom)
+	}
+
+	fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+	where
+		E: Error
+	{
+		Ok((value as u32).into())
+	}
+
+	fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+	where
+		E: Error
+	{
+		if value < 0
+		{
+			Err(E::custom("color as i64 cannot be negative"))
+		}
+		else
+		{
+			self.visit_u64(value as u64)
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for Color
+{
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>
+	{
+		deserializer.deserialize_str(ColorVisitor)
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn colors_are_saved_as_they_were_written()
+	{
+		let colors: NamedColors = vec![("accent".to_string(), Color::new(0, 0x80, 0xff))].into_iter().collect();
+		let written = "[accent, 'accent @ 50%', ff0000, FF0000, 'rgb(1, 2, 3)']";
+		let parsed: Vec<Color> = Color::with_named_colors(&colors, || serde_yaml::from_str(written)).unwrap();
+
+		assert_eq!(parsed[0], Color::new(0, 0x80, 0xff));
+		assert_eq!(parsed[1], Color::new(0, 0x40, 0x80));
+		assert_eq!(serde_yaml::to_value(&parsed).unwrap()[0], serde_yaml::Value::from("0080ff"));
+		assert_eq!(Color::with_spellings(|| serde_yaml::to_value(&parsed)).unwrap(),
+			serde_yaml::from_str::<serde_yaml::Value>(written).unwrap());
+	}
+
+	#[test]
+	fn colors_are_parsed_in_every_notation()
+	{
+		let colors: NamedColors = vec![("accent".to_string(), Color::new(0, 0x80, 0xff))].into_iter().collect();
+		let parse = |written: &str| Color::with_named_colors(&colors, || Color::parse(written));
+
+		for (written, expected) in [
+			("00ff00", Color::new(0, 0xff, 0)),
+			("rgb(0, 255, 0)", Color::new(0, 0xff, 0)),
+			("hsl(120, 100%, 50%)", Color::new(0, 0xff, 0)),
+			("hsl(120deg, 100%, 50%)", Color::new(0, 0xff, 0)),
+			// hues wrap around the circle, either way
+			("hsl(480, 100%, 50%)", Color::new(0, 0xff, 0)),
+			("hsl(-240deg, 100%, 50%)", Color::new(0, 0xff, 0)),
+			("hsl(0, 0%, 100%)", Color::new(0xff, 0xff, 0xff)),
+			("accent", Color::new(0, 0x80, 0xff)),
+			("accent @ 50%", Color::new(0, 0x40, 0x80)),
+			("accent@0%", Color::black()),
+			("rgb(200, 100, 0) @ 50%", Color::new(100, 50, 0))
+		].iter()
+		{
+			assert_eq!(parse(written), Ok(*expected), "{}", written);
+		}
+	}
+
+	#[test]
+	fn invalid_colors_are_errors()
+	{
+		let colors: NamedColors = vec![("accent".to_string(), Color::black())].into_iter().collect();
+
+		for written in [
+			"rgb(256, 0, 0)",
+			"rgb(-1, 0, 0)",
+			"rgb(1, 2)",
+			"hsl(1, 2%, 3%, 4%)",
+			"hsl(0, 120%, 50%)",
+			"hsl(0, 100, 50%)",
+			"hsl(NaN, 100%, 50%)",
+			"hsl(inf, 100%, 50%)",
+			"hsl(-infdeg, 100%, 50%)",
+			"ff0000 @ 120%",
+			"ff0000 @ 50",
+			"teal",
+			"ff00"
+		].iter()
+		{
+			assert!(Color::with_named_colors(&colors, || Color::parse(written)).is_err(), "{}", written);
+		}
+
+		// without a config's named colors in scope, anything else has to be hex
+		assert_eq!(Color::parse("accent"), Err("invalid hex color code: accent".to_string()));
+		assert_eq!(Color::with_named_colors(&colors, || Color::parse("teal")), Err("unknown color name: teal".to_string()));
+	}
+
+	#[test]
+	fn only_colors_in_a_config_keep_their_spelling()
+	{
+		assert_eq!(Color::parse("FF0000 @ 50%").unwrap().spelling, None);
+		assert_eq!(Color::with_named_colors(&NamedColors::new(), || Color::parse("FF0000 @ 50%")).unwrap().spelling,
+			Some("FF0000 @ 50%"));
+	}
+
+	#[test]
+	fn named_colors_go_out_of_scope_after_a_panic()
+	{
+		let colors: NamedColors = vec![("accent".to_string(), Color::black())].into_iter().collect();
+		let result = std::panic::catch_unwind(|| Color::with_named_colors(&colors, || panic!("deserializing failed")));
+
+		assert!(result.is_err());
+		assert!(Color::named("accent").is_none());
+	}
+}
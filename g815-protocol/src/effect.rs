@@ -0,0 +1,37 @@
+use serde::{Serialize, Deserialize};
+
+use super::color::Color;
+
+/// The parts of the keyboard that show effects separately
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum EffectGroup
+{
+	Logo = 0x00,
+	Keys = 0x01
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EffectDirection
+{
+	Horizontal = 0x01,
+	Vertical = 0x02,
+	CenterOut = 0x03,
+	CenterIn = 0x08,
+	ReverseHorizontal = 0x06,
+	ReverseVertical = 0x07
+}
+
+/// An effect the keyboard runs itself, durations are in milliseconds and
+/// brightness is a percentage
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EffectConfiguration
+{
+	None,
+	Static { color: Color },
+	Breathing { color: Color, duration: u16, brightness: u8 },
+	Cycle { duration: u16, brightness: u8 },
+	ColorWave { direction: EffectDirection, duration: u16, brightness: u8 },
+	Ripple { color: Color, duration: u16 }
+}
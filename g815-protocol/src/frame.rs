@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::color::Color;
+
+/// A command that sets the colors of some keys, by their rgb ids
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameCommand
+{
+	/// up to 4 keys, each with its own color
+	Set4(Vec<(u8, Color)>),
+	/// up to 13 keys in one color
+	Set13(Color, Vec<u8>)
+}
+
+/// Collects the key colors set until the next commit, so they're sent in as few
+/// commands as possible: a key set more than once is only sent with its last
+/// color, keys already showing their color aren't sent at all, and keys
+/// sharing a color are sent 13 at a time.
+#[derive(Debug, Default)]
+pub struct FrameBuffer
+{
+	// rgb id -> color, ordered so the same frame always makes the same commands
+	colors: BTreeMap<u8, Color>,
+	// rgb id -> the color it was last sent, as far as we know
+	sent: HashMap<u8, Color>
+}
+
+impl FrameBuffer
+{
+	pub const SET_4_KEYS: usize = 4;
+	pub const SET_13_KEYS: usize = 13;
+
+	pub fn set(&mut self, rgb_id: u8, color: Color)
+	{
+		self.colors.insert(rgb_id, color);
+	}
+
+	pub fn is_empty(&self) -> bool
+	{
+		self.colors.is_empty()
+	}
+
+	/// Sends every key next time, for when the keyboard may no longer be
+	/// showing what was sent (an effect ran, or a command failed)
+	pub fn forget_sent(&mut self)
+	{
+		self.sent.clear();
+	}
+
+	/// Empties the buffer, returning the commands that set its colors
+	pub fn take_commands(&mut self) -> Vec<FrameCommand>
+	{
+		let mut by_color: HashMap<Color, Vec<u8>> = HashMap::new();
+
+		for (rgb_id, color) in std::mem::take(&mut self.colors)
+		{
+			if self.sent.insert(rgb_id, color) != Some(color)
+			{
+				by_color.entry(color).or_default().push(rgb_id);
+			}
+		}
+
+		// sorted by each color's first key, so commands come out in a stable order
+		let mut by_color: Vec<(Color, Vec<u8>)> = by_color.into_iter().collect();
+		by_color.sort_by_key(|(_color, rgb_ids)| rgb_ids[0]);
+
+		let mut commands = Vec::new();
+		let mut singles = Vec::new();
+
+		for (color, rgb_ids) in by_color
+		{
+			for chunk in rgb_ids.chunks(Self::SET_13_KEYS)
+			{
+				// a few keys are cheaper sent alongside other colors
+				match chunk.len() > Self::SET_4_KEYS
+				{
+					true => commands.push(FrameCommand::Set13(color, chunk.to_vec())),
+					false => singles.extend(chunk.iter().map(|rgb_id| (*rgb_id, color)))
+				}
+			}
+		}
+
+		commands.extend(singles
+			.chunks(Self::SET_4_KEYS)
+			.map(|chunk| FrameCommand::Set4(chunk.to_vec())));
+
+		commands
+	}
+}
@@ -0,0 +1,1489 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+use std::fmt;
+use std::borrow::Cow;
+
+use hidapi::HidError;
+use log::{trace, debug, warn};
+
+use super::{DeviceEvent, KeyType, MediaKey, Capability, CapabilityData, CapabilityInfo, CommandResult, CommandError,
+	CommandStats, DeviceInfo, OnboardLighting};
+use super::color::Color;
+use super::effect::{EffectConfiguration, EffectDirection, EffectGroup};
+use super::scancode::Scancode;
+use super::frame::{FrameBuffer, FrameCommand};
+use super::transport::Transport;
+
+/*
+ * Note: on startup, ghub seems to send an initializer/session nibble
+ * that is then used as the lower nibble of the lower byte of every command
+ * All commands in the enum are defined as if the initializer of 11 ff 00 1a
+ * has been sent (as a result the last nibble of each command ends in a)
+ * If you change InitializeSession, you have to update all other constants
+ */
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+enum Command
+{
+	InitializeSession = 0x001a,
+	SetModeLeds = 0x0b1a, // followed by bitmask of mode key leds
+	Set13 = 0x106a, // followed by r, g, b, [13 keycodes]
+	Set4 = 0x101a, // followed by (keycode, r, g, b){1,4}, [ff terminator if < 4]
+	SetEffect = 0x0f1a, // followed by group, effect, r, g, b, [period h..l], [00..00..01]
+	Commit = 0x107a,
+	ResetGameMode = 0x083a, // removes all non-default game mode key disables
+	GameModeAddKeys = 0x081a, // followed by (usb scancode){1,15}
+	SetMacroRecordMode = 0x0c0a, // followed by 00 or 01 for MR led off/on
+	// the onboard profiles feature, whose function 1 is SetControlMode
+	OnboardProfilesInfo = 0x110a, // sector size in bytes 7..8
+	SetControlMode = 0x111a, // 01 for hardware, 02 for software
+	SetOnboardProfile = 0x113a, // followed by 00, profile number
+	ReadMemory = 0x115a, // followed by sector h..l, offset h..l, returns 16 bytes
+	StartMemoryWrite = 0x116a, // followed by sector h..l, offset h..l, length h..l
+	WriteMemory = 0x117a, // followed by 16 bytes
+	EndMemoryWrite = 0x118a,
+	SetGKeysMode = 0x0a2a, // 00 G-keys in F-key mode, 01 in software mode
+	GetVersion = 0x021a,
+	CapabilityInfo = 0x000a, // OR this with (capabilityid << 8) to get capability info, otherwise id
+	LightingEnabled = 0x0f7a,
+	EffectsEnabled = 0x0f5a
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub enum ControlMode
+{
+	Hardware = 0x01,
+	Software = 0x02
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash)]
+pub enum GKeysMode
+{
+	Default = 0x00,
+	Software = 0x01
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Effect
+{
+	None,
+	Static,
+	Breathing,
+	Cycle,
+	ColorWave,
+	Ripple
+}
+
+impl Effect
+{
+	/// The id of each effect on the keys and logo groups, or None where the
+	/// group can't show it. The logo group has the ids of breathing and cycle
+	/// swapped, and ignores color wave (it has no keys to ripple either).
+	const IDS: [(Effect, Option<u8>, Option<u8>); 6] = [
+		(Effect::None, Some(0x00), Some(0x00)),
+		(Effect::Static, Some(0x01), Some(0x01)),
+		(Effect::Breathing, Some(0x02), Some(0x03)),
+		(Effect::Cycle, Some(0x03), Some(0x02)),
+		(Effect::ColorWave, Some(0x04), None),
+		(Effect::Ripple, Some(0x05), None)
+	];
+
+	fn of(effect: &EffectConfiguration) -> Self
+	{
+		match effect
+		{
+			EffectConfiguration::None => Effect::None,
+			EffectConfiguration::Static { .. } => Effect::Static,
+			EffectConfiguration::Breathing { .. } => Effect::Breathing,
+			EffectConfiguration::Cycle { .. } => Effect::Cycle,
+			EffectConfiguration::ColorWave { .. } => Effect::ColorWave,
+			EffectConfiguration::Ripple { .. } => Effect::Ripple
+		}
+	}
+
+	/// The id of this effect on an effect group, if the group can show it
+	fn id(self, group: EffectGroup) -> Option<u8>
+	{
+		Self::IDS
+			.iter()
+			.find(|(effect, ..)| *effect == self)
+			.and_then(|(_effect, keys_id, logo_id)| match group
+			{
+				EffectGroup::Keys => *keys_id,
+				EffectGroup::Logo => *logo_id
+			})
+	}
+}
+
+/// Whether an effect group can show an effect itself, rather than the nearest
+/// effect it supports
+pub fn supports_effect(group: EffectGroup, effect: &EffectConfiguration) -> bool
+{
+	Effect::of(effect).id(group).is_some()
+}
+
+/// Report rates are set as an interval of 1 to 8ms, so these are the only
+/// rates (in Hz) a device could offer. Which of them it actually does is only
+/// known once it's been opened.
+pub fn is_report_rate(rate: u16) -> bool
+{
+	(1..=8).any(|interval| 1000 / interval == rate)
+}
+
+impl From<HidError> for CommandError
+{
+	fn from(error: HidError) -> Self
+	{
+		CommandError::HidError(error)
+	}
+}
+
+impl From<String> for CommandError
+{
+	fn from(message: String) -> Self
+	{
+		CommandError::Failure(message)
+	}
+}
+
+pub struct G815Keyboard
+{
+	device: Box<dyn Transport>,
+	serial_number: String,
+	capabilities: HashMap<Capability, CapabilityData>,
+	capability_id_cache: HashMap<u8, Capability>,
+	key_bitmasks: HashMap<KeyType, u8>,
+	mode_leds: u8,
+	gkeys_mode: GKeysMode,
+	// events parsed from interrupts that arrived whilst waiting for a command's
+	// response, returned by the next call to get_events()
+	pending_events: Vec<DeviceEvent>,
+	// layout overrides of the keys' rgb ids
+	rgb_ids: HashMap<Scancode, u8>,
+	// the low power mode cap on each key's r + g + b, if it's on
+	power_cap: Option<u16>,
+	// the night mode color temperature, if it's night
+	color_temperature: Option<u16>,
+	// key colors set since the last commit, sent just before it
+	frame: FrameBuffer,
+	// whether anything has been sent since the last commit
+	needs_commit: bool,
+	// the groups with a firmware effect running
+	effects_running: HashSet<EffectGroup>,
+	command_stats: CommandStats,
+	consecutive_failures: u32
+}
+
+impl G815Keyboard
+{
+	pub fn init(device: Box<dyn Transport>) -> Box<dyn super::Device>
+	{
+		let mut keyboard = Self::new(device);
+		keyboard.load_capabilities();
+		Box::new(keyboard)
+	}
+
+	fn new(device: Box<dyn Transport>) -> Self
+	{
+		let serial_number = device
+			.serial_number()
+			.unwrap_or_default();
+
+		G815Keyboard
+		{
+			device,
+			serial_number,
+			capabilities: HashMap::new(),
+			capability_id_cache: HashMap::new(),
+			key_bitmasks: HashMap::new(),
+			pending_events: Vec::new(),
+			gkeys_mode: GKeysMode::Default,
+			mode_leds: 0x0,
+			rgb_ids: HashMap::new(),
+			power_cap: None,
+			color_temperature: None,
+			frame: FrameBuffer::default(),
+			needs_commit: false,
+			effects_running: HashSet::new(),
+			command_stats: CommandStats::default(),
+			consecutive_failures: 0
+		}
+	}
+
+	fn rgb_id(&self, scancode: Scancode) -> u8
+	{
+		self.rgb_ids
+			.get(&scancode)
+			.copied()
+			.unwrap_or_else(|| scancode.rgb_id())
+	}
+
+	/// A color as it's sent to the keyboard, warmed by night mode then capped
+	/// by low power mode
+	fn capped(&self, color: Color) -> Color
+	{
+		let color = match self.color_temperature
+		{
+			Some(temperature) => color.warmed(temperature),
+			None => color
+		};
+
+		match self.power_cap
+		{
+			Some(max_channel_sum) => color.capped(max_channel_sum),
+			None => color
+		}
+	}
+
+	/// Hardware effects that pick their own colors are capped by dimming them
+	/// as much as a capped white would be
+	fn capped_effect_brightness(&self, brightness: u8) -> u8
+	{
+		match self.power_cap
+		{
+			Some(max_channel_sum) => (brightness as u32 * max_channel_sum.min(765) as u32 / 765) as u8,
+			None => brightness
+		}
+	}
+
+	// how much of the onboard memory is read or written by each command
+	const MEMORY_CHUNK: usize = 16;
+	// where an onboard profile keeps the effects of the keys then the logo, 11
+	// bytes each (the same layout as logitech's mice)
+	const PROFILE_LIGHTING_OFFSET: usize = 0xd0;
+	const PROFILE_EFFECT_LENGTH: usize = 11;
+	// sectors from here on are the built in profiles, which can't be written
+	const ROM_SECTORS: u16 = 0x0100;
+
+	// how long to wait for the response to a command, and how many times to send
+	// it before giving up
+	const RESPONSE_TIMEOUT: Duration = Duration::from_millis(500);
+	const WRITE_ATTEMPTS: u8 = 3;
+	// how long to wait before resending a command that failed, doubled after
+	// each attempt
+	const RETRY_BACKOFF: Duration = Duration::from_millis(20);
+
+	/// Sends a command and returns the data from its response, resending it if no
+	/// response arrives in time (or sending it failed in a way that might not
+	/// happen again)
+	fn write(&mut self, command: u16, data: &[u8]) -> CommandResult<Vec<u8>>
+	{
+		// key colors wait for the commit, but anything sent before it (i.e. an
+		// effect) still has to come after them
+		if command != Command::Set4 as u16 && command != Command::Set13 as u16 && !self.frame.is_empty()
+		{
+			self.send_frame()?;
+		}
+
+		if command != Command::Commit as u16
+		{
+			self.needs_commit = true;
+		}
+
+		let mut attempt = 1;
+		let mut backoff = Self::RETRY_BACKOFF;
+		let started = Instant::now();
+
+		let result = loop
+		{
+			match self.write_once(command, data)
+			{
+				Err(error) if error.is_transient() && attempt < Self::WRITE_ATTEMPTS =>
+				{
+					warn!("command {:#06x} failed (attempt {}): {}, resending", command, attempt, error);
+
+					if let CommandError::Timeout = error
+					{
+						self.command_stats.timeouts += 1;
+					}
+
+					std::thread::sleep(backoff);
+					backoff *= 2;
+					attempt += 1;
+				},
+				result => break result
+			}
+		};
+
+		self.command_stats.commands += 1;
+		self.command_stats.latency += started.elapsed();
+
+		match &result
+		{
+			Err(error) =>
+			{
+				if let CommandError::Timeout = error
+				{
+					self.command_stats.timeouts += 1;
+				}
+
+				self.command_stats.errors += 1;
+
+				if error.is_transient()
+				{
+					self.consecutive_failures += 1;
+				}
+			},
+			Ok(_) => self.consecutive_failures = 0
+		}
+
+		result
+	}
+
+	/// Sends a command and waits for its response. Anything else that arrives in
+	/// the meantime (key presses etc) is handed to the event parser straight away,
+	/// however much of it there is.
+	fn write_once(&mut self, command: u16, data: &[u8]) -> CommandResult<Vec<u8>>
+	{
+		let mut buffer = vec![
+			0x11,
+			0xff,
+			(command >> 8) as u8,
+			command as u8
+		];
+
+		buffer.extend(data);
+		buffer.resize(20, 0);
+
+		let mut expected_return = [0; 4];
+		expected_return.clone_from_slice(&buffer[..4]);
+
+		let mut error_response = expected_return.to_vec();
+		error_response.insert(2, 0xff);
+
+		self.device.write(&buffer)?;
+
+		trace!("OUT {:02x?}", &buffer);
+
+		let deadline = Instant::now() + Self::RESPONSE_TIMEOUT;
+
+		loop
+		{
+			let remaining = deadline.saturating_duration_since(Instant::now());
+
+			if remaining == Duration::from_millis(0)
+			{
+				return Err(CommandError::Timeout)
+			}
+
+			buffer.clear();
+			buffer.resize(20, 0);
+			// round up so we never busy-wait on a sub-millisecond timeout
+			let bytes_read = self.device.read_timeout(&mut buffer, remaining.as_millis() as i32 + 1)?;
+			buffer.truncate(bytes_read);
+
+			if bytes_read == 0
+			{
+				continue
+			}
+
+			if bytes_read >= 4 && buffer[..4] == expected_return
+			{
+				trace!("ACK {:02x?}", &buffer);
+
+				buffer.drain(0..4);
+				return Ok(buffer);
+			}
+
+			if bytes_read >= 5 && buffer[..5] == error_response[..]
+			{
+				trace!("ERR {:02x?}", &buffer);
+				return Err(CommandError::Failure(
+					format!("device didn't like command {:#?}", &expected_return)))
+			}
+
+			trace!("IN {:02x?}", &buffer);
+			let events = self.events_from_interrupt(&buffer);
+			self.pending_events.extend(events);
+		}
+	}
+
+	fn execute(&mut self, command: Command, data: &[u8]) -> CommandResult<Vec<u8>>
+	{
+		self.write(command as u16, data)
+	}
+
+	/// Sends the key colors set since the last commit
+	fn send_frame(&mut self) -> CommandResult<()>
+	{
+		let result = self.frame.take_commands().iter().try_for_each(|command|
+		{
+			match command
+			{
+				FrameCommand::Set4(keys) =>
+				{
+					let mut data: Vec<u8> = keys
+						.iter()
+						.map(|(rgb_id, color)| vec![*rgb_id, color.r, color.g, color.b])
+						.flatten()
+						.collect();
+
+					if keys.len() < FrameBuffer::SET_4_KEYS
+					{
+						data.push(0xff);
+					}
+
+					self.execute(Command::Set4, &data).map(|_| ())
+				},
+				FrameCommand::Set13(color, rgb_ids) =>
+				{
+					let mut data = [0; 16];
+					data[0] = color.r;
+					data[1] = color.g;
+					data[2] = color.b;
+					data[3..3 + rgb_ids.len()].copy_from_slice(rgb_ids);
+
+					self.execute(Command::Set13, &data).map(|_| ())
+				}
+			}
+		});
+
+		// the keyboard could be showing anything now
+		if result.is_err()
+		{
+			self.frame.forget_sent();
+		}
+
+		result
+	}
+
+	fn version(&mut self, firmware_bank: u8) -> CommandResult<String>
+	{
+		let data = self.execute(Command::GetVersion, &[firmware_bank])?;
+
+		let name = String::from_utf8_lossy(&data[1..4]);
+		let major = 100 + (10 * (data[4] as u16 >> 4)) + (data[4] as u16 & 0xf);
+		let minor = (10 * (data[5] as u16 >> 4)) + (data[5] as u16 & 0xf);
+		let build = (10 * (data[7] as u16 >> 4)) + (data[7] as u16 & 0xf);
+
+		Ok(format!("{}: {}.{}.{}", name.trim(), major, minor, build))
+	}
+
+	fn capability_data(&self, capability: Capability) -> CommandResult<&CapabilityData>
+	{
+		match self.capabilities.get(&capability)
+		{
+			Some(capability_data) => Ok(capability_data),
+			None => Err(CommandError::LogicError(format!(
+				"attempt to get capability_data for non initialized capability '{}'",
+				capability as u8)))
+		}
+	}
+
+	fn load_capabilities(&mut self) -> CommandResult<()>
+	{
+		let capabilities = [
+			Capability::GKeys,
+			Capability::ModeSwitching,
+			Capability::GameMode,
+			Capability::MacroRecording,
+			Capability::BrightnessAdjustment,
+			Capability::ReportRate
+		];
+
+		let capabilities = capabilities
+			.iter()
+			.try_for_each(|capability| self.load_capability_data(*capability).map(|_| ()));
+
+		if !self.has_capability(Capability::ReportRate)
+		{
+			debug!("device can't change its report rate");
+		}
+
+		trace!("capability id cache: {:#0x?}", &self.capability_id_cache);
+		capabilities
+	}
+
+	fn load_capability_data(&mut self, capability: Capability) -> CommandResult<&CapabilityData>
+	{
+		let id_result = self.execute(
+			Command::CapabilityInfo,
+			&[((capability as u16) >> 8) as u8, capability as u8])?;
+
+		debug!("loading data for capability {:?}, id is: {:#04x}", capability, id_result[0]);
+
+		let capability_data = match id_result[0]
+		{
+			0 => CapabilityData::default(),
+			capability_id =>
+			{
+				let data_command = ((capability_id as u16) << 8) | (Command::CapabilityInfo as u16);
+				let data = self.write(data_command, &[0; 0])?;
+
+				debug!("capability data: {:02x?}", &data);
+
+				let mut cap_data = CapabilityData
+				{
+					id: capability_id,
+					raw: None,
+					key_count: match capability
+					{
+						Capability::GKeys => Some(data[0]),
+						Capability::ModeSwitching => Some(data[0]),
+						Capability::GameMode => Some(1),
+						Capability::MacroRecording => Some(1),
+						Capability::BrightnessAdjustment => Some(1),
+						Capability::ReportRate => None
+					},
+					key_type: match capability
+					{
+						Capability::GKeys => Some(KeyType::GKey),
+						Capability::ModeSwitching => Some(KeyType::Mode),
+						Capability::GameMode => Some(KeyType::GameMode),
+						Capability::MacroRecording => Some(KeyType::MacroRecord),
+						Capability::BrightnessAdjustment => Some(KeyType::Light),
+						Capability::ReportRate => None
+					}
+				};
+
+				cap_data.raw = Some(data);
+				cap_data
+			}
+		};
+
+		self.capability_id_cache.insert(capability_data.id, capability);
+
+		let data_ref = self.capabilities
+			.entry(capability)
+			.or_insert(capability_data);
+
+		Ok(data_ref)
+	}
+
+	fn has_capability(&self, capability: Capability) -> bool
+	{
+		match self.capabilities.get(&capability)
+		{
+			Some(data) => data.id > 0,
+			None => false
+		}
+	}
+
+	fn report_rate_capability(&self) -> CommandResult<&CapabilityData>
+	{
+		match self.has_capability(Capability::ReportRate)
+		{
+			true => self.capability_data(Capability::ReportRate),
+			false => Err(CommandError::Failure("the device can't change its report rate".to_string()))
+		}
+	}
+
+	fn bootloader_version(&mut self) -> CommandResult<String>
+	{
+		self.version(0x00)
+	}
+
+	fn firmware_version(&mut self) -> CommandResult<String>
+	{
+		self.version(0x01)
+	}
+
+	fn set_control_mode(&mut self, mode: ControlMode) -> CommandResult<()>
+	{
+		self.execute(Command::SetControlMode, &[mode as u8; 1]).map(|_| ())
+	}
+
+	fn set_gkeys_mode(&mut self, mode: GKeysMode) -> CommandResult<()>
+	{
+		self.execute(Command::SetGKeysMode, &[mode as u8; 1])?;
+		self.gkeys_mode = mode;
+		Ok(())
+	}
+
+	/// Translates an effect into one the logo group can actually show: it
+	/// ignores color wave and has no keys to ripple, so the nearest effect is
+	/// used instead
+	fn logo_effect(effect: &EffectConfiguration) -> Cow<'_, EffectConfiguration>
+	{
+		match effect
+		{
+			EffectConfiguration::ColorWave { duration, brightness, .. } =>
+				Cow::Owned(EffectConfiguration::Cycle { duration: *duration, brightness: *brightness }),
+			EffectConfiguration::Ripple { color, .. } =>
+				Cow::Owned(EffectConfiguration::Static { color: *color }),
+			_ => Cow::Borrowed(effect)
+		}
+	}
+
+	/// The data of the command that starts an effect on a group. Bytes 1 to 11
+	/// (the effect's id and parameters) are also how onboard profiles store it.
+	fn effect_data(&self, group: EffectGroup, effect: &EffectConfiguration) -> CommandResult<[u8; 16]>
+	{
+		let mut data = [
+			group as u8,
+			0, // effect id
+			0, // r
+			0, // g
+			0, // b
+			0, // [5] duration high byte for breathing, 0x02 for fixed, otherwise 0
+			0, // duration low byte for breathing
+			0, // duration high byte for cycle, ripple (only one byte)
+			0, // duration low byte for cycle, color wave & brightness for breathing
+			0, // brightness for cycle, direction for color wave
+			0, // brightness for color wave
+			0, // duration high for color wave
+			// always ends with this
+			1, 0, 0, 0
+		];
+
+		let effect = match group
+		{
+			EffectGroup::Logo => Self::logo_effect(effect),
+			EffectGroup::Keys => Cow::Borrowed(effect)
+		};
+
+		data[1] = Effect::of(&effect)
+			.id(group)
+			.ok_or_else(|| format!("the {:?} group can't show {:?}", group, effect))?;
+
+		match effect.as_ref()
+		{
+			EffectConfiguration::None => (),
+			EffectConfiguration::Static { color } =>
+			{
+				let color = self.capped(*color);
+				data[2] = color.r;
+				data[3] = color.g;
+				data[4] = color.b;
+				data[5] = 0x02;
+			},
+			EffectConfiguration::Breathing { color, duration, brightness } =>
+			{
+				let color = self.capped(*color);
+				data[2] = color.r;
+				data[3] = color.g;
+				data[4] = color.b;
+				data[5] = (duration >> 8) as u8;
+				data[6] = *duration as u8;
+				data[7] = *brightness;
+			},
+			EffectConfiguration::Cycle { duration, brightness } =>
+			{
+				data[7] = (duration >> 8) as u8;
+				data[8] = *duration as u8;
+				data[9] = self.capped_effect_brightness(*brightness);
+			},
+			EffectConfiguration::ColorWave { direction, duration, brightness } =>
+			{
+				data[8] = *duration as u8;
+				data[9] = *direction as u8;
+				data[10] = self.capped_effect_brightness(*brightness);
+				data[11] = (duration >> 8) as u8;
+			}
+			EffectConfiguration::Ripple { color, duration } =>
+			{
+				// this is ghubs limit so we'll also use it
+				if *duration > 200
+				{
+					return Err(CommandError::Failure("duration for ripple must be <= 200".into()))
+				}
+
+				let color = self.capped(*color);
+				data[2] = color.r;
+				data[3] = color.g;
+				data[4] = color.b;
+				data[7] = *duration as u8;
+			}
+		}
+
+		Ok(data)
+	}
+
+	/// The effect stored as `block` (an effect id and its parameters, as in
+	/// effect_data) for a group
+	fn decode_effect(group: EffectGroup, block: &[u8]) -> CommandResult<EffectConfiguration>
+	{
+		// same offsets as effect_data, which starts a byte earlier
+		let byte = |index: usize| block[index - 1];
+		let color = || Color::new(byte(2), byte(3), byte(4));
+		let unknown = || CommandError::Failure(format!("unknown {:?} effect {:02x?}", group, block));
+
+		let effect = Effect::IDS
+			.iter()
+			.find(|(_effect, keys_id, logo_id)| match group
+			{
+				EffectGroup::Keys => *keys_id,
+				EffectGroup::Logo => *logo_id
+			} == Some(block[0]))
+			.map(|(effect, ..)| *effect)
+			.ok_or_else(unknown)?;
+
+		Ok(match effect
+		{
+			Effect::None => EffectConfiguration::None,
+			Effect::Static => EffectConfiguration::Static { color: color() },
+			Effect::Breathing => EffectConfiguration::Breathing
+			{
+				color: color(),
+				duration: (byte(5) as u16) << 8 | byte(6) as u16,
+				brightness: byte(7)
+			},
+			Effect::Cycle => EffectConfiguration::Cycle
+			{
+				duration: (byte(7) as u16) << 8 | byte(8) as u16,
+				brightness: byte(9)
+			},
+			Effect::ColorWave => EffectConfiguration::ColorWave
+			{
+				direction: match byte(9)
+				{
+					0x01 => EffectDirection::Horizontal,
+					0x02 => EffectDirection::Vertical,
+					0x03 => EffectDirection::CenterOut,
+					0x06 => EffectDirection::ReverseHorizontal,
+					0x07 => EffectDirection::ReverseVertical,
+					0x08 => EffectDirection::CenterIn,
+					_ => return Err(unknown())
+				},
+				duration: (byte(11) as u16) << 8 | byte(8) as u16,
+				brightness: byte(10)
+			},
+			Effect::Ripple => EffectConfiguration::Ripple { color: color(), duration: byte(7) as u16 }
+		})
+	}
+
+	fn onboard_sector_size(&mut self) -> CommandResult<usize>
+	{
+		let data = self.execute(Command::OnboardProfilesInfo, &[0; 0])?;
+		Ok((data[7] as usize) << 8 | data[8] as usize)
+	}
+
+	/// Reads a whole sector of the onboard memory, warning if its checksum is wrong
+	fn read_sector(&mut self, sector: u16, size: usize) -> CommandResult<Vec<u8>>
+	{
+		let mut data = Vec::with_capacity(size);
+
+		while data.len() < size
+		{
+			// reads can't run past the end of a sector, so the last one may overlap
+			let offset = data.len().min(size - Self::MEMORY_CHUNK);
+			let chunk = self.execute(Command::ReadMemory,
+				&[(sector >> 8) as u8, sector as u8, (offset >> 8) as u8, offset as u8])?;
+
+			data.truncate(offset);
+			data.extend_from_slice(&chunk[..Self::MEMORY_CHUNK]);
+		}
+
+		let checksum = (data[size - 2] as u16) << 8 | data[size - 1] as u16;
+
+		if checksum != crc(&data[..size - 2])
+		{
+			warn!("sector {:#06x} of the onboard memory has a bad checksum", sector);
+		}
+
+		Ok(data)
+	}
+
+	/// Replaces a sector of the onboard memory, with its checksum filled in
+	fn write_sector(&mut self, sector: u16, data: &mut [u8]) -> CommandResult<()>
+	{
+		let size = data.len();
+		let checksum = crc(&data[..size - 2]);
+		data[size - 2] = (checksum >> 8) as u8;
+		data[size - 1] = checksum as u8;
+
+		self.execute(Command::StartMemoryWrite,
+			&[(sector >> 8) as u8, sector as u8, 0, 0, (size >> 8) as u8, size as u8])?;
+
+		data
+			.chunks(Self::MEMORY_CHUNK)
+			.try_for_each(|chunk| self.execute(Command::WriteMemory, chunk).map(|_| ()))?;
+
+		self.execute(Command::EndMemoryWrite, &[0; 0]).map(|_| ())
+	}
+
+	/// The sector an onboard profile (numbered from 1) is stored in, from the
+	/// directory of profiles in sector 0
+	fn onboard_profile_sector(&mut self, profile: u8, size: usize) -> CommandResult<u16>
+	{
+		let directory = self.read_sector(0, size)?;
+
+		profile
+			.checked_sub(1)
+			.and_then(|index| directory
+				.chunks(4)
+				.take_while(|entry| entry[..2] != [0xff, 0xff])
+				.nth(index as usize))
+			.map(|entry| (entry[0] as u16) << 8 | entry[1] as u16)
+			.ok_or_else(|| CommandError::Failure(format!("the keyboard has no onboard profile {}", profile)))
+	}
+
+	fn events_from_interrupt(&mut self, buffer: &[u8]) -> Vec<DeviceEvent>
+	{
+		if buffer[0] == 0x03
+		{
+			return self.handle_media_key_interrupt(buffer[1])
+		}
+
+		// if it's not a media key or a capability key then ignore it
+		// note: 11 ff 0f 10 [00/01] comes in regularly, seems to be effect cycle done/restarting?
+
+		if buffer.len() < 3 || buffer[0] != 0x11 || buffer[1] != 0xff
+		{
+			return Vec::new()
+		}
+
+		match self.capability_id_cache.get(&buffer[2])
+		{
+			Some(capability) =>
+			{
+				let cap_id = *capability;
+				self.handle_capability_key_interrupt(cap_id, &buffer[4..])
+			},
+			None => Vec::new()
+		}
+	}
+
+	fn handle_media_key_interrupt(&mut self, current_bitmask: u8) -> Vec<DeviceEvent>
+	{
+		let previous_bitmask = self.key_bitmasks.get(&KeyType::MediaControl).unwrap_or(&0);
+		let change_bitmask = previous_bitmask ^ current_bitmask;
+
+		debug!(
+			"media key interrupt:\n\tprevious {:08b}\n\tcurrent  {:08b}\n\tchange   {:08b}",
+			&previous_bitmask,
+			&current_bitmask,
+			&change_bitmask);
+
+		self.key_bitmasks.insert(KeyType::MediaControl, current_bitmask);
+
+		(0..7)
+			.filter(|bit| (change_bitmask >> bit) & 0x1 == 0x1)
+			.filter_map(|bit|
+			{
+				let key = match 1 << bit
+				{
+					0x01 => Some(MediaKey::Next),
+					0x02 => Some(MediaKey::Previous),
+					0x08 => Some(MediaKey::PlayPause),
+					0x10 => Some(MediaKey::VolumeUp),
+					0x20 => Some(MediaKey::VolumeDown),
+					0x40 => Some(MediaKey::Mute),
+					_ => None
+				};
+
+				key.map(|key| (bit, key))
+			})
+			.map(|(bit, key)| match (current_bitmask >> bit) & 0x1 == 0x1
+			{
+				true => DeviceEvent::MediaKeyDown(key),
+				false => DeviceEvent::MediaKeyUp(key)
+			})
+			.collect()
+	}
+
+	/// Handles conversion of a capability key interrupt (gkeys, mode keys etc)
+	/// into a list of device events based on previous and current bitmasks
+	fn handle_capability_key_interrupt(&mut self, capability: Capability, data: &[u8])
+		-> Vec<DeviceEvent>
+	{
+		let capability_data = self.capability_data(capability).unwrap();
+		let key_type = capability_data.key_type.unwrap();
+
+		match key_type
+		{
+			KeyType::Light => vec![DeviceEvent::BrightnessLevelChanged(data[1])],
+			KeyType::GKey
+				| KeyType::GameMode
+				| KeyType::MacroRecord
+				| KeyType::Mode =>
+			{
+				let key_count = capability_data.key_count.unwrap();
+				let current_bitmask = data[0];
+				let previous_bitmask = self.key_bitmasks.get(&key_type).unwrap_or(&0);
+				let change_bitmask = previous_bitmask ^ current_bitmask;
+
+				debug!("capability key interrupt:\n\tcapability {:?}\n\t \
+					previous {:08b}\n\tcurrent  {:08b}\n\tchange   {:08b}",
+					&capability,
+					&previous_bitmask,
+					&current_bitmask,
+					&change_bitmask);
+
+				self.key_bitmasks.insert(key_type, current_bitmask);
+
+				(0..key_count)
+					.filter(|key| (change_bitmask >> key) & 0x1 == 0x1)
+					.map(|key| match (current_bitmask >> key) & 0x1 == 0x1
+					{
+						false => DeviceEvent::KeyUp(key_type, key + 1),
+						true => DeviceEvent::KeyDown(key_type, key + 1)
+					})
+					.collect()
+			},
+			_ => Vec::new()
+		}
+	}
+}
+
+impl fmt::Display for G815Keyboard
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "{}\nSerial: {}",
+			self.device.product_string()
+				.unwrap_or_else(|| "unknown product string".to_string()),
+			self.serial_number)
+	}
+}
+
+impl super::Device for G815Keyboard
+{
+	fn serial_number(&self) -> &str
+	{
+		&self.serial_number
+	}
+
+	fn firmware_info(&mut self) -> String
+	{
+		format!(
+			"Firmware: {}\nBootloader: {}",
+			self.firmware_version().unwrap_or_else(|e| format!("{:?}", e)),
+			self.bootloader_version().unwrap_or_else(|e| format!("{:?}", e)))
+	}
+
+	fn info(&mut self) -> DeviceInfo
+	{
+		let mut capabilities: Vec<CapabilityInfo> = self.capabilities
+			.iter()
+			.map(|(capability, data)| CapabilityInfo
+			{
+				name: format!("{:?}", capability),
+				feature: format!("{:#06x}", *capability as u16),
+				index: data.id,
+				key_count: data.key_count
+			})
+			.collect();
+
+		capabilities.sort_by(|a, b| a.feature.cmp(&b.feature));
+
+		DeviceInfo
+		{
+			product: self.device.product_string().unwrap_or_default(),
+			serial_number: self.serial_number.clone(),
+			firmware: self.firmware_version().ok(),
+			bootloader: self.bootloader_version().ok(),
+			capabilities,
+			report_rates: self.report_rates()
+		}
+	}
+
+	fn take_control(&mut self) -> CommandResult<()>
+	{
+		// the keyboard starts out (or was reset to) showing its own lighting
+		self.frame.forget_sent();
+		self.execute(Command::InitializeSession, &[0; 0])?;
+		self.set_control_mode(ControlMode::Software)?;
+		self.set_gkeys_mode(GKeysMode::Software)?;
+		self.set_macro_recording(false)?;
+		self.set_mode(1)?;
+		self.reset_game_mode_keys()?;
+		self.execute(Command::LightingEnabled, &[1; 1])?;
+		// TODO don't know what these numbers do, last byte can be 0x03, 0x05, 0x07
+		// none of them seem to have any visual effect on a running effect but it must
+		// be called for effects to work
+		self.execute(Command::EffectsEnabled, &[0x01, 0x03, 0x03])?;
+		self.stop_effects();
+		self.clear();
+		Ok(())
+	}
+
+	fn release_control(&mut self) -> CommandResult<()>
+	{
+		self.set_macro_recording(false)?;
+		self.set_gkeys_mode(GKeysMode::Default)?;
+		self.set_control_mode(ControlMode::Hardware)
+	}
+
+	fn set_gkeys_software_mode(&mut self, software: bool) -> CommandResult<()>
+	{
+		let mode = match software
+		{
+			true => GKeysMode::Software,
+			false => GKeysMode::Default
+		};
+
+		match mode == self.gkeys_mode
+		{
+			true => Ok(()),
+			false => self.set_gkeys_mode(mode)
+		}
+	}
+
+	fn mode_count(&self) -> CommandResult<u8>
+	{
+		self.capability_data(Capability::ModeSwitching)
+			.map(|data| data.key_count.unwrap_or(0))
+	}
+
+	// both only fill in the frame, which is sent at the next commit (or
+	// before any other command)
+	fn set_4(&mut self, keys: &[(Scancode, Color)]) -> CommandResult<()>
+	{
+		for (scancode, color) in keys
+		{
+			self.frame.set(self.rgb_id(*scancode), self.capped(*color));
+		}
+
+		Ok(())
+	}
+
+	fn set_13(&mut self, color: Color, keys: &[Scancode]) -> CommandResult<()>
+	{
+		let color = self.capped(color);
+
+		for scancode in keys
+		{
+			self.frame.set(self.rgb_id(*scancode), color);
+		}
+
+		Ok(())
+	}
+
+	fn commit(&mut self) -> CommandResult<()>
+	{
+		self.send_frame()?;
+
+		// i.e. a theme applied again, with every key already showing its color
+		if !self.needs_commit
+		{
+			return Ok(())
+		}
+
+		self.execute(Command::Commit, &[0; 0]).map(|_| self.needs_commit = false)
+	}
+
+	fn set_mode_leds(&mut self, mask: u8) -> CommandResult<()>
+	{
+		match self.mode_leds ^ mask
+		{
+			0 => Ok(()),
+			_=>
+			{
+				self.mode_leds = mask;
+				self.execute(Command::SetModeLeds, &[self.mode_leds; 1]).map(|_| ())
+			}
+		}
+	}
+
+	fn set_macro_recording(&mut self, recording: bool) -> CommandResult<()>
+	{
+		self.execute(Command::SetMacroRecordMode, &[recording as u8; 1]).map(|_| ())
+	}
+
+	fn brightness(&mut self) -> CommandResult<u8>
+	{
+		// function 1 of the brightness capability, the level is a big endian u16
+		let command = ((self.capability_data(Capability::BrightnessAdjustment)?.id as u16) << 8) | 0x1a;
+		self.write(command, &[0; 0]).map(|data| data[1])
+	}
+
+	fn set_brightness(&mut self, level: u8) -> CommandResult<()>
+	{
+		// function 2 of the brightness capability
+		let command = ((self.capability_data(Capability::BrightnessAdjustment)?.id as u16) << 8) | 0x2a;
+		self.write(command, &[0, level.min(100)]).map(|_| ())
+	}
+
+	fn report_rates(&self) -> Vec<u16>
+	{
+		// function 0 of the report rate capability (loaded with the capability)
+		// is a bitmask of the supported report intervals, bit n is n + 1 ms
+		let supported = match self.capabilities.get(&Capability::ReportRate)
+		{
+			Some(CapabilityData { id, raw: Some(raw), .. }) if *id > 0 && !raw.is_empty() => raw[0],
+			_ => return Vec::new()
+		};
+
+		(0..8)
+			.filter(|bit| supported & (1 << bit) != 0)
+			.map(|bit| 1000 / (bit + 1))
+			.collect()
+	}
+
+	fn report_rate(&mut self) -> CommandResult<u16>
+	{
+		// function 1 of the report rate capability, the interval in ms
+		let command = ((self.report_rate_capability()?.id as u16) << 8) | 0x1a;
+		self.write(command, &[0; 0]).map(|data| 1000 / data[0].max(1) as u16)
+	}
+
+	fn set_report_rate(&mut self, rate: u16) -> CommandResult<()>
+	{
+		if !self.report_rates().contains(&rate)
+		{
+			return Err(CommandError::Failure(format!("the device can't report at {}Hz", rate)))
+		}
+
+		// function 2 of the report rate capability
+		let command = ((self.report_rate_capability()?.id as u16) << 8) | 0x2a;
+		self.write(command, &[(1000 / rate) as u8]).map(|_| ())
+	}
+
+	fn set_rgb_ids(&mut self, rgb_ids: HashMap<Scancode, u8>)
+	{
+		self.rgb_ids = rgb_ids;
+		self.frame.forget_sent();
+	}
+
+	fn set_power_cap(&mut self, max_channel_sum: Option<u16>)
+	{
+		self.power_cap = max_channel_sum;
+	}
+
+	fn set_color_temperature(&mut self, temperature: Option<u16>)
+	{
+		self.color_temperature = temperature;
+	}
+
+	fn set_effect(&mut self, group: EffectGroup, effect: &EffectConfiguration)
+		-> CommandResult<()>
+	{
+		// effects draw over the keys' colors, so whatever they were showing is
+		// unknown until the next frame
+		let running = *effect != EffectConfiguration::None;
+
+		if running || self.effects_running.contains(&group)
+		{
+			self.frame.forget_sent();
+		}
+
+		match running
+		{
+			true => self.effects_running.insert(group),
+			false => self.effects_running.remove(&group)
+		};
+
+		let data = self.effect_data(group, effect)?;
+		self.execute(Command::SetEffect, &data).map(|_| ())
+	}
+
+	fn add_game_mode_keys(&mut self, scancodes: &[Scancode]) -> CommandResult<()>
+	{
+		scancodes
+			.iter()
+			.filter_map(|code| match code
+			{
+				// ghub doesn't let you add these to game mode so we probably
+				// shouldnt either
+
+				Scancode::LeftMeta
+					| Scancode::RightMeta
+					| Scancode::ContextMenu
+					| Scancode::Mute
+					| Scancode::Light
+					| Scancode::G1
+					| Scancode::G2
+					| Scancode::G3
+					| Scancode::G4
+					| Scancode::G5
+					| Scancode::Logo
+					| Scancode::MediaPrevious
+					| Scancode::MediaNext
+					| Scancode::MediaPlayPause => None,
+				code => Some(*code as u8)
+			})
+			.collect::<Vec<u8>>()
+			.chunks(15) // last byte always seems to be 00 even if there are more than 15
+			.try_for_each(|scancodes| self
+				.write(Command::GameModeAddKeys as u16, scancodes)
+				.map(|_| ()))
+	}
+
+	fn reset_game_mode_keys(&mut self) -> CommandResult<()>
+	{
+		self.write(Command::ResetGameMode as u16, &[0; 0]).map(|_| ())
+	}
+
+	fn onboard_lighting(&mut self, profile: u8) -> CommandResult<OnboardLighting>
+	{
+		let size = self.onboard_sector_size()?;
+		let sector = self.onboard_profile_sector(profile, size)?;
+		let data = self.read_sector(sector, size)?;
+		let block = |index: usize| &data[Self::PROFILE_LIGHTING_OFFSET + index * Self::PROFILE_EFFECT_LENGTH..]
+			[..Self::PROFILE_EFFECT_LENGTH];
+
+		Ok(OnboardLighting
+		{
+			keys: Self::decode_effect(EffectGroup::Keys, block(0))?,
+			logo: Self::decode_effect(EffectGroup::Logo, block(1))?
+		})
+	}
+
+	fn set_onboard_lighting(&mut self, profile: u8, lighting: &OnboardLighting) -> CommandResult<()>
+	{
+		let size = self.onboard_sector_size()?;
+		let sector = self.onboard_profile_sector(profile, size)?;
+
+		if sector >= Self::ROM_SECTORS
+		{
+			return Err(CommandError::Failure(format!("onboard profile {} is built in, so can't be changed", profile)))
+		}
+
+		// everything else in the profile (i.e. its report rate) is kept as it is
+		let mut data = self.read_sector(sector, size)?;
+
+		for (index, (group, effect)) in [(EffectGroup::Keys, &lighting.keys), (EffectGroup::Logo, &lighting.logo)]
+			.iter()
+			.enumerate()
+		{
+			let offset = Self::PROFILE_LIGHTING_OFFSET + index * Self::PROFILE_EFFECT_LENGTH;
+			let effect_data = self.effect_data(*group, effect)?;
+
+			data[offset..offset + Self::PROFILE_EFFECT_LENGTH]
+				.copy_from_slice(&effect_data[1..=Self::PROFILE_EFFECT_LENGTH]);
+		}
+
+		self.write_sector(sector, &mut data)?;
+
+		// the keyboard only reloads a profile when it's switched to
+		self.execute(Command::SetOnboardProfile, &[0, profile]).map(|_| ())
+	}
+
+	fn take_command_stats(&mut self) -> CommandStats
+	{
+		std::mem::take(&mut self.command_stats)
+	}
+
+	fn consecutive_failures(&self) -> u32
+	{
+		self.consecutive_failures
+	}
+
+	fn get_events(&mut self) -> Vec<DeviceEvent>
+	{
+		let mut events: Vec<DeviceEvent> = self.pending_events.drain(..).collect();
+		let mut buffer = [0; 20];
+		let bytes_read = self.device.read_timeout(&mut buffer, 0).unwrap_or(0);
+
+		if !events.is_empty() || bytes_read > 0
+		{
+			debug!("device polled: {} events pending, {} bytes read just now",
+			   events.len(),
+			   bytes_read);
+		}
+
+		if bytes_read > 0
+		{
+			trace!("IN {:02x?}", &buffer);
+			events.extend(self.events_from_interrupt(&buffer));
+		}
+
+		events
+	}
+
+	fn wait_for_events(&mut self, timeout: Duration)
+	{
+		if !self.pending_events.is_empty()
+		{
+			return
+		}
+
+		let mut buffer = [0; 20];
+
+		match self.device.read_timeout(&mut buffer, timeout.as_millis() as i32)
+		{
+			Ok(0) => (),
+			Ok(_bytes_read) =>
+			{
+				trace!("IN {:02x?}", &buffer);
+				let events = self.events_from_interrupt(&buffer);
+				self.pending_events.extend(events);
+			},
+			// a device that's gone away fails straight away, so don't spin on it
+			Err(_error) => std::thread::sleep(timeout)
+		}
+	}
+}
+
+/// CRC-CCITT (starting from 0xffff), which ends every sector of the onboard memory
+fn crc(data: &[u8]) -> u16
+{
+	data.iter().fold(0xffff, |crc, byte|
+	{
+		(0..8).fold(crc ^ (*byte as u16) << 8, |crc, _bit| match crc & 0x8000 != 0
+		{
+			true => crc << 1 ^ 0x1021,
+			false => crc << 1
+		})
+	})
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashSet;
+
+	use super::*;
+	use crate::Device;
+	use crate::mock::MockTransport;
+
+	const SET_13: [u8; 2] = [0x10, 0x6a];
+	const COMMIT: [u8; 2] = [0x10, 0x7a];
+
+	/// A keyboard, and its transport with the capability queries cleared out
+	fn keyboard() -> (Box<dyn Device>, MockTransport)
+	{
+		let transport = MockTransport::g815();
+		let keyboard = G815Keyboard::init(Box::new(transport.clone()));
+		transport.take_written();
+		(keyboard, transport)
+	}
+
+	#[test]
+	fn capabilities_are_read_from_the_device()
+	{
+		let (keyboard, _transport) = keyboard();
+
+		assert_eq!(keyboard.mode_count().unwrap(), 3);
+		assert_eq!(keyboard.report_rates(), vec![1000, 500]);
+	}
+
+	#[test]
+	fn capability_key_interrupts_become_events()
+	{
+		let (mut keyboard, transport) = keyboard();
+
+		// G2 down, then G2 up and G3 down at once
+		transport.interrupt(&[0x11, 0xff, 0x0a, 0x00, 0b010]);
+		assert!(matches!(keyboard.get_events()[..], [DeviceEvent::KeyDown(KeyType::GKey, 2)]));
+
+		transport.interrupt(&[0x11, 0xff, 0x0a, 0x00, 0b100]);
+		assert!(matches!(keyboard.get_events()[..],
+			[DeviceEvent::KeyUp(KeyType::GKey, 2), DeviceEvent::KeyDown(KeyType::GKey, 3)]));
+	}
+
+	#[test]
+	fn interrupts_during_a_command_are_kept()
+	{
+		let (mut keyboard, transport) = keyboard();
+
+		// arrives before the response to the command
+		transport.interrupt(&[0x11, 0xff, 0x0b, 0x00, 0b001]);
+		keyboard.set_macro_recording(true).unwrap();
+
+		assert!(matches!(keyboard.get_events()[..], [DeviceEvent::KeyDown(KeyType::Mode, 1)]));
+	}
+
+	#[test]
+	fn onboard_effects_decode_to_what_was_encoded()
+	{
+		let effects = [
+			(EffectGroup::Keys, EffectConfiguration::None),
+			(EffectGroup::Keys, EffectConfiguration::Static { color: Color::new(0x12, 0x34, 0x56) }),
+			(EffectGroup::Keys, EffectConfiguration::Breathing { color: Color::new(0xff, 0, 0), duration: 0x1234, brightness: 50 }),
+			(EffectGroup::Keys, EffectConfiguration::ColorWave { direction: EffectDirection::CenterIn, duration: 0x0bb8, brightness: 80 }),
+			(EffectGroup::Keys, EffectConfiguration::Ripple { color: Color::new(0, 0xff, 0), duration: 150 }),
+			(EffectGroup::Logo, EffectConfiguration::Cycle { duration: 0x2710, brightness: 100 })
+		];
+
+		let keyboard = G815Keyboard::new(Box::new(MockTransport::default()));
+
+		for (group, effect) in effects.iter()
+		{
+			let data = keyboard.effect_data(*group, effect).unwrap();
+			let block = &data[1..=G815Keyboard::PROFILE_EFFECT_LENGTH];
+
+			assert_eq!(G815Keyboard::decode_effect(*group, block).unwrap(), *effect);
+		}
+	}
+
+	#[test]
+	fn onboard_lighting_is_written_with_a_checksum()
+	{
+		let (mut keyboard, transport) = keyboard();
+
+		// 256 byte sectors, with onboard profile 1 in sector 1
+		transport.respond(0x110a, &[], &[0, 0, 0, 0, 0, 0, 0, 0x01, 0x00]);
+		transport.respond(0x115a, &[0, 0, 0, 0], &[0x00, 0x01, 0x01, 0x00, 0xff, 0xff]);
+
+		keyboard.set_onboard_lighting(1, &OnboardLighting
+		{
+			keys: EffectConfiguration::Static { color: Color::new(0xff, 0, 0) },
+			logo: EffectConfiguration::None
+		}).unwrap();
+
+		let written = transport.take_written();
+		let sector: Vec<u8> = written
+			.iter()
+			.filter(|report| report[2..4] == [0x11, 0x7a])
+			.flat_map(|report| report[4..].iter().copied())
+			.collect();
+
+		assert_eq!(sector.len(), 256);
+		assert_eq!(sector[0xd0..0xdb], [0x01, 0xff, 0, 0, 0x02, 0, 0, 0, 0, 0, 0]);
+		assert_eq!(sector[0xdb..0xe6], [0; 11]);
+		assert_eq!(crc(&sector[..254]), (sector[254] as u16) << 8 | sector[255] as u16);
+		assert_eq!(written.last().unwrap()[2..6], [0x11, 0x3a, 0x00, 0x01]);
+	}
+
+	#[test]
+	fn effects_are_encoded()
+	{
+		let (mut keyboard, transport) = keyboard();
+
+		keyboard.set_effect(EffectGroup::Keys, &EffectConfiguration::Breathing
+		{
+			color: Color::new(0xff, 0x80, 0x00),
+			duration: 0x1234,
+			brightness: 50
+		}).unwrap();
+
+		// the logo has the ids of breathing and cycle swapped
+		keyboard.set_effect(EffectGroup::Logo, &EffectConfiguration::Cycle { duration: 0x0bb8, brightness: 100 }).unwrap();
+
+		assert_eq!(transport.take_written(), vec![
+			vec![0x11, 0xff, 0x0f, 0x1a, 0x01, 0x02, 0xff, 0x80, 0x00, 0x12, 0x34, 50, 0, 0, 0, 0, 1, 0, 0, 0],
+			vec![0x11, 0xff, 0x0f, 0x1a, 0x00, 0x02, 0, 0, 0, 0, 0, 0x0b, 0xb8, 100, 0, 0, 1, 0, 0, 0]
+		]);
+	}
+
+	#[test]
+	fn keys_of_one_color_are_sent_13_at_a_time()
+	{
+		let (mut keyboard, transport) = keyboard();
+		let color = Color::new(0x12, 0x34, 0x56);
+
+		keyboard.set_all(color).unwrap();
+		keyboard.commit().unwrap();
+
+		let written = transport.take_written();
+		let (commit, set_13s) = written.split_last().unwrap();
+		let rgb_ids: HashSet<u8> = Scancode::iter_variants().map(|scancode| scancode.rgb_id()).collect();
+		let mut sent = HashSet::new();
+
+		for report in set_13s
+		{
+			assert_eq!(report[2..4], SET_13);
+			assert_eq!(report[4..7], [0x12, 0x34, 0x56]);
+			sent.extend(report[7..].iter().copied().filter(|rgb_id| *rgb_id != 0));
+		}
+
+		assert_eq!(commit[2..4], COMMIT);
+		assert_eq!(set_13s.len(), (rgb_ids.len() + 12) / 13);
+		assert_eq!(sent, rgb_ids.into_iter().filter(|rgb_id| *rgb_id != 0).collect());
+	}
+
+	#[test]
+	fn keys_already_showing_their_color_are_not_resent()
+	{
+		let (mut keyboard, transport) = keyboard();
+		let red = Color::new(0xff, 0, 0);
+
+		keyboard.set_4(&[(Scancode::A, red), (Scancode::B, red)]).unwrap();
+		keyboard.commit().unwrap();
+		assert_eq!(transport.take_written().len(), 2);
+
+		// nothing has changed, so there's nothing to commit either
+		keyboard.set_4(&[(Scancode::A, red), (Scancode::B, red)]).unwrap();
+		keyboard.commit().unwrap();
+		assert!(transport.take_written().is_empty());
+
+		keyboard.set_4(&[(Scancode::A, red), (Scancode::B, Color::black())]).unwrap();
+		keyboard.commit().unwrap();
+
+		let written = transport.take_written();
+		assert_eq!(written.len(), 2);
+		assert_eq!(written[0][4..9], [Scancode::B.rgb_id(), 0, 0, 0, 0xff]);
+	}
+}
@@ -0,0 +1,162 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+// g815d-hidraw-helper includes this file too, so it only uses std and libc
+
+/// where g815d-hidraw-helper listens by default
+pub const DEFAULT_SOCKET: &str = "/run/g815d-hidraw.sock";
+
+/// (vendor id, product id, interface number) of the nodes the driver opens,
+/// kept in step with device::find_devices
+pub const SUPPORTED_DEVICES: &[(u16, u16, i32)] = &[(0x046d, 0xc33f, 1)];
+
+/// What sysfs says about a hidraw node, readable without any privileges
+#[derive(Debug, Clone)]
+pub struct NodeInfo
+{
+	/// i.e. hidraw3
+	pub node: String,
+	pub vendor_id: u16,
+	pub product_id: u16,
+	pub interface_number: i32,
+	pub product_string: String,
+	pub serial_number: String
+}
+
+impl NodeInfo
+{
+	pub fn read(node: &str) -> Option<Self>
+	{
+		let device = Path::new("/sys/class/hidraw").join(node).join("device");
+		let uevent = fs::read_to_string(device.join("uevent")).ok()?;
+		let field = |name: &str| uevent
+			.lines()
+			.find_map(|line| line.strip_prefix(name).and_then(|line| line.strip_prefix('=')))
+			.unwrap_or_default()
+			.to_string();
+
+		// HID_ID=0003:0000046D:0000C33F
+		let ids = field("HID_ID");
+		let mut ids = ids.split(':').skip(1).map(|id| u32::from_str_radix(id, 16).ok());
+		let vendor_id = ids.next().flatten()? as u16;
+		let product_id = ids.next().flatten()? as u16;
+
+		// the hid device sits inside the usb interface it belongs to
+		let interface_number = fs::canonicalize(&device)
+			.ok()
+			.and_then(|device| fs::read_to_string(device.parent()?.join("bInterfaceNumber")).ok())
+			.and_then(|number| i32::from_str_radix(number.trim(), 16).ok())
+			.unwrap_or(-1);
+
+		Some(Self
+		{
+			node: node.to_string(),
+			vendor_id,
+			product_id,
+			interface_number,
+			product_string: field("HID_NAME"),
+			serial_number: field("HID_UNIQ")
+		})
+	}
+
+	pub fn is_supported(&self) -> bool
+	{
+		SUPPORTED_DEVICES.contains(&(self.vendor_id, self.product_id, self.interface_number))
+	}
+
+	pub fn path(&self) -> String
+	{
+		format!("/dev/{}", self.node)
+	}
+}
+
+/// Whether a name is just a hidraw node (hidraw followed by a number), so it
+/// can't be used to reach anything else in /dev
+pub fn is_node_name(name: &str) -> bool
+{
+	name.strip_prefix("hidraw")
+		.map(|number| !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()))
+		.unwrap_or(false)
+}
+
+/// The supported keyboards' hidraw nodes
+pub fn supported_nodes() -> Vec<NodeInfo>
+{
+	let mut nodes: Vec<NodeInfo> = fs::read_dir("/sys/class/hidraw")
+		.map(|entries| entries
+			.filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+			.filter(|name| is_node_name(name))
+			.filter_map(|name| NodeInfo::read(&name))
+			.filter(NodeInfo::is_supported)
+			.collect())
+		.unwrap_or_default();
+
+	nodes.sort_by(|a, b| a.node.cmp(&b.node));
+	nodes
+}
+
+/// Asks the helper listening on `socket` to open a hidraw node
+pub fn request_node(socket: &Path, node: &str) -> io::Result<fs::File>
+{
+	use std::os::unix::io::FromRawFd;
+
+	let mut stream = UnixStream::connect(socket)?;
+	writeln!(stream, "{}", node)?;
+
+	let fd = receive_fd(&stream)?;
+	Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+/// Receives the file descriptor sent by the helper, or the error sent in its place
+pub fn receive_fd(stream: &UnixStream) -> io::Result<RawFd>
+{
+	let mut data = [0u8; 256];
+	let mut iov = libc::iovec { iov_base: data.as_mut_ptr() as *mut libc::c_void, iov_len: data.len() };
+	let space = unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) } as usize;
+	let mut control = vec![0u8; space];
+
+	let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+	message.msg_iov = &mut iov;
+	message.msg_iovlen = 1;
+	message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+	message.msg_controllen = space as _;
+
+	let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut message, libc::MSG_CMSG_CLOEXEC) };
+
+	if received < 0
+	{
+		return Err(io::Error::last_os_error())
+	}
+
+	let fd = unsafe
+	{
+		let header = libc::CMSG_FIRSTHDR(&message);
+
+		match !header.is_null() && (*header).cmsg_level == libc::SOL_SOCKET && (*header).cmsg_type == libc::SCM_RIGHTS
+		{
+			true => Some(std::ptr::read_unaligned(libc::CMSG_DATA(header) as *const RawFd)),
+			false => None
+		}
+	};
+
+	match fd
+	{
+		Some(fd) => Ok(fd),
+		None =>
+		{
+			// the error may not have fit in one read
+			let mut error = String::from_utf8_lossy(&data[..received as usize]).into_owned();
+			let mut stream = stream;
+			let _ = stream.read_to_string(&mut error);
+
+			Err(io::Error::new(io::ErrorKind::PermissionDenied, match error.is_empty()
+			{
+				true => "the helper closed the connection".to_string(),
+				false => error
+			}))
+		}
+	}
+}
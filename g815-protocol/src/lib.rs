@@ -0,0 +1,387 @@
+#![allow(unused_must_use)]
+#![recursion_limit="512"]
+#![allow(clippy::suspicious_else_formatting)]
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+use log::{debug, error, info};
+
+pub use color::Color;
+pub use effect::{EffectConfiguration, EffectDirection, EffectGroup};
+pub use scancode::Scancode;
+
+pub mod g815;
+pub mod scancode;
+pub mod effect;
+pub mod color;
+pub mod frame;
+pub mod hidraw;
+pub mod transport;
+pub mod capture;
+#[cfg(test)]
+pub mod mock;
+
+/// The keys that send events to the driver rather than to the computer
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Deserialize, Serialize)]
+pub enum KeyType
+{
+	GKey,
+	Mode,
+	GameMode,
+	MacroRecord,
+	Light,
+	MediaControl
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaKey
+{
+	Next,
+	Previous,
+	PlayPause,
+	VolumeUp,
+	VolumeDown,
+	Mute
+}
+
+/// Something the keyboard reported, with keys numbered from 1 (i.e. G1 is
+/// `KeyDown(KeyType::GKey, 1)`)
+#[derive(Debug)]
+pub enum DeviceEvent
+{
+	KeyDown(KeyType, u8),
+	KeyUp(KeyType, u8),
+	MediaKeyUp(MediaKey),
+	MediaKeyDown(MediaKey),
+	BrightnessLevelChanged(u8)
+}
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug)]
+pub enum Capability
+{
+	GKeys = 0x8010, // usual id = 0x0a
+	ModeSwitching = 0x8020, // usual id = 0x0b
+	MacroRecording = 0x8030, // usual id = 0x0c
+	BrightnessAdjustment = 0x8040, // usual id = 0x0d
+	ReportRate = 0x8060,
+	GameMode = 0x4522 // usual id = 0x08
+}
+
+/// The effects an onboard profile shows on the keys and logo, whilst the
+/// keyboard is left to light itself (i.e. the driver isn't running)
+#[derive(Clone, Debug, PartialEq)]
+pub struct OnboardLighting
+{
+	pub keys: EffectConfiguration,
+	pub logo: EffectConfiguration
+}
+
+/// What a keyboard reports about itself, for GetDeviceInfo and --info
+#[derive(Clone, Debug, Serialize)]
+pub struct DeviceInfo
+{
+	pub product: String,
+	pub serial_number: String,
+	pub firmware: Option<String>,
+	pub bootloader: Option<String>,
+	pub capabilities: Vec<CapabilityInfo>,
+	/// the report rates (in Hz) it can be set to
+	pub report_rates: Vec<u16>
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CapabilityInfo
+{
+	pub name: String,
+	/// the HID++ feature, i.e. 0x8010
+	pub feature: String,
+	/// where the keyboard has the feature, 0 if it doesn't have it
+	pub index: u8,
+	pub key_count: Option<u8>
+}
+
+#[derive(Debug)]
+pub struct CapabilityData
+{
+	id: u8,
+	key_type: Option<KeyType>,
+	key_count: Option<u8>,
+	raw: Option<Vec<u8>>
+}
+
+impl Default for CapabilityData
+{
+	fn default() -> Self
+	{
+		CapabilityData
+		{
+			id: 0,
+			key_type: None,
+			key_count: None,
+			raw: None
+		}
+	}
+}
+
+pub type CommandResult<T> = Result<T, CommandError>;
+
+/// Counts of the commands a device has sent, see Device::take_command_stats
+#[derive(Debug, Default, Clone)]
+pub struct CommandStats
+{
+	pub commands: u64,
+	/// commands that failed, including those that timed out
+	pub errors: u64,
+	pub timeouts: u64,
+	/// the total time spent waiting for responses
+	pub latency: std::time::Duration
+}
+
+#[derive(Debug)]
+pub enum CommandError
+{
+	HidError(hidapi::HidError),
+	/// reading or writing a hidraw node opened by the helper failed
+	Io(String),
+	LogicError(String),
+	Failure(String),
+	/// the device didn't respond to a command in time
+	Timeout
+}
+
+impl CommandError
+{
+	/// Whether sending the command again might work, rather than the device
+	/// having refused it
+	pub fn is_transient(&self) -> bool
+	{
+		match self
+		{
+			CommandError::HidError(_) | CommandError::Io(_) | CommandError::Timeout => true,
+			CommandError::LogicError(_) | CommandError::Failure(_) => false
+		}
+	}
+}
+
+impl std::fmt::Display for CommandError
+{
+	fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result
+	{
+		match self
+		{
+			CommandError::HidError(error) => write!(formatter, "hid error: {}", error),
+			CommandError::Io(error) => write!(formatter, "{}", error),
+			CommandError::LogicError(error) => write!(formatter, "{}", error),
+			CommandError::Failure(error) => write!(formatter, "{}", error),
+			CommandError::Timeout => write!(formatter, "no response in time")
+		}
+	}
+}
+
+type Initializer = &'static dyn Fn(Box<dyn transport::Transport>) -> Box<dyn Device>;
+
+/// How to set up each supported device, see hidraw::SUPPORTED_DEVICES
+fn initializer(vendor_id: u16, product_id: u16, interface_number: i32) -> Option<Initializer>
+{
+	match (vendor_id, product_id, interface_number)
+	{
+		(0x046d, 0xc33f, 1) => Some(&g815::G815Keyboard::init),
+		_ => None
+	}
+}
+
+/// Opens the supported devices that aren't already open. `opened` holds the
+/// paths of the devices opened so far, and has any newly opened ones added.
+/// With a `helper` socket, g815d-hidraw-helper opens them instead of hidapi,
+/// and with a `capture` everything sent to and from them is recorded.
+pub fn find_devices(hidapi: &hidapi::HidApi, helper: Option<&Path>, capture: Option<&capture::Capture>,
+	opened: &mut HashSet<CString>) -> Vec<Box<dyn Device>>
+{
+	if let Some(helper) = helper
+	{
+		return find_devices_with_helper(helper, capture, opened)
+	}
+
+    hidapi
+        .device_list()
+		.filter(|dev| !opened.contains(dev.path()))
+		.filter_map(|dev|
+		{
+			let initializer = initializer(dev.vendor_id(), dev.product_id(), dev.interface_number());
+			let device_name = dev.product_string().unwrap_or("unknown");
+
+			initializer
+				.and_then(|initializer| dev
+					.open_device(hidapi)
+					.map_err(|e|
+					{
+						error!("Failed to open target device '{}': {:?}", &device_name, e);
+					})
+					.map(|device|
+					{
+						let mut device = initializer(captured(Box::new(device), capture));
+						let firmware_info = device.firmware_info();
+						info!("Successfully opened '{}' (serial number {})\n{}",
+							&device_name, device.serial_number(), firmware_info);
+						(dev.path().to_owned(), device)
+					})
+					.ok())
+		})
+		.collect::<Vec<(CString, Box<dyn Device>)>>()
+		.into_iter()
+		.map(|(path, device)|
+		{
+			opened.insert(path);
+			device
+		})
+		.collect()
+}
+
+fn find_devices_with_helper(helper: &Path, capture: Option<&capture::Capture>, opened: &mut HashSet<CString>)
+	-> Vec<Box<dyn Device>>
+{
+	hidraw::supported_nodes()
+		.into_iter()
+		.filter_map(|node|
+		{
+			let path = CString::new(node.path()).ok()?;
+
+			if opened.contains(&path)
+			{
+				return None
+			}
+
+			let initializer = initializer(node.vendor_id, node.product_id, node.interface_number)?;
+			debug!("asking the hidraw helper to open {}", node.node);
+
+			match hidraw::request_node(helper, &node.node)
+			{
+				Ok(file) =>
+				{
+					let device_name = node.product_string.clone();
+					let mut device = initializer(captured(Box::new(transport::HidrawTransport::new(file, node)), capture));
+					let firmware_info = device.firmware_info();
+					info!("Successfully opened '{}' through the hidraw helper (serial number {})\n{}",
+						&device_name, device.serial_number(), firmware_info);
+
+					opened.insert(path);
+					Some(device)
+				},
+				Err(error) =>
+				{
+					error!("Failed to open target device '{}' through the hidraw helper at {}: {}",
+						node.product_string, helper.display(), error);
+					None
+				}
+			}
+		})
+		.collect()
+}
+
+fn captured(transport: Box<dyn transport::Transport>, capture: Option<&capture::Capture>) -> Box<dyn transport::Transport>
+{
+	match capture
+	{
+		Some(capture) => capture.wrap(transport),
+		None => transport
+	}
+}
+
+/// A keyboard the driver can light and read keys from. Colors written with
+/// `set_4` and `set_13` aren't shown until `commit()`.
+pub trait Device where Self: std::fmt::Display + Send
+{
+	fn take_control(&mut self) -> CommandResult<()>;
+	fn release_control(&mut self) -> CommandResult<()>;
+	fn mode_count(&self) -> CommandResult<u8>;
+	/// Whether the gkeys are handled by the driver, or send their default keys
+	fn set_gkeys_software_mode(&mut self, software: bool) -> CommandResult<()>;
+	fn set_4(&mut self, keys: &[(Scancode, Color)]) -> CommandResult<()>;
+	fn set_13(&mut self, color: Color, keys: &[Scancode]) -> CommandResult<()>;
+	fn commit(&mut self) -> CommandResult<()>;
+	fn set_mode_leds(&mut self, leds: u8) -> CommandResult<()>;
+	fn set_macro_recording(&mut self, recording: bool) -> CommandResult<()>;
+	/// The keyboard's own brightness level (0 - 100%), as set by the light key
+	fn brightness(&mut self) -> CommandResult<u8>;
+	fn set_brightness(&mut self, level: u8) -> CommandResult<()>;
+	/// The report rates (in Hz) the device can be set to, empty if it can't
+	/// change its report rate
+	fn report_rates(&self) -> Vec<u16>;
+	fn report_rate(&mut self) -> CommandResult<u16>;
+	fn set_report_rate(&mut self, rate: u16) -> CommandResult<()>;
+	/// Replaces the built-in rgb ids of these keys, for other layouts
+	fn set_rgb_ids(&mut self, rgb_ids: HashMap<Scancode, u8>);
+	/// Caps the sum of each key's color channels in everything written from now
+	/// on (including effects), or removes the cap
+	fn set_power_cap(&mut self, max_channel_sum: Option<u16>);
+	/// Warms every color written from now on to a color temperature (in
+	/// kelvin, see Color::warmed), or stops warming them
+	fn set_color_temperature(&mut self, temperature: Option<u16>);
+	fn set_effect(&mut self, group: EffectGroup, effect: &EffectConfiguration)
+		-> CommandResult<()>;
+	fn add_game_mode_keys(&mut self, scancodes: &[Scancode]) -> CommandResult<()>;
+	fn reset_game_mode_keys(&mut self) -> CommandResult<()>;
+	/// The lighting stored in an onboard profile, numbered from 1
+	fn onboard_lighting(&mut self, profile: u8) -> CommandResult<OnboardLighting>;
+	/// Replaces an onboard profile's lighting, leaving the rest of it alone
+	fn set_onboard_lighting(&mut self, profile: u8, lighting: &OnboardLighting) -> CommandResult<()>;
+	fn get_events(&mut self) -> Vec<DeviceEvent>;
+	/// Sleeps until the device sends something or `timeout` has passed, keeping
+	/// any events it sent for the next get_events
+	fn wait_for_events(&mut self, timeout: Duration);
+	/// The commands sent since this was last called
+	fn take_command_stats(&mut self) -> CommandStats;
+	/// How many commands in a row have failed even after being resent, i.e.
+	/// because the device was reset or unplugged
+	fn consecutive_failures(&self) -> u32;
+	fn firmware_info(&mut self) -> String;
+	fn info(&mut self) -> DeviceInfo;
+	/// Tells keyboards of the same model apart, empty if the device doesn't
+	/// report one
+	fn serial_number(&self) -> &str;
+
+	fn set_mode(&mut self, mode: u8) -> CommandResult<()>
+	{
+		self.set_mode_leds(1 << (mode - 1))
+	}
+
+	fn apply_scancode_assignments(&mut self, color_map: &[(Color, Vec<Scancode>)])
+	{
+		for (color, scancodes) in color_map.iter()
+		{
+			self.set_13(*color, &scancodes);
+		}
+	}
+
+	fn stop_effects(&mut self)
+	{
+		self.set_effect(EffectGroup::Keys, &EffectConfiguration::None);
+		self.set_effect(EffectGroup::Logo, &EffectConfiguration::None);
+	}
+
+	fn clear(&mut self) -> CommandResult<()>
+	{
+		self.stop_effects();
+		self.set_all(Color::black())
+	}
+
+	fn set_all(&mut self, color: Color) -> CommandResult<()>
+	{
+		self.set_13(color, &Scancode::iter_variants().collect::<Vec<Scancode>>())
+	}
+}
+
+/// Opens every supported keyboard that's plugged in (and readable by this
+/// user) with hidapi. Each one needs `take_control()` before it can be lit,
+/// and `set_gkeys_software_mode(true)` for its gkeys to send events rather
+/// than their default keys.
+pub fn open_keyboards() -> Result<Vec<Box<dyn Device>>, hidapi::HidError>
+{
+	let hidapi = hidapi::HidApi::new()?;
+	Ok(find_devices(&hidapi, None, None, &mut HashSet::new()))
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use super::{Capability, CommandResult};
+use super::transport::Transport;
+
+/// A transport that answers commands the way a G815 does, and records every
+/// report written to it. Clones share everything, so a test can keep one
+/// after giving another to a G815Keyboard.
+#[derive(Clone, Default)]
+pub struct MockTransport
+{
+	inner: Arc<Mutex<TransportState>>
+}
+
+#[derive(Default)]
+struct TransportState
+{
+	// (command, start of the command's data, data of the response), commands
+	// without one are answered with zeros
+	responses: Vec<(u16, Vec<u8>, Vec<u8>)>,
+	written: Vec<Vec<u8>>,
+	// reports waiting to be read, responses and interrupts alike
+	incoming: VecDeque<Vec<u8>>
+}
+
+impl MockTransport
+{
+	/// A G815 with the usual capability ids: 5 gkeys, 3 modes, and a report
+	/// rate of 1000Hz that can also be 500Hz
+	pub fn g815() -> Self
+	{
+		let transport = Self::default();
+
+		let capabilities = [
+			(Capability::GKeys, 0x0a, 5),
+			(Capability::ModeSwitching, 0x0b, 3),
+			(Capability::MacroRecording, 0x0c, 1),
+			(Capability::BrightnessAdjustment, 0x0d, 1),
+			(Capability::ReportRate, 0x0e, 0b11),
+			(Capability::GameMode, 0x08, 1)
+		];
+
+		for (capability, id, data) in capabilities.iter()
+		{
+			let capability = *capability as u16;
+			transport.respond(0x000a, &[(capability >> 8) as u8, capability as u8], &[*id]);
+			transport.respond(((*id as u16) << 8) | 0x0a, &[], &[*data]);
+		}
+
+		transport
+	}
+
+	/// Answers a command (whose data starts with `data`) with `response`
+	pub fn respond(&self, command: u16, data: &[u8], response: &[u8])
+	{
+		self.state().responses.push((command, data.to_vec(), response.to_vec()));
+	}
+
+	/// Sends a report from the keyboard, i.e. a key press
+	pub fn interrupt(&self, report: &[u8])
+	{
+		let mut report = report.to_vec();
+		report.resize(20, 0);
+		self.state().incoming.push_back(report);
+	}
+
+	/// The reports written since this was last called
+	pub fn take_written(&self) -> Vec<Vec<u8>>
+	{
+		std::mem::take(&mut self.state().written)
+	}
+
+	fn state(&self) -> MutexGuard<'_, TransportState>
+	{
+		self.inner.lock().unwrap()
+	}
+}
+
+impl Transport for MockTransport
+{
+	fn write(&mut self, data: &[u8]) -> CommandResult<usize>
+	{
+		let mut state = self.state();
+		let command = ((data[2] as u16) << 8) | data[3] as u16;
+
+		let response = state.responses
+			.iter()
+			.find(|(response_command, start, _response)| *response_command == command && data[4..].starts_with(start))
+			.map(|(_command, _start, response)| response.clone())
+			.unwrap_or_default();
+
+		let mut report = data[..4].to_vec();
+		report.extend(response);
+		report.resize(20, 0);
+
+		state.written.push(data.to_vec());
+		state.incoming.push_back(report);
+		Ok(data.len())
+	}
+
+	fn read_timeout(&mut self, buffer: &mut [u8], _timeout: i32) -> CommandResult<usize>
+	{
+		match self.state().incoming.pop_front()
+		{
+			Some(report) =>
+			{
+				let length = report.len().min(buffer.len());
+				buffer[..length].copy_from_slice(&report[..length]);
+				Ok(length)
+			},
+			None => Ok(0)
+		}
+	}
+
+	fn product_string(&self) -> Option<String>
+	{
+		Some("G815 (mock)".to_string())
+	}
+
+	fn serial_number(&self) -> Option<String>
+	{
+		Some("mock".to_string())
+	}
+}
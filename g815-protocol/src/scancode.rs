@@ -0,0 +1,322 @@
+use macro_attr::*;
+use enum_derive::*;
+
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+/// Named sets of keys, i.e. the keygroups in the driver's config
+pub type Keygroups = HashMap<String, Vec<Scancode>>;
+
+macro_attr!
+{
+	#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash,
+		Serialize, Deserialize, 
+		EnumDisplay!, EnumFromStr!, IterVariants!(Scancodes))]
+	#[repr(u8)]
+	#[serde(rename_all = "snake_case")]
+	pub enum Scancode
+	{
+		// standard usb hid scancodes
+
+		A = 4, // 0x01 in rgb
+		B, C, D, E, F, G, H, I, J, K, L, N, M, O, P, Q, R, S, T, U, V, W, X, Y, Z, 
+		N1, N2, N3, N4, N5, N6, N7, N8, N9, N0,
+		Enter,
+		Escape,
+		Backspace,
+		Tab,
+		Space,
+		Minus,
+		Equals,
+		LeftBracket,
+		RightBracket,
+		#[serde(rename = "us_backslash")]
+		UsBackslash,
+		HashTilde,
+		Semicolon,
+		Apostrophe,
+		Grave,
+		Comma,
+		Dot,
+		Slash,
+		CapsLock,
+		F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+		PrintScreen,
+		ScrollLock,
+		Pause,
+		Insert,
+		Home,
+		PageUp,
+		Delete,
+		End,
+		PageDown,
+		Right,
+		Left,
+		Down,
+		Up,
+		NumLock,
+		NumpadDivide, NumpadMultiply, NumpadMinus, NumpadPlus, NumpadEnter,
+		Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9, Numpad0,
+		NumpadDot,
+		Backslash,
+		// NumpadEquals = 0x67,
+		ContextMenu = 0x76, // 0x62 in rgb
+		Mute = 0x7f,
+		// only on some regional (i.e. JIS) layouts, their rgb ids are a guess
+		// and can be corrected with layout overrides
+		Ro = 0x87,
+		KatakanaHiragana,
+		Yen,
+		Henkan,
+		Muhenkan,
+		LeftControl = 0xe0, // 0x68 in rgb
+		LeftShift,
+		LeftAlt,
+		LeftMeta,
+		RightControl,
+		RightShift,
+		RightAlt,
+		RightMeta,
+
+		// logitech-specific rgb codes (not real scan codes)
+
+		Light = 0x99,
+		G1 = 0xb4,
+		G2,
+		G3,
+		G4,
+		G5,
+		G6,
+		G7,
+		G8,
+		Logo = 0xd2,
+		MediaPrevious = 0x9e,
+		MediaNext = 0x9d,
+		MediaPlayPause = 0x9b
+	}
+}
+
+/// A description of a key for external tools, see `Scancode::describe_all()`
+#[derive(Debug, Serialize)]
+pub struct KeyDescription
+{
+	/// the name used for the key in the config file
+	pub name: String,
+	/// other names the key is known by (i.e. in log output)
+	pub aliases: Vec<String>,
+	pub scancode: u8,
+	pub rgb_id: u8,
+	/// the names of the keygroups the key is in
+	pub keygroups: Vec<String>
+}
+
+impl Scancode
+{
+	/// Describes every key the driver knows about, including which of the given
+	/// keygroups each one is in
+	pub fn describe_all(keygroups: &Keygroups) -> Vec<KeyDescription>
+	{
+		let mut keygroup_names: Vec<&String> = keygroups.keys().collect();
+		keygroup_names.sort();
+
+		Self::iter_variants()
+			.map(|scancode|
+			{
+				let name = scancode.config_name();
+				let aliases = Some(scancode.to_string())
+					.filter(|alias| *alias != name)
+					.into_iter()
+					.collect();
+
+				KeyDescription
+				{
+					name,
+					aliases,
+					scancode: scancode as u8,
+					rgb_id: scancode.rgb_id(),
+					keygroups: keygroup_names
+						.iter()
+						.filter(|keygroup| keygroups[**keygroup].contains(&scancode))
+						.map(|keygroup| keygroup.to_string())
+						.collect()
+				}
+			})
+			.collect()
+	}
+
+	pub fn from_gkey(gkey_number: u8) -> Option<Self>
+	{
+		match gkey_number
+		{
+			1 => Some(Self::G1),
+			2 => Some(Self::G2),
+			3 => Some(Self::G3),
+			4 => Some(Self::G4),
+			5 => Some(Self::G5),
+			6 => Some(Self::G6),
+			7 => Some(Self::G7),
+			8 => Some(Self::G8),
+			_ => None
+		}
+	}
+
+	/// The physical position of the center of this key, in key widths from the top
+	/// left of the keyboard (the logo). Keys the g815 doesn't have return None.
+	pub fn position(&self) -> Option<(f64, f64)>
+	{
+		use Scancode::*;
+
+		// rows, from the top: the logo/media row, function keys, then the main
+		// block. The g-keys column sits to the left of the main block.
+		const TOP: f64 = 0.5;
+		const FUNCTION: f64 = 1.5;
+		const NUMBER: f64 = 2.75;
+		const TAB: f64 = 3.75;
+		const CAPS: f64 = 4.75;
+		const SHIFT: f64 = 5.75;
+		const CONTROL: f64 = 6.75;
+		const MAIN: f64 = 1.5;
+
+		let (x, y) = match self
+		{
+			Logo => return Some((0.5, TOP)),
+			G1 => return Some((0.5, NUMBER)),
+			G2 => return Some((0.5, TAB)),
+			G3 => return Some((0.5, CAPS)),
+			G4 => return Some((0.5, SHIFT)),
+			G5 => return Some((0.5, CONTROL)),
+			G6 | G7 | G8 => return None,
+			Ro | KatakanaHiragana | Yen | Henkan | Muhenkan => return None,
+
+			Light => (16.75, TOP),
+			MediaPrevious => (19.0, TOP),
+			MediaPlayPause => (20.0, TOP),
+			MediaNext => (21.0, TOP),
+			Mute => (22.0, TOP),
+
+			Escape => (0.5, FUNCTION),
+			F1 => (2.5, FUNCTION), F2 => (3.5, FUNCTION), F3 => (4.5, FUNCTION), F4 => (5.5, FUNCTION),
+			F5 => (7.0, FUNCTION), F6 => (8.0, FUNCTION), F7 => (9.0, FUNCTION), F8 => (10.0, FUNCTION),
+			F9 => (11.5, FUNCTION), F10 => (12.5, FUNCTION), F11 => (13.5, FUNCTION), F12 => (14.5, FUNCTION),
+			PrintScreen => (15.75, FUNCTION), ScrollLock => (16.75, FUNCTION), Pause => (17.75, FUNCTION),
+
+			Grave => (0.5, NUMBER),
+			N1 => (1.5, NUMBER), N2 => (2.5, NUMBER), N3 => (3.5, NUMBER), N4 => (4.5, NUMBER),
+			N5 => (5.5, NUMBER), N6 => (6.5, NUMBER), N7 => (7.5, NUMBER), N8 => (8.5, NUMBER),
+			N9 => (9.5, NUMBER), N0 => (10.5, NUMBER), Minus => (11.5, NUMBER), Equals => (12.5, NUMBER),
+			Backspace => (14.0, NUMBER),
+			Insert => (15.75, NUMBER), Home => (16.75, NUMBER), PageUp => (17.75, NUMBER),
+			NumLock => (19.0, NUMBER), NumpadDivide => (20.0, NUMBER),
+			NumpadMultiply => (21.0, NUMBER), NumpadMinus => (22.0, NUMBER),
+
+			Tab => (0.75, TAB),
+			Q => (2.0, TAB), W => (3.0, TAB), E => (4.0, TAB), R => (5.0, TAB), T => (6.0, TAB),
+			Y => (7.0, TAB), U => (8.0, TAB), I => (9.0, TAB), O => (10.0, TAB), P => (11.0, TAB),
+			LeftBracket => (12.0, TAB), RightBracket => (13.0, TAB), UsBackslash => (14.25, TAB),
+			Delete => (15.75, TAB), End => (16.75, TAB), PageDown => (17.75, TAB),
+			Numpad7 => (19.0, TAB), Numpad8 => (20.0, TAB), Numpad9 => (21.0, TAB),
+			NumpadPlus => (22.0, (TAB + CAPS) / 2.0),
+
+			CapsLock => (0.875, CAPS),
+			A => (2.25, CAPS), S => (3.25, CAPS), D => (4.25, CAPS), F => (5.25, CAPS), G => (6.25, CAPS),
+			H => (7.25, CAPS), J => (8.25, CAPS), K => (9.25, CAPS), L => (10.25, CAPS),
+			Semicolon => (11.25, CAPS), Apostrophe => (12.25, CAPS), HashTilde => (13.25, CAPS),
+			Enter => (13.875, CAPS),
+			Numpad4 => (19.0, CAPS), Numpad5 => (20.0, CAPS), Numpad6 => (21.0, CAPS),
+
+			LeftShift => (0.625, SHIFT), Backslash => (1.75, SHIFT),
+			Z => (2.75, SHIFT), X => (3.75, SHIFT), C => (4.75, SHIFT), V => (5.75, SHIFT), B => (6.75, SHIFT),
+			N => (7.75, SHIFT), M => (8.75, SHIFT), Comma => (9.75, SHIFT), Dot => (10.75, SHIFT),
+			Slash => (11.75, SHIFT), RightShift => (13.625, SHIFT),
+			Up => (16.75, SHIFT),
+			Numpad1 => (19.0, SHIFT), Numpad2 => (20.0, SHIFT), Numpad3 => (21.0, SHIFT),
+			NumpadEnter => (22.0, (SHIFT + CONTROL) / 2.0),
+
+			LeftControl => (0.625, CONTROL), LeftMeta => (1.875, CONTROL), LeftAlt => (3.125, CONTROL),
+			Space => (6.875, CONTROL),
+			RightAlt => (10.625, CONTROL), RightMeta => (11.875, CONTROL),
+			ContextMenu => (13.125, CONTROL), RightControl => (14.375, CONTROL),
+			Left => (15.75, CONTROL), Down => (16.75, CONTROL), Right => (17.75, CONTROL),
+			Numpad0 => (19.5, CONTROL), NumpadDot => (21.0, CONTROL)
+		};
+
+		Some((x + MAIN, y))
+	}
+
+	/// The name used for this key in the config file (e.g. `left_meta`)
+	pub fn config_name(&self) -> String
+	{
+		// the same as serde's snake_case renaming of the variant
+		self.to_string()
+			.chars()
+			.enumerate()
+			.fold(String::new(), |mut name, (index, character)|
+			{
+				if character.is_uppercase() && index > 0
+				{
+					name.push('_');
+				}
+
+				name.push(character.to_ascii_lowercase());
+				name
+			})
+	}
+
+	pub fn _gkey_number(&self) -> Option<u8>
+	{
+		let val = *self as u8;
+		(0xb4..=0xbb).contains(&val).then(|| val - 0xb3)
+	}
+
+	pub fn rgb_id(&self) -> u8
+	{
+		let id = *self as u8;
+
+		match &self
+		{
+			Scancode::Mute => 0x9c,
+			Scancode::ContextMenu => 0x62,
+
+			// these don't have real scancodes so they're already
+			// rgb-only key ids
+
+			Scancode::G1 
+				| Scancode::G2 
+				| Scancode::G3 
+				| Scancode::G4 
+				| Scancode::G5 
+				| Scancode::MediaPrevious 
+				| Scancode::MediaNext 
+				| Scancode::MediaPlayPause 
+				| Scancode::Logo 
+				| Scancode::Light => id,
+
+			Scancode::LeftControl
+				| Scancode::LeftShift
+				| Scancode::LeftAlt
+				| Scancode::LeftMeta
+				| Scancode::RightControl
+				| Scancode::RightShift
+				| Scancode::RightAlt
+				| Scancode::RightMeta => id - 0x78,
+
+			_ => id - 0x03
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn config_names_match_the_serialized_names()
+	{
+		for scancode in Scancode::iter_variants()
+		{
+			assert_eq!(serde_yaml::to_value(scancode).unwrap(), serde_yaml::Value::from(scancode.config_name()));
+		}
+	}
+}
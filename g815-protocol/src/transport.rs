@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::AsRawFd;
+
+use hidapi::HidDevice;
+
+use super::hidraw::NodeInfo;
+use super::{CommandError, CommandResult};
+
+/// How reports get to and from a keyboard: through hidapi, or a hidraw node
+/// opened for us by g815d-hidraw-helper
+pub trait Transport where Self: Send
+{
+	/// writes a report, its first byte being the report id
+	fn write(&mut self, data: &[u8]) -> CommandResult<usize>;
+	/// reads a report, waiting up to `timeout` ms for one (0 doesn't wait).
+	/// Returns 0 if there wasn't one.
+	fn read_timeout(&mut self, buffer: &mut [u8], timeout: i32) -> CommandResult<usize>;
+	fn product_string(&self) -> Option<String>;
+	fn serial_number(&self) -> Option<String>;
+}
+
+impl Transport for HidDevice
+{
+	fn write(&mut self, data: &[u8]) -> CommandResult<usize>
+	{
+		Ok(HidDevice::write(self, data)?)
+	}
+
+	fn read_timeout(&mut self, buffer: &mut [u8], timeout: i32) -> CommandResult<usize>
+	{
+		Ok(HidDevice::read_timeout(self, buffer, timeout)?)
+	}
+
+	fn product_string(&self) -> Option<String>
+	{
+		self.get_product_string().ok().flatten()
+	}
+
+	fn serial_number(&self) -> Option<String>
+	{
+		self.get_serial_number_string().ok().flatten()
+	}
+}
+
+pub struct HidrawTransport
+{
+	file: File,
+	info: NodeInfo
+}
+
+impl HidrawTransport
+{
+	pub fn new(file: File, info: NodeInfo) -> Self
+	{
+		Self { file, info }
+	}
+}
+
+impl Transport for HidrawTransport
+{
+	fn write(&mut self, data: &[u8]) -> CommandResult<usize>
+	{
+		self.file.write(data).map_err(|error| CommandError::Io(error.to_string()))
+	}
+
+	fn read_timeout(&mut self, buffer: &mut [u8], timeout: i32) -> CommandResult<usize>
+	{
+		let mut poll = libc::pollfd { fd: self.file.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+
+		match unsafe { libc::poll(&mut poll, 1, timeout) }
+		{
+			ready if ready < 0 => Err(CommandError::Io(std::io::Error::last_os_error().to_string())),
+			0 => Ok(0),
+			_ => self.file.read(buffer).map_err(|error| CommandError::Io(error.to_string()))
+		}
+	}
+
+	fn product_string(&self) -> Option<String>
+	{
+		Some(self.info.product_string.clone()).filter(|product| !product.is_empty())
+	}
+
+	fn serial_number(&self) -> Option<String>
+	{
+		Some(self.info.serial_number.clone()).filter(|serial| !serial.is_empty())
+	}
+}
@@ -0,0 +1,176 @@
+// Opens the keyboards' hidraw nodes for a driver run by a user without access
+// to them (i.e. where udev rules can't be installed), and passes the open file
+// over a unix socket. It only opens nodes of the keyboards the driver supports.
+//
+// Run by systemd with socket activation (Accept=yes, so each connection is fd
+// 3), with the socket unit's SocketUser/SocketMode saying who can connect. Or
+// run it as root with a socket path and the uid of the user allowed to use it:
+//
+//     g815d-hidraw-helper /run/g815d-hidraw.sock 1000
+
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+#[allow(dead_code)]
+#[path = "../../g815-protocol/src/hidraw.rs"]
+mod hidraw;
+
+// the first fd passed by systemd
+const LISTEN_FD: RawFd = 3;
+
+fn main()
+{
+	let result = match env::var("LISTEN_FDS").ok().as_deref()
+	{
+		Some("1") => serve(&unsafe { UnixStream::from_raw_fd(LISTEN_FD) }),
+		_ =>
+		{
+			let args: Vec<String> = env::args().skip(1).collect();
+
+			match (args.get(0), args.get(1).and_then(|uid| uid.parse().ok()))
+			{
+				(Some(socket), Some(uid)) => listen(Path::new(socket), uid),
+				_ =>
+				{
+					eprintln!("usage: g815d-hidraw-helper <socket> <uid allowed to connect>");
+					std::process::exit(2);
+				}
+			}
+		}
+	};
+
+	if let Err(error) = result
+	{
+		eprintln!("g815d-hidraw-helper: {}", error);
+		std::process::exit(1);
+	}
+}
+
+fn listen(socket: &Path, uid: libc::uid_t) -> io::Result<()>
+{
+	// left over from a previous run
+	let _ = fs::remove_file(socket);
+	let listener = UnixListener::bind(socket)?;
+	fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+
+	let path = std::ffi::CString::new(socket.as_os_str().to_string_lossy().as_bytes())
+		.map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error))?;
+
+	if unsafe { libc::chown(path.as_ptr(), uid, libc::gid_t::MAX) } < 0
+	{
+		return Err(io::Error::last_os_error())
+	}
+
+	for stream in listener.incoming()
+	{
+		let stream = stream?;
+
+		// the socket's permissions should already stop anyone else, but it's
+		// cheap to make sure
+		match peer_uid(&stream)
+		{
+			Some(peer) if peer == uid || peer == 0 =>
+			{
+				if let Err(error) = serve(&stream)
+				{
+					eprintln!("g815d-hidraw-helper: {}", error);
+				}
+			},
+			peer => eprintln!("g815d-hidraw-helper: refusing a connection from uid {:?}", peer)
+		}
+	}
+
+	Ok(())
+}
+
+fn peer_uid(stream: &UnixStream) -> Option<libc::uid_t>
+{
+	let mut credentials: libc::ucred = unsafe { std::mem::zeroed() };
+	let mut length = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+
+	let result = unsafe
+	{
+		libc::getsockopt(
+			stream.as_raw_fd(),
+			libc::SOL_SOCKET,
+			libc::SO_PEERCRED,
+			&mut credentials as *mut libc::ucred as *mut libc::c_void,
+			&mut length)
+	};
+
+	match result
+	{
+		0 => Some(credentials.uid),
+		_ => None
+	}
+}
+
+/// Handles one request: the name of a hidraw node (i.e. hidraw3) on a line
+fn serve(stream: &UnixStream) -> io::Result<()>
+{
+	let mut node = String::new();
+	// requests are only a node name, so there's no need to read much
+	BufReader::new(io::Read::take(stream, 64)).read_line(&mut node)?;
+	let node = node.trim();
+
+	if !hidraw::is_node_name(node)
+	{
+		return send_error(stream, &format!("'{}' isn't a hidraw node", node))
+	}
+
+	match hidraw::NodeInfo::read(node)
+	{
+		Some(info) if info.is_supported() =>
+		{
+			match fs::OpenOptions::new().read(true).write(true).open(info.path())
+			{
+				Ok(file) => send_fd(stream, file.as_raw_fd()),
+				Err(error) => send_error(stream, &format!("unable to open {}: {}", info.path(), error))
+			}
+		},
+		_ => send_error(stream, &format!("{} isn't a supported keyboard", node))
+	}
+}
+
+/// Sends an open file descriptor, with a single byte as the message
+fn send_fd(stream: &UnixStream, fd: RawFd) -> io::Result<()>
+{
+	let mut data = [0u8; 1];
+	let mut iov = libc::iovec { iov_base: data.as_mut_ptr() as *mut libc::c_void, iov_len: data.len() };
+	let space = unsafe { libc::CMSG_SPACE(std::mem::size_of::<RawFd>() as u32) } as usize;
+	let mut control = vec![0u8; space];
+
+	let mut message: libc::msghdr = unsafe { std::mem::zeroed() };
+	message.msg_iov = &mut iov;
+	message.msg_iovlen = 1;
+	message.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+	message.msg_controllen = space as _;
+
+	let sent = unsafe
+	{
+		let header = libc::CMSG_FIRSTHDR(&message);
+		(*header).cmsg_level = libc::SOL_SOCKET;
+		(*header).cmsg_type = libc::SCM_RIGHTS;
+		(*header).cmsg_len = libc::CMSG_LEN(std::mem::size_of::<RawFd>() as u32) as _;
+		std::ptr::write_unaligned(libc::CMSG_DATA(header) as *mut RawFd, fd);
+
+		libc::sendmsg(stream.as_raw_fd(), &message, 0)
+	};
+
+	match sent < 0
+	{
+		true => Err(io::Error::last_os_error()),
+		false => Ok(())
+	}
+}
+
+/// Sends why a node couldn't be opened, in place of its file descriptor
+fn send_error(mut stream: &UnixStream, error: &str) -> io::Result<()>
+{
+	stream.write_all(error.as_bytes())
+}
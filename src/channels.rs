@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::{Duration, Instant};
+
+use log::{debug, error};
+use smol::channel::Receiver;
+use smol::future;
+
+/// A sender that can report whether its message was delivered
+pub trait RawSender<T>: Clone
+{
+	fn send_raw(&self, message: T) -> bool;
+}
+
+impl<T> RawSender<T> for smol::channel::Sender<T>
+{
+	fn send_raw(&self, message: T) -> bool
+	{
+		// the channels are unbounded, so this only fails once the receiver's gone
+		self.try_send(message).is_ok()
+	}
+}
+
+impl<T> RawSender<T> for crossbeam::Sender<T>
+{
+	fn send_raw(&self, message: T) -> bool
+	{
+		crossbeam::Sender::send(self, message).is_ok()
+	}
+}
+
+/// Sends each message to every receiver subscribed so far, one per keyboard.
+/// Receivers that have gone away (their keyboard was unplugged) are dropped
+/// rather than counted as failures.
+pub struct Broadcaster<T>
+{
+	senders: Arc<Mutex<Vec<crossbeam::Sender<T>>>>
+}
+
+impl<T> Broadcaster<T>
+{
+	pub fn subscribe(&self) -> crossbeam::Receiver<T>
+	{
+		let (sender, receiver) = crossbeam::unbounded();
+		self.senders.lock().unwrap().push(sender);
+		receiver
+	}
+}
+
+impl<T> Default for Broadcaster<T>
+{
+	fn default() -> Self
+	{
+		Self { senders: Arc::new(Mutex::new(Vec::new())) }
+	}
+}
+
+impl<T> Clone for Broadcaster<T>
+{
+	fn clone(&self) -> Self
+	{
+		Self { senders: Arc::clone(&self.senders) }
+	}
+}
+
+impl<T> RawSender<T> for Broadcaster<T>
+where
+	T: Clone
+{
+	fn send_raw(&self, message: T) -> bool
+	{
+		self.senders
+			.lock()
+			.unwrap()
+			.retain(|sender| sender.send(message.clone()).is_ok());
+		true
+	}
+}
+
+/// Counts the messages that couldn't be sent to each of the driver's threads.
+/// A send only fails when the receiving thread has stopped (usually because it
+/// panicked), so any failure means part of the driver isn't working.
+#[derive(Debug, Default)]
+pub struct ChannelHealth
+{
+	failed_sends: Mutex<HashMap<&'static str, usize>>
+}
+
+impl ChannelHealth
+{
+	/// Wraps a sender to a thread, so failed sends to it are counted
+	pub fn monitor<T, S>(self: &Arc<Self>, name: &'static str, sender: S) -> MonitoredSender<T, S>
+	where
+		S: RawSender<T>
+	{
+		MonitoredSender
+		{
+			name,
+			sender,
+			health: Arc::clone(self),
+			message: std::marker::PhantomData
+		}
+	}
+
+	fn send_failed(&self, name: &'static str)
+	{
+		let mut failed_sends = self.failed_sends.lock().unwrap();
+		let count = failed_sends.entry(name).or_insert(0);
+		*count += 1;
+
+		match *count
+		{
+			1 => error!("the {} thread has stopped, so anything relying on it won't work", name),
+			_ => debug!("dropped message {} to the {} thread", count, name)
+		}
+	}
+
+	#[cfg_attr(not(any(feature = "dbus", feature = "http")), allow(dead_code))]
+	pub fn failed_sends(&self) -> HashMap<&'static str, usize>
+	{
+		self.failed_sends.lock().unwrap().clone()
+	}
+
+	/// Whether any thread has stopped whilst others still needed it
+	#[cfg_attr(not(any(feature = "dbus", feature = "http")), allow(dead_code))]
+	pub fn degraded(&self) -> bool
+	{
+		!self.failed_sends.lock().unwrap().is_empty()
+	}
+}
+
+/// A sender to one of the driver's threads, which logs and counts messages that
+/// couldn't be delivered rather than leaving the caller to ignore the error
+pub struct MonitoredSender<T, S = smol::channel::Sender<T>>
+{
+	name: &'static str,
+	sender: S,
+	health: Arc<ChannelHealth>,
+	message: std::marker::PhantomData<fn(T)>
+}
+
+impl<T, S> MonitoredSender<T, S>
+where
+	S: RawSender<T>
+{
+	pub fn send(&self, message: T)
+	{
+		if !self.sender.send_raw(message)
+		{
+			self.health.send_failed(self.name);
+		}
+	}
+
+	/// Sends a message that the thread may have already stopped for (i.e. a
+	/// shutdown), without counting it as a failure
+	pub fn send_if_running(&self, message: T)
+	{
+		self.sender.send_raw(message);
+	}
+}
+
+impl<T, S> Clone for MonitoredSender<T, S>
+where
+	S: Clone
+{
+	fn clone(&self) -> Self
+	{
+		Self
+		{
+			name: self.name,
+			sender: self.sender.clone(),
+			health: Arc::clone(&self.health),
+			message: std::marker::PhantomData
+		}
+	}
+}
+
+/// Waits for the next message until `deadline`, for tasks that also have
+/// timers of their own to keep
+pub async fn recv_until<T>(rx: &Receiver<T>, deadline: Instant) -> Result<T, RecvTimeoutError>
+{
+	future::or(
+		async { rx.recv().await.map_err(|_closed| RecvTimeoutError::Disconnected) },
+		async
+		{
+			smol::Timer::at(deadline).await;
+			Err(RecvTimeoutError::Timeout)
+		}).await
+}
+
+/// Waits up to `timeout` for the next message, for the subsystems built around
+/// blocking libraries (i.e. xlib), which run on the runtime's blocking threads
+pub fn recv_timeout<T>(rx: &Receiver<T>, timeout: Duration) -> Result<T, RecvTimeoutError>
+{
+	smol::block_on(recv_until(rx, Instant::now() + timeout))
+}
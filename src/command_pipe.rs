@@ -0,0 +1,321 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+
+use log::{debug, info, warn};
+use smol::Async;
+use smol::channel::Receiver;
+use smol::future;
+use smol::io::AsyncReadExt;
+
+use crate::channels::MonitoredSender;
+use crate::daemon;
+use crate::device::client_colors::KeyColorUpdate;
+use crate::device::color::Color;
+use crate::device::rgb::{EffectConfiguration, EffectDirection};
+use crate::scheduler::PlaylistCommand;
+use crate::{SharedState, MainThreadSignal, parse_key, parse_mode_command};
+
+pub enum CommandPipeSignal
+{
+	Shutdown
+}
+
+/// A named pipe in the runtime folder (`command_pipe` in the config) taking a
+/// command per line, i.e. `echo "profile gaming" > $XDG_RUNTIME_DIR/g815d.cmd`,
+/// for shell scripts that would rather not use dbus. Like the http server,
+/// each command is turned into the same signal to the main thread as the dbus
+/// method it stands for. There's nowhere to send replies, so mistakes are logged.
+pub struct CommandPipe
+{
+	path: PathBuf,
+	pipe: Async<File>,
+	state: Arc<SharedState>,
+	tx: MonitoredSender<MainThreadSignal>,
+	// the end of what's been read, until the rest of its line arrives
+	partial: Vec<u8>
+}
+
+impl CommandPipe
+{
+	/// keys colored by `key` commands are set as this client
+	const CLIENT: &'static str = "pipe";
+	// milliseconds, for effects given without one
+	const DEFAULT_EFFECT_DURATION: u16 = 5000;
+
+	pub fn path() -> PathBuf
+	{
+		let mut path = daemon::runtime_folder();
+		path.push("g815d.cmd");
+		path
+	}
+
+	pub fn new(state: Arc<SharedState>, tx: MonitoredSender<MainThreadSignal>) -> Result<Self, String>
+	{
+		let path = Self::path();
+
+		let is_pipe = fs::metadata(&path)
+			.map(|metadata| metadata.file_type().is_fifo())
+			.unwrap_or(false);
+
+		if !is_pipe
+		{
+			let _ = fs::remove_file(&path);
+			let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|error| error.to_string())?;
+
+			// only the user can write commands
+			if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0
+			{
+				return Err(format!("unable to create {}: {}", path.display(), std::io::Error::last_os_error()))
+			}
+		}
+
+		// opened for writing too, so there's always a writer and reads wait for
+		// the next command rather than finding the end of the file
+		let pipe = OpenOptions::new()
+			.read(true)
+			.write(true)
+			.custom_flags(libc::O_NONBLOCK | libc::O_NOFOLLOW)
+			.open(&path)
+			.map_err(|error| format!("unable to open {}: {}", path.display(), error))?;
+
+		// without XDG_RUNTIME_DIR the pipe is in /tmp, where another user could
+		// have made it first to send their own commands. It's checked once
+		// it's open, so it can't be swapped in the meantime.
+		let metadata = pipe.metadata().map_err(|error| error.to_string())?;
+		let private = metadata.file_type().is_fifo()
+			&& metadata.uid() == unsafe { libc::getuid() }
+			&& metadata.mode() & 0o077 == 0;
+
+		if !private
+		{
+			return Err(format!("{} isn't a pipe only this user can use, remove it or set XDG_RUNTIME_DIR", path.display()))
+		}
+
+		let pipe = Async::new(pipe).map_err(|error| error.to_string())?;
+
+		info!("reading commands from {}", path.display());
+		Ok(Self { path, pipe, state, tx, partial: Vec::new() })
+	}
+
+	pub async fn run(&mut self, rx: Receiver<CommandPipeSignal>)
+	{
+		let mut buffer = [0_u8; 4096];
+
+		loop
+		{
+			// waits for the next command, or for the driver to shut down
+			let read = future::or(
+				async
+				{
+					match rx.recv().await
+					{
+						Ok(CommandPipeSignal::Shutdown)
+							| Err(_) => None
+					}
+				},
+				async { Some((&self.pipe).read(&mut buffer).await) }).await;
+
+			match read
+			{
+				Some(Ok(bytes_read)) => self.partial.extend_from_slice(&buffer[..bytes_read]),
+				Some(Err(error)) =>
+				{
+					warn!("stopped reading commands from {}: {}", self.path.display(), error);
+					break
+				},
+				None => break
+			}
+
+			while let Some(end) = self.partial.iter().position(|byte| *byte == b'\n')
+			{
+				let line: Vec<u8> = self.partial.drain(..=end).collect();
+				let line = String::from_utf8_lossy(&line);
+
+				if line.trim().is_empty()
+				{
+					continue
+				}
+
+				debug!("command from the pipe: {}", line.trim());
+
+				match self.parse(line.trim())
+				{
+					Ok(signal) => { self.tx.send(signal); },
+					Err(error) => warn!("ignoring the command '{}': {}", line.trim(), error)
+				}
+			}
+		}
+	}
+
+	/// Turns a line into the signal for it: the command, then its arguments
+	/// separated by spaces. Names (of profiles and macros) are the rest of the
+	/// line, so they can have spaces in them.
+	fn parse(&self, line: &str) -> Result<MainThreadSignal, String>
+	{
+		let (command, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+		let rest = rest.trim();
+		let arguments: Vec<&str> = rest.split_whitespace().collect();
+
+		match (command, arguments.as_slice())
+		{
+			("profile", [_, ..]) => self.profile(rest).map(MainThreadSignal::SwitchProfile),
+			("push", [_, ..]) => self.profile(rest).map(|name| MainThreadSignal::PushProfile(name, None)),
+			("pop", []) => Ok(MainThreadSignal::PopProfile),
+			("macro", [_, ..]) =>
+			{
+				let exists = self.state.config
+					.read()
+					.unwrap()
+					.macros
+					.as_ref()
+					.map(|macros| macros.contains_key(rest))
+					.unwrap_or(false);
+
+				match exists
+				{
+					true => Ok(MainThreadSignal::RunMacro(rest.to_string())),
+					false => Err(format!("there is no macro called '{}'", rest))
+				}
+			},
+			("mode", [mode]) =>
+			{
+				let modes = self.state.config.read().unwrap().modes();
+
+				mode.parse::<u8>()
+					.ok()
+					.filter(|mode| modes.contains(mode))
+					.map(MainThreadSignal::SetMode)
+					.ok_or_else(|| format!("there is no mode {}, only {} to {}", mode, modes.start(), modes.end()))
+			},
+			("key", [key, color]) | ("key", [key, color, _]) =>
+			{
+				let ttl = match arguments.get(2)
+				{
+					Some(ttl) => Some(ttl.parse::<u64>().map_err(|_| format!("'{}' isn't a number of milliseconds", ttl))?),
+					None => None
+				};
+
+				Ok(MainThreadSignal::SetKeyColors(KeyColorUpdate
+				{
+					client: Self::CLIENT.to_string(),
+					colors: vec![(parse_key(key)?, Color::parse(color)?)],
+					replace: false,
+					ttl: ttl.filter(|ttl| *ttl > 0)
+				}))
+			},
+			("clear", []) => Ok(MainThreadSignal::ClearKeyColors(Self::CLIENT.to_string())),
+			("logo", ["off"]) => Ok(MainThreadSignal::SetLogoColor(None)),
+			("logo", [color]) => Ok(MainThreadSignal::SetLogoColor(Some(Color::parse(color)?))),
+			("effect", ["off"]) => Ok(MainThreadSignal::SetEffect(None)),
+			("effect", [effect, effect_arguments @ ..]) => parse_effect(effect, effect_arguments)
+				.map(|effect| MainThreadSignal::SetEffect(Some(effect))),
+			("game-mode", [command]) => parse_mode_command(command).map(MainThreadSignal::SetGameMode),
+			("focus-mode", [command]) => parse_mode_command(command)
+				.map(|command| MainThreadSignal::SetFocusMode(command.enabled(self.state.focus_mode.load(Ordering::Relaxed)))),
+			("low-power-mode", [command]) => parse_mode_command(command)
+				.map(|command| MainThreadSignal::SetLowPowerMode(command.enabled(self.state.low_power.load(Ordering::Relaxed)))),
+			("playlist", ["skip"]) => Ok(MainThreadSignal::Playlist(PlaylistCommand::Skip)),
+			("playlist", ["pause"]) => Ok(MainThreadSignal::Playlist(PlaylistCommand::Pause)),
+			("playlist", ["resume"]) => Ok(MainThreadSignal::Playlist(PlaylistCommand::Resume)),
+			_ => Err("it isn't a command, or has the wrong arguments".to_string())
+		}
+	}
+
+	fn profile(&self, name: &str) -> Result<String, String>
+	{
+		match self.state.config.read().unwrap().profiles.contains_key(name)
+		{
+			true => Ok(name.to_string()),
+			false => Err(format!("there is no profile called '{}'", name))
+		}
+	}
+}
+
+impl Drop for CommandPipe
+{
+	fn drop(&mut self)
+	{
+		let _ = fs::remove_file(&self.path);
+	}
+}
+
+/// An effect and its arguments, in the order they're written in the config,
+/// i.e. `breathing ff0000 3000`. Durations (in milliseconds) can be left off,
+/// and brightness is always full.
+fn parse_effect(effect: &str, arguments: &[&str]) -> Result<EffectConfiguration, String>
+{
+	let duration = |index: usize| match arguments.get(index)
+	{
+		Some(duration) => duration.parse::<u16>().map_err(|_| format!("'{}' isn't a duration in milliseconds (up to 65535)", duration)),
+		None => Ok(CommandPipe::DEFAULT_EFFECT_DURATION)
+	};
+
+	let color = || arguments
+		.get(0)
+		.ok_or_else(|| format!("{} needs a color", effect))
+		.and_then(|color| Color::parse(color));
+
+	let allowed = match effect
+	{
+		"color_wave" | "breathing" | "ripple" => 2,
+		_ => 1
+	};
+
+	if arguments.len() > allowed
+	{
+		return Err(format!("{} has too many arguments", effect))
+	}
+
+	Ok(match effect
+	{
+		"static" => EffectConfiguration::Static { color: color()? },
+		"breathing" => EffectConfiguration::Breathing { color: color()?, duration: duration(1)?, brightness: 100 },
+		"cycle" => EffectConfiguration::Cycle { duration: duration(0)?, brightness: 100 },
+		"color_wave" => EffectConfiguration::ColorWave
+		{
+			direction: match arguments.get(0)
+			{
+				Some(direction) => serde_yaml::from_str::<EffectDirection>(direction)
+					.map_err(|_| format!("'{}' isn't a direction", direction))?,
+				None => EffectDirection::Horizontal
+			},
+			duration: duration(1)?,
+			brightness: 100
+		},
+		"ripple" => EffectConfiguration::Ripple { color: color()?, duration: duration(1)? },
+		_ => return Err(format!("there is no effect called '{}'", effect))
+	})
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn effects_are_read_as_written_in_the_config()
+	{
+		assert_eq!(parse_effect("cycle", &["3000"]), Ok(EffectConfiguration::Cycle { duration: 3000, brightness: 100 }));
+		assert_eq!(parse_effect("breathing", &["ff0000"]), Ok(EffectConfiguration::Breathing
+		{
+			color: Color::new(0xff, 0, 0),
+			duration: CommandPipe::DEFAULT_EFFECT_DURATION,
+			brightness: 100
+		}));
+		assert_eq!(parse_effect("color_wave", &["center_out", "2000"]), Ok(EffectConfiguration::ColorWave
+		{
+			direction: EffectDirection::CenterOut,
+			duration: 2000,
+			brightness: 100
+		}));
+
+		assert!(parse_effect("static", &[]).is_err());
+		assert!(parse_effect("cycle", &["70000"]).is_err());
+		assert!(parse_effect("sparkle", &[]).is_err());
+	}
+}
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+
+use crate::device::color::{Color, NamedColors};
+
+use super::{Configuration, ConfigError};
+
+/// Profiles and themes can be split out of config.yml into yaml files in these
+/// folders next to it. Each file is a map of names to profiles (or themes), as
+/// they'd be written under `profiles:` (or `themes:`) in config.yml.
+const PROFILES_FOLDER: &str = "profiles.d";
+const THEMES_FOLDER: &str = "themes.d";
+
+/// The names of everything merged in from the include folders, so they aren't
+/// written back into config.yml when it's saved
+#[derive(Debug, Default)]
+pub struct Included
+{
+	pub profiles: Vec<String>,
+	pub themes: Vec<String>
+}
+
+pub fn folders() -> Vec<PathBuf>
+{
+	[PROFILES_FOLDER, THEMES_FOLDER]
+		.iter()
+		.map(|folder|
+		{
+			let mut path = Configuration::config_folder();
+			path.push(folder);
+			path
+		})
+		.collect()
+}
+
+/// Whether a changed file is one that's merged into the config
+pub fn is_included_file(path: &Path) -> bool
+{
+	let in_folder = path
+		.parent()
+		.map(|parent| folders().iter().any(|folder| folder == parent))
+		.unwrap_or(false);
+
+	in_folder && is_yaml(path)
+}
+
+fn is_yaml(path: &Path) -> bool
+{
+	match path.extension().and_then(|extension| extension.to_str())
+	{
+		Some("yml") | Some("yaml") => true,
+		_ => false
+	}
+}
+
+/// Merges the files in the include folders into the config. A name can only be
+/// defined once, whether that's in config.yml or an included file.
+pub fn merge(config: &mut Configuration) -> Result<(), ConfigError>
+{
+	let profiles = load_folder(PROFILES_FOLDER, &config.colors)?;
+	let themes = load_folder(THEMES_FOLDER, &config.colors)?;
+
+	config.included = Included
+	{
+		profiles: insert_all(&mut config.profiles, profiles, "profile")?,
+		themes: insert_all(&mut config.themes, themes, "theme")?
+	};
+
+	Ok(())
+}
+
+/// Reads every yaml file in an include folder, in file name order. A folder
+/// that doesn't exist just has nothing in it.
+fn load_folder<T>(folder: &str, colors: &NamedColors) -> Result<Vec<(PathBuf, HashMap<String, T>)>, ConfigError>
+where
+	T: DeserializeOwned
+{
+	let mut path = Configuration::config_folder();
+	path.push(folder);
+
+	let entries = match fs::read_dir(&path)
+	{
+		Ok(entries) => entries,
+		Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+		Err(error) => return Err(ConfigError::UnableToOpen(error))
+	};
+
+	let mut paths: Vec<PathBuf> = entries
+		.filter_map(|entry| entry.ok())
+		.map(|entry| entry.path())
+		.filter(|path| is_yaml(path))
+		.collect();
+	paths.sort();
+
+	paths
+		.into_iter()
+		.map(|path|
+		{
+			let yaml_string = fs::read_to_string(&path).map_err(ConfigError::UnableToOpen)?;
+
+			// an empty file is fine, it just doesn't define anything yet
+			match yaml_string.trim().is_empty()
+			{
+				true => Ok((path, HashMap::new())),
+				false => Color::with_named_colors(colors, || serde_yaml::from_str(&yaml_string))
+					.map(|items| (path.clone(), items))
+					.map_err(|error| ConfigError::InvalidConfiguration(
+						format!("{} cannot be parsed: {}", path.display(), error)))
+			}
+		})
+		.collect()
+}
+
+fn insert_all<T>(target: &mut HashMap<String, T>, files: Vec<(PathBuf, HashMap<String, T>)>, kind: &str)
+	-> Result<Vec<String>, ConfigError>
+{
+	let mut names = Vec::new();
+
+	for (path, items) in files
+	{
+		for (name, item) in items
+		{
+			if target.contains_key(&name)
+			{
+				return Err(ConfigError::InvalidConfiguration(format!(
+					"{} defines {} '{}', which is already defined elsewhere", path.display(), kind, name)))
+			}
+
+			target.insert(name.clone(), item);
+			names.push(name);
+		}
+	}
+
+	Ok(names)
+}
@@ -0,0 +1,142 @@
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Deserializer, de::Error};
+
+use crate::device::scancode::Scancode;
+
+use super::{Keygroup, Keygroups};
+
+/// A keygroup as written in the config: either a plain list of keys, or other
+/// keygroups (built-in or not) plus some extra keys
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeygroupDefinition
+{
+	Keys(Keygroup),
+	Extended
+	{
+		include: Vec<String>,
+		#[serde(default)]
+		keys: Keygroup
+	}
+}
+
+/// The keygroups available in every config. A keygroup in the config with the
+/// same name replaces the built-in one.
+pub fn builtin_keygroups() -> Keygroups
+{
+	use Scancode::*;
+
+	let mut keygroups = Keygroups::new();
+	let mut add = |name: &str, keys: &[Scancode]| keygroups.insert(name.to_string(), keys.to_vec());
+
+	add("function_row", &[Escape, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12]);
+	add("number_row", &[Grave, N1, N2, N3, N4, N5, N6, N7, N8, N9, N0, Minus, Equals, Backspace]);
+	add("top_row", &[Tab, Q, W, E, R, T, Y, U, I, O, P, LeftBracket, RightBracket, UsBackslash]);
+	add("home_row", &[CapsLock, A, S, D, F, G, H, J, K, L, Semicolon, Apostrophe, HashTilde, Enter]);
+	add("bottom_row", &[LeftShift, Backslash, Z, X, C, V, B, N, M, Comma, Dot, Slash, RightShift]);
+	add("letters", &[A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z]);
+	add("wasd", &[W, A, S, D]);
+	add("arrows", &[Up, Down, Left, Right]);
+	add("navigation", &[PrintScreen, ScrollLock, Pause, Insert, Home, PageUp, Delete, End, PageDown]);
+	add("numpad", &[NumLock, NumpadDivide, NumpadMultiply, NumpadMinus, NumpadPlus, NumpadEnter,
+		Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9, Numpad0,
+		NumpadDot]);
+	add("modifiers", &[LeftControl, LeftShift, LeftAlt, LeftMeta,
+		RightControl, RightShift, RightAlt, RightMeta, ContextMenu]);
+	add("media_keys", &[Mute, MediaPrevious, MediaNext, MediaPlayPause]);
+	add("gkeys", &[G1, G2, G3, G4, G5]);
+
+	// every key that the keyboard actually has
+	keygroups.insert("all".to_string(), Scancode::iter_variants()
+		.filter(|scancode| scancode.position().is_some())
+		.collect());
+
+	keygroups
+}
+
+/// Expands a keygroup definition, including the keygroups it refers to.
+/// `expanding` holds the keygroups currently being expanded, to catch cycles.
+fn expand(
+	name: &str,
+	definitions: &HashMap<String, KeygroupDefinition>,
+	builtins: &Keygroups,
+	expanded: &mut Keygroups,
+	expanding: &mut Vec<String>) -> Result<Keygroup, String>
+{
+	if let Some(keygroup) = expanded.get(name)
+	{
+		return Ok(keygroup.clone())
+	}
+
+	let definition = match definitions.get(name)
+	{
+		Some(definition) => definition,
+		None => return builtins
+			.get(name)
+			.cloned()
+			.ok_or_else(|| format!("unknown keygroup: {}", name))
+	};
+
+	if expanding.iter().any(|parent| parent == name)
+	{
+		return Err(format!("keygroup {} includes itself", name))
+	}
+
+	let keygroup = match definition
+	{
+		KeygroupDefinition::Keys(keys) => keys.clone(),
+		KeygroupDefinition::Extended { include, keys } =>
+		{
+			expanding.push(name.to_string());
+
+			let mut keygroup = Keygroup::new();
+
+			for included in include
+			{
+				// a keygroup including itself extends the built-in keygroup it replaces
+				keygroup.extend(match included == name
+				{
+					true => builtins
+						.get(name)
+						.cloned()
+						.ok_or_else(|| format!("keygroup {} includes itself", name))?,
+					false => expand(included, definitions, builtins, expanded, expanding)?
+				});
+			}
+
+			expanding.pop();
+			keygroup.extend(keys);
+
+			let mut seen = HashSet::new();
+			keygroup.retain(|scancode| seen.insert(*scancode));
+			keygroup
+		}
+	};
+
+	expanded.insert(name.to_string(), keygroup.clone());
+	Ok(keygroup)
+}
+
+/// Deserializes the config's keygroups, expanding any includes and adding the
+/// built-in keygroups
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Keygroups, D::Error>
+where
+	D: Deserializer<'de>
+{
+	let definitions = HashMap::<String, KeygroupDefinition>::deserialize(deserializer)?;
+	let builtins = builtin_keygroups();
+	let mut expanded = Keygroups::new();
+
+	for name in definitions.keys()
+	{
+		expand(name, &definitions, &builtins, &mut expanded, &mut Vec::new()).map_err(D::Error::custom)?;
+	}
+
+	for (name, keygroup) in builtins
+	{
+		expanded.entry(name).or_insert(keygroup);
+	}
+
+	Ok(expanded)
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use serde::{Serialize, Deserialize};
+
+use crate::device::scancode::Scancode;
+
+use super::{Configuration, ConfigError};
+
+/// Corrections to the rgb ids used to light keys, for keyboards whose layout
+/// doesn't match the built-in one. They come from the config's `layout`, and
+/// the layout overrides file written by `--test-keys`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct LayoutOverrides
+{
+	#[serde(default)]
+	pub rgb_ids: HashMap<Scancode, u8>
+}
+
+impl LayoutOverrides
+{
+	pub const fn filename() -> &'static str
+	{
+		"layout_overrides.yml"
+	}
+
+	pub fn file_path() -> PathBuf
+	{
+		let mut path = Configuration::config_folder();
+		path.push(Self::filename());
+		path
+	}
+
+	/// Loads the layout overrides file, or no overrides if there isn't one
+	pub fn load() -> Result<Self, ConfigError>
+	{
+		match fs::read_to_string(Self::file_path())
+		{
+			Ok(yaml_string) => serde_yaml::from_str(&yaml_string).map_err(ConfigError::ParseError),
+			Err(error) if error.kind() == ErrorKind::NotFound => Ok(Self::default()),
+			Err(error) => Err(ConfigError::UnableToOpen(error))
+		}
+	}
+
+	/// Combines these overrides with others, which take priority
+	pub fn merge(&self, other: &Self) -> Self
+	{
+		let mut rgb_ids = self.rgb_ids.clone();
+		rgb_ids.extend(other.rgb_ids.iter().map(|(scancode, rgb_id)| (*scancode, *rgb_id)));
+
+		Self { rgb_ids }
+	}
+
+	pub fn save(&self) -> Result<(), ConfigError>
+	{
+		let yaml_string = serde_yaml::to_string(self).map_err(ConfigError::SerializeError)?;
+
+		Configuration::write_atomically(&Self::file_path(), yaml_string.as_bytes())
+			.map_err(ConfigError::UnableToWrite)
+	}
+}
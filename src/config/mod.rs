@@ -0,0 +1,1505 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::fs;
+use std::fmt;
+
+use serde::{Serialize, Deserialize, Serializer, Deserializer, de::Error};
+
+use regex::Regex;
+
+use crate::windowsystem::ActiveWindowInfo;
+use crate::media::{MediaState, PlayerStatus, AudioStream};
+use crate::device::scancode::Scancode;
+use crate::device::MediaKey;
+use crate::device::rgb::{Theme, IndicatorColor, KeySelection};
+use crate::device::color::{Color, NamedColors};
+use crate::macros::Macro;
+use crate::notifications::Notification;
+use crate::subsystem::Subsystem;
+
+mod resolver;
+mod keygroups;
+mod layout;
+mod validate;
+mod playlist;
+mod night_mode;
+mod schedule;
+pub mod includes;
+
+pub use resolver::ProfileResolver;
+pub use layout::LayoutOverrides;
+pub use playlist::{Playlist, PlaylistEntry};
+pub use night_mode::NightMode;
+pub use schedule::{LocalTime, Schedule};
+
+#[derive(Debug)]
+pub enum ConfigError
+{
+	UnableToOpen(std::io::Error),
+	UnableToWrite(std::io::Error),
+	ParseError(serde_yaml::Error),
+	SerializeError(serde_yaml::Error),
+	InvalidConfiguration(String),
+	/// everything wrong with the config's references, found by `validate()`
+	ValidationFailed(Vec<String>),
+	BackupNotFound(String)
+}
+
+impl fmt::Display for ConfigError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error>
+	{
+		match self
+		{
+			ConfigError::UnableToOpen(io_error) =>
+				write!(f, "unable to read the config file: {}", io_error),
+			ConfigError::UnableToWrite(io_error) =>
+				write!(f, "unable to write the config file: {}", io_error),
+			ConfigError::ParseError(serde_error) =>
+				write!(f, "your configuration file cannot be parsed: {}", serde_error),
+			ConfigError::SerializeError(serde_error) =>
+				write!(f, "your configuration could not be serialized: {}", serde_error),
+			ConfigError::InvalidConfiguration(reason) =>
+				write!(f, "your configuration is invalid: {}", reason),
+			ConfigError::ValidationFailed(problems) =>
+			{
+				write!(f, "your configuration has {} problem(s):", problems.len())?;
+				problems.iter().try_for_each(|problem| write!(f, "\n  - {}", problem))
+			},
+			ConfigError::BackupNotFound(name) =>
+				write!(f, "there is no config backup named '{}'", name)
+		}
+	}
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MacroKeyAssignment
+{
+	SimpleAction(crate::macros::Action),
+	NamedMacro(String),
+	/// a named macro, with a value for each of its parameters
+	MacroWithArguments
+	{
+		#[serde(rename = "macro")]
+		name: String,
+		#[serde(default)]
+		arguments: HashMap<String, String>
+	},
+	/// different assignments for a gkey being tapped, double tapped and held
+	Gestures(GkeyGestures),
+	/// separate assignments for a gkey being pressed and released, i.e. for
+	/// push to talk
+	PressRelease
+	{
+		on_press: Option<Box<MacroKeyAssignment>>,
+		on_release: Option<Box<MacroKeyAssignment>>
+	}
+}
+
+impl MacroKeyAssignment
+{
+	pub fn expand<'a>(&'a self, config: &'a Configuration) -> Option<Cow<'a, Macro>>
+	{
+		match self
+		{
+			Self::SimpleAction(action) => Some(Cow::Owned(Macro::from_action(action.clone()))),
+			Self::NamedMacro(macro_name) => config.macros
+				.as_ref()
+				.and_then(|macros| macros.get(macro_name))
+				.map(|_macro| Cow::Borrowed(_macro)),
+			Self::MacroWithArguments { name, arguments } => config.macros
+				.as_ref()
+				.and_then(|macros| macros.get(name))
+				.map(|_macro| Cow::Owned(_macro.with_arguments(arguments))),
+			Self::PressRelease { on_press, .. } => on_press
+				.as_ref()
+				.and_then(|assignment| assignment.expand(config)),
+			Self::Gestures(gestures) => gestures.tap
+				.as_ref()
+				.and_then(|assignment| assignment.expand(config))
+		}
+	}
+
+	/// What to call the assignment when showing it: the macro's name, or the
+	/// action as it's written in the config
+	pub fn name(&self) -> String
+	{
+		match self
+		{
+			Self::NamedMacro(name)
+				| Self::MacroWithArguments { name, .. } => name.clone(),
+			Self::SimpleAction(action) => serde_yaml::to_string(action)
+				.map(|yaml| yaml.trim_start_matches("---").trim().to_string())
+				.unwrap_or_else(|_| format!("{:?}", action)),
+			Self::PressRelease { on_press, .. } => on_press
+				.as_ref()
+				.map(|assignment| assignment.name())
+				.unwrap_or_default(),
+			Self::Gestures(gestures) => gestures.tap
+				.as_ref()
+				.map(|assignment| assignment.name())
+				.unwrap_or_default()
+		}
+	}
+
+	/// The macro to run when the key is released, if it has one
+	pub fn expand_release<'a>(&'a self, config: &'a Configuration) -> Option<Cow<'a, Macro>>
+	{
+		match self
+		{
+			Self::PressRelease { on_release: Some(assignment), .. } => assignment.expand(config),
+			_ => None
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gesture
+{
+	Tap,
+	DoubleTap,
+	Hold
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GkeyGestures
+{
+	pub tap: Option<Box<MacroKeyAssignment>>,
+	pub double_tap: Option<Box<MacroKeyAssignment>>,
+	pub hold: Option<Box<MacroKeyAssignment>>
+}
+
+impl GkeyGestures
+{
+	pub fn get(&self, gesture: Gesture) -> Option<&MacroKeyAssignment>
+	{
+		match gesture
+		{
+			Gesture::Tap => self.tap.as_deref(),
+			Gesture::DoubleTap => self.double_tap.as_deref(),
+			Gesture::Hold => self.hold.as_deref()
+		}
+	}
+}
+
+/// A gkey that, whilst it's held down, switches the other gkeys (and the media
+/// keys) to their own assignments, like G-Shift on Logitech's software
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GShift
+{
+	/// the gkey held down to shift
+	pub key: u8,
+	#[serde(default)]
+	pub gkeys: HashMap<u8, MacroKeyAssignment>,
+	#[serde(default)]
+	pub media_keys: HashMap<MediaKey, MacroKeyAssignment>
+}
+
+/// How long (in ms) gestures take to tell apart
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GestureTimings
+{
+	/// how soon the second tap of a double tap has to come
+	pub double_tap: u64,
+	/// how long a key has to be held down for it to count as held
+	pub hold: u64
+}
+
+impl Default for GestureTimings
+{
+	fn default() -> Self
+	{
+		Self { double_tap: 300, hold: 500 }
+	}
+}
+
+/// Adjusts how long every macro step takes
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MacroTiming
+{
+	/// varies each step's duration by up to this fraction of it either way
+	/// (i.e. 0.1 for 10%), so repeated inputs aren't identical
+	pub humanize: f64,
+	/// multiplies every step's duration, i.e. 2 to run macros at half speed
+	pub scale: f64
+}
+
+impl Default for MacroTiming
+{
+	fn default() -> Self
+	{
+		Self { humanize: 0_f64, scale: 1_f64 }
+	}
+}
+
+/// Holding a gkey down shows what it's assigned, for remembering what was
+/// bound where: the keys its macro presses light up, and the macro's name is
+/// logged and shown as a desktop notification
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MacroHints
+{
+	/// how long (in ms) a gkey has to be held down
+	pub hold: u64,
+	/// how long (in ms) the keys stay lit
+	pub duration: u64,
+	pub notify: bool
+}
+
+impl Default for MacroHints
+{
+	fn default() -> Self
+	{
+		Self { hold: 1000, duration: 2000, notify: true }
+	}
+}
+
+pub type Keygroup = Vec<Scancode>;
+pub type Keygroups = HashMap<String, Keygroup>;
+
+pub type GkeyAssignments = Option<HashMap<u8, MacroKeyAssignment>>;
+pub type GkeySets = Option<Vec<String>>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModeProfile
+{
+	theme: Option<String>,
+	logo_theme: Option<String>,
+	gkey_sets: GkeySets,
+	gkeys: GkeyAssignments
+}
+
+impl ModeProfile
+{
+	#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+	fn is_empty(&self) -> bool
+	{
+		self.theme.is_none() && self.logo_theme.is_none() && self.gkey_sets.is_none() && self.gkeys.is_none()
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Profile
+{
+	conditions: Option<ActiveWindowConditions>,
+	theme: Option<String>,
+	/// shown on the logo instead of the theme, which then only lights the keys
+	logo_theme: Option<String>,
+	gkey_sets: GkeySets,
+	gkeys: GkeyAssignments,
+	pub game_mode_keys: Option<Vec<Scancode>>,
+	/// turns game mode on (or off) when this profile becomes active
+	pub game_mode: Option<bool>,
+	media_keys: Option<HashMap<MediaKey, MacroKeyAssignment>>,
+	notifications: Option<NotificationIndicator>,
+	/// how often (in Hz) the keyboard reports key presses whilst this profile
+	/// is active, if it can be changed
+	report_rate: Option<u16>,
+	/// rotates through the playlist's themes instead of showing `theme`
+	pub playlist: Option<String>,
+	/// a second layer of assignments, used whilst a gkey is held down
+	gshift: Option<GShift>,
+	modes: Option<HashMap<u8, ModeProfile>>
+}
+
+impl Profile
+{
+	/// The gkey assignments of one of this profile's modes, or the profile's
+	/// own (used in modes without their own) for None, for editing
+	#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+	pub fn gkeys_mut(&mut self, mode: Option<u8>) -> &mut GkeyAssignments
+	{
+		match mode
+		{
+			Some(mode) => &mut self.modes
+				.get_or_insert_with(HashMap::new)
+				.entry(mode)
+				.or_default()
+				.gkeys,
+			None => &mut self.gkeys
+		}
+	}
+
+	/// Like `gkeys_mut`, but None for a mode the profile doesn't have rather
+	/// than adding it
+	#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+	pub fn existing_gkeys_mut(&mut self, mode: Option<u8>) -> Option<&mut GkeyAssignments>
+	{
+		match mode
+		{
+			Some(mode) => self.modes.as_mut()?.get_mut(&mode).map(|mode_profile| &mut mode_profile.gkeys),
+			None => Some(&mut self.gkeys)
+		}
+	}
+
+	/// Removes a mode left with nothing in it (i.e. once its last gkey
+	/// assignment is removed), and the profile's modes if it was the last, so
+	/// they aren't saved to the config file
+	#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+	pub fn remove_mode_if_empty(&mut self, mode: u8)
+	{
+		if let Some(modes) = &mut self.modes
+		{
+			if modes.get(&mode).map(ModeProfile::is_empty).unwrap_or(false)
+			{
+				modes.remove(&mode);
+			}
+
+			if modes.is_empty()
+			{
+				self.modes = None;
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct LightingOptions
+{
+	/// software brightness (0 - 100%) applied to static themes
+	#[serde(default = "LightingOptions::default_brightness")]
+	pub brightness: u8,
+	/// temporally dither colors that can't be represented exactly at the
+	/// current brightness
+	#[serde(default)]
+	pub dithering: bool,
+	/// render software effects, otherwise fall back to the nearest hardware effect
+	#[serde(default = "LightingOptions::default_software_effects")]
+	pub software_effects: bool,
+	/// time in milliseconds to crossfade between themes when they change, 0 to
+	/// switch instantly
+	#[serde(default)]
+	pub transition: u16,
+	/// start in low power mode, which can also be turned on and off over dbus
+	#[serde(default)]
+	pub low_power: bool,
+	/// how brightly each key can be lit in low power mode, as a percentage of
+	/// full white
+	#[serde(default = "LightingOptions::default_low_power_limit")]
+	pub low_power_limit: u8,
+	/// shows the first keyboard's lighting on every other keyboard, rather
+	/// than each drawing its own
+	#[serde(default)]
+	pub mirror: bool
+}
+
+impl LightingOptions
+{
+	fn default_brightness() -> u8
+	{
+		100
+	}
+
+	fn default_low_power_limit() -> u8
+	{
+		50
+	}
+
+	/// The most the channels of a key's color can add up to in low power mode
+	pub fn low_power_cap(&self) -> u16
+	{
+		((255 * 3) * self.low_power_limit.min(100) as u32 / 100) as u16
+	}
+
+	fn default_software_effects() -> bool
+	{
+		true
+	}
+}
+
+impl Default for LightingOptions
+{
+	fn default() -> Self
+	{
+		Self
+		{
+			brightness: Self::default_brightness(),
+			dithering: false,
+			software_effects: Self::default_software_effects(),
+			transition: 0,
+			low_power: false,
+			low_power_limit: Self::default_low_power_limit(),
+			mirror: false
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GkeyMode
+{
+	/// only take over the gkeys in profiles that assign something to them, so
+	/// they send their default keys everywhere else
+	Auto,
+	/// always take over the gkeys
+	Software
+}
+
+impl Default for GkeyMode
+{
+	fn default() -> Self
+	{
+		Self::Auto
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MediaOptions
+{
+	/// patterns matching the mpris players to prefer, in priority order. Matched
+	/// against both the player's identity and its dbus name
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub players: Vec<Regex>
+}
+
+/// Something the light key can do instead of changing the keyboard's brightness
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LightKeyAction
+{
+	/// show each of these themes in turn, then go back to the profile's theme
+	CycleThemes(Vec<String>),
+	/// turn all the lighting off, or back on again
+	ToggleLighting,
+	/// turn focus mode on or off
+	ToggleFocusMode,
+	/// apply a profile until the active window next changes
+	SwitchProfile(String),
+	Macro(MacroKeyAssignment)
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LightKeyBindings
+{
+	pub press: Option<LightKeyAction>,
+	pub double_press: Option<LightKeyAction>,
+	/// actions for brightness levels (0 - 100), only used when neither press is bound
+	#[serde(default)]
+	pub levels: HashMap<u8, LightKeyAction>
+}
+
+/// An application that colors the audio focus keys whilst it's playing audio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioApplication
+{
+	/// matched against the application's name and its executable
+	#[serde(with = "RegexSerializer")]
+	pub application: Regex,
+	pub color: Color
+}
+
+impl AudioApplication
+{
+	fn matches(&self, stream: &AudioStream) -> bool
+	{
+		self.application.is_match(&stream.application)
+			|| stream.binary
+				.as_ref()
+				.map(|binary| self.application.is_match(binary))
+				.unwrap_or(false)
+	}
+}
+
+/// Shows that there are notifications the user hasn't seen yet, by breathing
+/// the logo and/or lighting a key for each one
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationIndicator
+{
+	pub color: Color,
+	#[serde(default = "NotificationIndicator::default_breathe_logo")]
+	pub breathe_logo: bool,
+	/// lit in order, one for each pending notification
+	#[serde(default)]
+	pub counter: Vec<KeySelection>
+}
+
+impl NotificationIndicator
+{
+	fn default_breathe_logo() -> bool
+	{
+		true
+	}
+
+	pub fn counter_scancodes(&self, keygroups: &Keygroups) -> Vec<Scancode>
+	{
+		self.counter
+			.iter()
+			.flat_map(|selection| selection.scancodes(keygroups))
+			.collect()
+	}
+}
+
+/// Shell commands run when something happens in the driver, see hooks.rs
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks
+{
+	#[serde(default)]
+	pub profile_changed: Vec<String>,
+	#[serde(default)]
+	pub mode_changed: Vec<String>,
+	#[serde(default)]
+	pub macro_started: Vec<String>,
+	#[serde(default)]
+	pub recording_toggled: Vec<String>,
+	#[serde(default)]
+	pub device_connected: Vec<String>
+}
+
+/// The localhost http server, for controlling the driver from tools that can't
+/// use dbus. Only available when the driver is built with the `http` feature.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpOptions
+{
+	#[serde(default = "HttpOptions::default_port")]
+	pub port: u16
+}
+
+impl HttpOptions
+{
+	fn default_port() -> u16
+	{
+		8815
+	}
+}
+
+/// Lights keys for a while when a matching notification arrives, i.e. G5 in
+/// blue for new mail. Every pattern given has to match.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NotificationFlash
+{
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub application: Option<Regex>,
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub summary: Option<Regex>,
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub body: Option<Regex>,
+	pub keys: Vec<KeySelection>,
+	pub color: Color,
+	/// how long the keys are lit for, in ms
+	#[serde(default = "NotificationFlash::default_duration")]
+	pub duration: u64
+}
+
+impl NotificationFlash
+{
+	fn default_duration() -> u64
+	{
+		3000
+	}
+
+	pub fn matches(&self, notification: &Notification) -> bool
+	{
+		let matches = |pattern: &Option<Regex>, text: &str| pattern
+			.as_ref()
+			.map(|pattern| pattern.is_match(text))
+			.unwrap_or(true);
+
+		matches(&self.application, &notification.application)
+			&& matches(&self.summary, &notification.summary)
+			&& matches(&self.body, &notification.body)
+	}
+
+	pub fn scancodes(&self, keygroups: &Keygroups) -> Vec<Scancode>
+	{
+		self.keys
+			.iter()
+			.flat_map(|selection| selection.scancodes(keygroups))
+			.collect()
+	}
+}
+
+/// Lights a set of keys in the color of whichever application is playing audio
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AudioFocus
+{
+	#[serde(default = "AudioFocus::default_keys")]
+	pub keys: Vec<KeySelection>,
+	/// in priority order, for when several applications are playing at once
+	pub applications: Vec<AudioApplication>
+}
+
+impl AudioFocus
+{
+	fn default_keys() -> Vec<KeySelection>
+	{
+		vec![KeySelection::Single(Scancode::Logo)]
+	}
+
+	pub fn scancodes(&self, keygroups: &Keygroups) -> Vec<Scancode>
+	{
+		self.keys
+			.iter()
+			.flat_map(|selection| selection.scancodes(keygroups))
+			.collect()
+	}
+
+	/// The color of the highest priority application that's playing audio, if
+	/// any of them are
+	pub fn color(&self, streams: &[AudioStream]) -> Option<Color>
+	{
+		self.applications
+			.iter()
+			.find(|application| streams.iter().any(|stream| application.matches(stream)))
+			.map(|application| application.color)
+	}
+}
+
+/// The colors of the indicators the driver draws over themes. Any that aren't
+/// in the config keep their defaults.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndicatorColors
+{
+	/// the mute key whilst muted
+	pub muted: IndicatorColor,
+	/// the play/pause key whilst the player is paused
+	pub paused: IndicatorColor,
+	/// the media keys when there's no media player
+	pub no_media: IndicatorColor,
+	/// the lit part of the volume bar
+	pub volume: IndicatorColor,
+	/// the lit part of the volume bar whilst muted
+	pub volume_muted: IndicatorColor,
+	/// blinks on the G-key of a running macro
+	pub macro_running: IndicatorColor,
+	/// blinks on a G-key waiting to be pressed again to confirm its macro
+	pub macro_confirm: IndicatorColor,
+	/// briefly lights the keys disabled by game mode when it's turned on
+	pub game_mode: IndicatorColor,
+	/// lights the keys pressed by a held gkey's macro, see MacroHints
+	pub macro_hint: IndicatorColor
+}
+
+/// A high visibility scene for low vision users: just the important keys, lit
+/// brightly, with everything else off
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FocusMode
+{
+	#[serde(default = "FocusMode::default_keys")]
+	pub keys: Vec<KeySelection>,
+	#[serde(default = "FocusMode::default_color")]
+	pub color: Color,
+	/// used in place of the lighting brightness whilst focus mode is on
+	#[serde(default = "LightingOptions::default_brightness")]
+	pub brightness: u8
+}
+
+impl FocusMode
+{
+	fn default_keys() -> Vec<KeySelection>
+	{
+		vec![
+			KeySelection::Keygroup("home_row".to_string()),
+			KeySelection::Keygroup("modifiers".to_string())
+		]
+	}
+
+	fn default_color() -> Color
+	{
+		Color::new(255, 255, 255)
+	}
+
+	pub fn scancodes(&self, keygroups: &Keygroups) -> Vec<Scancode>
+	{
+		self.keys
+			.iter()
+			.flat_map(|selection| selection.scancodes(keygroups))
+			.collect()
+	}
+}
+
+impl Default for FocusMode
+{
+	fn default() -> Self
+	{
+		Self
+		{
+			keys: Self::default_keys(),
+			color: Self::default_color(),
+			brightness: LightingOptions::default_brightness()
+		}
+	}
+}
+
+/// Colors the logo shows the driver's status in, in place of whatever the
+/// profile puts on it. Each is off unless it's given a color.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct LogoStatusColors
+{
+	/// whilst macro recording is on
+	pub macro_recording: Option<Color>,
+	/// after the config failed to reload, until it next loads
+	pub config_error: Option<Color>,
+	/// breathes whilst a macro is running
+	pub macro_running: Option<Color>
+}
+
+impl Default for IndicatorColors
+{
+	fn default() -> Self
+	{
+		let red = Color::new(255, 0, 0).into();
+
+		Self
+		{
+			muted: red,
+			paused: red,
+			no_media: Color::black().into(),
+			volume: Color::new(255, 255, 255).into(),
+			volume_muted: red,
+			macro_running: red,
+			macro_confirm: Color::new(255, 191, 0).into(),
+			game_mode: red,
+			macro_hint: Color::new(255, 255, 255).into()
+		}
+	}
+}
+
+/// How an indicator blinks: on for `interval` ms then off for as long, with
+/// the cycle shifted by `phase` ms. An interval of 0 is lit steadily.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Blink
+{
+	pub interval: u64,
+	#[serde(default)]
+	pub phase: u64
+}
+
+impl Blink
+{
+	const fn new(interval: u64) -> Self
+	{
+		Self { interval, phase: 0 }
+	}
+
+	/// Whether the indicator is lit at a point (in ms) of the blink clock
+	pub fn is_on(&self, clock: u64) -> bool
+	{
+		match self.interval
+		{
+			0 => true,
+			interval => ((clock + self.phase) / interval) % 2 == 0
+		}
+	}
+}
+
+/// How fast each blinking indicator blinks. Any that aren't in the config keep
+/// their defaults.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BlinkRates
+{
+	/// the G-key of a running macro
+	pub macro_running: Blink,
+	/// a G-key waiting to be pressed again to confirm its macro
+	pub macro_confirm: Blink,
+	/// the MR key whilst recording, lit steadily by default
+	pub macro_recording: Blink,
+	/// the active mode's led whilst the profile is locked
+	pub profile_locked: Blink,
+	/// the leds of other modes with macros still running
+	pub background_macros: Blink,
+	/// the pattern of leds showing a virtual mode
+	pub virtual_mode: Blink
+}
+
+impl Default for BlinkRates
+{
+	fn default() -> Self
+	{
+		Self
+		{
+			macro_running: Blink::new(400),
+			macro_confirm: Blink::new(400),
+			macro_recording: Blink::new(0),
+			profile_locked: Blink::new(400),
+			background_macros: Blink::new(400),
+			virtual_mode: Blink::new(250)
+		}
+	}
+}
+
+/// Whether each blinking indicator is lit, at one point of the blink clock
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlinkStates
+{
+	pub macro_running: bool,
+	pub macro_confirm: bool,
+	pub macro_recording: bool,
+	pub profile_locked: bool,
+	pub background_macros: bool,
+	pub virtual_mode: bool
+}
+
+impl BlinkRates
+{
+	pub fn states(&self, clock: u64) -> BlinkStates
+	{
+		BlinkStates
+		{
+			macro_running: self.macro_running.is_on(clock),
+			macro_confirm: self.macro_confirm.is_on(clock),
+			macro_recording: self.macro_recording.is_on(clock),
+			profile_locked: self.profile_locked.is_on(clock),
+			background_macros: self.background_macros.is_on(clock),
+			virtual_mode: self.virtual_mode.is_on(clock)
+		}
+	}
+}
+
+/// Settings for one keyboard, found by its serial number, for when more than one
+/// is connected
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeviceConfiguration
+{
+	/// used in place of the default profile, when no other profile matches
+	pub default_profile: Option<String>,
+	/// shown instead of the active profile's theme
+	pub theme: Option<String>,
+	/// subsystems switched off for just this keyboard
+	#[serde(default)]
+	pub disabled: Vec<Subsystem>
+}
+
+/// Just the named colors from a configuration, which have to be known before
+/// the rest of it can be deserialized
+#[derive(Deserialize)]
+struct ColorPalette
+{
+	#[serde(default)]
+	colors: NamedColors
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Configuration
+{
+	#[serde(default)]
+	pub colors: NamedColors,
+	// profiles and themes can also come from the include folders
+	#[serde(default)]
+	pub profiles: HashMap<String, Profile>,
+	#[serde(default)]
+	pub themes: HashMap<String, Theme>,
+	#[serde(default = "keygroups::builtin_keygroups", deserialize_with = "keygroups::deserialize")]
+	pub keygroups: Keygroups,
+	pub gkey_sets: Option<HashMap<String, HashMap<u8, MacroKeyAssignment>>>,
+	pub macros: Option<HashMap<String, Macro>>,
+	#[serde(default)]
+	pub playlists: HashMap<String, Playlist>,
+	#[serde(default)]
+	pub lighting: LightingOptions,
+	#[serde(default)]
+	pub media: MediaOptions,
+	#[serde(default)]
+	pub indicators: IndicatorColors,
+	#[serde(default)]
+	pub blink: BlinkRates,
+	#[serde(default)]
+	pub gestures: GestureTimings,
+	/// off unless given
+	pub macro_hints: Option<MacroHints>,
+	#[serde(default)]
+	pub macro_timing: MacroTiming,
+	#[serde(default)]
+	pub logo_status: LogoStatusColors,
+	#[serde(default)]
+	pub focus_mode: FocusMode,
+	pub audio_focus: Option<AudioFocus>,
+	/// checked in order, every matching flash is shown
+	#[serde(default)]
+	pub notification_flashes: Vec<NotificationFlash>,
+	#[serde(default)]
+	pub light_key: LightKeyBindings,
+	#[serde(default)]
+	pub layout: LayoutOverrides,
+	// loaded from the layout overrides file, rather than the config itself
+	#[serde(skip)]
+	pub layout_file: LayoutOverrides,
+	// what was merged in from the include folders
+	#[serde(skip)]
+	pub included: includes::Included,
+	#[serde(default)]
+	pub gkey_mode: GkeyMode,
+	/// modes after the last mode key, reached by pressing it again (or over
+	/// dbus), so profiles can have more banks of gkeys
+	#[serde(default)]
+	pub virtual_modes: u8,
+	/// only read at startup
+	pub http: Option<HttpOptions>,
+	/// warms the lighting at night
+	pub night_mode: Option<NightMode>,
+	/// reads commands from a named pipe in the runtime folder, only read at startup
+	#[serde(default)]
+	pub command_pipe: bool,
+	/// per keyboard settings, by serial number
+	#[serde(default)]
+	pub devices: HashMap<String, DeviceConfiguration>,
+	/// count key presses for heatmap themes, off unless enabled
+	#[serde(default)]
+	pub key_statistics: bool,
+	#[serde(default)]
+	pub hooks: Hooks
+}
+
+impl Configuration
+{
+	/// how many old versions of the config file to keep when saving
+	const BACKUP_COUNT: usize = 10;
+	/// the commented example config, written on first run
+	pub const DEFAULT: &'static str = include_str!("../../config.default.yml");
+
+	/// how many mode keys the keyboard has
+	pub const MODE_KEYS: u8 = 3;
+	/// as many as the mode leds can show, counting in binary
+	pub const MAX_VIRTUAL_MODES: u8 = 7;
+
+	/// The modes profiles can set up: one for each mode key, then any virtual modes
+	pub fn modes(&self) -> std::ops::RangeInclusive<u8>
+	{
+		1..=Self::MODE_KEYS + self.virtual_modes
+	}
+
+	pub const fn config_filename() -> &'static str
+	{
+		"config.yml"
+	}
+
+	pub fn config_folder() -> PathBuf
+	{
+		let mut config_home = std::env::var("XDG_CONFIG_HOME").unwrap_or_else(|_| "".to_string());
+
+		if config_home.is_empty()
+		{
+			let home = std::env::var("HOME").expect("$XDG_CONFIG_HOME is not defined, and \
+				neither is $HOME. No idea where to find your config.yml file!");
+			config_home = format!("{}/.config", home);
+		}
+
+		let mut path = PathBuf::from(config_home);
+		path.push("g815d");
+
+		// it won't exist yet on first run
+		if !path.exists()
+		{
+			std::fs::create_dir_all(&path).expect("unable to create the config folder");
+		}
+
+		std::fs::canonicalize(path)
+			.expect("unable to convert config file location to an absolute path")
+	}
+
+	pub fn file_path() -> PathBuf
+	{
+		let mut path = Self::config_folder();
+		path.push(Self::config_filename());
+		path
+	}
+
+	pub fn load() -> Result<Self, ConfigError>
+	{
+		let mut config = std::fs::read_to_string(Self::file_path())
+			.map_err(ConfigError::UnableToOpen)
+			.and_then(|yaml_string| Self::parse(&yaml_string))?;
+
+		if !config.included.profiles.is_empty() || !config.included.themes.is_empty()
+		{
+			log::debug!("included profiles {:?} and themes {:?}", config.included.profiles, config.included.themes);
+		}
+
+		config.layout_file = LayoutOverrides::load().unwrap_or_else(|config_error|
+		{
+			log::warn!("unable to load the layout overrides file, ignoring it: {}", config_error);
+			LayoutOverrides::default()
+		});
+
+		Ok(config)
+	}
+
+	/// Writes the default config if there isn't a config file yet, returning
+	/// whether it did
+	pub fn write_default_if_missing() -> Result<bool, ConfigError>
+	{
+		let path = Self::file_path();
+
+		match path.exists()
+		{
+			true => Ok(false),
+			false => Self::write_atomically(&path, Self::DEFAULT.as_bytes())
+				.map(|()| true)
+				.map_err(ConfigError::UnableToWrite)
+		}
+	}
+
+	/// The layout overrides to use: the config's, then any in the layout
+	/// overrides file that the config doesn't override itself
+	pub fn layout(&self) -> LayoutOverrides
+	{
+		self.layout_file.merge(&self.layout)
+	}
+
+	/// Parses a configuration from a yaml string, merges in the include folders
+	/// and validates the result
+	fn parse(yaml_string: &str) -> Result<Self, ConfigError>
+	{
+		let palette: ColorPalette = serde_yaml::from_str(yaml_string)
+			.map_err(ConfigError::ParseError)?;
+
+		// a name that is also a hex code (or contains a brightness modifier)
+		// could never be referenced
+		if let Some(name) = palette.colors
+			.keys()
+			.find(|name| name.contains('@') || name.len() == 6 && name.chars().all(|c| c.is_ascii_hexdigit()))
+		{
+			return Err(ConfigError::InvalidConfiguration(format!(
+				"color name '{}' looks like a hex code or contains '@'", name)))
+		}
+
+		Color::with_named_colors(&palette.colors, || serde_yaml::from_str(yaml_string))
+			.map_err(ConfigError::ParseError)
+			.and_then(|mut config: Configuration|
+			{
+				includes::merge(&mut config)?;
+				let problems = validate::validate(&config);
+
+				match problems.is_empty()
+				{
+					true =>
+					{
+						for conflict in validate::conflicts(&config)
+						{
+							log::info!("{}", conflict);
+						}
+
+						Ok(config)
+					},
+					false => Err(ConfigError::ValidationFailed(problems))
+				}
+			})
+	}
+
+	/// Everything wrong with the config's references, as checked when it's
+	/// loaded, so changes can be checked before they're saved
+	#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+	pub fn problems(&self) -> Vec<String>
+	{
+		validate::validate(self)
+	}
+
+	/// Saves the config file, backing up the previous version first. The file is
+	/// written atomically so a crash can never leave a half written config behind.
+	/// Profiles and themes from the include folders stay in their own files.
+	#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+	pub fn save(&self) -> Result<(), ConfigError>
+	{
+		// colors are kept as they were written, i.e. `red` rather than ff0000
+		let mut value = Color::with_spellings(|| serde_yaml::to_value(self)).map_err(ConfigError::SerializeError)?;
+
+		for (key, names) in &[("profiles", &self.included.profiles), ("themes", &self.included.themes)]
+		{
+			if let Some(items) = value.get_mut(*key).and_then(|items| items.as_mapping_mut())
+			{
+				names.iter().for_each(|name| { items.remove(&serde_yaml::Value::from(name.as_str())); });
+			}
+		}
+
+		let yaml_string = serde_yaml::to_string(&value).map_err(ConfigError::SerializeError)?;
+
+		Self::backup()?;
+		Self::write_atomically(&Self::file_path(), yaml_string.as_bytes())
+			.map_err(ConfigError::UnableToWrite)
+	}
+
+	/// Writes to a temporary file next to the target, syncs it to disk and then
+	/// renames it over the target
+	pub(crate) fn write_atomically(path: &Path, contents: &[u8]) -> std::io::Result<()>
+	{
+		let file_name = path
+			.file_name()
+			.and_then(|file_name| file_name.to_str())
+			.unwrap_or_else(|| Self::config_filename());
+
+		let mut temp_path = path.to_path_buf();
+		temp_path.set_file_name(format!(".{}.tmp", file_name));
+
+		let mut file = fs::File::create(&temp_path)?;
+		file.write_all(contents)?;
+		file.sync_all()?;
+		fs::rename(&temp_path, path)?;
+
+		// sync the folder too, so the rename itself is on disk
+		if let Some(folder) = path.parent()
+		{
+			fs::File::open(folder)?.sync_all()?;
+		}
+
+		Ok(())
+	}
+
+	pub fn backup_folder() -> PathBuf
+	{
+		let mut path = Self::config_folder();
+		path.push("backups");
+		path
+	}
+
+	/// Lists the names of all config backups, newest first
+	pub fn backups() -> Result<Vec<String>, ConfigError>
+	{
+		let entries = match fs::read_dir(Self::backup_folder())
+		{
+			Ok(entries) => entries,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+			Err(error) => return Err(ConfigError::UnableToOpen(error))
+		};
+
+		let prefix = format!("{}.", Self::config_filename());
+		let mut backups: Vec<String> = entries
+			.filter_map(|entry| entry.ok())
+			.filter_map(|entry| entry.file_name().into_string().ok())
+			.filter(|name| name.starts_with(&prefix))
+			.collect();
+
+		// backups are suffixed with a timestamp, so sorting by length then name
+		// sorts them by age
+		backups.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| b.cmp(a)));
+		Ok(backups)
+	}
+
+	/// Copies the current config file into the backup folder (if there is one),
+	/// removing the oldest backups so only BACKUP_COUNT are kept
+	fn backup() -> Result<(), ConfigError>
+	{
+		let current = match fs::read(Self::file_path())
+		{
+			Ok(current) => current,
+			Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+			Err(error) => return Err(ConfigError::UnableToOpen(error))
+		};
+
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|duration| duration.as_millis())
+			.unwrap_or(0);
+
+		let mut backup_path = Self::backup_folder();
+		fs::create_dir_all(&backup_path).map_err(ConfigError::UnableToWrite)?;
+		backup_path.push(format!("{}.{}", Self::config_filename(), timestamp));
+		Self::write_atomically(&backup_path, &current).map_err(ConfigError::UnableToWrite)?;
+
+		for old_backup in Self::backups()?.iter().skip(Self::BACKUP_COUNT)
+		{
+			let mut old_backup_path = Self::backup_folder();
+			old_backup_path.push(old_backup);
+			fs::remove_file(old_backup_path).map_err(ConfigError::UnableToWrite)?;
+		}
+
+		Ok(())
+	}
+
+	/// Replaces the config file with a backup (the newest one if no name is given).
+	/// The current config file is backed up first, so a restore can be undone.
+	/// The backup must be a valid configuration to be restored.
+	pub fn restore_backup(name: Option<&str>) -> Result<String, ConfigError>
+	{
+		let backups = Self::backups()?;
+		let name = match name
+		{
+			Some(name) => backups
+				.iter()
+				.find(|backup| backup.as_str() == name)
+				.ok_or_else(|| ConfigError::BackupNotFound(name.to_string()))?,
+			None => backups
+				.first()
+				.ok_or_else(|| ConfigError::BackupNotFound("(newest)".to_string()))?
+		};
+
+		let mut backup_path = Self::backup_folder();
+		backup_path.push(name);
+
+		let contents = fs::read_to_string(&backup_path).map_err(ConfigError::UnableToOpen)?;
+		Self::parse(&contents)?;
+
+		Self::backup()?;
+		Self::write_atomically(&Self::file_path(), contents.as_bytes())
+			.map_err(ConfigError::UnableToWrite)?;
+
+		Ok(name.clone())
+	}
+
+	pub fn default_profile(&self) -> &Profile
+	{
+		self.profiles.get("default").unwrap()
+	}
+
+	/// The profile a keyboard shows when the default profile is active
+	pub fn default_profile_for_device(&self, serial_number: &str) -> &Profile
+	{
+		self.devices
+			.get(serial_number)
+			.and_then(|device| device.default_profile.as_ref())
+			.and_then(|name| self.profiles.get(name))
+			.unwrap_or_else(|| self.default_profile())
+	}
+
+	pub fn default_theme(&self) -> &Theme
+	{
+		self.themes.get("default").unwrap()
+	}
+
+	/// The profile whose conditions match, checking profiles with window or
+	/// player conditions before ones with only a schedule, so a profile for the
+	/// evening doesn't hide the profiles for particular windows
+	pub fn profile_for_active_window(&self, window: &Option<ActiveWindowInfo>, media: &MediaState)
+		-> (&str, &Profile)
+	{
+		let time = LocalTime::now();
+		let only_scheduled = |conditions: &ActiveWindowConditions| !conditions.has_window_conditions()
+			&& conditions.player.is_none();
+
+		let matching = |schedule_only: bool| self.profiles
+			.iter()
+			.filter(|(name, _profile)| name.as_str() != "default")
+			.find_map(|(name, profile)| profile.conditions
+				.as_ref()
+				.filter(|conditions| only_scheduled(conditions) == schedule_only)
+				.and_then(|conditions| conditions
+					.matches(window.as_ref(), media, time)
+					.then(|| (name.as_str(), profile))));
+
+		matching(false)
+			.or_else(|| matching(true))
+			.unwrap_or_else(|| ("default", self.default_profile()))
+	}
+
+	/// The profiles with a schedule that the time is in, sorted, so the main
+	/// loop can tell when a schedule starts or ends
+	pub fn scheduled_profiles(&self, time: Option<LocalTime>) -> Vec<String>
+	{
+		let mut names: Vec<String> = self.profiles
+			.iter()
+			.filter(|(_name, profile)| profile.conditions
+				.as_ref()
+				.and_then(|conditions| conditions.schedule.as_ref())
+				.zip(time)
+				.map(|(schedule, time)| schedule.contains(time))
+				.unwrap_or(false))
+			.map(|(name, _profile)| name.clone())
+			.collect();
+
+		names.sort();
+		names
+	}
+
+	pub fn gkey_set_assignment(&self, gkey_set: &str, key: u8) -> Option<&MacroKeyAssignment>
+	{
+		self.gkey_sets
+			.as_ref()
+			.and_then(|gkey_sets| gkey_sets
+				.get(gkey_set)
+				.and_then(|gkey_set| gkey_set.get(&key)))
+	}
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ActiveWindowConditions
+{
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub title: Option<Regex>,
+
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub executable: Option<Regex>,
+
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub class: Option<Regex>,
+
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub class_name: Option<Regex>,
+
+	#[serde(default)]
+	pub workspace: Option<u32>,
+
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub workspace_name: Option<Regex>,
+
+	#[serde(default)]
+	pub monitor: Option<u32>,
+
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub monitor_name: Option<Regex>,
+
+	#[serde(default)]
+	pub fullscreen: Option<bool>,
+
+	#[serde(with = "RegexSerializer")]
+	#[serde(default)]
+	pub player: Option<Regex>,
+
+	#[serde(default)]
+	pub schedule: Option<Schedule>
+}
+
+impl ActiveWindowConditions
+{
+	pub fn has_window_conditions(&self) -> bool
+	{
+		self.title.is_some()
+			|| self.executable.is_some()
+			|| self.class.is_some()
+			|| self.class_name.is_some()
+			|| self.workspace.is_some()
+			|| self.workspace_name.is_some()
+			|| self.monitor.is_some()
+			|| self.monitor_name.is_some()
+			|| self.fullscreen.is_some()
+	}
+
+	/// Checks all specified conditions against the active window, media state
+	/// and local time. If no conditions are specified at all, this never matches.
+	pub fn matches(&self, window: Option<&ActiveWindowInfo>, media: &MediaState, time: Option<LocalTime>) -> bool
+	{
+		if !self.has_window_conditions() && self.player.is_none() && self.schedule.is_none()
+		{
+			return false
+		}
+
+		// without the time, it's never in the schedule
+		let scheduled = match (&self.schedule, time)
+		{
+			(Some(schedule), Some(time)) => schedule.contains(time),
+			(Some(_schedule), None) => false,
+			(None, _) => true
+		};
+
+		let window_matches = !self.has_window_conditions() || window
+			.map(|window| window.matches_conditions(self))
+			.unwrap_or(false);
+
+		// the player condition only matches whilst that player is actually playing
+		let player_matches = self.player
+			.as_ref()
+			.map(|regex| media.player_status == PlayerStatus::Playing && media.player
+				.as_ref()
+				.map(|player| regex.is_match(player))
+				.unwrap_or(false))
+			.unwrap_or(true);
+
+		window_matches && player_matches && scheduled
+	}
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone)]
+struct RegexWrapper<T>(T);
+
+impl Serialize for RegexWrapper<&Regex>
+{
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer
+	{
+		serializer.serialize_str(self.0.as_str())
+	}
+}
+
+impl Serialize for RegexWrapper<&Option<Regex>>
+{
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer
+	{
+		match self.0
+		{
+			Some(ref regex) => serializer.serialize_some(&RegexWrapper(regex)),
+			None => serializer.serialize_none()
+		}
+	}
+}
+
+impl Serialize for RegexWrapper<&Vec<Regex>>
+{
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer
+	{
+		serializer.collect_seq(self.0.iter().map(RegexWrapper))
+	}
+}
+
+impl<'de> Deserialize<'de> for RegexWrapper<Regex>
+{
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>
+	{
+		match <std::borrow::Cow<str>>::deserialize(deserializer)?.parse()
+		{
+			Ok(regex) => Ok(RegexWrapper(regex)),
+			Err(e) => Err(D::Error::custom(e))
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for RegexWrapper<Option<Regex>>
+{
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>
+	{
+		Ok(match Option::<RegexWrapper<Regex>>::deserialize(deserializer)?
+		{
+			Some(RegexWrapper(regex)) => RegexWrapper(Some(regex)),
+			None => RegexWrapper(None),
+		})
+	}
+}
+
+impl<'de> Deserialize<'de> for RegexWrapper<Vec<Regex>>
+{
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: Deserializer<'de>
+	{
+		Ok(RegexWrapper(Vec::<RegexWrapper<Regex>>::deserialize(deserializer)?
+			.into_iter()
+			.map(|wrapper| wrapper.0)
+			.collect()))
+	}
+}
+
+struct RegexSerializer;
+
+impl RegexSerializer
+{
+	pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: Serializer,
+		for<'a> RegexWrapper<&'a T>: Serialize
+	{
+		RegexWrapper(value).serialize(serializer)
+	}
+
+	pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+	where
+		D: Deserializer<'de>,
+		RegexWrapper<T>: Deserialize<'de>
+	{
+		RegexWrapper::deserialize(deserializer).map(|wrapper| wrapper.0)
+	}
+}
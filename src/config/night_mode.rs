@@ -0,0 +1,180 @@
+use serde::{Serialize, Deserialize};
+
+use super::playlist::TimeWindow;
+use crate::device::color::Color;
+
+/// Warms every key's color at night, like redshift does for screens. Night is
+/// either between two local times, or from sunset to sunrise where the
+/// keyboard is.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NightMode
+{
+	/// the color temperature at night in kelvin, lower is warmer (6500 leaves
+	/// colors as they are)
+	#[serde(default = "NightMode::default_temperature")]
+	pub temperature: u16,
+	/// minutes to fade in over at the start of the night, and out before its end
+	#[serde(default = "NightMode::default_transition")]
+	pub transition: u16,
+	/// fixed times for the night, i.e. `["21:00", "07:00"]`
+	pub between: Option<TimeWindow>,
+	/// where to work out sunset and sunrise for, in degrees (north and east
+	/// are positive), used when `between` isn't given
+	pub latitude: Option<f64>,
+	pub longitude: Option<f64>
+}
+
+/// When night falls and ends on a day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Night
+{
+	Between(TimeWindow),
+	/// the sun doesn't rise (i.e. a polar night)
+	AllDay,
+	/// the sun doesn't set
+	None
+}
+
+impl NightMode
+{
+	const MINUTES_PER_DAY: f64 = 1440_f64;
+
+	fn default_temperature() -> u16
+	{
+		3400
+	}
+
+	fn default_transition() -> u16
+	{
+		30
+	}
+
+	/// The night on a day (numbered from 0 for the 1st of January), given the
+	/// local timezone's offset from UTC in minutes
+	pub fn night(&self, day_of_year: u16, utc_offset: i32) -> Night
+	{
+		match (self.between, self.latitude, self.longitude)
+		{
+			(Some(window), _, _) => Night::Between(window),
+			(None, Some(latitude), Some(longitude)) => sun_times(latitude, longitude, day_of_year)
+				.map(|(sunrise, sunset)|
+				{
+					let local = |minutes: f64| (minutes + utc_offset as f64)
+						.rem_euclid(Self::MINUTES_PER_DAY)
+						.round() as u16 % Self::MINUTES_PER_DAY as u16;
+
+					Night::Between(TimeWindow::new(local(sunset), local(sunrise)))
+				})
+				.unwrap_or_else(|polar_night| match polar_night
+				{
+					true => Night::AllDay,
+					false => Night::None
+				}),
+			_ => Night::None
+		}
+	}
+
+	/// The color temperature at a local time (in minutes since midnight), or
+	/// None whilst it's day
+	pub fn temperature_at(&self, night: Night, minutes: u16) -> Option<u16>
+	{
+		let strength = match night
+		{
+			Night::Between(window) => match window.position(minutes)
+			{
+				Some((since_start, until_end)) => match self.transition
+				{
+					0 => 1_f64,
+					transition => (since_start.min(until_end) as f64 / transition as f64).min(1_f64)
+				},
+				None => return None
+			},
+			Night::AllDay => 1_f64,
+			Night::None => return None
+		};
+
+		let daylight = Color::DAYLIGHT as f64;
+		Some((daylight - (daylight - self.temperature as f64) * strength).round() as u16)
+	}
+}
+
+/// When the sun rises and sets on a day, in minutes since midnight UTC (which
+/// can be outside 0 - 1440, i.e. a sunset after midnight UTC), using NOAA's
+/// approximation. When it doesn't do either, whether it's a polar night.
+fn sun_times(latitude: f64, longitude: f64, day_of_year: u16) -> Result<(f64, f64), bool>
+{
+	// the fraction of the year, in radians
+	let year = 2_f64 * std::f64::consts::PI / 365_f64 * day_of_year as f64;
+
+	// in minutes
+	let equation_of_time = 229.18 * (0.000075 + 0.001868 * year.cos() - 0.032077 * year.sin()
+		- 0.014615 * (2_f64 * year).cos() - 0.040849 * (2_f64 * year).sin());
+
+	// the sun's declination, in radians
+	let declination = 0.006918 - 0.399912 * year.cos() + 0.070257 * year.sin()
+		- 0.006758 * (2_f64 * year).cos() + 0.000907 * (2_f64 * year).sin()
+		- 0.002697 * (3_f64 * year).cos() + 0.00148 * (3_f64 * year).sin();
+
+	let latitude = latitude.to_radians();
+
+	// the sun's centre is 0.833 degrees below the horizon at sunrise and
+	// sunset, allowing for refraction and the size of its disc
+	let cos_hour_angle = 90.833_f64.to_radians().cos() / (latitude.cos() * declination.cos())
+		- latitude.tan() * declination.tan();
+
+	match cos_hour_angle
+	{
+		cos_hour_angle if cos_hour_angle > 1_f64 => Err(true),
+		cos_hour_angle if cos_hour_angle < -1_f64 => Err(false),
+		cos_hour_angle =>
+		{
+			let hour_angle = cos_hour_angle.acos().to_degrees();
+
+			Ok((720_f64 - 4_f64 * (longitude + hour_angle) - equation_of_time,
+				720_f64 - 4_f64 * (longitude - hour_angle) - equation_of_time))
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn parse(yaml: &str) -> NightMode
+	{
+		serde_yaml::from_str(yaml).unwrap()
+	}
+
+	#[test]
+	fn nights_fade_in_and_out()
+	{
+		let night_mode = parse("{temperature: 3500, transition: 60, between: ['22:00', '06:00']}");
+		let night = night_mode.night(0, 0);
+
+		assert_eq!(night_mode.temperature_at(night, 21 * 60), None);
+		assert_eq!(night_mode.temperature_at(night, 22 * 60 + 30), Some(5000));
+		assert_eq!(night_mode.temperature_at(night, 2 * 60), Some(3500));
+		assert_eq!(night_mode.temperature_at(night, 5 * 60 + 45), Some(5750));
+		assert_eq!(night_mode.temperature_at(night, 6 * 60), None);
+	}
+
+	#[test]
+	fn nights_follow_the_sun()
+	{
+		// London, in the middle of june and december
+		let night_mode = parse("{latitude: 51.5, longitude: -0.13}");
+		let summer = night_mode.night(171, 60);
+		let winter = night_mode.night(354, 0);
+
+		assert!(night_mode.temperature_at(summer, 21 * 60).is_none());
+		assert!(night_mode.temperature_at(summer, 21 * 60 + 45).is_some());
+		assert!(night_mode.temperature_at(winter, 15 * 60 + 30).is_none());
+		assert!(night_mode.temperature_at(winter, 16 * 60 + 30).is_some());
+
+		// the arctic circle
+		let night_mode = parse("{latitude: 78.2, longitude: 15.6}");
+		assert_eq!(night_mode.night(171, 120), Night::None);
+		assert_eq!(night_mode.night(354, 60), Night::AllDay);
+	}
+}
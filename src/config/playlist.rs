@@ -0,0 +1,99 @@
+use std::convert::TryFrom;
+
+use serde::{Serialize, Deserialize};
+
+/// A list of themes that profiles can rotate through, each shown for a while
+/// before moving on to the next
+pub type Playlist = Vec<PlaylistEntry>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaylistEntry
+{
+	pub theme: String,
+	/// how long the theme is shown for, in seconds
+	pub duration: u64,
+	/// only shown between these local times
+	pub between: Option<TimeWindow>
+}
+
+/// A daily window of local time, written as `["09:00", "17:30"]`. The end can
+/// be before the start, for a window that runs past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "(String, String)", into = "(String, String)")]
+pub struct TimeWindow
+{
+	/// minutes since midnight
+	start: u16,
+	end: u16
+}
+
+impl TimeWindow
+{
+	pub fn new(start: u16, end: u16) -> Self
+	{
+		Self { start, end }
+	}
+
+	/// Whether a time (in minutes since midnight) is in the window
+	pub fn contains(&self, minutes: u16) -> bool
+	{
+		match self.start <= self.end
+		{
+			true => (self.start..self.end).contains(&minutes),
+			false => minutes >= self.start || minutes < self.end
+		}
+	}
+
+	/// How many minutes a time is into the window, and how many are left of
+	/// it, or None if the time is outside the window
+	pub fn position(&self, minutes: u16) -> Option<(u16, u16)>
+	{
+		let since = |from: u16, to: u16| (to + 1440 - from) % 1440;
+
+		match self.contains(minutes)
+		{
+			true => Some((since(self.start, minutes), since(minutes, self.end))),
+			false => None
+		}
+	}
+
+	fn parse_time(time: &str) -> Result<u16, String>
+	{
+		let mut parts = time.trim().splitn(2, ':');
+		let hours = parts.next().and_then(|hours| hours.parse::<u16>().ok());
+		let minutes = parts.next().and_then(|minutes| minutes.parse::<u16>().ok());
+
+		match (hours, minutes)
+		{
+			(Some(hours), Some(minutes)) if hours < 24 && minutes < 60 => Ok(hours * 60 + minutes),
+			_ => Err(format!("'{}' isn't a time, times are written as HH:MM (i.e. 17:30)", time))
+		}
+	}
+
+	fn format_time(minutes: u16) -> String
+	{
+		format!("{:02}:{:02}", minutes / 60, minutes % 60)
+	}
+}
+
+impl TryFrom<(String, String)> for TimeWindow
+{
+	type Error = String;
+
+	fn try_from((start, end): (String, String)) -> Result<Self, Self::Error>
+	{
+		Ok(Self
+		{
+			start: Self::parse_time(&start)?,
+			end: Self::parse_time(&end)?
+		})
+	}
+}
+
+impl From<TimeWindow> for (String, String)
+{
+	fn from(window: TimeWindow) -> Self
+	{
+		(TimeWindow::format_time(window.start), TimeWindow::format_time(window.end))
+	}
+}
@@ -0,0 +1,682 @@
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::device::scancode::Scancode;
+use crate::device::MediaKey;
+use crate::device::rgb::Theme;
+use crate::macros::Macro;
+
+use super::{Configuration, Profile, ModeProfile, MacroKeyAssignment, GkeySets, GkeyAssignments,
+	NotificationIndicator, Gesture, GkeyGestures};
+
+/// One of the places a gkey is assigned, see `ProfileResolver::gkey_sources`
+#[derive(Debug, Clone)]
+pub struct GkeySource<'a>
+{
+	/// the mode the assignment is made in, None for the profile itself
+	pub mode: Option<u8>,
+	/// the gkey set it comes from, None for the profile's (or mode's) own gkeys
+	pub gkey_set: Option<&'a str>,
+	pub assignment: &'a MacroKeyAssignment
+}
+
+impl fmt::Display for GkeySource<'_>
+{
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result
+	{
+		match (self.mode, self.gkey_set)
+		{
+			(None, None) => write!(f, "gkeys"),
+			(None, Some(gkey_set)) => write!(f, "gkey set '{}'", gkey_set),
+			(Some(mode), None) => write!(f, "mode {} gkeys", mode),
+			(Some(mode), Some(gkey_set)) => write!(f, "mode {} gkey set '{}'", mode, gkey_set)
+		}
+	}
+}
+
+pub(super) trait ProfileKeyAssignment
+{
+	fn gkey_sets(&self) -> &GkeySets;
+	fn gkeys(&self) -> &GkeyAssignments;
+
+	/// A gkey's own assignment takes priority over the gkey sets
+	fn gkey_assignment<'a>(&'a self, config: &'a Configuration, key: u8) -> Option<&'a MacroKeyAssignment>
+	{
+		self.gkeys()
+			.as_ref()
+			.and_then(|gkey_assignments| gkey_assignments.get(&key))
+			.or_else(|| self.gkey_set_assignment(config, key))
+	}
+
+	/// Later gkey sets take priority over earlier ones
+	fn gkey_set_assignment<'a>(&'a self, config: &'a Configuration, key: u8) -> Option<&'a MacroKeyAssignment>
+	{
+		self.gkey_sets().as_ref().and_then(|gkey_sets| gkey_sets
+			.iter()
+			.rev()
+			.find_map(|gkey_set_name| config.gkey_set_assignment(gkey_set_name, key)))
+	}
+
+	/// Every assignment of a gkey at this level (the profile or one of its
+	/// modes), the one that's used first
+	fn gkey_sources<'a>(&'a self, config: &'a Configuration, key: u8, mode: Option<u8>) -> Vec<GkeySource<'a>>
+	{
+		let gkeys = self.gkeys()
+			.as_ref()
+			.and_then(|gkey_assignments| gkey_assignments.get(&key))
+			.map(|assignment| GkeySource { mode, gkey_set: None, assignment });
+
+		let gkey_sets = self.gkey_sets()
+			.iter()
+			.flatten()
+			.rev()
+			.filter_map(|gkey_set_name| config
+				.gkey_set_assignment(gkey_set_name, key)
+				.map(|assignment| GkeySource { mode, gkey_set: Some(gkey_set_name), assignment }));
+
+		gkeys.into_iter().chain(gkey_sets).collect()
+	}
+}
+
+impl ProfileKeyAssignment for Profile
+{
+	fn gkey_sets(&self) -> &GkeySets
+	{
+		&self.gkey_sets
+	}
+
+	fn gkeys(&self) -> &GkeyAssignments
+	{
+		&self.gkeys
+	}
+}
+
+impl ProfileKeyAssignment for ModeProfile
+{
+	fn gkey_sets(&self) -> &GkeySets
+	{
+		&self.gkey_sets
+	}
+
+	fn gkeys(&self) -> &GkeyAssignments
+	{
+		&self.gkeys
+	}
+}
+
+/// Works out what a profile actually means for a device in a given mode, all of
+/// the precedence rules between profiles, modes, gkey sets and themes live here:
+///
+/// * themes: the mode's theme, then the profile's theme, then the default theme
+/// * gkeys: the mode's gkeys, then the mode's gkey sets, then the profile's gkeys,
+///   then the profile's gkey sets. Within a list of gkey sets, later sets win.
+pub struct ProfileResolver<'a>
+{
+	config: &'a Configuration,
+	profile: &'a Profile,
+	mode: u8
+}
+
+impl<'a> ProfileResolver<'a>
+{
+	pub fn new(config: &'a Configuration, profile: &'a Profile, mode: u8) -> Self
+	{
+		Self { config, profile, mode }
+	}
+
+	fn mode_profile(&self) -> Option<&'a ModeProfile>
+	{
+		self.profile.modes
+			.as_ref()
+			.and_then(|modes| modes.get(&self.mode))
+	}
+
+	/// The name of the theme to show, if the profile (or mode) chooses one
+	pub fn theme_name(&self) -> Option<&'a str>
+	{
+		self.mode_profile()
+			.and_then(|mode_profile| mode_profile.theme.as_deref())
+			.or_else(|| self.profile.theme.as_deref())
+	}
+
+	/// The theme to show. A theme name that doesn't exist falls back to the
+	/// default theme.
+	pub fn theme(&self) -> &'a Theme
+	{
+		self.theme_name()
+			.and_then(|theme_name| self.config.themes.get(theme_name))
+			.unwrap_or_else(|| self.config.default_theme())
+	}
+
+	/// The theme to show on the logo, if the profile (or mode) gives it its own.
+	/// Otherwise the logo is lit by the main theme.
+	pub fn logo_theme(&self) -> Option<&'a Theme>
+	{
+		self.mode_profile()
+			.and_then(|mode_profile| mode_profile.logo_theme.as_deref())
+			.or_else(|| self.profile.logo_theme.as_deref())
+			.and_then(|theme_name| self.config.themes.get(theme_name))
+	}
+
+	/// How pending notifications are shown, if the profile shows them at all
+	pub fn notifications(&self) -> Option<&'a NotificationIndicator>
+	{
+		self.profile.notifications.as_ref()
+	}
+
+	fn gkey_assignment(&self, gkey: u8) -> Option<&'a MacroKeyAssignment>
+	{
+		self.mode_profile()
+			.and_then(|mode_profile| mode_profile.gkey_assignment(self.config, gkey))
+			.or_else(|| self.profile.gkey_assignment(self.config, gkey))
+	}
+
+	/// Every assignment of a gkey in this profile and mode, in order of
+	/// precedence: the first is the one used, and overrides the rest
+	pub fn gkey_sources(&self, gkey: u8) -> Vec<GkeySource<'a>>
+	{
+		let mut sources = self.mode_profile()
+			.map(|mode_profile| mode_profile.gkey_sources(self.config, gkey, Some(self.mode)))
+			.unwrap_or_default();
+
+		sources.extend(self.profile.gkey_sources(self.config, gkey, None));
+		sources
+	}
+
+	pub fn macro_for_gkey(&self, gkey: u8) -> Option<Cow<'a, Macro>>
+	{
+		self.gkey_assignment(gkey)
+			.and_then(|assignment| assignment.expand(self.config))
+	}
+
+	/// The gkey held down to use the profile's gshift assignments
+	pub fn gshift_key(&self) -> Option<u8>
+	{
+		self.profile.gshift.as_ref().map(|gshift| gshift.key)
+	}
+
+	/// The macro bound to a gkey whilst the gshift key is held down, falling
+	/// back to its usual one
+	pub fn shifted_macro_for_gkey(&self, gkey: u8) -> Option<Cow<'a, Macro>>
+	{
+		match self.profile.gshift.as_ref().and_then(|gshift| gshift.gkeys.get(&gkey))
+		{
+			Some(assignment) => assignment.expand(self.config),
+			None => self.macro_for_gkey(gkey)
+		}
+	}
+
+	/// The macro bound to a media key whilst the gshift key is held down,
+	/// falling back to its usual one
+	pub fn shifted_macro_for_media_key(&self, key: MediaKey) -> Option<Cow<'a, Macro>>
+	{
+		match self.profile.gshift.as_ref().and_then(|gshift| gshift.media_keys.get(&key))
+		{
+			Some(assignment) => assignment.expand(self.config),
+			None => self.macro_for_media_key(key)
+		}
+	}
+
+	/// The gestures a gkey tells apart, if it has gesture assignments
+	pub fn gestures_for_gkey(&self, gkey: u8) -> Option<&'a GkeyGestures>
+	{
+		match self.gkey_assignment(gkey)
+		{
+			Some(MacroKeyAssignment::Gestures(gestures)) => Some(gestures),
+			_ => None
+		}
+	}
+
+	pub fn macro_for_gesture(&self, gkey: u8, gesture: Gesture) -> Option<Cow<'a, Macro>>
+	{
+		self.gestures_for_gkey(gkey)
+			.and_then(|gestures| gestures.get(gesture))
+			.and_then(|assignment| assignment.expand(self.config))
+	}
+
+	pub fn release_macro_for_gkey(&self, gkey: u8) -> Option<Cow<'a, Macro>>
+	{
+		self.gkey_assignment(gkey)
+			.and_then(|assignment| assignment.expand_release(self.config))
+	}
+
+	/// Gets the macro bound to a media key in this profile, if there is one. If not
+	/// the media key should keep its default behaviour.
+	pub fn macro_for_media_key(&self, key: MediaKey) -> Option<Cow<'a, Macro>>
+	{
+		self.profile.media_keys
+			.as_ref()
+			.and_then(|media_keys| media_keys.get(&key))
+			.and_then(|assignment| assignment.expand(self.config))
+	}
+
+	/// Whether anything in the profile (in any mode) is bound to a gkey
+	pub fn has_gkey_assignments(&self) -> bool
+	{
+		fn assigns(gkeys: &GkeyAssignments, gkey_sets: &GkeySets) -> bool
+		{
+			gkeys.as_ref().map(|gkeys| !gkeys.is_empty()).unwrap_or(false)
+				|| gkey_sets.as_ref().map(|sets| !sets.is_empty()).unwrap_or(false)
+		}
+
+		assigns(&self.profile.gkeys, &self.profile.gkey_sets) || self.profile.gshift.is_some() || self.profile.modes
+			.as_ref()
+			.map(|modes| modes
+				.values()
+				.any(|mode_profile| assigns(&mode_profile.gkeys, &mode_profile.gkey_sets)))
+			.unwrap_or(false)
+	}
+
+	pub fn game_mode_keys(&self) -> &'a [Scancode]
+	{
+		self.profile.game_mode_keys
+			.as_deref()
+			.unwrap_or_default()
+	}
+
+	pub fn playlist(&self) -> Option<&'a str>
+	{
+		self.profile.playlist.as_deref()
+	}
+
+	pub fn report_rate(&self) -> Option<u16>
+	{
+		self.profile.report_rate
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::collections::HashMap;
+
+	use proptest::prelude::*;
+
+	use super::*;
+
+	const BASE_CONFIG: &str = "
+themes:
+  default: []
+  profile: []
+  mode: []
+keygroups: {}
+macros:
+  profile_macro: &macro
+    activation_type: singular
+    steps:
+      - action: { debug_print: profile }
+        duration: 0
+  first: *macro
+  second: *macro
+  profile_gkey: *macro
+  mode_gkey: *macro
+gkey_sets:
+  first: { 1: first, 2: first }
+  second: { 1: second }
+profiles:
+";
+
+	fn config(profiles: &str) -> Configuration
+	{
+		Configuration::parse(&format!("{}{}", BASE_CONFIG, profiles))
+			.expect("test config should be valid")
+	}
+
+	fn assigned(config: &Configuration, profile: &str, mode: u8, gkey: u8) -> Option<String>
+	{
+		let profile = config.profiles.get(profile).unwrap();
+
+		match ProfileResolver::new(config, profile, mode).gkey_assignment(gkey)
+		{
+			Some(MacroKeyAssignment::NamedMacro(name))
+				| Some(MacroKeyAssignment::MacroWithArguments { name, .. }) => Some(name.clone()),
+			Some(MacroKeyAssignment::SimpleAction(action)) => Some(format!("{:?}", action)),
+			Some(MacroKeyAssignment::PressRelease { .. }) => Some("press/release".into()),
+			Some(MacroKeyAssignment::Gestures(_)) => Some("gestures".into()),
+			None => None
+		}
+	}
+
+	fn theme<'a>(config: &'a Configuration, profile: &str, mode: u8) -> &'a Theme
+	{
+		ProfileResolver::new(config, config.profiles.get(profile).unwrap(), mode).theme()
+	}
+
+	#[test]
+	fn theme_falls_back_to_default()
+	{
+		let config = config("  default: {}\n");
+		assert_eq!(theme(&config, "default", 1), config.themes.get("default").unwrap());
+	}
+
+	#[test]
+	fn mode_theme_overrides_profile_theme()
+	{
+		let config = config("
+  default:
+    theme: profile
+    modes:
+      2: { theme: mode }
+");
+		assert_eq!(theme(&config, "default", 1), config.themes.get("profile").unwrap());
+		assert_eq!(theme(&config, "default", 2), config.themes.get("mode").unwrap());
+		assert_eq!(theme(&config, "default", 3), config.themes.get("profile").unwrap());
+	}
+
+	#[test]
+	fn missing_theme_falls_back_to_default_theme()
+	{
+		let mut config = config("
+  default:
+    theme: profile
+    modes:
+      1: { theme: mode }
+");
+		// validation won't let a config name a missing theme, but edits can
+		config.profiles.get_mut("default").unwrap().modes.as_mut().unwrap().get_mut(&1).unwrap().theme = Some("missing".into());
+
+		assert_eq!(theme(&config, "default", 1), config.themes.get("default").unwrap());
+	}
+
+	#[test]
+	fn gkeys_override_gkey_sets()
+	{
+		let config = config("
+  default:
+    gkey_sets: [first]
+    gkeys: { 1: profile_macro }
+");
+		assert_eq!(assigned(&config, "default", 1, 1).as_deref(), Some("profile_macro"));
+		assert_eq!(assigned(&config, "default", 1, 2).as_deref(), Some("first"));
+		assert_eq!(assigned(&config, "default", 1, 3), None);
+	}
+
+	#[test]
+	fn later_gkey_sets_win()
+	{
+		let config = config("
+  default:
+    gkey_sets: [first, second]
+  reversed:
+    gkey_sets: [second, first]
+");
+		assert_eq!(assigned(&config, "default", 1, 1).as_deref(), Some("second"));
+		assert_eq!(assigned(&config, "default", 1, 2).as_deref(), Some("first"));
+		assert_eq!(assigned(&config, "reversed", 1, 1).as_deref(), Some("first"));
+	}
+
+	#[test]
+	fn mode_overrides_profile()
+	{
+		let config = config("
+  default:
+    gkeys: { 1: profile_macro, 2: profile_macro }
+    modes:
+      2:
+        gkey_sets: [second]
+");
+		assert_eq!(assigned(&config, "default", 1, 1).as_deref(), Some("profile_macro"));
+		assert_eq!(assigned(&config, "default", 2, 1).as_deref(), Some("second"));
+		assert_eq!(assigned(&config, "default", 2, 2).as_deref(), Some("profile_macro"));
+	}
+
+	#[test]
+	fn edited_assignments_are_resolved()
+	{
+		let mut config = config("  default: {}\n");
+		let profile = config.profiles.get_mut("default").unwrap();
+
+		profile.gkeys_mut(None).get_or_insert_with(HashMap::new).insert(1, MacroKeyAssignment::NamedMacro("profile_macro".into()));
+		profile.gkeys_mut(Some(3)).get_or_insert_with(HashMap::new).insert(1, MacroKeyAssignment::NamedMacro("mode_macro".into()));
+
+		assert_eq!(assigned(&config, "default", 1, 1).as_deref(), Some("profile_macro"));
+		assert_eq!(assigned(&config, "default", 3, 1).as_deref(), Some("mode_macro"));
+	}
+
+	#[test]
+	fn removed_assignments_leave_no_empty_modes()
+	{
+		let mut config = config("  default: {}\n");
+		let profile = config.profiles.get_mut("default").unwrap();
+
+		assert!(profile.existing_gkeys_mut(Some(2)).is_none());
+		assert!(profile.modes.is_none());
+
+		profile.gkeys_mut(Some(2)).get_or_insert_with(HashMap::new).insert(1, MacroKeyAssignment::NamedMacro("profile_macro".into()));
+		profile.remove_mode_if_empty(2);
+		assert!(profile.modes.is_some());
+
+		*profile.gkeys_mut(Some(2)) = None;
+		profile.remove_mode_if_empty(2);
+		assert!(profile.modes.is_none());
+	}
+
+	#[test]
+	fn gkey_sources_are_in_precedence_order()
+	{
+		let config = config("
+  default:
+    gkey_sets: [first, second]
+    gkeys: { 1: profile_macro }
+    modes:
+      2:
+        gkey_sets: [second]
+");
+		let sources = |mode: u8, gkey: u8| ProfileResolver::new(&config, config.default_profile(), mode)
+			.gkey_sources(gkey)
+			.iter()
+			.map(ToString::to_string)
+			.collect::<Vec<String>>();
+
+		assert_eq!(sources(1, 1), ["gkeys", "gkey set 'second'", "gkey set 'first'"]);
+		assert_eq!(sources(2, 1), ["mode 2 gkey set 'second'", "gkeys", "gkey set 'second'", "gkey set 'first'"]);
+		assert_eq!(sources(1, 2), ["gkey set 'first'"]);
+		assert!(sources(1, 3).is_empty());
+	}
+
+	#[test]
+	fn gkeys_expand_to_macros()
+	{
+		let mut config = config("
+  default:
+    gkeys: { 1: profile_macro, 3: { debug_print: simple } }
+");
+		config.profiles.get_mut("default").unwrap().gkeys.as_mut().unwrap().insert(2, MacroKeyAssignment::NamedMacro("missing_macro".into()));
+
+		let resolver = ProfileResolver::new(&config, config.default_profile(), 1);
+
+		assert!(matches!(resolver.macro_for_gkey(1), Some(Cow::Borrowed(_))));
+		assert!(resolver.macro_for_gkey(2).is_none());
+		assert!(matches!(resolver.macro_for_gkey(3), Some(Cow::Owned(_))));
+	}
+
+	#[test]
+	fn arguments_fill_in_macro_parameters()
+	{
+		let mut config = config("  default: {}\n");
+		config.macros.get_or_insert_with(HashMap::new).insert("greeting".into(), serde_yaml::from_str("
+activation_type: singular
+parameters: [name]
+steps:
+  - action: { type_unicode: 'hello {{name}}' }
+    duration: 0
+").unwrap());
+		config.profiles.get_mut("default").unwrap().gkeys = Some(serde_yaml::from_str("
+1: { macro: greeting, arguments: { name: world } }
+").unwrap());
+
+		let macro_ = ProfileResolver::new(&config, config.default_profile(), 1).macro_for_gkey(1).unwrap();
+		assert!(format!("{:?}", macro_.steps).contains("TypeUnicode(\"hello world\")"));
+	}
+
+	#[test]
+	fn press_and_release_run_their_own_macros()
+	{
+		let config = config("
+  default:
+    gkeys:
+      1: { on_press: { key_down: 'ctrl+m' }, on_release: { key_up: 'ctrl+m' } }
+      2: { on_release: { key_press: 'a' } }
+");
+		let resolver = ProfileResolver::new(&config, config.default_profile(), 1);
+
+		assert!(format!("{:?}", resolver.macro_for_gkey(1).unwrap().steps).contains("KeyDown(\"ctrl+m\")"));
+		assert!(format!("{:?}", resolver.release_macro_for_gkey(1).unwrap().steps).contains("KeyUp(\"ctrl+m\")"));
+		assert!(resolver.macro_for_gkey(2).is_none());
+		assert!(resolver.release_macro_for_gkey(2).is_some());
+	}
+
+	#[test]
+	fn gestures_run_their_own_macros()
+	{
+		let config = config("
+  default:
+    gkeys:
+      1: { tap: { key_press: a }, hold: { key_press: b } }
+      2: { on_press: { key_press: c } }
+");
+		let resolver = ProfileResolver::new(&config, config.default_profile(), 1);
+
+		assert!(format!("{:?}", resolver.macro_for_gesture(1, Gesture::Hold).unwrap().steps).contains("KeyPress(\"b\")"));
+		assert!(resolver.macro_for_gesture(1, Gesture::DoubleTap).is_none());
+		assert!(resolver.gestures_for_gkey(2).is_none());
+	}
+
+	#[test]
+	fn gkey_assignments_are_found_in_any_mode()
+	{
+		let config = config("
+  default: {}
+  sets:
+    gkey_sets: [first]
+  mode_only:
+    modes:
+      3: { gkeys: { 1: profile_macro } }
+  empty:
+    gkeys: {}
+    modes:
+      2: { gkey_sets: [] }
+");
+		let has_assignments = |profile: &str| ProfileResolver::new(&config, config.profiles.get(profile).unwrap(), 1)
+			.has_gkey_assignments();
+
+		assert!(!has_assignments("default"));
+		assert!(has_assignments("sets"));
+		assert!(has_assignments("mode_only"));
+		assert!(!has_assignments("empty"));
+	}
+
+	#[test]
+	fn media_keys_and_game_mode_keys_come_from_the_profile()
+	{
+		let config = config("
+  default:
+    game_mode_keys: [left_meta]
+    media_keys: { mute: profile_macro }
+");
+		let resolver = ProfileResolver::new(&config, config.default_profile(), 1);
+
+		assert_eq!(resolver.game_mode_keys(), &[Scancode::LeftMeta]);
+		assert!(resolver.macro_for_media_key(MediaKey::Mute).is_some());
+		assert!(resolver.macro_for_media_key(MediaKey::PlayPause).is_none());
+	}
+
+	/// Builds a config where each of the given layers either assigns gkey 1 or
+	/// doesn't. Gkey sets are named after their layer and position.
+	fn layered_config(mode_gkey: bool, mode_sets: &[bool], profile_gkey: bool, profile_sets: &[bool])
+		-> Configuration
+	{
+		let gkeys = |assigned: bool, name: &str| match assigned
+		{
+			true => format!("{{ 1: {} }}", name),
+			false => "{}".to_string()
+		};
+
+		let set_names = |prefix: &str, sets: &[bool]| (0..sets.len())
+			.map(|i| format!("{}_{}", prefix, i))
+			.collect::<Vec<String>>();
+
+		let mut config = config(&format!("
+  default:
+    gkeys: {}
+    modes:
+      1:
+        gkeys: {}
+",
+			gkeys(profile_gkey, "profile_gkey"),
+			gkeys(mode_gkey, "mode_gkey")));
+
+		// the sets are added once the config's been validated, as they name
+		// macros that don't exist
+		let profile = config.profiles.get_mut("default").unwrap();
+		profile.gkey_sets = Some(set_names("profile_set", profile_sets));
+		profile.modes.as_mut().unwrap().get_mut(&1).unwrap().gkey_sets = Some(set_names("mode_set", mode_sets));
+
+		let gkey_sets = config.gkey_sets.get_or_insert_with(HashMap::new);
+
+		for (prefix, sets) in &[("mode_set", mode_sets), ("profile_set", profile_sets)]
+		{
+			for (name, assigned) in set_names(prefix, sets).into_iter().zip(sets.iter())
+			{
+				let mut gkey_set = HashMap::new();
+
+				if *assigned
+				{
+					gkey_set.insert(1, MacroKeyAssignment::NamedMacro(name.clone()));
+				}
+
+				gkey_sets.insert(name, gkey_set);
+			}
+		}
+
+		config
+	}
+
+	proptest!
+	{
+		#[test]
+		fn highest_precedence_assignment_wins(
+			mode_gkey in any::<bool>(),
+			mode_sets in proptest::collection::vec(any::<bool>(), 0..4),
+			profile_gkey in any::<bool>(),
+			profile_sets in proptest::collection::vec(any::<bool>(), 0..4))
+		{
+			let config = layered_config(mode_gkey, &mode_sets, profile_gkey, &profile_sets);
+
+			let last_set = |prefix: &str, sets: &[bool]| sets
+				.iter()
+				.rposition(|assigned| *assigned)
+				.map(|i| format!("{}_{}", prefix, i));
+
+			let expected = None
+				.or_else(|| mode_gkey.then(|| "mode_gkey".to_string()))
+				.or_else(|| last_set("mode_set", &mode_sets))
+				.or_else(|| profile_gkey.then(|| "profile_gkey".to_string()))
+				.or_else(|| last_set("profile_set", &profile_sets));
+
+			prop_assert_eq!(assigned(&config, "default", 1, 1), expected.clone());
+
+			// the first source listed is always the one used
+			let first_source = ProfileResolver::new(&config, config.default_profile(), 1)
+				.gkey_sources(1)
+				.first()
+				.map(|source| match source.assignment
+				{
+					MacroKeyAssignment::NamedMacro(name)
+						| MacroKeyAssignment::MacroWithArguments { name, .. } => name.clone(),
+					MacroKeyAssignment::SimpleAction(action) => format!("{:?}", action),
+					MacroKeyAssignment::PressRelease { .. } => "press/release".into(),
+					MacroKeyAssignment::Gestures(_) => "gestures".into()
+				});
+
+			prop_assert_eq!(first_source, expected.clone());
+
+			// other modes only ever see the profile's own assignments
+			let profile_expected = None
+				.or_else(|| profile_gkey.then(|| "profile_gkey".to_string()))
+				.or_else(|| last_set("profile_set", &profile_sets));
+
+			prop_assert_eq!(assigned(&config, "default", 2, 1), profile_expected);
+		}
+	}
+}
@@ -0,0 +1,136 @@
+use serde::{Serialize, Deserialize};
+
+use super::playlist::TimeWindow;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday
+{
+	#[serde(alias = "mon")]
+	Monday,
+	#[serde(alias = "tue")]
+	Tuesday,
+	#[serde(alias = "wed")]
+	Wednesday,
+	#[serde(alias = "thu")]
+	Thursday,
+	#[serde(alias = "fri")]
+	Friday,
+	#[serde(alias = "sat")]
+	Saturday,
+	#[serde(alias = "sun")]
+	Sunday
+}
+
+impl Weekday
+{
+	const ALL: [Weekday; 7] = [
+		Weekday::Monday,
+		Weekday::Tuesday,
+		Weekday::Wednesday,
+		Weekday::Thursday,
+		Weekday::Friday,
+		Weekday::Saturday,
+		Weekday::Sunday
+	];
+
+	/// The day numbered as `tm_wday` does, from 0 for Sunday
+	pub fn from_tm_wday(day: i32) -> Self
+	{
+		Self::ALL[(day + 6).rem_euclid(7) as usize]
+	}
+
+	fn previous(self) -> Self
+	{
+		Self::ALL[(self as usize + 6) % 7]
+	}
+}
+
+/// A day of the week and the time on it, which schedules are checked against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LocalTime
+{
+	pub weekday: Weekday,
+	/// minutes since midnight
+	pub minutes: u16
+}
+
+impl LocalTime
+{
+	/// Now, in the system's timezone (or $TZ)
+	pub fn now() -> Option<Self>
+	{
+		crate::scheduler::local_time().map(|time| Self
+		{
+			weekday: Weekday::from_tm_wday(time.tm_wday),
+			minutes: (time.tm_hour * 60 + time.tm_min) as u16
+		})
+	}
+}
+
+/// When a profile can apply, as a profile condition: between two local times,
+/// on some days of the week, or both. A window running past midnight belongs
+/// to the day it starts on, so `days: [friday]` with `between: ["22:00",
+/// "02:00"]` takes in the small hours of Saturday.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Schedule
+{
+	pub between: Option<TimeWindow>,
+	/// every day if it's left out
+	#[serde(default)]
+	pub days: Vec<Weekday>
+}
+
+impl Schedule
+{
+	pub fn contains(&self, time: LocalTime) -> bool
+	{
+		let on = |weekday: Weekday| self.days.is_empty() || self.days.contains(&weekday);
+
+		match self.between
+		{
+			Some(window) => match window.position(time.minutes)
+			{
+				// the window started the day before when it's run past midnight
+				Some((since_start, _until_end)) if since_start > time.minutes => on(time.weekday.previous()),
+				Some(_) => on(time.weekday),
+				None => false
+			},
+			None => on(time.weekday)
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn at(weekday: Weekday, hours: u16, minutes: u16) -> LocalTime
+	{
+		LocalTime { weekday, minutes: hours * 60 + minutes }
+	}
+
+	#[test]
+	fn schedules_take_in_windows_past_midnight()
+	{
+		let working_hours: Schedule = serde_yaml::from_str("{between: ['09:00', '17:30'], days: [mon, tue, wednesday]}").unwrap();
+
+		assert!(working_hours.contains(at(Weekday::Monday, 9, 0)));
+		assert!(!working_hours.contains(at(Weekday::Monday, 17, 30)));
+		assert!(!working_hours.contains(at(Weekday::Thursday, 12, 0)));
+
+		let friday_night: Schedule = serde_yaml::from_str("{between: ['22:00', '02:00'], days: [friday]}").unwrap();
+
+		assert!(friday_night.contains(at(Weekday::Friday, 23, 0)));
+		assert!(friday_night.contains(at(Weekday::Saturday, 1, 0)));
+		assert!(!friday_night.contains(at(Weekday::Saturday, 23, 0)));
+		assert!(!friday_night.contains(at(Weekday::Friday, 1, 0)));
+
+		let weekends: Schedule = serde_yaml::from_str("{days: [saturday, sunday]}").unwrap();
+
+		assert!(weekends.contains(at(Weekday::Sunday, 12, 0)));
+		assert!(!weekends.contains(at(Weekday::Monday, 0, 0)));
+		assert_eq!(Weekday::from_tm_wday(0), Weekday::Sunday);
+	}
+}
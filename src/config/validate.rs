@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+
+use crate::device::g815;
+use crate::device::rgb::{KeySelection, Theme, EffectGroup};
+use crate::macros::{Action, Step};
+
+use super::{Configuration, MacroKeyAssignment, LightKeyAction, GkeySets, GkeyAssignments, GShift};
+use super::resolver::ProfileKeyAssignment;
+
+const GKEYS: std::ops::RangeInclusive<u8> = 1..=5;
+
+/// Checks everything the config refers to by name actually exists, and that
+/// key numbers are in range. Every problem found is returned (sorted, so
+/// they're listed in the same order each time), rather than just the first.
+pub fn validate(config: &Configuration) -> Vec<String>
+{
+	let mut problems = vec![];
+
+	if config.virtual_modes > Configuration::MAX_VIRTUAL_MODES
+	{
+		problems.push(format!("virtual_modes: there can only be {} virtual modes", Configuration::MAX_VIRTUAL_MODES));
+	}
+
+	if !(0_f64..=1_f64).contains(&config.macro_timing.humanize)
+	{
+		problems.push("macro_timing: humanize is a fraction of each step's duration, from 0 to 1".to_string());
+	}
+
+	if config.macro_timing.scale <= 0_f64 || config.macro_timing.scale.is_nan()
+	{
+		problems.push("macro_timing: scale has to be more than 0".to_string());
+	}
+
+	if !config.profiles.contains_key("default")
+	{
+		problems.push("there is no default profile".to_string());
+	}
+
+	if !config.themes.contains_key("default")
+	{
+		problems.push("there is no default theme".to_string());
+	}
+
+	for (name, profile) in &config.profiles
+	{
+		let context = format!("profile '{}'", name);
+
+		check_theme(config, &context, profile.theme.as_deref(), &mut problems);
+		check_logo_theme(config, &context, profile.logo_theme.as_deref(), &mut problems);
+		check_gkeys(config, &context, &profile.gkey_sets, &profile.gkeys, &mut problems);
+
+		for (media_key, assignment) in profile.media_keys.iter().flatten()
+		{
+			check_assignment(config, &format!("{}, media key {:?}", context, media_key), assignment, &mut problems);
+		}
+
+		if let Some(gshift) = &profile.gshift
+		{
+			check_gshift(config, &context, gshift, &mut problems);
+		}
+
+		if let Some(rate) = profile.report_rate
+		{
+			if !g815::is_report_rate(rate)
+			{
+				problems.push(format!("{}: the keyboard can't report at {}Hz, try 1000, 500, 250 or 125", context, rate));
+			}
+		}
+
+		if let Some(playlist) = &profile.playlist
+		{
+			if !config.playlists.contains_key(playlist)
+			{
+				problems.push(format!("{}: there is no playlist called '{}'", context, playlist));
+			}
+		}
+
+		if let Some(notifications) = &profile.notifications
+		{
+			check_keygroups(config, &format!("{}'s notification counter", context), &notifications.counter, &mut problems);
+		}
+
+		for (mode, mode_profile) in profile.modes.iter().flatten()
+		{
+			let context = format!("{}, mode {}", context, mode);
+
+			if !config.modes().contains(mode)
+			{
+				problems.push(format!("{}: there is no mode {}, only {} to {} (virtual_modes adds more)",
+					context, mode, config.modes().start(), config.modes().end()));
+			}
+
+			check_theme(config, &context, mode_profile.theme.as_deref(), &mut problems);
+			check_logo_theme(config, &context, mode_profile.logo_theme.as_deref(), &mut problems);
+			check_gkeys(config, &context, &mode_profile.gkey_sets, &mode_profile.gkeys, &mut problems);
+		}
+	}
+
+	for (name, theme) in &config.themes
+	{
+		check_keygroups(config, &format!("theme '{}'", name), theme.key_selections(), &mut problems);
+
+		if let Theme::Screen { screen, .. } = theme
+		{
+			if screen.smoothing > 100
+			{
+				problems.push(format!("theme '{}': smoothing is a percentage, so can't be more than 100", name));
+			}
+		}
+	}
+
+	for (name, playlist) in &config.playlists
+	{
+		let context = format!("playlist '{}'", name);
+
+		if playlist.is_empty()
+		{
+			problems.push(format!("{}: has no themes", context));
+		}
+
+		for entry in playlist
+		{
+			check_theme(config, &context, Some(&entry.theme), &mut problems);
+
+			if entry.duration == 0
+			{
+				problems.push(format!("{}: theme '{}' has a duration of 0 seconds", context, entry.theme));
+			}
+		}
+	}
+
+	if let Some(night_mode) = &config.night_mode
+	{
+		if night_mode.between.is_none() && (night_mode.latitude.is_none() || night_mode.longitude.is_none())
+		{
+			problems.push("night_mode: needs either `between` or a `latitude` and `longitude`".to_string());
+		}
+
+		if !(1000..=6500).contains(&night_mode.temperature)
+		{
+			problems.push(format!("night_mode: {}K isn't a temperature between 1000K and 6500K", night_mode.temperature));
+		}
+	}
+
+	for (name, macro_) in config.macros.iter().flatten()
+	{
+		for called in macro_.called_macros()
+		{
+			if !config.macros.as_ref().map(|macros| macros.contains_key(called)).unwrap_or(false)
+			{
+				problems.push(format!("macro '{}': there is no macro called '{}'", name, called));
+			}
+		}
+
+		check_profiles(config, &format!("macro '{}'", name), macro_.cycled_profiles(), &mut problems);
+
+		if macro_.steps.iter().filter_map(Step::jitter).any(|jitter| jitter.min > jitter.max)
+		{
+			problems.push(format!("macro '{}': a step's jitter has a min longer than its max", name));
+		}
+		check_keygroups(config, &format!("macro '{}'", name), macro_.lit_keys(), &mut problems);
+	}
+
+	for (name, gkey_set) in config.gkey_sets.iter().flatten()
+	{
+		check_gkey_assignments(config, &format!("gkey set '{}'", name), gkey_set, &mut problems);
+	}
+
+	for (serial_number, device) in &config.devices
+	{
+		let context = format!("device '{}'", serial_number);
+
+		if let Some(profile) = &device.default_profile
+		{
+			if !config.profiles.contains_key(profile)
+			{
+				problems.push(format!("{}: there is no profile called '{}'", context, profile));
+			}
+		}
+
+		check_theme(config, &context, device.theme.as_deref(), &mut problems);
+
+		for subsystem in device.disabled.iter().filter(|subsystem| !subsystem.per_device())
+		{
+			problems.push(format!("{}: the {} subsystem can't be switched off for a single keyboard",
+				context, subsystem.name()));
+		}
+	}
+
+	check_keygroups(config, "focus_mode", &config.focus_mode.keys, &mut problems);
+
+	for (index, flash) in config.notification_flashes.iter().enumerate()
+	{
+		check_keygroups(config, &format!("notification flash {}", index + 1), &flash.keys, &mut problems);
+	}
+
+	if let Some(audio_focus) = &config.audio_focus
+	{
+		check_keygroups(config, "audio_focus", &audio_focus.keys, &mut problems);
+	}
+
+	let light_key = &config.light_key;
+	let light_key_actions = light_key.press
+		.iter()
+		.map(|action| ("light_key press".to_string(), action))
+		.chain(light_key.double_press.iter().map(|action| ("light_key double_press".to_string(), action)))
+		.chain(light_key.levels.iter().map(|(level, action)| (format!("light_key level {}", level), action)));
+
+	for (context, action) in light_key_actions
+	{
+		match action
+		{
+			LightKeyAction::CycleThemes(themes) => for theme in themes
+			{
+				check_theme(config, &context, Some(theme), &mut problems);
+			},
+			LightKeyAction::SwitchProfile(profile) if !config.profiles.contains_key(profile) =>
+				problems.push(format!("{}: there is no profile called '{}'", context, profile)),
+			LightKeyAction::Macro(assignment) => check_assignment(config, &context, assignment, &mut problems),
+			_ => ()
+		}
+	}
+
+	problems.sort();
+	problems
+}
+
+/// Finds gkeys assigned more than once in the same profile (or mode), i.e. in
+/// both its gkeys and a gkey set, or in two of its gkey sets. These aren't
+/// problems, precedence decides which is used, but the ones overridden are
+/// easy to miss. Sorted, like `validate()`.
+pub fn conflicts(config: &Configuration) -> Vec<String>
+{
+	let mut conflicts = vec![];
+
+	for (name, profile) in &config.profiles
+	{
+		let levels = std::iter::once((None, profile as &dyn ProfileKeyAssignment))
+			.chain(profile.modes
+				.iter()
+				.flatten()
+				.map(|(mode, mode_profile)| (Some(*mode), mode_profile as &dyn ProfileKeyAssignment)));
+
+		for (mode, level) in levels
+		{
+			for gkey in GKEYS
+			{
+				let sources = level.gkey_sources(config, gkey, mode);
+
+				if let [used, overridden @ ..] = sources.as_slice()
+				{
+					if !overridden.is_empty()
+					{
+						let overridden: Vec<String> = overridden.iter().map(ToString::to_string).collect();
+
+						conflicts.push(format!("profile '{}', G{}: {} overrides {}",
+							name, gkey, used, overridden.join(", ")));
+					}
+				}
+			}
+		}
+	}
+
+	conflicts.sort();
+	conflicts
+}
+
+fn check_theme(config: &Configuration, context: &str, theme: Option<&str>, problems: &mut Vec<String>)
+{
+	if let Some(theme) = theme
+	{
+		if !config.themes.contains_key(theme)
+		{
+			problems.push(format!("{}: there is no theme called '{}'", context, theme));
+		}
+	}
+}
+
+/// Logo themes are checked as well, as the logo can't show every effect
+fn check_logo_theme(config: &Configuration, context: &str, theme: Option<&str>, problems: &mut Vec<String>)
+{
+	check_theme(config, context, theme, problems);
+
+	let logo_theme = theme.and_then(|name| config.themes.get(name).map(|theme| (name, theme)));
+
+	if let Some((name, Theme::Effect(effect))) = logo_theme
+	{
+		if !g815::supports_effect(EffectGroup::Logo, effect)
+		{
+			problems.push(format!("{}: the logo can't show the effect in logo theme '{}' ({:?}), \
+				use static, breathing or cycle instead", context, name, effect));
+		}
+	}
+}
+
+fn check_gkeys(config: &Configuration, context: &str, gkey_sets: &GkeySets, gkeys: &GkeyAssignments,
+	problems: &mut Vec<String>)
+{
+	for gkey_set in gkey_sets.iter().flatten()
+	{
+		let exists = config.gkey_sets
+			.as_ref()
+			.map(|gkey_sets| gkey_sets.contains_key(gkey_set))
+			.unwrap_or(false);
+
+		if !exists
+		{
+			problems.push(format!("{}: there is no gkey set called '{}'", context, gkey_set));
+		}
+	}
+
+	if let Some(gkeys) = gkeys
+	{
+		check_gkey_assignments(config, context, gkeys, problems);
+	}
+}
+
+fn check_gkey_assignments(config: &Configuration, context: &str, gkeys: &HashMap<u8, MacroKeyAssignment>,
+	problems: &mut Vec<String>)
+{
+	for (gkey, assignment) in gkeys
+	{
+		let context = format!("{}, G{}", context, gkey);
+
+		if !GKEYS.contains(gkey)
+		{
+			problems.push(format!("{}: there is no G{}, only G{} to G{}",
+				context, gkey, GKEYS.start(), GKEYS.end()));
+		}
+
+		check_gkey_assignment(config, &context, assignment, problems);
+	}
+}
+
+fn check_gshift(config: &Configuration, context: &str, gshift: &GShift, problems: &mut Vec<String>)
+{
+	let context = format!("{}, gshift", context);
+
+	if !GKEYS.contains(&gshift.key)
+	{
+		problems.push(format!("{}: there is no G{}, only G{} to G{}",
+			context, gshift.key, GKEYS.start(), GKEYS.end()));
+	}
+
+	for (gkey, assignment) in &gshift.gkeys
+	{
+		let context = format!("{} G{}", context, gkey);
+
+		if !GKEYS.contains(gkey)
+		{
+			problems.push(format!("{}: there is no G{}, only G{} to G{}",
+				context, gkey, GKEYS.start(), GKEYS.end()));
+		}
+
+		if *gkey == gshift.key
+		{
+			problems.push(format!("{}: G{} is the gshift key, so it can't be shifted", context, gkey));
+		}
+
+		check_assignment(config, &context, assignment, problems);
+	}
+
+	for (media_key, assignment) in &gshift.media_keys
+	{
+		check_assignment(config, &format!("{} media key {:?}", context, media_key), assignment, problems);
+	}
+}
+
+fn check_gkey_assignment(config: &Configuration, context: &str, assignment: &MacroKeyAssignment,
+	problems: &mut Vec<String>)
+{
+	match assignment
+	{
+		MacroKeyAssignment::PressRelease { on_press, on_release } =>
+		{
+			if on_press.is_none() && on_release.is_none()
+			{
+				problems.push(format!("{}: needs an on_press or on_release", context));
+			}
+
+			for (name, assignment) in [("on_press", on_press), ("on_release", on_release)].iter()
+			{
+				if let Some(assignment) = assignment
+				{
+					check_assignment(config, &format!("{} {}", context, name), assignment, problems);
+				}
+			}
+		},
+		MacroKeyAssignment::Gestures(gestures) =>
+		{
+			if gestures.tap.is_none() && gestures.double_tap.is_none() && gestures.hold.is_none()
+			{
+				problems.push(format!("{}: needs a tap, double_tap or hold", context));
+			}
+
+			for (name, assignment) in [("tap", &gestures.tap), ("double_tap", &gestures.double_tap), ("hold", &gestures.hold)].iter()
+			{
+				if let Some(assignment) = assignment
+				{
+					check_assignment(config, &format!("{} {}", context, name), assignment, problems);
+				}
+			}
+		},
+		_ => check_assignment(config, context, assignment, problems)
+	}
+}
+
+fn check_assignment(config: &Configuration, context: &str, assignment: &MacroKeyAssignment,
+	problems: &mut Vec<String>)
+{
+	let (name, arguments) = match assignment
+	{
+		MacroKeyAssignment::NamedMacro(name) => (name, None),
+		MacroKeyAssignment::MacroWithArguments { name, arguments } => (name, Some(arguments)),
+		MacroKeyAssignment::SimpleAction(Action::CycleProfiles(profiles)) =>
+		{
+			check_profiles(config, context, profiles.iter().map(String::as_str), problems);
+			return
+		},
+		MacroKeyAssignment::SimpleAction(_) => return,
+		MacroKeyAssignment::PressRelease { .. } =>
+		{
+			problems.push(format!("{}: on_press and on_release can only be used on gkeys (outside gshift)", context));
+			return
+		},
+		MacroKeyAssignment::Gestures(_) =>
+		{
+			problems.push(format!("{}: tap, double_tap and hold can only be used on gkeys (outside gshift)", context));
+			return
+		}
+	};
+
+	let macro_ = match config.macros.as_ref().and_then(|macros| macros.get(name))
+	{
+		Some(macro_) => macro_,
+		None =>
+		{
+			problems.push(format!("{}: there is no macro called '{}'", context, name));
+			return
+		}
+	};
+
+	for parameter in &macro_.parameters
+	{
+		if !arguments.map(|arguments| arguments.contains_key(parameter)).unwrap_or(false)
+		{
+			problems.push(format!("{}: macro '{}' needs a value for '{}'", context, name, parameter));
+		}
+	}
+
+	for argument in arguments.iter().flat_map(|arguments| arguments.keys())
+	{
+		if !macro_.parameters.contains(argument)
+		{
+			problems.push(format!("{}: macro '{}' has no parameter called '{}'", context, name, argument));
+		}
+	}
+}
+
+fn check_profiles<'a, I>(config: &Configuration, context: &str, profiles: I, problems: &mut Vec<String>)
+where
+	I: IntoIterator<Item = &'a str>
+{
+	for profile in profiles.into_iter().filter(|profile| !config.profiles.contains_key(*profile))
+	{
+		problems.push(format!("{}: there is no profile called '{}'", context, profile));
+	}
+}
+
+fn check_keygroups<'a, I>(config: &Configuration, context: &str, selections: I, problems: &mut Vec<String>)
+where
+	I: IntoIterator<Item = &'a KeySelection>
+{
+	for keygroup in selections.into_iter().filter_map(KeySelection::keygroup)
+	{
+		if !config.keygroups.contains_key(keygroup)
+		{
+			problems.push(format!("{}: there is no keygroup called '{}'", context, keygroup));
+		}
+	}
+}
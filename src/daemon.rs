@@ -0,0 +1,196 @@
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use log::{debug, warn};
+use smol::Async;
+
+/// Where files that only matter whilst the driver is running (its pid file and
+/// log when daemonized) are kept
+pub fn runtime_folder() -> PathBuf
+{
+	env::var_os("XDG_RUNTIME_DIR")
+		.map(PathBuf::from)
+		.unwrap_or_else(env::temp_dir)
+}
+
+pub fn default_pid_file() -> PathBuf
+{
+	let mut path = runtime_folder();
+	path.push("g815d.pid");
+	path
+}
+
+/// Starts the driver again in the background, detached from the terminal, with
+/// the same arguments apart from `--daemonize`. Its output goes to g815d.log in
+/// the runtime folder. Returns the new process's pid.
+pub fn daemonize(pid_file: Option<&str>) -> Result<u32, String>
+{
+	let mut log_path = runtime_folder();
+	log_path.push("g815d.log");
+
+	let log_file = create_private_file(&log_path)
+		.map_err(|error| format!("unable to create {}: {}", log_path.display(), error))?;
+	let log_file_stdout = log_file.try_clone().map_err(|error| error.to_string())?;
+
+	let mut args: Vec<_> = env::args_os()
+		.skip(1)
+		.filter(|arg| arg != "--daemonize")
+		.collect();
+
+	// the background process writes the pid file, so it can remove it too
+	if pid_file.is_none()
+	{
+		args.push("--pid-file".into());
+		args.push(default_pid_file().into());
+	}
+
+	let child = Command::new(env::current_exe().map_err(|error| error.to_string())?)
+		.args(args)
+		.stdin(Stdio::null())
+		.stdout(log_file_stdout)
+		.stderr(log_file)
+		// its own process group, so ctrl+c in the terminal doesn't stop it
+		.process_group(0)
+		.spawn()
+		.map_err(|error| error.to_string())?;
+
+	Ok(child.id())
+}
+
+/// Creates a file only the user can read or write, in place of any left by an
+/// earlier run. Whatever is already there is removed rather than written
+/// through, as without XDG_RUNTIME_DIR it's in /tmp, where another user could
+/// have left a symlink to one of the user's files.
+pub fn create_private_file(path: &Path) -> io::Result<fs::File>
+{
+	match fs::remove_file(path)
+	{
+		Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error),
+		_ => ()
+	}
+
+	fs::OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.mode(0o600)
+		.custom_flags(libc::O_NOFOLLOW)
+		.open(path)
+}
+
+/// A file holding the driver's pid, removed when the driver exits
+pub struct PidFile
+{
+	path: PathBuf
+}
+
+impl PidFile
+{
+	pub fn create(path: PathBuf) -> Result<Self, String>
+	{
+		create_private_file(&path)
+			.and_then(|mut file| writeln!(file, "{}", std::process::id()))
+			.map_err(|error| format!("unable to write the pid file {}: {}", path.display(), error))?;
+
+		Ok(Self { path })
+	}
+}
+
+impl Drop for PidFile
+{
+	fn drop(&mut self)
+	{
+		if let Err(error) = fs::remove_file(&self.path)
+		{
+			warn!("unable to remove the pid file {}: {}", self.path.display(), error);
+		}
+	}
+}
+
+/// Tells systemd about the driver's state (i.e. `READY=1`) when it's run as a
+/// `Type=notify` service. Does nothing otherwise.
+pub fn notify_systemd(state: &str)
+{
+	let socket_path = match env::var("NOTIFY_SOCKET")
+	{
+		Ok(socket_path) => socket_path,
+		Err(_) => return
+	};
+
+	// a leading @ is an abstract socket
+	let address = match socket_path.strip_prefix('@')
+	{
+		Some(name) =>
+		{
+			use std::os::linux::net::SocketAddrExt;
+			SocketAddr::from_abstract_name(name.as_bytes())
+		},
+		None => SocketAddr::from_pathname(&socket_path)
+	};
+
+	let result = address.and_then(|address| UnixDatagram::unbound()
+		.and_then(|socket| socket.send_to_addr(state.as_bytes(), &address)));
+
+	match result
+	{
+		Ok(_) => debug!("notified systemd: {}", state),
+		Err(error) => warn!("unable to notify systemd ({}): {}", state, error)
+	}
+}
+
+/// Wakes a task when there's something to read from a file descriptor that
+/// belongs to something else, i.e. a zbus connection, which keeps doing its
+/// own reading
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+pub struct Readable(Async<OwnedFd>);
+
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+impl Readable
+{
+	pub fn new(fd: RawFd) -> io::Result<Self>
+	{
+		// a duplicate for the runtime to register, left in whichever blocking
+		// mode its owner wants
+		let fd = unsafe { BorrowedFd::borrow_raw(fd) }.try_clone_to_owned()?;
+		Ok(Self(Async::new_nonblocking(fd)?))
+	}
+
+	pub async fn wait(&self) -> io::Result<()>
+	{
+		self.0.readable().await
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use std::os::unix::fs::PermissionsExt;
+
+	use super::*;
+
+	#[test]
+	fn private_files_replace_symlinks_rather_than_following_them()
+	{
+		let folder = env::temp_dir().join(format!("g815d-test-{}", std::process::id()));
+		fs::create_dir_all(&folder).unwrap();
+
+		let target = folder.join("target");
+		let link = folder.join("g815d.pid");
+		fs::write(&target, "untouched").unwrap();
+		std::os::unix::fs::symlink(&target, &link).unwrap();
+
+		write!(create_private_file(&link).unwrap(), "1234").unwrap();
+
+		assert_eq!(fs::read_to_string(&target).unwrap(), "untouched");
+		assert_eq!(fs::read_to_string(&link).unwrap(), "1234");
+		assert_eq!(fs::metadata(&link).unwrap().permissions().mode() & 0o777, 0o600);
+
+		fs::remove_dir_all(&folder).unwrap();
+	}
+}
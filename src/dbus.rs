@@ -1,35 +1,893 @@
-use std::sync::mpsc::{Sender, Receiver, TryRecvError};
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
 use std::time::Duration;
-use std::thread;
 use std::convert::TryInto;
 
+use smol::channel::Receiver;
+use smol::future;
 use zbus::{Connection, ObjectServer, dbus_interface};
 use zbus::fdo::{DBusProxy, RequestNameFlags};
+use zvariant::{OwnedValue, Value};
 
-use crate::MainThreadSignal;
+use crate::channels::MonitoredSender;
+use crate::daemon;
+use crate::{SharedState, MainThreadSignal, DBusSignal};
+use crate::config::{Configuration, Profile, ProfileResolver, MacroKeyAssignment};
+use crate::device::scancode::Scancode;
+use crate::device::color::Color;
+use crate::device::rgb::{EffectConfiguration, EffectGroup};
+use crate::device::client_colors::KeyColorUpdate;
+use crate::notifications::Notification;
+use crate::macros::{GameModeCommand, Macro};
+use crate::subsystem::Subsystem;
+use crate::scheduler::PlaylistCommand;
+use crate::stats::GameModeLearning;
 
-struct ServerInterface;
+struct ServerInterface
+{
+	state: Arc<SharedState>,
+	tx: MonitoredSender<MainThreadSignal>
+}
 
-#[dbus_interface(name = "rs.lave.g815_driver")]
+/// The driver's interface. Its name ends in the major version, which changes
+/// whenever a method, property or signal is removed or changed incompatibly.
+/// Additions only bump `InterfaceVersion`. The methods and properties are
+/// described in the introspection data (org.freedesktop.DBus.Introspectable).
+#[dbus_interface(name = "rs.lave.g815_driver1")]
 impl ServerInterface
 {
-	pub fn test(&mut self) -> String
+	/// Lists the keys currently disabled by game mode on the device, using the
+	/// same key names as the config file
+	pub fn get_game_mode_keys(&self) -> Vec<String>
+	{
+		self.state.game_mode_keys
+			.read()
+			.unwrap()
+			.iter()
+			.map(|scancode| scancode.config_name())
+			.collect()
+	}
+
+	/// Lists every supported key, with its ids and the keygroups it's in, as
+	/// either `yaml` or `json`
+	pub fn list_keys(&self, format: String) -> zbus::fdo::Result<String>
+	{
+		let config = self.state.config.read().unwrap();
+
+		crate::key_list(&config.keygroups, &format)
+			.map_err(zbus::fdo::Error::InvalidArgs)
+	}
+
+	/// Gets the timelines of the most recent macro runs (newest first) as json:
+	/// what started each run, when each step started and how long it took, and
+	/// why the run ended. Runs are only added once they've ended.
+	pub fn get_last_macro_runs(&self, count: u32) -> zbus::fdo::Result<String>
+	{
+		let macro_runs = self.state.macro_runs.read().unwrap();
+		let runs: Vec<_> = macro_runs.iter().take(count as usize).collect();
+
+		serde_json::to_string_pretty(&runs).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Forgets all the key presses counted for heatmap themes
+	pub fn reset_key_statistics(&mut self)
+	{
+		log::info!("resetting key statistics");
+		self.state.key_statistics.write().unwrap().clear();
+	}
+
+	/// Starts counting the keys pressed whilst the active profile stays active,
+	/// so its game mode can be offered the keys that get in the way of games
+	/// but weren't used (see StopGameModeLearning)
+	pub fn start_game_mode_learning(&mut self)
+	{
+		let profile = self.state.active_profile_name.read().unwrap().clone();
+
+		log::info!("learning which keys are used in profile '{}'", profile);
+		*self.state.game_mode_learning.write().unwrap() = Some(GameModeLearning::new(profile));
+		self.tx.send(MainThreadSignal::KeyCountingChanged);
+	}
+
+	/// Stops game mode learning, returning as json the profile it was learning,
+	/// the keys pressed in it, and the keys suggested for its game_mode_keys,
+	/// which AddGameModeKeys can then save
+	pub fn stop_game_mode_learning(&mut self) -> zbus::fdo::Result<String>
+	{
+		let learning = self.state.game_mode_learning
+			.write()
+			.unwrap()
+			.take()
+			.ok_or_else(|| zbus::fdo::Error::Failed("game mode learning isn't on".to_string()))?;
+
+		self.tx.send(MainThreadSignal::KeyCountingChanged);
+
+		let config = self.state.config.read().unwrap();
+		let game_mode_keys = config.profiles
+			.get(&learning.profile)
+			.and_then(|profile| profile.game_mode_keys.as_deref())
+			.unwrap_or_default();
+
+		let pressed: HashMap<String, u64> = learning.pressed
+			.iter()
+			.map(|(scancode, count)| (scancode.config_name(), *count))
+			.collect();
+
+		let suggested: Vec<String> = learning
+			.suggested_keys(game_mode_keys)
+			.iter()
+			.map(|scancode| scancode.config_name())
+			.collect();
+
+		serde_json::to_string_pretty(&serde_json::json!({
+			"profile": learning.profile,
+			"pressed": pressed,
+			"suggested": suggested
+		})).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Adds keys to a profile's game_mode_keys and saves the config file,
+	/// which is then reloaded as if it had been edited. Profiles from the
+	/// include folders have to be edited by hand.
+	pub fn add_game_mode_keys(&mut self, profile: String, keys: Vec<String>) -> zbus::fdo::Result<()>
+	{
+		let keys = keys
+			.iter()
+			.map(|key| parse_key(key))
+			.collect::<zbus::fdo::Result<Vec<Scancode>>>()?;
+
+		log::info!("adding game mode keys to profile '{}'", profile);
+
+		self.edit_config(|config|
+		{
+			let game_mode_keys = &mut editable_profile(config, &profile)?.game_mode_keys;
+			let previous = game_mode_keys.clone();
+			let added = game_mode_keys.get_or_insert_with(Vec::new);
+
+			for scancode in keys
+			{
+				if !added.contains(&scancode)
+				{
+					added.push(scancode);
+				}
+			}
+
+			Ok(move |config: &mut Configuration| if let Some(profile) = config.profiles.get_mut(&profile)
+			{
+				profile.game_mode_keys = previous;
+			})
+		})
+	}
+
+	/// Adds a macro to the config (replacing any with the same name), written
+	/// as it would be in the config file, and saves the config file
+	pub fn add_macro(&mut self, name: String, yaml: String) -> zbus::fdo::Result<()>
+	{
+		if name.is_empty()
+		{
+			return Err(zbus::fdo::Error::InvalidArgs("macros need a name".to_string()))
+		}
+
+		let colors = self.state.config.read().unwrap().colors.clone();
+		let new_macro: Macro = Color::with_named_colors(&colors, || serde_yaml::from_str(&yaml))
+			.map_err(|error| zbus::fdo::Error::InvalidArgs(format!("the macro isn't valid: {}", error)))?;
+
+		log::info!("adding macro '{}'", name);
+
+		self.edit_config(|config|
+		{
+			let previous = config.macros
+				.get_or_insert_with(HashMap::new)
+				.insert(name.clone(), new_macro);
+
+			Ok(move |config: &mut Configuration|
+			{
+				let macros = config.macros.get_or_insert_with(HashMap::new);
+
+				match previous
+				{
+					Some(previous) => { macros.insert(name, previous); },
+					None => { macros.remove(&name); }
+				}
+			})
+		})
+	}
+
+	/// Assigns a named macro to a gkey in one of a profile's modes (or in the
+	/// profile itself, for every mode without its own assignment, with mode
+	/// 0), and saves the config file
+	pub fn assign_g_key(&mut self, profile: String, mode: u8, gkey: u8, macro_name: String) -> zbus::fdo::Result<()>
+	{
+		log::info!("assigning macro '{}' to G{} in mode {} of profile '{}'", macro_name, gkey, mode, profile);
+
+		self.edit_config(|config|
+		{
+			let (edited, mode) = editable_mode(config, &profile, mode)?;
+			let previous = edited
+				.gkeys_mut(mode)
+				.get_or_insert_with(HashMap::new)
+				.insert(gkey, MacroKeyAssignment::NamedMacro(macro_name));
+
+			Ok(move |config: &mut Configuration| if let Ok(edited) = editable_profile(config, &profile)
+			{
+				restore_gkey(edited, mode, gkey, previous);
+			})
+		})
+	}
+
+	/// Removes a gkey's assignment from one of a profile's modes (0 for the
+	/// profile itself), and saves the config file
+	pub fn remove_assignment(&mut self, profile: String, mode: u8, gkey: u8) -> zbus::fdo::Result<()>
+	{
+		log::info!("removing the assignment of G{} in mode {} of profile '{}'", gkey, mode, profile);
+
+		self.edit_config(|config|
+		{
+			let (edited, edited_mode) = editable_mode(config, &profile, mode)?;
+
+			// looked up without adding the mode, so nothing is left behind if
+			// the gkey isn't assigned
+			let previous = edited
+				.existing_gkeys_mut(edited_mode)
+				.and_then(|gkeys| gkeys.as_mut())
+				.and_then(|assignments| assignments.remove(&gkey))
+				.ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("G{} isn't assigned in mode {} of profile '{}'",
+					gkey, mode, profile)))?;
+
+			restore_gkey(edited, edited_mode, gkey, None);
+
+			Ok(move |config: &mut Configuration| if let Ok(edited) = editable_profile(config, &profile)
+			{
+				restore_gkey(edited, edited_mode, gkey, Some(previous));
+			})
+		})
+	}
+
+	/// Lists the names of the config file backups, newest first
+	pub fn list_backups(&self) -> zbus::fdo::Result<Vec<String>>
+	{
+		Configuration::backups().map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Restores a config file backup (the newest if the name is empty). The
+	/// restored config is then reloaded as if it had been edited.
+	pub fn restore_backup(&mut self, name: String) -> zbus::fdo::Result<String>
+	{
+		let name = Some(name.as_str()).filter(|name| !name.is_empty());
+
+		Configuration::restore_backup(name).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Puts all connected devices into palette (tester) mode, starting at pure red.
+	/// The current color is rendered across the whole keyboard until stopped.
+	pub fn start_palette_mode(&mut self)
+	{
+		let start = hsl::HSL { h: 0_f64, s: 1_f64, l: 0.5_f64 };
+		self.tx.send(MainThreadSignal::PaletteChanged(Some(start)));
+	}
+
+	/// Leaves palette mode, returning the devices to the active profile's theme
+	pub fn stop_palette_mode(&mut self)
+	{
+		self.tx.send(MainThreadSignal::PaletteChanged(None));
+	}
+
+	/// Shifts the hue of the palette color by the given number of degrees
+	/// (negative to decrease). Does nothing if palette mode isn't active.
+	pub fn adjust_palette_hue(&mut self, degrees: f64)
+	{
+		let current = { *self.state.palette.read().unwrap() };
+
+		if let Some(mut palette) = current
+		{
+			palette.h = (palette.h + degrees).rem_euclid(360_f64);
+			self.tx.send(MainThreadSignal::PaletteChanged(Some(palette)));
+		}
+	}
+
+	/// Pins the media player to track and control, by a regex matching either its
+	/// identity or dbus name. Takes priority over the players in the config file.
+	pub fn pin_media_player(&mut self, pattern: String) -> zbus::fdo::Result<()>
 	{
-		log::debug!("test was called");
-		"test".into()
+		let regex = regex::Regex::new(&pattern)
+			.map_err(|e| zbus::fdo::Error::InvalidArgs(e.to_string()))?;
+
+		self.tx.send(MainThreadSignal::PinMediaPlayer(Some(regex)));
+		Ok(())
+	}
+
+	/// Removes the pinned media player, going back to the config's priority order
+	pub fn unpin_media_player(&mut self)
+	{
+		self.tx.send(MainThreadSignal::PinMediaPlayer(None));
+	}
+
+	/// Turns game mode on or off
+	pub fn set_game_mode(&mut self, enabled: bool)
+	{
+		self.tx.send(MainThreadSignal::SetGameMode(match enabled
+		{
+			true => GameModeCommand::On,
+			false => GameModeCommand::Off
+		}));
+	}
+
+	/// Turns game mode on if it's off, or off if it's on
+	pub fn toggle_game_mode(&mut self)
+	{
+		self.tx.send(MainThreadSignal::SetGameMode(GameModeCommand::Toggle));
+	}
+
+	/// Switches a part of the driver on or off: `media` (watching media players
+	/// and volume), `windows` (switching profiles by the active window),
+	/// `reactive_lighting` (indicators drawn over themes), `widgets` (hud,
+	/// heatmap and screen themes) or `scheduler` (playlists). Switched off subsystems stay
+	/// off after a restart.
+	pub fn enable_subsystem(&mut self, name: String, enabled: bool) -> zbus::fdo::Result<()>
+	{
+		let subsystem = Subsystem::from_name(&name)
+			.ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("there is no subsystem called '{}'", name)))?;
+
+		self.tx.send(MainThreadSignal::EnableSubsystem(subsystem, enabled));
+		Ok(())
+	}
+
+	/// Gets the driver's current state as json: the active profile, the modes
+	/// it's in, which subsystems are switched on, and whether it's degraded
+	/// (a thread has stopped, with the number of messages it's missed)
+	pub fn get_state(&self) -> zbus::fdo::Result<String>
+	{
+		serde_json::to_string_pretty(&self.state.summary()).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Gets the driver's health counters as json, see health::DriverStats
+	pub fn get_stats(&self) -> zbus::fdo::Result<String>
+	{
+		serde_json::to_string_pretty(&self.state.driver_stats.summary()).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Gets what each open keyboard reports about itself as json: its product,
+	/// serial number, firmware and bootloader versions, the HID++ features the
+	/// driver uses (with their key counts) and its report rates
+	pub fn get_device_info(&self) -> zbus::fdo::Result<String>
+	{
+		serde_json::to_string_pretty(&*self.state.devices.read().unwrap()).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Switches every keyboard to a mode, including virtual modes
+	pub fn set_mode(&mut self, mode: u8) -> zbus::fdo::Result<()>
+	{
+		let modes = self.state.config.read().unwrap().modes();
+
+		if !modes.contains(&mode)
+		{
+			return Err(zbus::fdo::Error::InvalidArgs(format!("there is no mode {}, only {} to {}",
+				mode, modes.start(), modes.end())))
+		}
+
+		self.tx.send(MainThreadSignal::SetMode(mode));
+		Ok(())
+	}
+
+	/// Switches to a profile until the active window next changes (or for good,
+	/// whilst the profile is locked)
+	pub fn switch_profile(&mut self, name: String) -> zbus::fdo::Result<()>
+	{
+		if !self.state.config.read().unwrap().profiles.contains_key(&name)
+		{
+			return Err(zbus::fdo::Error::InvalidArgs(format!("there is no profile called '{}'", name)))
+		}
+
+		self.tx.send(MainThreadSignal::SwitchProfile(name));
+		Ok(())
+	}
+
+	/// Shows a profile, whatever the active window, until it's popped or `ttl`
+	/// ms have passed (0 for no ttl). Pushing another profile shows that one
+	/// instead, until it's popped in turn
+	pub fn push_profile(&mut self, name: String, ttl: u32) -> zbus::fdo::Result<()>
+	{
+		if !self.state.config.read().unwrap().profiles.contains_key(&name)
+		{
+			return Err(zbus::fdo::Error::InvalidArgs(format!("there is no profile called '{}'", name)))
+		}
+
+		self.tx.send(MainThreadSignal::PushProfile(name, (ttl > 0).then(|| Duration::from_millis(ttl as u64))));
+		Ok(())
+	}
+
+	/// Removes the last pushed profile
+	pub fn pop_profile(&mut self) -> zbus::fdo::Result<()>
+	{
+		if self.state.profile_overrides.read().unwrap().is_empty()
+		{
+			return Err(zbus::fdo::Error::Failed("no profile has been pushed".into()))
+		}
+
+		self.tx.send(MainThreadSignal::PopProfile);
+		Ok(())
+	}
+
+	/// Runs one of the config's named macros, as if a gkey assigned it had
+	/// been pressed: running it again stops a toggle macro, or restarts the
+	/// count of a repeat macro
+	pub fn run_macro(&mut self, name: String) -> zbus::fdo::Result<()>
+	{
+		let exists = self.state.config
+			.read()
+			.unwrap()
+			.macros
+			.as_ref()
+			.map(|macros| macros.contains_key(&name))
+			.unwrap_or(false);
+
+		if !exists
+		{
+			return Err(zbus::fdo::Error::InvalidArgs(format!("there is no macro called '{}'", name)))
+		}
+
+		self.tx.send(MainThreadSignal::RunMacro(name));
+		Ok(())
+	}
+
+	/// Gets what the active profile means in each mode as json: the theme and
+	/// the assignment of each gkey, with where it came from and the assignments
+	/// it overrides (i.e. the same gkey in an earlier gkey set)
+	pub fn get_effective_profile(&self) -> zbus::fdo::Result<String>
+	{
+		let config = self.state.config.read().unwrap();
+		let profile = self.state.active_profile.read().unwrap();
+
+		let modes: serde_json::Map<String, serde_json::Value> = config.modes()
+			.map(|mode|
+			{
+				let resolver = ProfileResolver::new(&config, &profile, mode);
+
+				(mode.to_string(), serde_json::json!({
+					"theme": resolver.theme_name().unwrap_or("default"),
+					"gkeys": gkey_assignments(&resolver)
+				}))
+			})
+			.collect();
+
+		let effective_profile = serde_json::json!({
+			"profile": *self.state.active_profile_name.read().unwrap(),
+			"modes": modes
+		});
+
+		serde_json::to_string_pretty(&effective_profile).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Gets everything resolved for the active window as json, to explain why
+	/// a key is lit the way it is or a gkey runs what it does: the window, the
+	/// profile and why it's active, the mode, the theme and anything shown in
+	/// its place, the gkey assignments in the mode, and the color of every key
+	/// and effect as last drawn on the (first) keyboard
+	pub fn get_resolved_state(&self) -> zbus::fdo::Result<String>
+	{
+		let config = self.state.config.read().unwrap();
+		let profile = self.state.active_profile.read().unwrap();
+		let name = self.state.active_profile_name.read().unwrap().clone();
+		let active_window = self.state.active_window.read().unwrap().clone();
+
+		let mode = self.state.persisted
+			.read()
+			.profile_modes
+			.get(&name)
+			.copied()
+			.filter(|mode| config.modes().contains(mode))
+			.unwrap_or(1);
+
+		let reason = match (self.state.profile_overrides.read().unwrap().is_empty(), self.state.profile_locked.load(Ordering::Relaxed))
+		{
+			(false, _) => "pushed with PushProfile",
+			(true, true) => "locked, by a long press of a mode key or cycle_profiles",
+			(true, false) if name == "default" => "no other profile's conditions match",
+			(true, false) => "its conditions match the active window, media player or time"
+		};
+
+		let resolver = ProfileResolver::new(&config, &profile, mode);
+		let (colors, effects) = self.state.mirror.leader_lighting();
+
+		let keys: std::collections::BTreeMap<String, Color> = colors
+			.into_iter()
+			.map(|(scancode, color)| (scancode.config_name(), color))
+			.collect();
+
+		let effect = |group| effects.get(&group).filter(|effect| **effect != EffectConfiguration::None);
+
+		let resolved_state = serde_json::json!({
+			"window": active_window,
+			"profile": name,
+			"reason": reason,
+			"mode": mode,
+			"theme": resolver.theme_name().unwrap_or("default"),
+			"playlist": *self.state.playlist.read().unwrap(),
+			"effect_override": *self.state.effect.read().unwrap(),
+			"focus_mode": self.state.focus_mode.load(Ordering::Relaxed),
+			"game_mode": self.state.game_mode.load(Ordering::Relaxed),
+			"color_temperature": *self.state.color_temperature.read().unwrap(),
+			"gkeys": gkey_assignments(&resolver),
+			"keys": keys,
+			"effects": {
+				"keys": effect(EffectGroup::Keys),
+				"logo": effect(EffectGroup::Logo)
+			}
+		});
+
+		serde_json::to_string_pretty(&resolved_state).map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+	}
+
+	/// Lights the logo in a color (as written in the config), in place of the
+	/// profile's logo, until it's cleared with an empty string. The driver's
+	/// own status (i.e. macro recording) is still shown over it.
+	pub fn set_logo_color(&mut self, color: String) -> zbus::fdo::Result<()>
+	{
+		let color = match color.trim().is_empty()
+		{
+			true => None,
+			false => Some(Color::parse(&color).map_err(zbus::fdo::Error::InvalidArgs)?)
+		};
+
+		self.tx.send(MainThreadSignal::SetLogoColor(color));
+		Ok(())
+	}
+
+	/// Shows a firmware effect on the keys (written as in the config, i.e.
+	/// `{type: cycle, duration: 5000, brightness: 100}`) in place of the
+	/// profile's theme, until it's cleared with an empty string
+	pub fn set_effect(&mut self, effect: String) -> zbus::fdo::Result<()>
+	{
+		let effect = match effect.trim().is_empty()
+		{
+			true => None,
+			false => Some(serde_yaml::from_str(&effect)
+				.map_err(|error| zbus::fdo::Error::InvalidArgs(format!("that isn't an effect: {}", error)))?)
+		};
+
+		self.tx.send(MainThreadSignal::SetEffect(effect));
+		Ok(())
+	}
+
+	/// Multiplies every macro step's duration by `scale` (i.e. 0.5 to run
+	/// macros twice as fast) in place of the config's macro_timing scale, or
+	/// goes back to the config's with 0. Running macros pick it up the next
+	/// time they repeat.
+	pub fn set_macro_timing_scale(&mut self, scale: f64) -> zbus::fdo::Result<()>
+	{
+		let scale = match scale
+		{
+			scale if scale == 0_f64 => None,
+			scale if scale > 0_f64 && scale.is_finite() => Some(scale),
+			_ => return Err(zbus::fdo::Error::InvalidArgs("the scale has to be more than 0 (or 0 for the config's)".to_string()))
+		};
+
+		self.tx.send(MainThreadSignal::SetMacroTimingScale(scale));
+		Ok(())
+	}
+
+	/// Flashes the keys of any notification_flashes matching a notification,
+	/// for programs that don't send desktop notifications
+	pub fn notify(&mut self, application: String, summary: String, body: String)
+	{
+		self.tx.send(MainThreadSignal::FlashNotification(Notification
+		{
+			application,
+			summary,
+			body
+		}));
+	}
+
+	/// Moves the active profile's playlist on to its next theme
+	pub fn skip_playlist_theme(&mut self)
+	{
+		self.tx.send(MainThreadSignal::Playlist(PlaylistCommand::Skip));
+	}
+
+	/// Stops the active profile's playlist on its current theme, until it's
+	/// resumed or the profile changes
+	pub fn pause_playlist(&mut self)
+	{
+		self.tx.send(MainThreadSignal::Playlist(PlaylistCommand::Pause));
+	}
+
+	pub fn resume_playlist(&mut self)
+	{
+		self.tx.send(MainThreadSignal::Playlist(PlaylistCommand::Resume));
+	}
+
+	/// Turns focus mode on or off, lighting just the important keys brightly
+	pub fn set_focus_mode(&mut self, enabled: bool)
+	{
+		self.tx.send(MainThreadSignal::SetFocusMode(enabled));
+	}
+
+	/// Turns focus mode on if it's off, or off if it's on
+	pub fn toggle_focus_mode(&mut self)
+	{
+		let enabled = self.state.focus_mode.load(Ordering::Relaxed);
+		self.tx.send(MainThreadSignal::SetFocusMode(!enabled));
+	}
+
+	/// Turns low power mode on or off, capping how brightly each key is lit
+	pub fn set_low_power_mode(&mut self, enabled: bool)
+	{
+		self.tx.send(MainThreadSignal::SetLowPowerMode(enabled));
+	}
+
+	/// Turns low power mode on if it's off, or off if it's on
+	pub fn toggle_low_power_mode(&mut self)
+	{
+		let enabled = self.state.low_power.load(Ordering::Relaxed);
+		self.tx.send(MainThreadSignal::SetLowPowerMode(!enabled));
+	}
+
+	/// Sets the color of many keys at once for a client (a name chosen by the
+	/// caller, each client has its own set of colors). Colors are a map of key
+	/// name to either a color string as written in the config or a `0xrrggbb`
+	/// integer. They're removed after `ttl` ms, or stay until cleared if it's 0.
+	pub fn set_key_colors(&mut self, client: String, colors: HashMap<String, OwnedValue>, ttl: u32)
+		-> zbus::fdo::Result<()>
+	{
+		let colors = colors
+			.iter()
+			.map(|(key, color)| Ok((parse_key(key)?, parse_color(key, color)?)))
+			.collect::<zbus::fdo::Result<_>>()?;
+
+		self.set_client_colors(client, colors, false, ttl);
+		Ok(())
+	}
+
+	/// Sets the color of every key for a client in one go, replacing any it set
+	/// before. The frame is the red, green and blue bytes of each key, in the
+	/// order given by `GetFrameKeys`.
+	pub fn set_key_frame(&mut self, client: String, frame: Vec<u8>, ttl: u32) -> zbus::fdo::Result<()>
+	{
+		let keys: Vec<Scancode> = Scancode::iter_variants().collect();
+
+		if frame.len() != keys.len() * 3
+		{
+			return Err(zbus::fdo::Error::InvalidArgs(format!(
+				"a frame is 3 bytes for each of the {} keys, not {} bytes", keys.len(), frame.len())))
+		}
+
+		let colors = keys
+			.into_iter()
+			.zip(frame.chunks(3))
+			.map(|(scancode, rgb)| (scancode, Color::new(rgb[0], rgb[1], rgb[2])))
+			.collect();
+
+		self.set_client_colors(client, colors, true, ttl);
+		Ok(())
+	}
+
+	/// Lists the keys in a frame sent to `SetKeyFrame`, in order
+	pub fn get_frame_keys(&self) -> Vec<String>
+	{
+		Scancode::iter_variants()
+			.map(|scancode| scancode.config_name())
+			.collect()
+	}
+
+	/// Removes all of the colors set by a client
+	pub fn clear_key_colors(&mut self, client: String)
+	{
+		self.tx.send(MainThreadSignal::ClearKeyColors(client));
+	}
+
+	/// The minor version of this interface, increased whenever something is added
+	#[dbus_interface(property)]
+	fn interface_version(&self) -> u32
+	{
+		Server::INTERFACE_VERSION
+	}
+
+	/// Whether game mode is on, disabling the profile's game mode keys
+	#[dbus_interface(property)]
+	fn game_mode(&self) -> bool
+	{
+		self.state.game_mode.load(Ordering::Relaxed)
+	}
+
+	/// Sent when game mode is turned on or off
+	#[dbus_interface(signal)]
+	fn game_mode_changed(&self, enabled: bool) -> zbus::Result<()>;
+
+	/// Whether low power mode is on, capping how brightly each key is lit
+	#[dbus_interface(property)]
+	fn low_power_mode(&self) -> bool
+	{
+		self.state.low_power.load(Ordering::Relaxed)
+	}
+
+	/// Sent when low power mode is turned on or off
+	#[dbus_interface(signal)]
+	fn low_power_mode_changed(&self, enabled: bool) -> zbus::Result<()>;
+
+	/// Whether focus mode is on, lighting just the important keys brightly
+	#[dbus_interface(property)]
+	fn focus_mode(&self) -> bool
+	{
+		self.state.focus_mode.load(Ordering::Relaxed)
+	}
+
+	/// Sent when focus mode is turned on or off
+	#[dbus_interface(signal)]
+	fn focus_mode_changed(&self, enabled: bool) -> zbus::Result<()>;
+
+	/// The identity of the media player being tracked (e.g. "Spotify"), or an empty
+	/// string if there isn't one
+	#[dbus_interface(property)]
+	fn media_player(&self) -> String
+	{
+		self.state.media_state.read().unwrap().player.clone().unwrap_or_default()
+	}
+
+	/// The artist(s) of the currently playing track, or an empty string if unknown
+	#[dbus_interface(property)]
+	fn media_artist(&self) -> String
+	{
+		self.state.media_state.read().unwrap().artist.clone().unwrap_or_default()
+	}
+
+	/// The title of the currently playing track, or an empty string if unknown
+	#[dbus_interface(property)]
+	fn media_title(&self) -> String
+	{
+		self.state.media_state.read().unwrap().title.clone().unwrap_or_default()
+	}
+
+	/// The current palette color as a hex code, or an empty string if palette
+	/// mode isn't active
+	#[dbus_interface(property)]
+	fn palette_color(&self) -> String
+	{
+		self.state.palette
+			.read()
+			.unwrap()
+			.map(|palette| format!("{:x}", crate::device::color::Color::from(palette)))
+			.unwrap_or_default()
+	}
+}
+
+impl ServerInterface
+{
+	/// Changes the config in memory, then saves it to the config file (which is
+	/// then reloaded as if it had been edited). `edit` returns how to undo its
+	/// change, which is done if the config would no longer be valid or it
+	/// can't be saved, so the config in memory is always what's in the file.
+	fn edit_config<E, U>(&self, edit: E) -> zbus::fdo::Result<()>
+		where E: FnOnce(&mut Configuration) -> zbus::fdo::Result<U>,
+			U: FnOnce(&mut Configuration)
+	{
+		let mut config = self.state.config.write().unwrap();
+		let undo = edit(&mut config)?;
+		let problems = config.problems();
+
+		let saved = match problems.is_empty()
+		{
+			true => config.save().map_err(|error| zbus::fdo::Error::Failed(error.to_string())),
+			false => Err(zbus::fdo::Error::InvalidArgs(problems.join("\n")))
+		};
+
+		if saved.is_err()
+		{
+			undo(&mut config);
+		}
+
+		saved
+	}
+
+	fn set_client_colors(&self, client: String, colors: Vec<(Scancode, Color)>, replace: bool, ttl: u32)
+	{
+		self.tx.send(MainThreadSignal::SetKeyColors(KeyColorUpdate
+		{
+			client,
+			colors,
+			replace,
+			ttl: (ttl > 0).then(|| ttl as u64)
+		}));
 	}
 }
 
-pub enum DBusSignal
+/// The assignment each gkey uses in a profile's mode, with where it came from
+/// and the assignments it overrides (i.e. the same gkey in an earlier gkey set)
+fn gkey_assignments(resolver: &ProfileResolver) -> serde_json::Map<String, serde_json::Value>
 {
-	Shutdown,
-	SendMessage(zbus::Message)
+	(1..=5)
+		.filter_map(|gkey|
+		{
+			let sources = resolver.gkey_sources(gkey);
+			let (used, overridden) = sources.split_first()?;
+
+			Some((format!("G{}", gkey), serde_json::json!({
+				"assignment": used.assignment,
+				"source": used.to_string(),
+				"overrides": overridden.iter().map(ToString::to_string).collect::<Vec<String>>()
+			})))
+		})
+		.collect()
+}
+
+/// A profile that can be changed and saved, which those from the include
+/// folders can't be
+fn editable_profile<'a>(config: &'a mut Configuration, profile: &str) -> zbus::fdo::Result<&'a mut Profile>
+{
+	if config.included.profiles.iter().any(|included| included == profile)
+	{
+		return Err(zbus::fdo::Error::InvalidArgs(format!(
+			"profile '{}' is from an include folder, so can't be saved", profile)))
+	}
+
+	config.profiles
+		.get_mut(profile)
+		.ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("there is no profile called '{}'", profile)))
+}
+
+/// A profile that can be edited, and which of its modes to edit the gkeys of
+/// (None for the profile's own, given as mode 0)
+fn editable_mode<'a>(config: &'a mut Configuration, profile: &str, mode: u8) -> zbus::fdo::Result<(&'a mut Profile, Option<u8>)>
+{
+	let modes = config.modes();
+
+	let mode = match mode
+	{
+		0 => None,
+		mode if modes.contains(&mode) => Some(mode),
+		mode => return Err(zbus::fdo::Error::InvalidArgs(format!(
+			"there is no mode {}, only {} to {} (or 0 for the profile's own gkeys)", mode, modes.start(), modes.end())))
+	};
+
+	Ok((editable_profile(config, profile)?, mode))
+}
+
+/// Puts a gkey's assignment back as it was, leaving no assignments (or mode)
+/// rather than an empty set of them
+fn restore_gkey(profile: &mut Profile, mode: Option<u8>, gkey: u8, assignment: Option<MacroKeyAssignment>)
+{
+	let gkeys = profile.gkeys_mut(mode);
+	let assignments = gkeys.get_or_insert_with(HashMap::new);
+
+	match assignment
+	{
+		Some(assignment) => { assignments.insert(gkey, assignment); },
+		None => { assignments.remove(&gkey); }
+	}
+
+	if assignments.is_empty()
+	{
+		*gkeys = None;
+	}
+
+	if let Some(mode) = mode
+	{
+		profile.remove_mode_if_empty(mode);
+	}
+}
+
+fn parse_key(name: &str) -> zbus::fdo::Result<Scancode>
+{
+	crate::parse_key(name).map_err(zbus::fdo::Error::InvalidArgs)
+}
+
+fn parse_color(key: &str, color: &OwnedValue) -> zbus::fdo::Result<Color>
+{
+	match &**color
+	{
+		Value::U32(color) => Ok(Color::from(*color)),
+		Value::Str(color) => Color::parse(color.as_str())
+			.map_err(|error| zbus::fdo::Error::InvalidArgs(format!("{}: {}", key, error))),
+		_ => Err(zbus::fdo::Error::InvalidArgs(format!(
+			"{}: colors must be a string or an unsigned integer", key)))
+	}
 }
 
 pub struct Server
 {
 	rx: Receiver<DBusSignal>,
-	tx: Sender<MainThreadSignal>,
+	tx: MonitoredSender<MainThreadSignal>,
+	state: Arc<SharedState>,
 	proxy: DBusProxy<'static>,
 	connection: Connection,
 	server: ObjectServer<'static>
@@ -39,8 +897,28 @@ impl Server
 {
 	const BUS_NAME: &'static str = "rs.lave.g815_driver";
 	const BUS_PATH: &'static str = "/rs/lave/g815_driver";
+	const INTERFACE_VERSION: u32 = 19;
+
+	/// Whether another instance of the driver already owns the bus name. If the
+	/// session bus can't be reached there's nothing to tell, so it's assumed not.
+	pub fn already_running() -> bool
+	{
+		Connection::new_session()
+			.and_then(|connection| Ok(DBusProxy::new(&connection)?.name_has_owner(Self::BUS_NAME)?))
+			.unwrap_or(false)
+	}
 
-	pub fn new(rx: Receiver<DBusSignal>, tx: Sender<MainThreadSignal>) -> Self
+	/// Asks the running driver for GetResolvedState
+	pub fn resolved_state() -> zbus::Result<String>
+	{
+		let reply = Connection::new_session()?
+			.call_method(Some(Self::BUS_NAME), Self::BUS_PATH, Some("rs.lave.g815_driver1"), "GetResolvedState", &())?;
+
+		Ok(reply.body::<String>()?)
+	}
+
+	pub fn new(rx: Receiver<DBusSignal>, tx: MonitoredSender<MainThreadSignal>, state: Arc<SharedState>)
+		-> Self
 	{
 		let handshake = zbus::handshake::ClientHandshake::new_session_nonblock().unwrap();
 		let authenticated_socket = handshake.blocking_finish().unwrap();
@@ -53,7 +931,7 @@ impl Server
 		proxy.request_name(Self::BUS_NAME, RequestNameFlags::ReplaceExisting.into()).unwrap();
 
 		let mut server = ObjectServer::new(&connection);
-		let interface = ServerInterface {};
+		let interface = ServerInterface { state: Arc::clone(&state), tx: tx.clone() };
 
 		server.at(&Self::BUS_PATH.try_into().unwrap(), interface).unwrap();
 
@@ -61,32 +939,142 @@ impl Server
 		{
 			tx,
 			rx,
+			state,
 			proxy,
 			server,
 			connection
 		}
 	}
 
-	pub fn run(&mut self)
+	pub async fn run(&mut self)
 	{
+		let bus = match daemon::Readable::new(self.connection.as_raw_fd())
+		{
+			Ok(bus) => bus,
+			Err(error) => return log::error!("unable to wait for dbus messages: {}", error)
+		};
+
 		loop
 		{
-			match self.rx.try_recv()
+			// woken by a signal from the rest of the driver, or by something to
+			// read from the bus (the Err)
+			let woken = future::or(
+				async { Ok(self.rx.recv().await) },
+				async { Err(bus.wait().await) }).await;
+
+			let signal = match woken
+			{
+				Ok(signal) => signal,
+				Err(Ok(())) =>
+				{
+					self.handle_messages();
+					continue
+				},
+				Err(Err(error)) =>
+				{
+					log::error!("stopped handling dbus messages: {}", error);
+					break
+				}
+			};
+
+			match signal
 			{
 				Ok(DBusSignal::Shutdown)
-					| Err(TryRecvError::Disconnected) => break,
+					| Err(_) => break,
+
+				Ok(DBusSignal::Notify { summary, body }) =>
+				{
+					let hints: HashMap<&str, Value> = HashMap::new();
+					let message = zbus::Message::method(
+						None,
+						Some("org.freedesktop.Notifications"),
+						"/org/freedesktop/Notifications",
+						Some("org.freedesktop.Notifications"),
+						"Notify",
+						&(Notification::APPLICATION, 0_u32, "input-keyboard", summary, body, Vec::<&str>::new(), hints, -1_i32));
+
+					self.send_message(message);
+				},
+
+				Ok(DBusSignal::CallMethod { destination, path, interface, method, arguments }) =>
+				{
+					let message = zbus::Message::method(
+						None,
+						Some(&destination),
+						&path,
+						Some(&interface),
+						&method,
+						&arguments);
+
+					self.send_message(message);
+				},
+
+				Ok(DBusSignal::MediaControl(command)) =>
+				{
+					let player_service = self.state.media_state.read().unwrap().player_service.clone();
+
+					match player_service.map(|service| command.message(&service))
+					{
+						Some(Ok(message)) =>
+						{
+							if let Err(error) = self.connection.send_message(message)
+							{
+								log::warn!("failed to send media command ({:#?})", error);
+							}
+						},
+						Some(Err(error)) => log::warn!("failed to build media command ({:#?})", error),
+						None => log::warn!("no media player to send {:?} to", command)
+					}
+				},
+
+				Ok(DBusSignal::GameModeChanged(enabled)) =>
+				{
+					let result = self.server.with(
+						&Self::BUS_PATH.try_into().unwrap(),
+						|interface: &ServerInterface| interface.game_mode_changed(enabled));
+
+					if let Err(error) = result
+					{
+						log::warn!("failed to send game mode signal ({:#?})", error);
+					}
+				},
 
-				Err(TryRecvError::Empty) => thread::sleep(Duration::from_millis(10)),
+				Ok(DBusSignal::LowPowerModeChanged(enabled)) =>
+				{
+					let result = self.server.with(
+						&Self::BUS_PATH.try_into().unwrap(),
+						|interface: &ServerInterface| interface.low_power_mode_changed(enabled));
+
+					if let Err(error) = result
+					{
+						log::warn!("failed to send low power mode signal ({:#?})", error);
+					}
+				},
 
-				Ok(DBusSignal::SendMessage(message)) =>
+				Ok(DBusSignal::FocusModeChanged(enabled)) =>
 				{
-					if let Err(error) = self.connection.send_message(message)
+					let result = self.server.with(
+						&Self::BUS_PATH.try_into().unwrap(),
+						|interface: &ServerInterface| interface.focus_mode_changed(enabled));
+
+					if let Err(error) = result
 					{
-						log::warn!("failed to send dbus message ({:#?})", error);
+						log::warn!("failed to send focus mode signal ({:#?})", error);
 					}
 				}
 			}
 
+		}
+
+		self.server.remove::<ServerInterface>(&Self::BUS_PATH.try_into().unwrap());
+		self.proxy.release_name(Self::BUS_NAME);
+	}
+
+	/// Handles everything that's arrived on the bus, until there's nothing left to read
+	fn handle_messages(&mut self)
+	{
+		loop
+		{
 			match self.server.try_handle_next()
 			{
 				Err(zbus::Error::Io(io_error)) =>
@@ -95,6 +1083,8 @@ impl Server
 					{
 						log::warn!("dbus io error = {:?}", io_error);
 					}
+
+					break
 				},
 				Err(error) =>
 				{
@@ -103,8 +1093,15 @@ impl Server
 				_ => ()
 			}
 		}
+	}
 
-		self.server.remove::<ServerInterface>(&Self::BUS_PATH.try_into().unwrap());
-		self.proxy.release_name(Self::BUS_NAME);
+	fn send_message(&self, message: Result<zbus::Message, zbus::MessageError>)
+	{
+		match message.map(|message| self.connection.send_message(message))
+		{
+			Ok(Ok(_)) => (),
+			Ok(Err(error)) => log::warn!("failed to send dbus message ({:#?})", error),
+			Err(error) => log::warn!("failed to build dbus message ({:#?})", error)
+		}
 	}
 }
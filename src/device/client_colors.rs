@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use super::color::Color;
+use super::scancode::Scancode;
+
+/// Key colors sent by another program (over dbus), i.e. a music visualizer
+#[derive(Debug, Clone)]
+pub struct KeyColorUpdate
+{
+	/// the name the program gave itself, each has its own set of colors
+	pub client: String,
+	pub colors: Vec<(Scancode, Color)>,
+	/// replace all of the client's colors (a whole frame), rather than just
+	/// the keys given
+	pub replace: bool,
+	/// ms until the colors are removed again, None to keep them until cleared
+	pub ttl: Option<u64>
+}
+
+/// Tells whoever sent some key colors that they've been drawn, so a macro can
+/// press keys in the same frame as it lights them
+#[derive(Debug, Clone)]
+pub struct DrawnSignal(Sender<()>);
+
+impl DrawnSignal
+{
+	pub fn new() -> (Self, Receiver<()>)
+	{
+		let (tx, rx) = channel();
+		(Self(tx), rx)
+	}
+
+	pub fn drawn(&self)
+	{
+		// nobody waiting any more isn't a problem
+		let _ = self.0.send(());
+	}
+}
+
+/// The key colors set by each client. Clients are layered in the order they
+/// first set a color, so a later client's colors are drawn over an earlier's.
+#[derive(Default)]
+pub struct ClientColors
+{
+	layers: Vec<(String, HashMap<Scancode, (Color, Option<u64>)>)>
+}
+
+impl ClientColors
+{
+	/// Updates a client's colors, returning the keys that may need redrawing
+	pub fn update(&mut self, update: KeyColorUpdate) -> Vec<Scancode>
+	{
+		let index = match self.layers.iter().position(|(client, _)| *client == update.client)
+		{
+			Some(index) => index,
+			None =>
+			{
+				self.layers.push((update.client, HashMap::new()));
+				self.layers.len() - 1
+			}
+		};
+
+		let layer = &mut self.layers[index].1;
+		let mut changed: Vec<Scancode> = match update.replace
+		{
+			true => layer.drain().map(|(scancode, _)| scancode).collect(),
+			false => Vec::new()
+		};
+
+		for (scancode, color) in update.colors
+		{
+			layer.insert(scancode, (color, update.ttl));
+			changed.push(scancode);
+		}
+
+		changed
+	}
+
+	/// Removes all of a client's colors, returning the keys they were on
+	pub fn clear(&mut self, client: &str) -> Vec<Scancode>
+	{
+		match self.layers.iter().position(|(name, _)| name == client)
+		{
+			Some(index) => self.layers.remove(index).1.into_keys().collect(),
+			None => Vec::new()
+		}
+	}
+
+	/// Counts down the colors with a ttl, removing the ones that have expired
+	/// (and any clients left without colors), returning the keys they were on
+	pub fn expire(&mut self, elapsed: u64) -> Vec<Scancode>
+	{
+		let mut expired = Vec::new();
+
+		for (_, layer) in &mut self.layers
+		{
+			layer.retain(|scancode, (_, ttl)| match ttl
+			{
+				Some(ttl) if *ttl <= elapsed =>
+				{
+					expired.push(*scancode);
+					false
+				},
+				Some(ttl) =>
+				{
+					*ttl -= elapsed;
+					true
+				},
+				None => true
+			});
+		}
+
+		self.layers.retain(|(_, layer)| !layer.is_empty());
+		expired
+	}
+
+	/// The color of the topmost client's key, if any client has set it
+	pub fn color(&self, scancode: Scancode) -> Option<Color>
+	{
+		self.layers
+			.iter()
+			.rev()
+			.find_map(|(_, layer)| layer.get(&scancode).map(|(color, _)| *color))
+	}
+
+	/// Every key set by a client, with its topmost color
+	pub fn colors(&self) -> HashMap<Scancode, Color>
+	{
+		self.layers
+			.iter()
+			.flat_map(|(_, layer)| layer.iter().map(|(scancode, (color, _))| (*scancode, *color)))
+			.collect()
+	}
+
+	pub fn is_empty(&self) -> bool
+	{
+		self.layers.is_empty()
+	}
+}
@@ -0,0 +1,451 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use super::{CommandResult, CommandStats, Device, DeviceEvent, DeviceInfo, OnboardLighting};
+use super::color::Color;
+use super::rgb::{EffectConfiguration, EffectGroup};
+use super::scancode::Scancode;
+
+/// Shows the same lighting on every keyboard (lighting.mirror). The first
+/// keyboard opened leads: it draws as usual, and each frame it commits is kept
+/// here for the others to show in place of drawing their own.
+#[derive(Default)]
+pub struct LightingMirror
+{
+	inner: Mutex<MirrorState>
+}
+
+#[derive(Default)]
+struct MirrorState
+{
+	enabled: bool,
+	// the open keyboards' serial numbers, in the order they were opened
+	keyboards: Vec<String>,
+	// the leader's lighting as of its last commit
+	colors: HashMap<Scancode, Color>,
+	effects: HashMap<EffectGroup, EffectConfiguration>,
+	// counts the leader's changes, so followers know when they're behind
+	generation: u64
+}
+
+impl LightingMirror
+{
+	pub fn set_enabled(&self, enabled: bool)
+	{
+		self.inner.lock().unwrap().enabled = enabled;
+	}
+
+	fn join(&self, serial_number: &str)
+	{
+		self.inner.lock().unwrap().keyboards.push(serial_number.to_string());
+	}
+
+	fn leave(&self, serial_number: &str)
+	{
+		let mut state = self.inner.lock().unwrap();
+
+		if let Some(index) = state.keyboards.iter().position(|keyboard| keyboard == serial_number)
+		{
+			state.keyboards.remove(index);
+		}
+	}
+
+	/// The leader is kept up to date even whilst mirroring is off, so the
+	/// others can follow it as soon as it's switched on
+	fn is_leading(&self, serial_number: &str) -> bool
+	{
+		self.inner.lock().unwrap().keyboards.first().map(String::as_str) == Some(serial_number)
+	}
+
+	/// Whether a keyboard shows the leader's lighting instead of its own
+	pub fn is_following(&self, serial_number: &str) -> bool
+	{
+		let state = self.inner.lock().unwrap();
+
+		state.enabled
+			&& state.keyboards.first().map(String::as_str) != Some(serial_number)
+			&& state.keyboards.iter().any(|keyboard| keyboard == serial_number)
+	}
+
+	fn publish_colors(&self, colors: HashMap<Scancode, Color>)
+	{
+		let mut state = self.inner.lock().unwrap();
+		state.colors.extend(colors);
+		state.generation += 1;
+	}
+
+	fn publish_effect(&self, group: EffectGroup, effect: &EffectConfiguration)
+	{
+		let mut state = self.inner.lock().unwrap();
+		state.effects.insert(group, effect.clone());
+		state.generation += 1;
+	}
+
+	/// The leader's lighting as of its last commit, each key's color and the
+	/// effects it's running, which is what every keyboard shows unless they
+	/// draw their own
+	pub fn leader_lighting(&self) -> (HashMap<Scancode, Color>, HashMap<EffectGroup, EffectConfiguration>)
+	{
+		let state = self.inner.lock().unwrap();
+		(state.colors.clone(), state.effects.clone())
+	}
+
+	/// The leader's lighting, if it's changed since `generation`
+	fn changes_since(&self, generation: u64) -> Option<(u64, HashMap<Scancode, Color>, HashMap<EffectGroup, EffectConfiguration>)>
+	{
+		let state = self.inner.lock().unwrap();
+
+		match state.generation == generation
+		{
+			true => None,
+			false => Some((state.generation, state.colors.clone(), state.effects.clone()))
+		}
+	}
+}
+
+/// A keyboard taking part in a LightingMirror: the leader's lighting is passed
+/// on to the mirror as well as the keyboard, and whilst following, the
+/// keyboard's own lighting is dropped for the leader's
+pub struct MirroredDevice
+{
+	device: Box<dyn Device>,
+	mirror: Arc<LightingMirror>,
+	serial_number: String,
+	// keys set since the last commit, whilst leading
+	frame: HashMap<Scancode, Color>,
+	// the leader's generation last shown, whilst following
+	shown: Option<u64>,
+	// the effects last started whilst following
+	effects: HashMap<EffectGroup, EffectConfiguration>
+}
+
+impl MirroredDevice
+{
+	pub fn new(device: Box<dyn Device>, mirror: Arc<LightingMirror>) -> Self
+	{
+		let serial_number = device.serial_number().to_string();
+		mirror.join(&serial_number);
+
+		Self
+		{
+			device,
+			mirror,
+			serial_number,
+			frame: HashMap::new(),
+			shown: None,
+			effects: HashMap::new()
+		}
+	}
+
+	/// Shows the leader's lighting, if it's changed since it was last shown
+	fn follow(&mut self)
+	{
+		if !self.mirror.is_following(&self.serial_number)
+		{
+			// shown in full the next time it follows
+			self.shown = None;
+			return
+		}
+
+		let (generation, colors, effects) = match self.mirror.changes_since(self.shown.unwrap_or(u64::MAX))
+		{
+			Some(changes) => changes,
+			None => return
+		};
+
+		self.shown = Some(generation);
+
+		let effect = |effects: &HashMap<EffectGroup, EffectConfiguration>, group| effects
+			.get(&group)
+			.cloned()
+			.unwrap_or(EffectConfiguration::None);
+
+		// effects are stopped before the keys are drawn, and started after, as
+		// the leader would have
+		for group in [EffectGroup::Keys, EffectGroup::Logo].iter()
+		{
+			if effect(&effects, *group) == EffectConfiguration::None && effect(&self.effects, *group) != EffectConfiguration::None
+			{
+				self.device.set_effect(*group, &EffectConfiguration::None);
+			}
+		}
+
+		let mut by_color: HashMap<Color, Vec<Scancode>> = HashMap::new();
+
+		for (scancode, color) in colors
+		{
+			by_color.entry(color).or_default().push(scancode);
+		}
+
+		for (color, scancodes) in by_color
+		{
+			self.device.set_13(color, &scancodes);
+		}
+
+		self.device.commit();
+
+		for group in [EffectGroup::Keys, EffectGroup::Logo].iter()
+		{
+			let started = effect(&effects, *group);
+
+			if started != EffectConfiguration::None && started != effect(&self.effects, *group)
+			{
+				self.device.set_effect(*group, &started);
+			}
+		}
+
+		self.effects = effects;
+	}
+}
+
+impl Drop for MirroredDevice
+{
+	fn drop(&mut self)
+	{
+		self.mirror.leave(&self.serial_number);
+	}
+}
+
+impl fmt::Display for MirroredDevice
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		self.device.fmt(f)
+	}
+}
+
+impl Device for MirroredDevice
+{
+	fn take_control(&mut self) -> CommandResult<()>
+	{
+		// whatever was shown has been cleared
+		self.shown = None;
+		self.effects.clear();
+		self.device.take_control()
+	}
+
+	fn release_control(&mut self) -> CommandResult<()>
+	{
+		self.device.release_control()
+	}
+
+	fn mode_count(&self) -> CommandResult<u8>
+	{
+		self.device.mode_count()
+	}
+
+	fn set_gkeys_software_mode(&mut self, software: bool) -> CommandResult<()>
+	{
+		self.device.set_gkeys_software_mode(software)
+	}
+
+	fn set_4(&mut self, keys: &[(Scancode, Color)]) -> CommandResult<()>
+	{
+		if self.mirror.is_following(&self.serial_number)
+		{
+			return Ok(())
+		}
+
+		if self.mirror.is_leading(&self.serial_number)
+		{
+			self.frame.extend(keys.iter().copied());
+		}
+
+		self.device.set_4(keys)
+	}
+
+	fn set_13(&mut self, color: Color, keys: &[Scancode]) -> CommandResult<()>
+	{
+		if self.mirror.is_following(&self.serial_number)
+		{
+			return Ok(())
+		}
+
+		if self.mirror.is_leading(&self.serial_number)
+		{
+			self.frame.extend(keys.iter().map(|scancode| (*scancode, color)));
+		}
+
+		self.device.set_13(color, keys)
+	}
+
+	fn commit(&mut self) -> CommandResult<()>
+	{
+		if self.mirror.is_following(&self.serial_number)
+		{
+			return Ok(())
+		}
+
+		if self.mirror.is_leading(&self.serial_number) && !self.frame.is_empty()
+		{
+			self.mirror.publish_colors(std::mem::take(&mut self.frame));
+		}
+
+		self.device.commit()
+	}
+
+	fn set_mode_leds(&mut self, leds: u8) -> CommandResult<()>
+	{
+		self.device.set_mode_leds(leds)
+	}
+
+	fn set_macro_recording(&mut self, recording: bool) -> CommandResult<()>
+	{
+		self.device.set_macro_recording(recording)
+	}
+
+	fn brightness(&mut self) -> CommandResult<u8>
+	{
+		self.device.brightness()
+	}
+
+	fn set_brightness(&mut self, level: u8) -> CommandResult<()>
+	{
+		self.device.set_brightness(level)
+	}
+
+	fn report_rates(&self) -> Vec<u16>
+	{
+		self.device.report_rates()
+	}
+
+	fn report_rate(&mut self) -> CommandResult<u16>
+	{
+		self.device.report_rate()
+	}
+
+	fn set_report_rate(&mut self, rate: u16) -> CommandResult<()>
+	{
+		self.device.set_report_rate(rate)
+	}
+
+	fn set_rgb_ids(&mut self, rgb_ids: HashMap<Scancode, u8>)
+	{
+		self.device.set_rgb_ids(rgb_ids)
+	}
+
+	fn set_power_cap(&mut self, max_channel_sum: Option<u16>)
+	{
+		self.device.set_power_cap(max_channel_sum)
+	}
+
+	fn set_color_temperature(&mut self, temperature: Option<u16>)
+	{
+		self.device.set_color_temperature(temperature)
+	}
+
+	fn set_effect(&mut self, group: EffectGroup, effect: &EffectConfiguration) -> CommandResult<()>
+	{
+		if self.mirror.is_following(&self.serial_number)
+		{
+			return Ok(())
+		}
+
+		if self.mirror.is_leading(&self.serial_number)
+		{
+			self.mirror.publish_effect(group, effect);
+		}
+
+		self.device.set_effect(group, effect)
+	}
+
+	fn add_game_mode_keys(&mut self, scancodes: &[Scancode]) -> CommandResult<()>
+	{
+		self.device.add_game_mode_keys(scancodes)
+	}
+
+	fn reset_game_mode_keys(&mut self) -> CommandResult<()>
+	{
+		self.device.reset_game_mode_keys()
+	}
+
+	fn onboard_lighting(&mut self, profile: u8) -> CommandResult<OnboardLighting>
+	{
+		self.device.onboard_lighting(profile)
+	}
+
+	fn set_onboard_lighting(&mut self, profile: u8, lighting: &OnboardLighting) -> CommandResult<()>
+	{
+		self.device.set_onboard_lighting(profile, lighting)
+	}
+
+	// called every pass of the event loop, so a follower catches up here
+	fn get_events(&mut self) -> Vec<DeviceEvent>
+	{
+		self.follow();
+		self.device.get_events()
+	}
+
+	fn wait_for_events(&mut self, timeout: Duration)
+	{
+		self.device.wait_for_events(timeout)
+	}
+
+	fn take_command_stats(&mut self) -> CommandStats
+	{
+		self.device.take_command_stats()
+	}
+
+	fn consecutive_failures(&self) -> u32
+	{
+		self.device.consecutive_failures()
+	}
+
+	fn firmware_info(&mut self) -> String
+	{
+		self.device.firmware_info()
+	}
+
+	fn info(&mut self) -> DeviceInfo
+	{
+		self.device.info()
+	}
+
+	fn serial_number(&self) -> &str
+	{
+		self.device.serial_number()
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::device::mock::MockDevice;
+
+	#[test]
+	fn followers_show_the_leaders_lighting()
+	{
+		let mirror = Arc::new(LightingMirror::default());
+		let (leader_keyboard, follower_keyboard) = (MockDevice::default(), MockDevice::with_serial_number("second"));
+		let mut leader = MirroredDevice::new(Box::new(leader_keyboard), Arc::clone(&mirror));
+		let mut follower = MirroredDevice::new(Box::new(follower_keyboard.clone()), Arc::clone(&mirror));
+		let (red, blue) = (Color::new(0xff, 0, 0), Color::new(0, 0, 0xff));
+		let breathing = EffectConfiguration::Breathing { color: blue, duration: 2000, brightness: 100 };
+
+		mirror.set_enabled(true);
+
+		leader.set_13(red, &[Scancode::A, Scancode::B]).unwrap();
+		leader.commit().unwrap();
+		leader.set_effect(EffectGroup::Logo, &breathing).unwrap();
+
+		// the follower's own lighting is dropped
+		follower.set_13(blue, &[Scancode::A]).unwrap();
+		follower.commit().unwrap();
+		follower.get_events();
+
+		assert_eq!(follower_keyboard.color(Scancode::A), Some(red));
+		assert_eq!(follower_keyboard.color(Scancode::B), Some(red));
+		assert_eq!(follower_keyboard.state().effects.get(&EffectGroup::Logo), Some(&breathing));
+
+		// once the leader is unplugged, the next keyboard draws its own lighting
+		drop(leader);
+		assert!(!mirror.is_following("second"));
+
+		follower.set_13(blue, &[Scancode::A]).unwrap();
+		follower.commit().unwrap();
+		assert_eq!(follower_keyboard.color(Scancode::A), Some(blue));
+	}
+}
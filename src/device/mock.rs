@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use super::{CommandError, CommandResult, CommandStats, Device, DeviceEvent, DeviceInfo, OnboardLighting};
+use super::color::Color;
+use super::rgb::{EffectConfiguration, EffectGroup};
+use super::scancode::Scancode;
+
+/// A keyboard for DeviceThread tests, which keeps what it's been told to show
+/// where the test can see it. Clones share their state.
+#[derive(Clone, Default)]
+pub struct MockDevice
+{
+	inner: Arc<Mutex<MockDeviceState>>,
+	// "mock" unless a test needs keyboards told apart
+	serial_number: Option<String>
+}
+
+#[derive(Default)]
+pub struct MockDeviceState
+{
+	// key colors set since the last commit
+	frame: HashMap<Scancode, Color>,
+	/// each key's color as of the last commit
+	pub colors: HashMap<Scancode, Color>,
+	pub effects: HashMap<EffectGroup, EffectConfiguration>,
+	pub mode_leds: u8,
+	pub recording: bool,
+	pub gkeys_software_mode: bool,
+	pub commits: usize,
+	/// returned by the next get_events
+	pub events: Vec<DeviceEvent>
+}
+
+impl MockDevice
+{
+	pub const MODE_COUNT: u8 = 3;
+
+	pub fn with_serial_number(serial_number: &str) -> Self
+	{
+		Self { serial_number: Some(serial_number.to_string()), ..Self::default() }
+	}
+
+	pub fn state(&self) -> MutexGuard<'_, MockDeviceState>
+	{
+		self.inner.lock().unwrap()
+	}
+
+	pub fn color(&self, scancode: Scancode) -> Option<Color>
+	{
+		self.state().colors.get(&scancode).copied()
+	}
+}
+
+impl fmt::Display for MockDevice
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "Mock G815")
+	}
+}
+
+impl Device for MockDevice
+{
+	fn take_control(&mut self) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	fn release_control(&mut self) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	fn mode_count(&self) -> CommandResult<u8>
+	{
+		Ok(Self::MODE_COUNT)
+	}
+
+	fn set_gkeys_software_mode(&mut self, software: bool) -> CommandResult<()>
+	{
+		self.state().gkeys_software_mode = software;
+		Ok(())
+	}
+
+	fn set_4(&mut self, keys: &[(Scancode, Color)]) -> CommandResult<()>
+	{
+		self.state().frame.extend(keys.iter().copied());
+		Ok(())
+	}
+
+	fn set_13(&mut self, color: Color, keys: &[Scancode]) -> CommandResult<()>
+	{
+		self.state().frame.extend(keys.iter().map(|scancode| (*scancode, color)));
+		Ok(())
+	}
+
+	fn commit(&mut self) -> CommandResult<()>
+	{
+		let mut state = self.state();
+		let frame = std::mem::take(&mut state.frame);
+		state.colors.extend(frame);
+		state.commits += 1;
+		Ok(())
+	}
+
+	fn set_mode_leds(&mut self, leds: u8) -> CommandResult<()>
+	{
+		self.state().mode_leds = leds;
+		Ok(())
+	}
+
+	fn set_macro_recording(&mut self, recording: bool) -> CommandResult<()>
+	{
+		self.state().recording = recording;
+		Ok(())
+	}
+
+	fn brightness(&mut self) -> CommandResult<u8>
+	{
+		Ok(100)
+	}
+
+	fn set_brightness(&mut self, _level: u8) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	fn report_rates(&self) -> Vec<u16>
+	{
+		Vec::new()
+	}
+
+	fn report_rate(&mut self) -> CommandResult<u16>
+	{
+		Ok(1000)
+	}
+
+	fn set_report_rate(&mut self, _rate: u16) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	fn set_rgb_ids(&mut self, _rgb_ids: HashMap<Scancode, u8>) {}
+
+	fn set_power_cap(&mut self, _max_channel_sum: Option<u16>) {}
+
+	fn set_color_temperature(&mut self, _temperature: Option<u16>) {}
+
+	fn set_effect(&mut self, group: EffectGroup, effect: &EffectConfiguration) -> CommandResult<()>
+	{
+		let mut state = self.state();
+
+		match effect
+		{
+			EffectConfiguration::None => state.effects.remove(&group),
+			effect => state.effects.insert(group, effect.clone())
+		};
+
+		Ok(())
+	}
+
+	fn add_game_mode_keys(&mut self, _scancodes: &[Scancode]) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	fn reset_game_mode_keys(&mut self) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	fn onboard_lighting(&mut self, _profile: u8) -> CommandResult<OnboardLighting>
+	{
+		Err(CommandError::Failure("the mock keyboard has no onboard profiles".to_string()))
+	}
+
+	fn set_onboard_lighting(&mut self, _profile: u8, _lighting: &OnboardLighting) -> CommandResult<()>
+	{
+		Err(CommandError::Failure("the mock keyboard has no onboard profiles".to_string()))
+	}
+
+	fn get_events(&mut self) -> Vec<DeviceEvent>
+	{
+		std::mem::take(&mut self.state().events)
+	}
+
+	fn wait_for_events(&mut self, _timeout: Duration) {}
+
+	fn take_command_stats(&mut self) -> CommandStats
+	{
+		CommandStats::default()
+	}
+
+	fn consecutive_failures(&self) -> u32
+	{
+		0
+	}
+
+	fn firmware_info(&mut self) -> String
+	{
+		"Firmware: mock".to_string()
+	}
+
+	fn info(&mut self) -> DeviceInfo
+	{
+		DeviceInfo
+		{
+			product: "Mock G815".to_string(),
+			serial_number: self.serial_number().to_string(),
+			firmware: None,
+			bootloader: None,
+			capabilities: Vec::new(),
+			report_rates: Vec::new()
+		}
+	}
+
+	fn serial_number(&self) -> &str
+	{
+		self.serial_number.as_deref().unwrap_or("mock")
+	}
+}
@@ -1,23 +1,23 @@
+use std::borrow::Cow;
+use std::time::Duration;
+
+use hsl::HSL;
 use serde::{Serialize, Deserialize};
 
 use crate::device::scancode::Scancode;
 use crate::config::Keygroups;
+use crate::metrics::SystemMetrics;
+use crate::stats::KeyCounts;
 pub use crate::device::color::Color;
+pub use crate::device::effect::{EffectGroup, EffectDirection, EffectConfiguration};
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum Effect
 {
 	Off,
 	Static,
-	Cycle = 0x03, // or 0x02 when on the logo group?!
-	ColorWave = 0x04 // doesn't seem to set the logo at all?
-}
-
-#[derive(Copy, Clone, Eq, PartialEq, Hash)]
-pub enum EffectGroup
-{
-	Logo = 0x00,
-	Keys = 0x01
+	Cycle = 0x03,
+	ColorWave = 0x04
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -43,49 +43,617 @@ impl KeySelection
 				.unwrap_or_default()
 		}
 	}
+
+	/// The name of the keygroup this selects, if it selects one
+	pub fn keygroup(&self) -> Option<&str>
+	{
+		match self
+		{
+			Self::Keygroup(group_name) => Some(group_name),
+			_ => None
+		}
+	}
 }
 
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
-pub enum EffectDirection
+pub enum GradientDirection
+{
+	Horizontal,
+	Vertical,
+	/// top left to bottom right
+	Diagonal
+}
+
+impl Default for GradientDirection
+{
+	fn default() -> Self
+	{
+		Self::Horizontal
+	}
+}
+
+impl GradientDirection
 {
-	Horizontal = 0x01,
-	Vertical = 0x02,
-	CenterOut = 0x03,
-	CenterIn = 0x08,
-	ReverseHorizontal = 0x06,
-	ReverseVertical = 0x07
+	/// How far along this direction each of the keys is (0.0 - 1.0), based on
+	/// their physical positions. Keys without a position are placed at the start.
+	fn progress(&self, scancodes: &[Scancode]) -> Vec<(Scancode, f64)>
+	{
+		let distance = |scancode: &Scancode| scancode.position().map(|(x, y)| match self
+		{
+			Self::Horizontal => x,
+			Self::Vertical => y,
+			Self::Diagonal => x + y
+		});
+
+		let distances: Vec<f64> = scancodes.iter().filter_map(distance).collect();
+		let start = distances.iter().copied().fold(f64::INFINITY, f64::min);
+		let length = distances.iter().copied().fold(f64::NEG_INFINITY, f64::max) - start;
+
+		scancodes
+			.iter()
+			.map(|scancode| (*scancode, match (distance(scancode), length > 0_f64)
+			{
+				(Some(distance), true) => (distance - start) / length,
+				_ => 0_f64
+			}))
+			.collect()
+	}
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum EffectConfiguration
+pub struct Gradient
 {
-	None,
-	Static { color: Color },
-	Breathing { color: Color, duration: u16, brightness: u8 },
-	Cycle { duration: u16, brightness: u8 },
-	ColorWave { direction: EffectDirection, duration: u16, brightness: u8 },
-	Ripple { color: Color, duration: u16 }
+	from: Color,
+	to: Color,
+	#[serde(default)]
+	direction: GradientDirection,
+	keys: Vec<KeySelection>
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
-pub struct ColorAssignment
+pub struct Rainbow
 {
-	color: Color,
+	#[serde(default)]
+	direction: GradientDirection,
+	/// the hue (in degrees) the rainbow starts at
+	#[serde(default)]
+	hue: u16,
 	keys: Vec<KeySelection>
 }
 
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ColorAssignment
+{
+	Solid { color: Color, keys: Vec<KeySelection> },
+	Gradient { gradient: Gradient },
+	Rainbow { rainbow: Rainbow }
+}
+
 impl ColorAssignment
 {
+	fn keys(&self) -> &[KeySelection]
+	{
+		match self
+		{
+			Self::Solid { keys, .. } => keys,
+			Self::Gradient { gradient } => &gradient.keys,
+			Self::Rainbow { rainbow } => &rainbow.keys
+		}
+	}
+
 	pub fn scancodes(&self, keygroups: &Keygroups) -> Vec<Scancode>
 	{
-		self.keys
+		self.keys()
 			.iter()
 			.map(|selection| selection.scancodes(keygroups))
 			.flatten()
 			.collect()
 	}
+
+	/// The main color of this assignment, for when only a single color can be
+	/// shown (i.e. hardware effects)
+	pub fn primary_color(&self) -> Color
+	{
+		match self
+		{
+			Self::Solid { color, .. } => *color,
+			Self::Gradient { gradient } => gradient.from,
+			Self::Rainbow { rainbow } => HSL { h: rainbow.hue as f64 % 360_f64, s: 1_f64, l: 0.5_f64 }.into()
+		}
+	}
+
+	/// The color of each of this assignment's keys
+	pub fn key_colors(&self, keygroups: &Keygroups) -> Vec<(Scancode, Color)>
+	{
+		let scancodes = self.scancodes(keygroups);
+
+		match self
+		{
+			Self::Solid { color, .. } => scancodes
+				.into_iter()
+				.map(|scancode| (scancode, *color))
+				.collect(),
+			Self::Gradient { gradient } => gradient.direction
+				.progress(&scancodes)
+				.into_iter()
+				.map(|(scancode, progress)| (scancode, gradient.from.mix(gradient.to, progress)))
+				.collect(),
+			Self::Rainbow { rainbow } => rainbow.direction
+				.progress(&scancodes)
+				.into_iter()
+				.map(|(scancode, progress)| (scancode, HSL
+				{
+					h: (rainbow.hue as f64 + progress * 360_f64) % 360_f64,
+					s: 1_f64,
+					l: 0.5_f64
+				}.into()))
+				.collect()
+		}
+	}
+
+	/// Groups the keys of this assignment by color, in the order they were first
+	/// seen so that the same assignment always produces the same result
+	fn color_groups(&self, keygroups: &Keygroups) -> ScancodeAssignments
+	{
+		let mut groups: ScancodeAssignments = Vec::new();
+
+		for (scancode, color) in self.key_colors(keygroups)
+		{
+			match groups.iter_mut().find(|(group_color, _scancodes)| *group_color == color)
+			{
+				Some((_color, scancodes)) => scancodes.push(scancode),
+				None => groups.push((color, vec![scancode]))
+			}
+		}
+
+		groups
+	}
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric
+{
+	Cpu,
+	/// one key per core, in order
+	CpuCores,
+	Memory,
+	Network,
+	Disk
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricStyle
+{
+	/// light up a proportion of the keys, like a bar graph
+	Bar,
+	/// blend every key between the low and high colors
+	Level
+}
+
+impl Default for MetricStyle
+{
+	fn default() -> Self
+	{
+		Self::Bar
+	}
+}
+
+/// A set of keys showing a system metric, as part of a hud theme
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MetricZone
+{
+	metric: Metric,
+	#[serde(default)]
+	style: MetricStyle,
+	low: Color,
+	high: Color,
+	/// the network throughput (in bytes per second) shown as 100%
+	#[serde(default = "MetricZone::default_network_max")]
+	network_max: u64,
+	keys: Vec<KeySelection>
+}
+
+impl MetricZone
+{
+	fn default_network_max() -> u64
+	{
+		// 100Mbit/s
+		12_500_000
+	}
+
+	pub fn scancodes(&self, keygroups: &Keygroups) -> Vec<Scancode>
+	{
+		self.keys
+			.iter()
+			.flat_map(|selection| selection.scancodes(keygroups))
+			.collect()
+	}
+
+	/// The color of each of the zone's keys for a sample of the metrics
+	pub fn key_colors(&self, scancodes: &[Scancode], metrics: &SystemMetrics) -> Vec<(Scancode, Color)>
+	{
+		let value = match self.metric
+		{
+			Metric::CpuCores => return scancodes
+				.iter()
+				.zip(metrics.cpu_cores.iter().map(Some).chain(std::iter::repeat(None)))
+				.map(|(scancode, usage)| (*scancode, match usage
+				{
+					Some(usage) => self.low.mix(self.high, *usage),
+					None => self.low
+				}))
+				.collect(),
+			Metric::Cpu => metrics.cpu,
+			Metric::Memory => metrics.memory,
+			Metric::Network => metrics.network / self.network_max.max(1) as f64,
+			Metric::Disk => metrics.disk
+		};
+
+		let value = value.max(0_f64).min(1_f64);
+		let lit_keys = (value * scancodes.len() as f64).round() as usize;
+
+		scancodes
+			.iter()
+			.enumerate()
+			.map(|(i, scancode)| (*scancode, match self.style
+			{
+				MetricStyle::Level => self.low.mix(self.high, value),
+				MetricStyle::Bar if i < lit_keys => self.high,
+				MetricStyle::Bar => self.low
+			}))
+			.collect()
+	}
+}
+
+/// Colors keys by how often they've been pressed, from `cold` for the least
+/// used to `hot` for the most used
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Heatmap
+{
+	cold: Color,
+	hot: Color,
+	/// the keys to show the heatmap on, every key if empty
+	#[serde(default)]
+	keys: Vec<KeySelection>
+}
+
+impl Heatmap
+{
+	pub fn scancodes(&self, keygroups: &Keygroups) -> Vec<Scancode>
+	{
+		match self.keys.is_empty()
+		{
+			true => Scancode::iter_variants()
+				.filter(|scancode| scancode.position().is_some())
+				.collect(),
+			false => self.keys
+				.iter()
+				.flat_map(|selection| selection.scancodes(keygroups))
+				.collect()
+		}
+	}
+
+	/// The color of each key for the given key press counts. The counts are
+	/// scaled logarithmically, otherwise a few keys (space, e) would be hot and
+	/// everything else cold.
+	pub fn key_colors(&self, scancodes: &[Scancode], counts: &KeyCounts) -> Vec<(Scancode, Color)>
+	{
+		let count = |scancode: &Scancode| counts.get(scancode).copied().unwrap_or(0) as f64;
+		let max = scancodes.iter().map(count).fold(0_f64, f64::max);
+
+		scancodes
+			.iter()
+			.map(|scancode| (*scancode, match max > 0_f64
+			{
+				true => self.cold.mix(self.hot, count(scancode).ln_1p() / max.ln_1p()),
+				false => self.cold
+			}))
+			.collect()
+	}
+}
+
+/// Colors keys with the colors along the screen, like an ambilight: the screen
+/// is split into vertical strips, and each key takes the color of the strip
+/// above it
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ScreenSync
+{
+	/// how much of the previous colors to keep each sample (0 - 100), higher
+	/// fades between colors more slowly
+	#[serde(default = "ScreenSync::default_smoothing")]
+	pub smoothing: u8,
+	/// the keys to show the screen on, every key if empty
+	#[serde(default)]
+	keys: Vec<KeySelection>
+}
+
+impl ScreenSync
+{
+	/// how many strips the screen is split into, about as many as there are
+	/// keys across the keyboard
+	pub const COLUMNS: usize = 22;
+
+	fn default_smoothing() -> u8
+	{
+		50
+	}
+
+	pub fn scancodes(&self, keygroups: &Keygroups) -> Vec<Scancode>
+	{
+		match self.keys.is_empty()
+		{
+			true => Scancode::iter_variants()
+				.filter(|scancode| scancode.position().is_some())
+				.collect(),
+			false => self.keys
+				.iter()
+				.flat_map(|selection| selection.scancodes(keygroups))
+				.collect()
+		}
+	}
+
+	/// Blends newly sampled colors into the ones being shown
+	pub fn smooth(&self, shown: &[Color], sampled: &[Color]) -> Vec<Color>
+	{
+		match shown.len() == sampled.len()
+		{
+			true => shown
+				.iter()
+				.zip(sampled)
+				.map(|(shown, sampled)| shown.mix(*sampled, 1_f64 - self.smoothing.min(100) as f64 / 100_f64))
+				.collect(),
+			false => sampled.to_vec()
+		}
+	}
+
+	/// The color of each key, from the strip of the screen at the same
+	/// distance across as the key is across the keyboard
+	pub fn key_colors(&self, scancodes: &[Scancode], columns: &[Color]) -> Vec<(Scancode, Color)>
+	{
+		if columns.is_empty()
+		{
+			return vec![]
+		}
+
+		let positions: Vec<(Scancode, f64)> = scancodes
+			.iter()
+			.filter_map(|scancode| scancode.position().map(|(x, _y)| (*scancode, x)))
+			.collect();
+
+		let left = positions.iter().map(|(_scancode, x)| *x).fold(f64::INFINITY, f64::min);
+		let right = positions.iter().map(|(_scancode, x)| *x).fold(f64::NEG_INFINITY, f64::max);
+
+		positions
+			.into_iter()
+			.map(|(scancode, x)|
+			{
+				let across = match right > left
+				{
+					true => (x - left) / (right - left),
+					false => 0.5
+				};
+
+				let column = ((across * columns.len() as f64) as usize).min(columns.len() - 1);
+				(scancode, columns[column])
+			})
+			.collect()
+	}
+}
+
+/// The colors of the theme being shown that indicators can refer to
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ThemePalette
+{
+	/// the color covering the most keys
+	pub background: Color,
+	/// the most colorful of the theme's other colors
+	pub accent: Color
+}
+
+impl ThemePalette
+{
+	pub fn from_assignments(assignments: &[(Color, Vec<Scancode>)]) -> Self
+	{
+		let mut key_counts: Vec<(Color, usize)> = Vec::new();
+
+		for (color, scancodes) in assignments
+		{
+			match key_counts.iter_mut().find(|(counted_color, _count)| counted_color == color)
+			{
+				Some((_color, count)) => *count += scancodes.len(),
+				None => key_counts.push((*color, scancodes.len()))
+			}
+		}
+
+		let background = key_counts
+			.iter()
+			.fold(None, |most: Option<&(Color, usize)>, counted| match most
+			{
+				Some(most) if most.1 >= counted.1 => Some(most),
+				_ => Some(counted)
+			})
+			.map(|(color, _count)| *color)
+			.unwrap_or_default();
+
+		let accent = key_counts
+			.iter()
+			.map(|(color, _count)| *color)
+			.filter(|color| *color != background)
+			.fold(None, |most: Option<Color>, color| match most
+			{
+				Some(most) if most.chroma() >= color.chroma() => Some(most),
+				_ => Some(color)
+			})
+			.unwrap_or_else(|| background.contrasting());
+
+		Self { background, accent }
+	}
+
+	pub fn from_effect(effect: &EffectConfiguration) -> Self
+	{
+		match effect
+		{
+			EffectConfiguration::Static { color }
+				| EffectConfiguration::Breathing { color, .. }
+				| EffectConfiguration::Ripple { color, .. } =>
+				Self { background: *color, accent: color.contrasting() },
+			_ => Self { background: Color::black(), accent: Color::new(255, 255, 255) }
+		}
+	}
+}
+
+/// A color for an indicator, which can refer to the theme being shown so that
+/// it stays visible whatever the theme is. Written as a color, `theme:accent`,
+/// `theme:background` or `contrast:auto` (contrasting with the key's own color).
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum IndicatorColor
+{
+	Fixed(Color),
+	ThemeAccent,
+	ThemeBackground,
+	Contrast
+}
+
+impl IndicatorColor
+{
+	/// The actual color to show, given the theme's palette and the color the
+	/// key would otherwise be
+	pub fn resolve(&self, palette: &ThemePalette, key_color: Color) -> Color
+	{
+		match self
+		{
+			Self::Fixed(color) => *color,
+			Self::ThemeAccent => palette.accent,
+			Self::ThemeBackground => palette.background,
+			Self::Contrast => key_color.contrasting()
+		}
+	}
+}
+
+impl From<Color> for IndicatorColor
+{
+	fn from(color: Color) -> Self
+	{
+		Self::Fixed(color)
+	}
+}
+
+impl Serialize for IndicatorColor
+{
+	fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+	where
+		S: serde::Serializer
+	{
+		match self
+		{
+			Self::Fixed(color) => color.serialize(serializer),
+			Self::ThemeAccent => serializer.serialize_str("theme:accent"),
+			Self::ThemeBackground => serializer.serialize_str("theme:background"),
+			Self::Contrast => serializer.serialize_str("contrast:auto")
+		}
+	}
+}
+
+struct IndicatorColorVisitor;
+
+impl<'de> serde::de::Visitor<'de> for IndicatorColorVisitor
+{
+	type Value = IndicatorColor;
+
+	fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result
+	{
+		formatter.write_str("a color, theme:accent, theme:background or contrast:auto")
+	}
+
+	fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+	where
+		E: serde::de::Error
+	{
+		match value
+		{
+			"theme:accent" => Ok(IndicatorColor::ThemeAccent),
+			"theme:background" => Ok(IndicatorColor::ThemeBackground),
+			"contrast:auto" => Ok(IndicatorColor::Contrast),
+			_ => Color::parse(value).map(IndicatorColor::Fixed).map_err(E::custom)
+		}
+	}
+
+	fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+	where
+		E: serde::de::Error
+	{
+		Ok(IndicatorColor::Fixed((value as u32).into()))
+	}
+}
+
+impl<'de> Deserialize<'de> for IndicatorColor
+{
+	fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+	where
+		D: serde::Deserializer<'de>
+	{
+		deserializer.deserialize_str(IndicatorColorVisitor)
+	}
+}
+
+/// Effects rendered frame by frame by the driver itself, on top of a set of
+/// color assignments, rather than by the keyboard's firmware
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SoftwareEffect
+{
+	Breathing { duration: u16 }
+}
+
+impl SoftwareEffect
+{
+	/// The brightness (0.0 - 1.0) the effect should be rendered at, the given
+	/// amount of time after it started
+	pub fn brightness_at(&self, elapsed: Duration) -> f64
+	{
+		match self
+		{
+			Self::Breathing { duration } =>
+			{
+				let period = (*duration).max(1) as f64;
+				let phase = (elapsed.as_millis() as f64 % period) / period;
+				0.5 + 0.5 * (phase * 2_f64 * std::f64::consts::PI).cos()
+			}
+		}
+	}
+
+	/// The nearest effect the keyboard can render by itself, for when the driver
+	/// isn't able to render frames. As hardware effects are a single color, the
+	/// color of the first assignment is used.
+	pub fn hardware_fallback(&self, colors: &[ColorAssignment]) -> EffectConfiguration
+	{
+		let color = colors
+			.first()
+			.map(|assignment| assignment.primary_color())
+			.unwrap_or_default();
+
+		match self
+		{
+			Self::Breathing { duration } => EffectConfiguration::Breathing
+			{
+				color,
+				duration: *duration,
+				brightness: 100
+			}
+		}
+	}
+}
+
+/// What can currently be rendered on a device, used to decide how a theme
+/// should be displayed
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct RenderCapabilities
+{
+	pub software_effects: bool
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -93,13 +661,74 @@ impl ColorAssignment
 pub enum Theme
 {
 	Static(Vec<ColorAssignment>),
-	Effect(EffectConfiguration)
+	Effect(EffectConfiguration),
+	Animated { software_effect: SoftwareEffect, colors: Vec<ColorAssignment> },
+	/// system metrics shown on top of a set of color assignments, refreshed every
+	/// `interval` milliseconds
+	Hud
+	{
+		hud: Vec<MetricZone>,
+		#[serde(default)]
+		colors: Vec<ColorAssignment>,
+		#[serde(default = "Theme::default_refresh_interval")]
+		interval: u16
+	},
+	/// a heatmap of key presses on top of a set of color assignments, refreshed
+	/// every `interval` milliseconds
+	Heatmap
+	{
+		heatmap: Heatmap,
+		#[serde(default)]
+		colors: Vec<ColorAssignment>,
+		#[serde(default = "Theme::default_refresh_interval")]
+		interval: u16
+	},
+	/// the colors along the screen on top of a set of color assignments, sampled
+	/// every `interval` milliseconds
+	Screen
+	{
+		screen: ScreenSync,
+		#[serde(default)]
+		colors: Vec<ColorAssignment>,
+		#[serde(default = "Theme::default_sample_interval")]
+		interval: u16
+	}
 }
 
 pub type ScancodeAssignments = Vec<(Color, Vec<Scancode>)>;
 
 impl Theme
 {
+	fn default_refresh_interval() -> u16
+	{
+		1000
+	}
+
+	fn default_sample_interval() -> u16
+	{
+		100
+	}
+
+	/// Every key selection used by this theme, for validating the config
+	pub fn key_selections(&self) -> Vec<&KeySelection>
+	{
+		let (colors, extra): (&[ColorAssignment], Vec<&KeySelection>) = match self
+		{
+			Self::Static(colors)
+				| Self::Animated { colors, .. } => (colors, vec![]),
+			Self::Effect(_effect) => (&[], vec![]),
+			Self::Hud { hud, colors, .. } => (colors, hud.iter().flat_map(|zone| zone.keys.iter()).collect()),
+			Self::Heatmap { heatmap, colors, .. } => (colors, heatmap.keys.iter().collect()),
+			Self::Screen { screen, colors, .. } => (colors, screen.keys.iter().collect())
+		};
+
+		colors
+			.iter()
+			.flat_map(|assignment| assignment.keys().iter())
+			.chain(extra)
+			.collect()
+	}
+
 	/// Turns this theme's set of color to user-friendly keyselections assignments
 	/// into a device-friendly map of color -> scancodes. If this theme is an Effect
 	/// theme, this will return None.
@@ -107,11 +736,28 @@ impl Theme
 	{
 		match self
 		{
-			Self::Static(assignments) => Some(assignments
+			Self::Static(assignments)
+				| Self::Animated { colors: assignments, .. }
+				| Self::Hud { colors: assignments, .. }
+				| Self::Heatmap { colors: assignments, .. }
+				| Self::Screen { colors: assignments, .. } => Some(assignments
 				.iter()
-				.map(|assignment| (assignment.color, assignment.scancodes(keygroups)))
+				.flat_map(|assignment| assignment.color_groups(keygroups))
 				.collect()),
 			Self::Effect(_effect) => None
 		}
 	}
+
+	/// Resolves this theme into one that can be displayed with the given
+	/// capabilities, falling back to the nearest hardware effect for any software
+	/// effect that can't currently be rendered.
+	pub fn negotiate(&self, capabilities: &RenderCapabilities) -> Cow<'_, Theme>
+	{
+		match self
+		{
+			Self::Animated { software_effect, colors } if !capabilities.software_effects =>
+				Cow::Owned(Self::Effect(software_effect.hardware_fallback(colors))),
+			_ => Cow::Borrowed(self)
+		}
+	}
 }
@@ -0,0 +1,355 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::time::Duration;
+
+use super::{CommandError, CommandResult, CommandStats, Device, DeviceEvent, DeviceInfo, OnboardLighting};
+use super::color::Color;
+use super::rgb::{EffectConfiguration, EffectGroup};
+use super::scancode::Scancode;
+
+/// A keyboard that isn't there (--simulate), which draws what a real one would
+/// be showing in the terminal, so themes can be worked on without one
+pub struct SimulatedKeyboard
+{
+	// key colors set since the last commit
+	frame: HashMap<Scancode, Color>,
+	// what the keyboard is showing, as of the last commit
+	colors: HashMap<Scancode, Color>,
+	effects: HashMap<EffectGroup, EffectConfiguration>,
+	mode_leds: u8,
+	recording: bool,
+	brightness: u8,
+	game_mode_keys: Vec<Scancode>,
+	power_cap: Option<u16>,
+	color_temperature: Option<u16>,
+	// the last thing drawn, so nothing is drawn until it changes
+	drawn: String
+}
+
+impl SimulatedKeyboard
+{
+	const MODE_COUNT: u8 = 3;
+	// characters per key width, keys are drawn 2 wide
+	const KEY_WIDTH: f64 = 3.0;
+
+	pub fn new() -> Self
+	{
+		Self
+		{
+			frame: HashMap::new(),
+			colors: HashMap::new(),
+			effects: HashMap::new(),
+			mode_leds: 0,
+			recording: false,
+			brightness: 100,
+			game_mode_keys: Vec::new(),
+			power_cap: None,
+			color_temperature: None,
+			drawn: String::new()
+		}
+	}
+
+	/// Each key in its color (as 24 bit color escapes) where it sits on the
+	/// keyboard, with what the grid can't show underneath
+	fn render(&self) -> String
+	{
+		let mut rows: Vec<Vec<Option<Color>>> = Vec::new();
+
+		for scancode in Scancode::iter_variants()
+		{
+			if let Some((x, y)) = scancode.position()
+			{
+				let row = y.floor() as usize;
+				let column = ((x - 0.5) * Self::KEY_WIDTH).round() as usize;
+
+				if rows.len() <= row
+				{
+					rows.resize(row + 1, Vec::new());
+				}
+
+				if rows[row].len() <= column
+				{
+					rows[row].resize(column + 1, None);
+				}
+
+				rows[row][column] = Some(self.colors.get(&scancode).copied().unwrap_or_else(Color::black));
+			}
+		}
+
+		let mut output = String::new();
+
+		for row in rows
+		{
+			let mut skip = 0;
+
+			for cell in row
+			{
+				if skip > 0
+				{
+					skip -= 1;
+					continue
+				}
+
+				match cell
+				{
+					// unlit keys are outlined, so the layout can still be seen
+					Some(color) if color == Color::black() =>
+					{
+						output.push_str("[]");
+						skip = 1;
+					},
+					Some(color) =>
+					{
+						output.push_str(&format!("\x1b[38;2;{};{};{}m\u{2588}\u{2588}\x1b[0m", color.r, color.g, color.b));
+						skip = 1;
+					},
+					None => output.push(' ')
+				}
+			}
+
+			output.push('\n');
+		}
+
+		let effect = |group| self.effects
+			.get(&group)
+			.map(|effect| format!("{:?}", effect))
+			.unwrap_or_else(|| "none".to_string());
+
+		let modes: Vec<String> = (0..Self::MODE_COUNT)
+			.filter(|mode| self.mode_leds & (1 << mode) != 0)
+			.map(|mode| format!("M{}", mode + 1))
+			.collect();
+
+		output.push_str(&format!("\nkeys effect: {}\nlogo effect: {}\n", effect(EffectGroup::Keys), effect(EffectGroup::Logo)));
+		output.push_str(&format!("mode leds: {}, brightness: {}%{}, game mode keys: {}\n",
+			modes.join(" "),
+			self.brightness,
+			match self.recording
+			{
+				true => ", MR lit",
+				false => ""
+			},
+			self.game_mode_keys.len()));
+
+		output
+	}
+
+	/// Redraws the keyboard if anything on it has changed
+	fn draw(&mut self)
+	{
+		let rendered = self.render();
+
+		if rendered != self.drawn
+		{
+			// clear the screen and draw from the top left
+			print!("\x1b[H\x1b[2J{}", &rendered);
+			let _ = std::io::stdout().flush();
+			self.drawn = rendered;
+		}
+	}
+
+	fn capped(&self, color: Color) -> Color
+	{
+		let color = match self.color_temperature
+		{
+			Some(temperature) => color.warmed(temperature),
+			None => color
+		};
+
+		match self.power_cap
+		{
+			Some(max_channel_sum) => color.capped(max_channel_sum),
+			None => color
+		}
+	}
+}
+
+impl fmt::Display for SimulatedKeyboard
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		write!(f, "Simulated G815")
+	}
+}
+
+impl Device for SimulatedKeyboard
+{
+	fn take_control(&mut self) -> CommandResult<()>
+	{
+		self.draw();
+		Ok(())
+	}
+
+	fn release_control(&mut self) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	fn mode_count(&self) -> CommandResult<u8>
+	{
+		Ok(Self::MODE_COUNT)
+	}
+
+	fn set_gkeys_software_mode(&mut self, _software: bool) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	fn set_4(&mut self, keys: &[(Scancode, Color)]) -> CommandResult<()>
+	{
+		for (scancode, color) in keys
+		{
+			self.frame.insert(*scancode, self.capped(*color));
+		}
+
+		Ok(())
+	}
+
+	fn set_13(&mut self, color: Color, keys: &[Scancode]) -> CommandResult<()>
+	{
+		let color = self.capped(color);
+		self.frame.extend(keys.iter().map(|scancode| (*scancode, color)));
+		Ok(())
+	}
+
+	fn commit(&mut self) -> CommandResult<()>
+	{
+		let frame = std::mem::take(&mut self.frame);
+		self.colors.extend(frame);
+		self.draw();
+		Ok(())
+	}
+
+	fn set_mode_leds(&mut self, leds: u8) -> CommandResult<()>
+	{
+		self.mode_leds = leds;
+		self.draw();
+		Ok(())
+	}
+
+	fn set_macro_recording(&mut self, recording: bool) -> CommandResult<()>
+	{
+		self.recording = recording;
+		self.draw();
+		Ok(())
+	}
+
+	fn brightness(&mut self) -> CommandResult<u8>
+	{
+		Ok(self.brightness)
+	}
+
+	fn set_brightness(&mut self, level: u8) -> CommandResult<()>
+	{
+		self.brightness = level;
+		self.draw();
+		Ok(())
+	}
+
+	fn report_rates(&self) -> Vec<u16>
+	{
+		Vec::new()
+	}
+
+	fn report_rate(&mut self) -> CommandResult<u16>
+	{
+		Ok(1000)
+	}
+
+	fn set_report_rate(&mut self, _rate: u16) -> CommandResult<()>
+	{
+		Ok(())
+	}
+
+	// keys are drawn by scancode, so other layouts' rgb ids don't matter
+	fn set_rgb_ids(&mut self, _rgb_ids: HashMap<Scancode, u8>) {}
+
+	fn set_power_cap(&mut self, max_channel_sum: Option<u16>)
+	{
+		self.power_cap = max_channel_sum;
+	}
+
+	fn set_color_temperature(&mut self, temperature: Option<u16>)
+	{
+		self.color_temperature = temperature;
+	}
+
+	fn set_effect(&mut self, group: EffectGroup, effect: &EffectConfiguration) -> CommandResult<()>
+	{
+		match effect
+		{
+			EffectConfiguration::None => self.effects.remove(&group),
+			effect => self.effects.insert(group, effect.clone())
+		};
+
+		self.draw();
+		Ok(())
+	}
+
+	fn add_game_mode_keys(&mut self, scancodes: &[Scancode]) -> CommandResult<()>
+	{
+		self.game_mode_keys.extend_from_slice(scancodes);
+		Ok(())
+	}
+
+	fn reset_game_mode_keys(&mut self) -> CommandResult<()>
+	{
+		self.game_mode_keys.clear();
+		Ok(())
+	}
+
+	fn onboard_lighting(&mut self, _profile: u8) -> CommandResult<OnboardLighting>
+	{
+		Err(CommandError::Failure("the simulated keyboard has no onboard profiles".to_string()))
+	}
+
+	fn set_onboard_lighting(&mut self, _profile: u8, _lighting: &OnboardLighting) -> CommandResult<()>
+	{
+		Err(CommandError::Failure("the simulated keyboard has no onboard profiles".to_string()))
+	}
+
+	// there are no keys to press
+	fn get_events(&mut self) -> Vec<DeviceEvent>
+	{
+		Vec::new()
+	}
+
+	fn wait_for_events(&mut self, timeout: Duration)
+	{
+		std::thread::sleep(timeout);
+	}
+
+	fn take_command_stats(&mut self) -> CommandStats
+	{
+		CommandStats::default()
+	}
+
+	fn consecutive_failures(&self) -> u32
+	{
+		0
+	}
+
+	fn firmware_info(&mut self) -> String
+	{
+		"Firmware: simulated".to_string()
+	}
+
+	fn info(&mut self) -> DeviceInfo
+	{
+		DeviceInfo
+		{
+			product: "Simulated G815".to_string(),
+			serial_number: "simulated".to_string(),
+			firmware: None,
+			bootloader: None,
+			capabilities: Vec::new(),
+			report_rates: Vec::new()
+		}
+	}
+
+	fn serial_number(&self) -> &str
+	{
+		"simulated"
+	}
+}
@@ -1,84 +1,298 @@
 use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
+use std::borrow::Cow;
 use std::sync::Arc;
-use std::sync::mpsc::{channel, Sender};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
-use std::thread;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
 
-use log::{info, debug};
+use log::{info, debug, warn};
 use crossbeam::{Receiver, TryRecvError};
 
+use crate::channels::MonitoredSender;
 use crate::{SharedState, MainThreadSignal};
-use crate::macros::{Macro, MacroSignal, ActivationType};
-use crate::dbus::DBusSignal;
-use crate::windowsystem::WindowSystemSignal;
-use super::rgb::{ScancodeAssignments, EffectGroup, EffectConfiguration, Theme, Color};
+use crate::config::{BlinkStates, Configuration, DeviceConfiguration, LogoStatusColors, Profile, LightingOptions, ProfileResolver, GkeyMode, LightKeyAction, Gesture, MacroHints};
+use crate::macros::{self, Macro, MacroSignal, MacroState, ActivationType, GameModeCommand};
+use crate::DBusSignal;
+use crate::hooks::HookEvent;
+use crate::media::MediaCommand;
+use crate::windowsystem::{WindowSystemSignal, ScreenSampler};
+use crate::metrics::MetricsSampler;
+use crate::subsystem::Subsystem;
+use super::rgb::{ScancodeAssignments, EffectGroup, EffectConfiguration, Theme, Color, MetricZone,
+	Heatmap, ScreenSync, SoftwareEffect, RenderCapabilities, IndicatorColor, ThemePalette};
 use super::scancode::Scancode;
-use super::{Device, DeviceEvent, KeyType, MediaKey};
+use super::client_colors::{ClientColors, DrawnSignal, KeyColorUpdate};
+use super::mirror::MirroredDevice;
+use super::{Device, DeviceEvent, KeyType, MediaKey, CommandError};
 
-type MacroState = (Sender<MacroSignal>, Arc<AtomicBool>, ActivationType);
 
+#[derive(Clone)]
 pub enum DeviceSignal
 {
 	Shutdown,
 	ProfileChanged,
 	ConfigurationReloaded,
-	MediaStateChanged
+	MediaStateChanged,
+	PaletteChanged,
+	EffectChanged,
+	NightModeChanged,
+	SetGameMode(GameModeCommand),
+	NotificationsChanged,
+	LowPowerChanged,
+	SubsystemsChanged,
+	PlaylistChanged,
+	FocusModeChanged,
+	SetKeyColors(KeyColorUpdate),
+	ClearKeyColors(String),
+	/// key colors drawn straight away rather than on the next frame, then
+	/// signalled as drawn
+	SetKeyColorsNow(KeyColorUpdate, DrawnSignal),
+	SetMode(u8)
+}
+
+/// What a live theme draws over its background each time it's refreshed
+enum LiveLayer
+{
+	/// the metric zones of a hud theme, and their keys
+	Hud(Vec<(MetricZone, Vec<Scancode>)>, MetricsSampler),
+	Heatmap(Heatmap, Vec<Scancode>),
+	/// the screen's keys, and the colors of the strips of the screen being shown
+	Screen(ScreenSync, Vec<Scancode>, ScreenSampler, Vec<Color>)
+}
+
+/// A theme that's redrawn periodically (hud, heatmap or screen): its layer is drawn
+/// over the theme's own color assignments
+struct LiveThemeState
+{
+	layer: LiveLayer,
+	background: ScancodeAssignments,
+	interval: u64,
+	timer: u64
+}
+
+/// A gkey with gesture assignments, whilst it's still unclear which gesture it is
+struct PendingGesture
+{
+	pressed_at: Instant,
+	// when it was let go of, whilst waiting to see if it's tapped again
+	released_at: Option<Instant>,
+	// whether the hold gesture has already run
+	held: bool,
+	double_tap_bound: bool,
+	hold_bound: bool
+}
+
+/// How the logo is lit, when the profile gives it its own theme
+enum LogoLighting
+{
+	FollowTheme,
+	Color(Color),
+	Effect(EffectConfiguration)
 }
 
 enum CurrentLightingState
 {
 	Custom(ScancodeAssignments),
-	Effect(EffectConfiguration)
+	Effect(EffectConfiguration),
+	Palette(Color),
+	// focus mode's keys, with nothing drawn over them
+	Focus(ScancodeAssignments),
+	// turned off with the light key
+	Off
 }
 
 pub struct DeviceThread
 {
 	device: Box<dyn Device>,
+	// finds this keyboard's section of the config, if it has one
+	serial_number: String,
+	// that section, updated whenever the profile is applied
+	device_config: DeviceConfiguration,
 	state: Arc<SharedState>,
-	main_thread_tx: Sender<MainThreadSignal>,
-	dbus_tx: Sender<DBusSignal>,
-	window_system_tx: Sender<WindowSystemSignal>,
+	main_thread_tx: MonitoredSender<MainThreadSignal>,
+	dbus_tx: MonitoredSender<DBusSignal>,
+	window_system_tx: MonitoredSender<WindowSystemSignal>,
 	// map of mode number -> gkey number = Current macro state
 	macro_states: HashMap<u8, HashMap<u8, MacroState>>,
+	// macros started by media keys bound in the active profile
+	media_key_macros: HashMap<MediaKey, MacroState>,
+	// map of gkey number -> the on_release macro it last started
+	release_macros: HashMap<u8, MacroState>,
+	// map of gkey number (in the active mode) -> ms left to confirm the macro
+	awaiting_confirmation: HashMap<u8, u64>,
 	lighting_state: CurrentLightingState,
-	blink_timer: u64,
-	blink_state: bool,
+	// ms since the driver started, which every indicator's blink is timed by
+	blink_clock: u64,
+	// the blink states last drawn, None to redraw the indicators straight away
+	blink_states: Option<BlinkStates>,
+	volume_indicator_timer: u64,
+	// the keys lit to show game mode has been turned on, and ms left to show them
+	game_mode_indicator: Option<(Vec<Scancode>, u64)>,
+	lighting_options: LightingOptions,
+	// the software effect being rendered on top of the custom lighting, and when it started
+	software_effect: Option<(SoftwareEffect, Instant)>,
+	effect_brightness: f64,
+	// the colors of each key when the current crossfade started, and when that was
+	transition: Option<(HashMap<Scancode, Color>, Instant)>,
+	live_theme: Option<LiveThemeState>,
+	frame_timer: u64,
+	dither_frame: u8,
 	active_mode: u8,
 	mode_count: u8,
-	overrides: HashMap<Scancode, Color>
+	overrides: HashMap<Scancode, IndicatorColor>,
+	// keys whose override has changed since they were last drawn, and ms until
+	// they're next drawn
+	changed_overrides: HashSet<Scancode>,
+	changed_overrides_timer: u64,
+	// colors set by other programs, drawn over the theme but under indicators
+	client_colors: ClientColors,
+	// the keys currently lit by audio focus
+	audio_focus_keys: Vec<Scancode>,
+	// what the logo is showing for the driver's status, see logo_status()
+	logo_status: Option<EffectConfiguration>,
+	// the firmware effect running on the logo, if there is one
+	logo_effect: Option<EffectConfiguration>,
+	// the game mode keys last programmed into the device, None if unknown
+	game_mode_keys: Option<Vec<Scancode>>,
+	// the brightness level last reported by (or set on) the device
+	brightness: Option<u8>,
+	// the report rate the device had when it was opened, used by profiles
+	// without one, and the rate it has now (both None if it can't be changed)
+	initial_report_rate: Option<u16>,
+	report_rate: Option<u16>,
+	// the rate the active profile asked for, so problems are only logged once
+	profile_report_rate: Option<u16>,
+	// a theme chosen with the light key, shown instead of the profile's theme
+	theme_override: Option<String>,
+	lighting_enabled: bool,
+	light_key_macro: Option<MacroState>,
+	// the mode key being held down, when it was pressed, and whether it's been
+	// held long enough to lock the profile
+	mode_key_held: Option<(u8, Instant, bool)>,
+	// when the light key was pressed, whilst waiting to see if it's a double press
+	light_key_pressed_at: Option<Instant>,
+	// map of gkey number -> gesture being told apart
+	pending_gestures: HashMap<u8, PendingGesture>,
+	// map of gkey number -> when it was pressed, whilst it's held down and
+	// could show a macro hint
+	held_gkeys: HashMap<u8, Instant>,
+	// the keys lit by a macro hint, and ms left to show them
+	macro_hint: Option<(Vec<Scancode>, u64)>,
+	// the profile's gshift key, whilst it's held down, and the gkeys pressed
+	// whilst it was
+	gshift_held: Option<u8>,
+	shifted_gkeys: HashSet<u8>,
+	// the keys lit to count pending notifications
+	notification_keys: Vec<Scancode>,
+	// whether the logo is breathing for pending notifications
+	notification_breathing: bool,
+	// ms the last pass of the event loop took, which the timers count down by,
+	// and when it started
+	tick: u64,
+	tick_started: Instant,
+	// when to next try taking control of the keyboard again once it's stopped
+	// answering, and how long to wait after that if it still isn't
+	reinitialize_at: Instant,
+	reinitialize_backoff: Duration,
+	// whether the keyboard is showing another's lighting, see LightingMirror
+	following: bool
 }
 
 impl DeviceThread
 {
-	// both these in milliseconds
+	// all these in milliseconds
+	// how long to wait for the device whilst anything is animating or timing,
+	// and whilst nothing is, which is as late as a signal from the main thread
+	// can be noticed
 	const POLL_INTERVAL: u64 = 5;
-	const BLINK_DELAY: u64 = 400;
+	const IDLE_INTERVAL: u64 = 20;
+	// commands failing in a row before the keyboard is taken control of again
+	const REINITIALIZE_AFTER_FAILURES: u32 = 5;
+	const REINITIALIZE_BACKOFF: Duration = Duration::from_secs(1);
+	const MAX_REINITIALIZE_BACKOFF: Duration = Duration::from_secs(60);
+	const VOLUME_INDICATOR_DURATION: u64 = 1500;
+	const GAME_MODE_INDICATOR_DURATION: u64 = 1500;
+	const FRAME_INTERVAL: u64 = 20;
+	const CONFIRM_TIMEOUT: u64 = 2000;
+	const MODE_KEY_LONG_PRESS: u64 = 800;
+	const DOUBLE_PRESS_INTERVAL: u64 = 350;
+	const NOTIFICATION_BREATHING_DURATION: u16 = 4000;
+	const LOGO_STATUS_BREATHING_DURATION: u16 = 2000;
+
+	const VOLUME_INDICATOR_KEYS: [Scancode; 12] = [
+		Scancode::F1, Scancode::F2, Scancode::F3, Scancode::F4,
+		Scancode::F5, Scancode::F6, Scancode::F7, Scancode::F8,
+		Scancode::F9, Scancode::F10, Scancode::F11, Scancode::F12
+	];
 
 	pub fn new(
-		device: Box<dyn Device>,
+		mut device: Box<dyn Device>,
 		state: Arc<SharedState>,
-		dbus_tx: Sender<DBusSignal>,
-		window_system_tx: Sender<WindowSystemSignal>,
-		main_thread_tx: Sender<MainThreadSignal>) -> Self
+		dbus_tx: MonitoredSender<DBusSignal>,
+		window_system_tx: MonitoredSender<WindowSystemSignal>,
+		main_thread_tx: MonitoredSender<MainThreadSignal>) -> Self
 	{
 		let mode_count = device.mode_count().unwrap_or(0);
+		let serial_number = device.serial_number().to_string();
+		state.device_threads.fetch_add(1, Ordering::SeqCst);
+		state.devices.write().unwrap().push(device.info());
 
 		Self
 		{
-			device,
+			device: Box::new(MirroredDevice::new(device, Arc::clone(&state.mirror))),
+			serial_number,
+			device_config: DeviceConfiguration::default(),
 			state,
 			main_thread_tx,
 			window_system_tx,
 			dbus_tx,
 			mode_count,
 			macro_states: HashMap::new(),
+			media_key_macros: HashMap::new(),
+			release_macros: HashMap::new(),
+			awaiting_confirmation: HashMap::new(),
 			lighting_state: CurrentLightingState::Effect(EffectConfiguration::None),
-			blink_timer: 0,
-			blink_state: false,
+			blink_clock: 0,
+			blink_states: None,
+			volume_indicator_timer: 0,
+			game_mode_indicator: None,
+			lighting_options: LightingOptions::default(),
+			software_effect: None,
+			effect_brightness: 1_f64,
+			transition: None,
+			live_theme: None,
+			frame_timer: 0,
+			dither_frame: 0,
 			active_mode: 1,
-			overrides: HashMap::new()
+			overrides: HashMap::new(),
+			changed_overrides: HashSet::new(),
+			changed_overrides_timer: 0,
+			client_colors: ClientColors::default(),
+			audio_focus_keys: Vec::new(),
+			logo_status: None,
+			logo_effect: None,
+			game_mode_keys: None,
+			brightness: None,
+			initial_report_rate: None,
+			report_rate: None,
+			profile_report_rate: None,
+			theme_override: None,
+			lighting_enabled: true,
+			light_key_macro: None,
+			mode_key_held: None,
+			light_key_pressed_at: None,
+			pending_gestures: HashMap::new(),
+			held_gkeys: HashMap::new(),
+			macro_hint: None,
+			tick: 0,
+			tick_started: Instant::now(),
+			gshift_held: None,
+			shifted_gkeys: HashSet::new(),
+			reinitialize_at: Instant::now(),
+			reinitialize_backoff: Self::REINITIALIZE_BACKOFF,
+			following: false,
+			notification_keys: Vec::new(),
+			notification_breathing: false
 		}
 	}
 
@@ -90,27 +304,91 @@ impl DeviceThread
 	fn macro_for_gkey(&self, gkey_number: u8) -> Option<Macro>
 	{
 		let config = self.state.config.read().unwrap();
-		let current_profile = self.state.active_profile.read().unwrap();
+		let current_profile = self.active_profile(&config);
+
+		let resolver = ProfileResolver::new(&config, &current_profile, self.active_mode);
+
+		match self.gshift_held.is_some()
+		{
+			true => resolver.shifted_macro_for_gkey(gkey_number),
+			false => resolver.macro_for_gkey(gkey_number)
+		}
+		.map(|macro_| macro_.into_owned())
+	}
+
+	fn gshift_key(&self) -> Option<u8>
+	{
+		let config = self.state.config.read().unwrap();
+		let current_profile = self.active_profile(&config);
+
+		ProfileResolver::new(&config, &current_profile, self.active_mode).gshift_key()
+	}
+
+	fn release_macro_for_gkey(&self, gkey_number: u8) -> Option<Macro>
+	{
+		let config = self.state.config.read().unwrap();
+		let current_profile = self.active_profile(&config);
 
-		current_profile
-			.macro_for_gkey(&config, self.active_mode, gkey_number)
+		ProfileResolver::new(&config, &current_profile, self.active_mode)
+			.release_macro_for_gkey(gkey_number)
 			.map(|macro_| macro_.into_owned())
 	}
 
+	fn macro_for_media_key(&self, key: MediaKey) -> Option<Macro>
+	{
+		let config = self.state.config.read().unwrap();
+		let current_profile = self.active_profile(&config);
+
+		let resolver = ProfileResolver::new(&config, &current_profile, self.active_mode);
+
+		match self.gshift_held.is_some()
+		{
+			true => resolver.shifted_macro_for_media_key(key),
+			false => resolver.macro_for_media_key(key)
+		}
+		.map(|macro_| macro_.into_owned())
+	}
+
 	fn last_color_for_scancode(&self, scancode: Scancode) -> Color
 	{
 		let last_color = match &self.lighting_state
 		{
-			CurrentLightingState::Custom(color_data) => color_data
+			CurrentLightingState::Custom(color_data)
+				| CurrentLightingState::Focus(color_data) => color_data
 				.iter()
 				.find(|(_color, scancodes)| scancodes.contains(&scancode))
 				.map(|(color, _scancodes)| *color),
-			CurrentLightingState::Effect(_data) => None
+			CurrentLightingState::Effect(_data) => None,
+			CurrentLightingState::Palette(color) => Some(*color),
+			CurrentLightingState::Off => None
 		};
 
 		last_color.unwrap_or_else(Color::black)
 	}
 
+	/// Resolves an indicator color for a key against the lighting being shown
+	fn indicator_color(&self, color: IndicatorColor, scancode: Scancode) -> Color
+	{
+		let palette = match &self.lighting_state
+		{
+			CurrentLightingState::Custom(assignments)
+				| CurrentLightingState::Focus(assignments) => ThemePalette::from_assignments(assignments),
+			CurrentLightingState::Effect(effect) => ThemePalette::from_effect(effect),
+			CurrentLightingState::Palette(color) => ThemePalette
+			{
+				background: *color,
+				accent: color.contrasting()
+			},
+			CurrentLightingState::Off => ThemePalette
+			{
+				background: Color::black(),
+				accent: Color::black().contrasting()
+			}
+		};
+
+		color.resolve(&palette, self.last_color_for_scancode(scancode))
+	}
+
 	/// Main event loop for a connected device. General flow is:
 	///    - Poll for events from the device, then handle them
 	///    - Handle any signals from other threads
@@ -118,13 +396,31 @@ impl DeviceThread
 	pub fn event_loop(&mut self, rx: Receiver<DeviceSignal>)
 	{
 		self.device.take_control();
+		self.brightness = self.device.brightness().ok();
+		self.initial_report_rate = match self.device.report_rates().is_empty()
+		{
+			true => None,
+			false => self.device.report_rate().ok()
+		};
+		self.report_rate = self.initial_report_rate;
+		self.update_device_config();
+		// devices opened after startup have missed the first profile change
+		self.apply_profile();
+		self.device.commit();
+		self.main_thread_tx.send(MainThreadSignal::Hook(HookEvent::DeviceConnected { serial_number: self.serial_number.clone() }));
 
 		loop
 		{
-			self.device
-				.get_events()
-				.iter()
-				.for_each(|event| self.handle_event(event));
+			let events = self.device.get_events();
+			events.iter().for_each(|event| self.handle_event(event));
+			self.state.driver_stats.events_processed(events.len());
+			self.state.driver_stats.add_commands(&self.device.take_command_stats());
+
+			if self.device.consecutive_failures() >= Self::REINITIALIZE_AFTER_FAILURES
+				&& Instant::now() >= self.reinitialize_at
+			{
+				self.reinitialize();
+			}
 
 			match rx.try_recv()
 			{
@@ -133,301 +429,2030 @@ impl DeviceThread
 				Err(TryRecvError::Disconnected)
 					| Ok(DeviceSignal::Shutdown) => break,
 
-				Ok(DeviceSignal::ConfigurationReloaded)
-					| Ok(DeviceSignal::ProfileChanged) =>
+				Ok(DeviceSignal::ConfigurationReloaded) =>
+				{
+					// indicators are rebuilt if this keyboard's subsystems changed
+					if self.update_device_config()
+					{
+						self.overrides.clear();
+						self.update_media_indicators();
+					}
+
+					self.change_profile();
+				},
+
+				Ok(DeviceSignal::ProfileChanged) => self.change_profile(),
+
+				Ok(DeviceSignal::MediaStateChanged) =>
+				{
+					self.update_media_indicators();
+
+					if self.volume_indicator_timer > 0
+					{
+						self.show_volume_indicator();
+					}
+
+					// only the keys that changed are redrawn, on the next frame
+					self.update_audio_focus();
+				},
+
+				Ok(DeviceSignal::SetGameMode(command)) => self.set_game_mode(command),
+
+				Ok(DeviceSignal::LowPowerChanged) => self.apply_profile(),
+
+				// indicators are rebuilt from scratch, so they're gone if reactive
+				// lighting was switched off, or back if it was switched on
+				Ok(DeviceSignal::SubsystemsChanged) =>
 				{
-					self.blink_timer = Self::BLINK_DELAY;
-					self.stop_and_remove_all_macros();
+					self.overrides.clear();
+					self.update_media_indicators();
+					self.update_audio_focus();
+					self.update_notification_counter();
 					self.apply_profile();
 					self.apply_overrides();
 					self.device.commit();
 				},
 
-				Ok(DeviceSignal::MediaStateChanged) =>
+				Ok(DeviceSignal::NotificationsChanged) =>
 				{
-					use crate::media::PlayerStatus;
+					self.update_notification_counter();
+
+					let breathe_logo =
+					{
+						let config = self.state.config.read().unwrap();
+						let profile = self.active_profile(&config);
+
+						ProfileResolver::new(&config, &profile, self.active_mode)
+							.notifications()
+							.map(|notifications| notifications.breathe_logo)
+							.unwrap_or(false)
+					};
 
-					let media_state = { *self.state.media_state.read().unwrap() };
-					let no_media = media_state.player_status == PlayerStatus::NoMedia;
-					let red = Color::new(255, 0, 0);
+					let pending = self.state.pending_notifications.load(Ordering::Relaxed) > 0;
 
-					self.set_override(Scancode::Mute, media_state.muted.then(|| red));
-					self.set_override(Scancode::MediaPrevious, no_media.then(Color::black));
-					self.set_override(Scancode::MediaNext, no_media.then(Color::black));
-					self.set_override(Scancode::MediaPlayPause, match media_state.player_status
+					// the counter keys are redrawn on their own, only the logo needs the profile
+					if (breathe_logo && pending) != self.notification_breathing
 					{
-						PlayerStatus::Playing => None,
-						PlayerStatus::Paused => Some(red),
-						PlayerStatus::NoMedia => Some(Color::black())
-					});
+						self.apply_profile();
+						self.apply_overrides();
+						self.device.commit();
+					}
+				},
+
+				// drawn with the overrides, on the next frame
+				Ok(DeviceSignal::SetKeyColors(update)) =>
+				{
+					let changed = self.client_colors.update(update);
+					self.changed_overrides.extend(changed);
+				},
+
+				Ok(DeviceSignal::ClearKeyColors(client)) =>
+				{
+					let changed = self.client_colors.clear(&client);
+					self.changed_overrides.extend(changed);
+				},
+
+				Ok(DeviceSignal::SetKeyColorsNow(update, drawn)) =>
+				{
+					let changed = self.client_colors.update(update);
+					self.changed_overrides.extend(changed);
+					self.changed_overrides_timer = 0;
+					self.update_changed_overrides();
+					drawn.drawn();
+				},
+
+				Ok(DeviceSignal::SetMode(mode)) =>
+				{
+					match mode <= self.mode_count + self.virtual_modes()
+					{
+						true => self.switch_mode(mode),
+						false => warn!("this keyboard doesn't have a mode {}", mode)
+					}
+				},
 
+				Ok(DeviceSignal::PaletteChanged)
+					| Ok(DeviceSignal::EffectChanged)
+					| Ok(DeviceSignal::NightModeChanged)
+					| Ok(DeviceSignal::PlaylistChanged)
+					| Ok(DeviceSignal::FocusModeChanged) =>
+				{
 					self.apply_profile();
 					self.apply_overrides();
 					self.device.commit();
 				}
 			}
 
+			self.update_following();
+
 			// don't try and override keys if an effect is running
 			if let CurrentLightingState::Custom(_data) = &self.lighting_state
 			{
 				self.update_macro_indicators();
 			}
 
-			thread::sleep(Duration::from_millis(Self::POLL_INTERVAL));
-		}
+			self.update_volume_indicator();
+			self.update_game_mode_indicator();
+			self.update_mode_key_hold();
+			self.update_light_key();
+			self.update_gestures();
+			self.update_macro_hints();
+			self.update_logo_status();
+			self.update_client_colors();
+			self.update_changed_overrides();
+			// a follower is shown the leader's frames instead of drawing its own
+			if !self.following
+			{
+				self.update_frames();
+			}
+
+			self.update_confirmations();
+
+			// key presses wake this straight away, only timers need a timeout
+			self.device.wait_for_events(Duration::from_millis(self.wait_interval()));
 
-		self.device.release_control();
+			let now = Instant::now();
+			self.tick = now.duration_since(self.tick_started).as_millis() as u64;
+			self.tick_started = now;
+		}
 	}
 
-	fn apply_profile(&mut self)
+	/// Draws the keyboard's own lighting again once it stops following another
+	/// (mirroring was switched off, or the leader was unplugged)
+	fn update_following(&mut self)
 	{
-		let config = self.state.config.read().unwrap();
-		let profile = self.state.active_profile.read().unwrap();
-		let theme = profile.theme(&config, self.active_mode);
-
-		self.device.reset_game_mode_keys();
+		let following = self.state.mirror.is_following(&self.serial_number);
 
-		if let Some(game_mode_scancodes) = &profile.game_mode_keys
+		if following == self.following
 		{
-			self.device.add_game_mode_keys(game_mode_scancodes);
+			return
 		}
 
-		match theme
+		self.following = following;
+
+		if !following
 		{
-			Theme::Static(_assignments) =>
-			{
-				// fine to unwrap this, None is only returned for Theme::Effect variants
-				let scancodes = theme.scancode_assignments(&config.keygroups).unwrap();
-				//self.device.clear(); this is causing flickering
-				self.device.set_all(Color::black());
-				self.device.apply_scancode_assignments(&scancodes);
-				self.device.commit();
-				self.lighting_state = CurrentLightingState::Custom(scancodes);
-			},
-			Theme::Effect(effect) =>
-			{
-				// TODO work out wtf is going on with the logo
-				let group = EffectGroup::Keys;
-				self.device.set_effect(group, effect);
-				self.lighting_state = CurrentLightingState::Effect(effect.clone());
-			}
+			self.blink_states = None;
+			self.apply_profile();
+			self.apply_overrides();
+			self.device.commit();
 		}
 	}
 
-	fn set_override<C>(&mut self, scancode: Scancode, color: C)
-	where
-		C: Into<Option<Color>> + std::fmt::Debug
+	/// How long the event loop can wait for the device before a timer or
+	/// animation needs it
+	fn wait_interval(&self) -> u64
 	{
-		debug!("set override for {:?} to {:?}", &scancode, &color);
-		if let Some(color) = color.into()
+		let options = &self.lighting_options;
+		let animating = self.following
+			|| self.software_effect.is_some()
+			|| self.transition.is_some()
+			|| self.live_theme.is_some()
+			|| (options.dithering && options.brightness < 100);
+
+		let timing = self.volume_indicator_timer > 0
+			|| self.game_mode_indicator.is_some()
+			|| !self.awaiting_confirmation.is_empty()
+			|| !self.changed_overrides.is_empty()
+			|| !self.pending_gestures.is_empty()
+			|| !self.held_gkeys.is_empty()
+			|| self.macro_hint.is_some()
+			|| self.mode_key_held.is_some()
+			|| self.light_key_pressed_at.is_some();
+
+		match animating || timing
 		{
-			self.overrides.insert(scancode, color);
-		}
-		else
-		{
-			self.overrides.remove(&scancode);
+			true => Self::POLL_INTERVAL,
+			false => Self::IDLE_INTERVAL
 		}
 	}
 
-	fn apply_overrides(&mut self)
+	fn change_profile(&mut self)
 	{
-		if let CurrentLightingState::Custom(_) = &self.lighting_state
-		{
-			let mut assignments = HashMap::new();
-
-			for (scancode, color) in &self.overrides
-			{
-				assignments
-					.entry(*color)
-					.or_insert_with(Vec::new)
-					.push(*scancode);
-			}
-
-			let assignments: ScancodeAssignments = assignments.drain().collect();
-			self.device.apply_scancode_assignments(assignments.as_ref());
-		}
+		self.restore_profile_mode();
+		self.theme_override = None;
+		self.blink_states = None;
+		self.awaiting_confirmation.clear();
+		self.held_gkeys.clear();
+		self.stop_and_remove_all_macros();
+		self.update_audio_focus();
+		self.update_notification_counter();
+		self.apply_profile();
+		self.apply_overrides();
+		self.device.commit();
 	}
 
-	fn handle_event(&mut self, event: &DeviceEvent)
+	/// Takes control of the keyboard again once it's stopped answering commands
+	/// (i.e. it was reset by a usb hiccup), and puts back everything the driver
+	/// had set on it. Tries again later, waiting longer each time, if it still
+	/// isn't answering.
+	fn reinitialize(&mut self)
 	{
-		match event
-		{
-			DeviceEvent::KeyDown(KeyType::GKey, number) => self.macro_keydown(*number),
-			DeviceEvent::KeyUp(KeyType::GKey, number) => self.macro_keyup(*number),
+		warn!("keyboard {} has stopped answering, taking control of it again", &self.serial_number);
 
-			DeviceEvent::BrightnessLevelChanged(brightness) =>
-			{
-				// not sure what use this will be yet, maybe send a notification
-				// via dbus..?
-				info!("brightness level was changed to {}%", brightness)
-			},
+		if let Err(error) = self.device.take_control()
+		{
+			warn!("unable to take control of keyboard {} ({}), trying again in {}s",
+				&self.serial_number, error, self.reinitialize_backoff.as_secs());
 
-			DeviceEvent::KeyUp(KeyType::MacroRecord, _) =>
-			{
-				let new_state = !self.state.macro_recording.load(Ordering::Relaxed);
-				self.state.macro_recording.store(new_state, Ordering::Relaxed);
-				self.device.set_macro_recording(new_state);
-			},
+			self.reinitialize_at = Instant::now() + self.reinitialize_backoff;
+			self.reinitialize_backoff = (self.reinitialize_backoff * 2).min(Self::MAX_REINITIALIZE_BACKOFF);
+			return
+		}
 
-			DeviceEvent::KeyDown(KeyType::Mode, mode) =>
-			{
-				debug!("mode changed to: {}", mode);
-				self.active_mode = *mode;
-				self.blink_timer = Self::BLINK_DELAY;
-				self.stop_all_hold_to_repeat_macros();
-			},
+		info!("keyboard {} is answering again", &self.serial_number);
+		self.reinitialize_backoff = Self::REINITIALIZE_BACKOFF;
 
-			DeviceEvent::MediaKeyDown(key) => self.window_system_tx
-				.send(WindowSystemSignal::SendKeyCombo(match key
-				{
-					MediaKey::Mute => "XF86AudioMute",
-					MediaKey::PlayPause => "XF86AudioPlay",
-					MediaKey::Next => "XF86AudioNext",
-					MediaKey::Previous => "XF86AudioPrev",
-					MediaKey::VolumeUp => "XF86AudioRaiseVolume",
-					MediaKey::VolumeDown => "XF86AudioLowerVolume"
-				}.to_string()))
-				.unwrap_or(()),
+		// whatever the keyboard had is gone
+		self.game_mode_keys = None;
+		self.brightness = self.device.brightness().ok();
+		self.report_rate = match self.initial_report_rate
+		{
+			Some(_) => self.device.report_rate().ok(),
+			None => None
+		};
+		self.profile_report_rate = None;
+		self.device.set_macro_recording(self.state.macro_recording.load(Ordering::Relaxed));
+		self.device.set_mode(self.active_mode.min(self.mode_count));
 
-			_ => ()
-		}
+		self.apply_profile();
+		self.apply_overrides();
+		self.device.commit();
 	}
 
-	fn update_macro_indicators(&mut self)
+	/// Picks up this keyboard's section of the config, returning whether the
+	/// subsystems switched off for it have changed
+	fn update_device_config(&mut self) -> bool
 	{
-		self.blink_timer += Self::POLL_INTERVAL;
+		let device_config = self.state.config
+			.read()
+			.unwrap()
+			.devices
+			.get(&self.serial_number)
+			.cloned()
+			.unwrap_or_default();
+
+		let changed = device_config.disabled != self.device_config.disabled;
+		self.device_config = device_config;
+		changed
+	}
 
-		if self.blink_timer < Self::BLINK_DELAY
+	/// The profile shown on this keyboard: the active profile, unless that's the
+	/// default profile and the keyboard has a default profile of its own
+	fn active_profile(&self, config: &Configuration) -> Profile
+	{
+		match self.state.active_profile_name.read().unwrap().as_str()
 		{
-			return
+			"default" => config.default_profile_for_device(&self.serial_number).clone(),
+			_ => self.state.active_profile.read().unwrap().clone()
 		}
+	}
 
-		self.blink_timer = 0;
-		self.blink_state = !self.blink_state;
+	/// The theme shown on this keyboard: one picked with the light key, then the
+	/// keyboard's own theme, then the profile's playlist, then the profile's
+	fn theme<'a>(&self, config: &'a Configuration, resolver: &ProfileResolver<'a>) -> &'a Theme
+	{
+		// the playlist is the active profile's, which might not be the one this
+		// keyboard is showing
+		let playlist = self.state.playlist.read().unwrap().clone();
+		let playlist_theme = match playlist.playlist.is_some() && playlist.playlist.as_deref() == resolver.playlist()
+		{
+			true => playlist.theme,
+			false => None
+		};
 
-		let blink_color = Color::new(if self.blink_state { 255 } else { 0 }, 0, 0);
-		let mut gkey_data: Vec<(Scancode, Color)> = Vec::new();
+		self.theme_override
+			.as_ref()
+			.or(self.device_config.theme.as_ref())
+			.or(playlist_theme.as_ref())
+			.and_then(|theme_name| config.themes.get(theme_name))
+			.unwrap_or_else(|| resolver.theme())
+	}
 
-		// TODO proabably re-implement this section when drain_filter is added to HashMap
+	/// Whether a subsystem is on, both for the driver and for this keyboard
+	fn subsystem_enabled(&self, subsystem: Subsystem) -> bool
+	{
+		!self.device_config.disabled.contains(&subsystem) && self.state.subsystem_enabled(subsystem)
+	}
 
-		let stopped_macro_numbers: HashMap<u8, HashSet<u8>> = self.macro_states
-			.iter()
-			.map(|(mode, mode_states)|
+	fn apply_profile(&mut self)
+	{
+		let state = Arc::clone(&self.state);
+		let config = state.config.read().unwrap();
+		let profile = self.active_profile(&config);
+		self.lighting_options = config.lighting;
+		self.state.mirror.set_enabled(config.lighting.mirror);
+		self.device.set_rgb_ids(config.layout().rgb_ids);
+		self.device.set_power_cap(self.state.low_power
+			.load(Ordering::Relaxed)
+			.then(|| config.lighting.low_power_cap()));
+		self.device.set_color_temperature(*self.state.color_temperature.read().unwrap());
+
+		let capabilities = RenderCapabilities
+		{
+			software_effects: self.lighting_options.software_effects
+		};
+
+		let resolver = ProfileResolver::new(&config, &profile, self.active_mode);
+		let effect = self.state.effect.read().unwrap().clone();
+		let theme = match effect
+		{
+			// an effect set over dbus replaces whatever theme the keys would show
+			Some(effect) => Cow::Owned(Theme::Effect(effect)),
+			None => self.theme(&config, &resolver).negotiate(&capabilities)
+		};
+		let logo = match resolver.logo_theme().map(|logo_theme| logo_theme.negotiate(&capabilities))
+		{
+			Some(logo_theme) => match logo_theme.as_ref()
 			{
-				 let stopped_mode_macros = mode_states
-					.iter()
-					.filter_map(|(gkey_number, (_tx, stopped, _activation_type))|
-					{
-						let stopped = stopped.load(Ordering::Relaxed).then(|| *gkey_number);
+				Theme::Effect(effect) => LogoLighting::Effect(effect.clone()),
+				// fine to unwrap, as above
+				_ => LogoLighting::Color(Self::logo_color(&logo_theme
+					.scancode_assignments(&config.keygroups)
+					.unwrap()))
+			},
+			None => LogoLighting::FollowTheme
+		};
 
-						// if this is the current mode, and the macro is running or stopped,
-						// override the color of the key as appropriate
+		// pending notifications breathe the logo, in place of whatever else is on it
+		let pending_notifications = self.state.pending_notifications.load(Ordering::Relaxed);
+		let notification_breathing = resolver
+			.notifications()
+			.filter(|notifications| notifications.breathe_logo && pending_notifications > 0)
+			.map(|notifications| EffectConfiguration::Breathing
+			{
+				color: notifications.color,
+				duration: Self::NOTIFICATION_BREATHING_DURATION,
+				brightness: 100
+			});
 
-						if *mode == self.active_mode
-						{
-							let scancode = Scancode::from_gkey(*gkey_number).unwrap();
-							let set_color = stopped
-								.map(|_gkey_number| self.last_color_for_scancode(scancode))
-								.unwrap_or(blink_color);
-							gkey_data.push((scancode, set_color));
-						}
+		self.notification_breathing = notification_breathing.is_some();
 
-						stopped
-					})
-					.collect();
+		// the driver's status takes priority over anything else on the logo
+		self.logo_status = self.logo_status(&config.logo_status);
+		let logo = self.logo_status
+			.clone()
+			.or(notification_breathing)
+			.map(LogoLighting::Effect)
+			.unwrap_or(logo);
 
-				(*mode, stopped_mode_macros)
-			})
-			.collect();
+		self.software_effect = None;
+		self.effect_brightness = 1_f64;
+		self.live_theme = None;
 
-		for (mode, mode_states) in &mut self.macro_states
-		{
-			if let Some(mode_stopped_macros) = stopped_macro_numbers.get(mode)
-			{
-				debug!("clearing stopped macros in mode {}: {:#?}", mode, mode_stopped_macros);
+		let gkeys_in_use = config.gkey_mode == GkeyMode::Software || resolver.has_gkey_assignments();
+		self.device.set_gkeys_software_mode(gkeys_in_use);
 
-				mode_states.retain(|gkey_number, _state|
-					!mode_stopped_macros.contains(gkey_number));
-			}
+		self.program_game_mode_keys(resolver.game_mode_keys());
+		self.apply_report_rate(resolver.report_rate());
+
+		// palette (tester) mode takes priority over whatever the profile's theme is
+		if let Some(palette) = *self.state.palette.read().unwrap()
+		{
+			let color = palette.into();
+			self.device.stop_effects();
+			self.device.set_all(color);
+			self.lighting_state = CurrentLightingState::Palette(color);
+			self.logo_effect = None;
+			return
 		}
 
-		if !gkey_data.is_empty()
+		if !self.lighting_enabled
 		{
-			self.device.set_4(&gkey_data);
-			self.device.commit();
+			self.transition = None;
+			self.device.stop_effects();
+			self.device.set_all(Color::black());
+			self.lighting_state = CurrentLightingState::Off;
+			self.logo_effect = None;
+			return
 		}
 
-		let mut mode_leds = 0;
+		// a standing scene with its own brightness, which nothing else is drawn over
+		if self.state.focus_mode.load(Ordering::Relaxed)
+		{
+			let focus_mode = &config.focus_mode;
+			let assignments = vec![(focus_mode.color, focus_mode.scancodes(&config.keygroups))];
+
+			self.lighting_options.brightness = focus_mode.brightness;
+			self.transition = None;
+			self.device.stop_effects();
+			self.device.set_all(Color::black());
+			self.apply_assignments(&assignments);
+			self.lighting_state = CurrentLightingState::Focus(assignments);
+			self.logo_effect = None;
+			return
+		}
 
-		for mode in 1..=self.mode_count
+		match theme.as_ref()
 		{
-			if mode != self.active_mode
+			Theme::Static(_) | Theme::Animated { .. } | Theme::Hud { .. } | Theme::Heatmap { .. }
+				| Theme::Screen { .. } =>
 			{
-				let mode_has_active_macros = self.macro_states
-					.get(&mode)
-					.map(|mode_macros| !mode_macros.is_empty())
-					.unwrap_or(false);
+				// fine to unwrap this, None is only returned for Theme::Effect variants
+				let mut scancodes = theme.scancode_assignments(&config.keygroups).unwrap();
 
-				if !mode_has_active_macros || !self.blink_state
+				// a logo with its own theme is left out of the keys' assignments
+				if let LogoLighting::Color(_) | LogoLighting::Effect(_) = logo
 				{
-					continue
+					for (_color, group) in scancodes.iter_mut()
+					{
+						group.retain(|scancode| *scancode != Scancode::Logo);
+					}
 				}
-			}
 
-			mode_leds |= 1 << (mode - 1);
-		}
-
-		self.device.set_mode_leds(mode_leds);
-	}
+				if let LogoLighting::Color(color) = logo
+				{
+					scancodes.push((color, vec![Scancode::Logo]));
+				}
 
-	fn macro_keydown(&mut self, gkey_number: u8)
-	{
-		debug!("gkey down {}", gkey_number);
+				let logo_effect = match &logo
+				{
+					LogoLighting::Effect(effect) => Some(effect.clone()),
+					_ => None
+				};
 
-		if let Entry::Occupied(ref entry) = self.current_mode_macro_states().entry(gkey_number)
-		{
-			let (tx, stopped, activation_type) = entry.get();
+				// writing the keys replaces any effect on the logo, so a logo effect
+				// has to be stopped first and started afterwards
+				if logo_effect.is_none()
+				{
+					self.set_logo_effect(None);
+				}
 
-			if !stopped.load(Ordering::Relaxed)
-			{
-				debug!("macro slot is already active, activationtype: {:#?}", &activation_type);
+				let widgets = self.subsystem_enabled(Subsystem::Widgets);
 
-				match activation_type
+				match theme.as_ref()
 				{
-					ActivationType::Toggle =>
+					Theme::Animated { software_effect, .. } =>
+						self.software_effect = Some((software_effect.clone(), Instant::now())),
+					Theme::Hud { hud, interval, .. } if widgets => self.live_theme = Some(LiveThemeState
 					{
-						debug!("stopping toggle macro");
-						tx.send(MacroSignal::Stop);
-						return
-					},
-					ActivationType::Repeat(_count) =>
+						layer: LiveLayer::Hud(
+							hud
+								.iter()
+								.map(|zone| (zone.clone(), zone.scancodes(&config.keygroups)))
+								.collect(),
+							MetricsSampler::new()),
+						background: scancodes.clone(),
+						interval: *interval as u64,
+						// sample straight away, so the rates are ready for the next frame
+						timer: *interval as u64
+					}),
+					Theme::Heatmap { heatmap, interval, .. } if widgets => self.live_theme = Some(LiveThemeState
 					{
-						debug!("resetting count on repeat macro");
-						tx.send(MacroSignal::ResetCount);
-						return
-					},
+						layer: LiveLayer::Heatmap(heatmap.clone(), heatmap.scancodes(&config.keygroups)),
+						background: scancodes.clone(),
+						interval: *interval as u64,
+						timer: *interval as u64
+					}),
+					Theme::Screen { screen, interval, .. } if widgets => self.live_theme = Some(LiveThemeState
+					{
+						layer: LiveLayer::Screen(screen.clone(), screen.scancodes(&config.keygroups), ScreenSampler::new(), vec![]),
+						background: scancodes.clone(),
+						interval: *interval as u64,
+						timer: *interval as u64
+					}),
 					_ => ()
 				}
+
+				self.start_transition(&scancodes);
+				self.lighting_state = CurrentLightingState::Custom(scancodes.clone());
+				let frame = self.transition_frame().unwrap_or(scancodes);
+
+				//self.device.clear(); this is causing flickering
+				self.device.set_all(Color::black());
+				self.apply_assignments(&frame);
+				self.device.commit();
+				self.set_logo_effect(logo_effect);
+			},
+			Theme::Effect(effect) =>
+			{
+				self.transition = None;
+				self.device.set_effect(EffectGroup::Keys, effect);
+				self.lighting_state = CurrentLightingState::Effect(effect.clone());
+
+				self.set_logo_effect(Some(match logo
+				{
+					LogoLighting::FollowTheme => effect.clone(),
+					LogoLighting::Color(color) => EffectConfiguration::Static { color },
+					LogoLighting::Effect(logo_effect) => logo_effect
+				}));
+			}
+		}
+	}
+
+	/// What the logo shows for the driver's status, if anything. In order: macro
+	/// recording, a failed config reload, a running macro, then a color set over
+	/// dbus.
+	fn logo_status(&self, colors: &LogoStatusColors) -> Option<EffectConfiguration>
+	{
+		let macro_running = self.macro_states
+			.values()
+			.flat_map(|mode_states| mode_states.values())
+			.chain(self.media_key_macros.values())
+			.any(|(_tx, stopped, _activation_type)| !stopped.load(Ordering::Relaxed));
+
+		let static_color = |color: Option<Color>, active: bool| color
+			.filter(|_color| active)
+			.map(|color| EffectConfiguration::Static { color });
+
+		static_color(colors.macro_recording, self.state.macro_recording.load(Ordering::Relaxed))
+			.or_else(|| static_color(colors.config_error, self.state.config_error.load(Ordering::Relaxed)))
+			.or_else(|| colors.macro_running
+				.filter(|_color| macro_running)
+				.map(|color| EffectConfiguration::Breathing
+				{
+					color,
+					duration: Self::LOGO_STATUS_BREATHING_DURATION,
+					brightness: 100
+				}))
+			.or_else(|| static_color(*self.state.logo_color.read().unwrap(), true))
+	}
+
+	/// Re-applies the profile when the logo's status has changed
+	fn update_logo_status(&mut self)
+	{
+		let logo_status =
+		{
+			let config = self.state.config.read().unwrap();
+			self.logo_status(&config.logo_status)
+		};
+
+		if logo_status != self.logo_status
+		{
+			self.apply_profile();
+			self.apply_overrides();
+			self.device.commit();
+		}
+	}
+
+	/// The color a theme without an effect gives the logo: the logo's own color
+	/// if it has one, otherwise the theme's first color
+	fn logo_color(assignments: &[(Color, Vec<Scancode>)]) -> Color
+	{
+		Self::key_colors(assignments)
+			.get(&Scancode::Logo)
+			.copied()
+			.or_else(|| assignments.first().map(|(color, _scancodes)| *color))
+			.unwrap_or_default()
+	}
+
+	/// Starts (or stops, with None) a firmware effect on the logo, if it isn't
+	/// already running
+	fn set_logo_effect(&mut self, effect: Option<EffectConfiguration>)
+	{
+		if self.logo_effect == effect
+		{
+			return
+		}
+
+		self.device.set_effect(EffectGroup::Logo, effect.as_ref().unwrap_or(&EffectConfiguration::None));
+		self.logo_effect = effect;
+	}
+
+	/// The color of each key in a set of assignments. Later assignments win, as
+	/// they're applied to the device in order.
+	fn key_colors(assignments: &[(Color, Vec<Scancode>)]) -> HashMap<Scancode, Color>
+	{
+		assignments
+			.iter()
+			.flat_map(|(color, scancodes)| scancodes.iter().map(move |scancode| (*scancode, *color)))
+			.collect()
+	}
+
+	/// Starts crossfading from whatever is currently shown to a new set of
+	/// assignments, if transitions are enabled and the lighting actually changes
+	fn start_transition(&mut self, to: &ScancodeAssignments)
+	{
+		// re-applying the same lighting (i.e. on media changes) leaves any running
+		// transition alone
+		match &self.lighting_state
+		{
+			CurrentLightingState::Custom(from) if from == to => return,
+			CurrentLightingState::Custom(_) => (),
+			_ => return self.transition = None
+		}
+
+		// if a transition is already running, carry on from wherever it got to
+		let from = self.transition_frame().or_else(|| match &self.lighting_state
+		{
+			CurrentLightingState::Custom(from) => Some(from.clone()),
+			_ => None
+		});
+
+		self.transition = match (from, self.lighting_options.transition)
+		{
+			(Some(from), duration) if duration > 0 => Some((Self::key_colors(&from), Instant::now())),
+			_ => None
+		};
+	}
+
+	/// The assignments for the current frame of the crossfade into the current
+	/// custom lighting, or None (ending the transition) if there isn't one running
+	fn transition_frame(&mut self) -> Option<ScancodeAssignments>
+	{
+		let progress = match (&self.transition, &self.lighting_state)
+		{
+			(Some((_from, started)), CurrentLightingState::Custom(_)) =>
+				started.elapsed().as_millis() as f64 / self.lighting_options.transition.max(1) as f64,
+			_ => return None
+		};
+
+		if progress >= 1_f64
+		{
+			self.transition = None;
+			return None
+		}
+
+		let (from, to) = match (&self.transition, &self.lighting_state)
+		{
+			(Some((from, _started)), CurrentLightingState::Custom(to)) => (from, Self::key_colors(to)),
+			_ => return None
+		};
+
+		let mut frame: HashMap<Color, Vec<Scancode>> = HashMap::new();
+		let scancodes: HashSet<&Scancode> = from.keys().chain(to.keys()).collect();
+
+		for scancode in scancodes
+		{
+			let from_color = from.get(scancode).copied().unwrap_or_else(Color::black);
+			let to_color = to.get(scancode).copied().unwrap_or_else(Color::black);
+
+			frame
+				.entry(from_color.mix(to_color, progress))
+				.or_insert_with(Vec::new)
+				.push(*scancode);
+		}
+
+		Some(frame.into_iter().collect())
+	}
+
+	/// Shows the media state on the media keys
+	fn update_media_indicators(&mut self)
+	{
+		use crate::media::PlayerStatus;
+
+		let media_state = self.state.media_state.read().unwrap().clone();
+		let no_media = media_state.player_status == PlayerStatus::NoMedia;
+		let indicators = self.state.config.read().unwrap().indicators;
+
+		self.set_override(Scancode::Mute, media_state.muted.then(|| indicators.muted));
+		self.set_override(Scancode::MediaPrevious, no_media.then(|| indicators.no_media));
+		self.set_override(Scancode::MediaNext, no_media.then(|| indicators.no_media));
+		self.set_override(Scancode::MediaPlayPause, match media_state.player_status
+		{
+			PlayerStatus::Playing => None,
+			PlayerStatus::Paused => Some(indicators.paused),
+			PlayerStatus::NoMedia => Some(indicators.no_media)
+		});
+	}
+
+	/// Overrides are how indicators are drawn, so none are set whilst reactive
+	/// lighting is switched off
+	fn set_override<C>(&mut self, scancode: Scancode, color: C)
+	where
+		C: Into<Option<IndicatorColor>> + std::fmt::Debug
+	{
+		debug!("set override for {:?} to {:?}", &scancode, &color);
+		let color = color.into().filter(|_color| self.subsystem_enabled(Subsystem::ReactiveLighting));
+
+		let previous = match color
+		{
+			Some(color) => self.overrides.insert(scancode, color),
+			None => self.overrides.remove(&scancode)
+		};
+
+		if previous != color
+		{
+			self.changed_overrides.insert(scancode);
+		}
+	}
+
+	/// Redraws just the keys whose overrides have changed, at most once a frame,
+	/// so a burst of changes (i.e. skipping through tracks) is a single write
+	fn update_changed_overrides(&mut self)
+	{
+		self.changed_overrides_timer = self.changed_overrides_timer.saturating_sub(self.tick);
+
+		if self.changed_overrides.is_empty() || self.changed_overrides_timer > 0
+		{
+			return
+		}
+
+		self.changed_overrides_timer = Self::FRAME_INTERVAL;
+		let changed: Vec<Scancode> = self.changed_overrides.drain().collect();
+
+		// overrides are only shown over custom lighting
+		if let CurrentLightingState::Custom(_) = &self.lighting_state
+		{
+			let key_colors: Vec<(Scancode, Color)> = changed
+				.into_iter()
+				.map(|scancode| (scancode, self.output_color(match self.overrides.get(&scancode)
+				{
+					Some(color) => self.indicator_color(*color, scancode),
+					None => self.client_colors
+						.color(scancode)
+						.unwrap_or_else(|| self.last_color_for_scancode(scancode))
+				})))
+				.collect();
+
+			self.device.set_4(&key_colors);
+			self.device.commit();
+		}
+	}
+
+	/// Removes client colors whose ttl has run out
+	fn update_client_colors(&mut self)
+	{
+		if !self.client_colors.is_empty()
+		{
+			let expired = self.client_colors.expire(self.tick);
+			self.changed_overrides.extend(expired);
+		}
+	}
+
+	fn apply_overrides(&mut self)
+	{
+		if let CurrentLightingState::Custom(_) = &self.lighting_state
+		{
+			let mut assignments = HashMap::new();
+
+			for (scancode, color) in self.client_colors.colors()
+			{
+				if !self.overrides.contains_key(&scancode)
+				{
+					assignments
+						.entry(color)
+						.or_insert_with(Vec::new)
+						.push(scancode);
+				}
+			}
+
+			// indicator colors are resolved against whatever is being shown now
+			for (scancode, color) in &self.overrides
+			{
+				assignments
+					.entry(self.indicator_color(*color, *scancode))
+					.or_insert_with(Vec::new)
+					.push(*scancode);
+			}
+
+			let assignments: ScancodeAssignments = assignments.drain().collect();
+			self.apply_assignments(&assignments);
+		}
+	}
+
+	/// Converts a color from the config into the color actually sent to the
+	/// device, taking the configured brightness, any running software effect
+	/// and dithering into account
+	fn output_color(&self, color: Color) -> Color
+	{
+		let options = &self.lighting_options;
+		let brightness = options.brightness as f64 / 100_f64 * self.effect_brightness;
+
+		match brightness >= 1_f64
+		{
+			true => color,
+			false => color.scaled(brightness, options.dithering.then(|| self.dither_frame))
+		}
+	}
+
+	fn apply_assignments(&mut self, assignments: &[(Color, Vec<Scancode>)])
+	{
+		let assignments: ScancodeAssignments = assignments
+			.iter()
+			.map(|(color, scancodes)| (self.output_color(*color), scancodes.clone()))
+			.collect();
+
+		self.device.apply_scancode_assignments(&assignments);
+	}
+
+	/// Renders the next frame of the current software effect, and advances the
+	/// dither frame if dithering is enabled and actually needed. If a frame can't
+	/// be written, the software effect is replaced with its hardware fallback.
+	fn update_frames(&mut self)
+	{
+		let options = &self.lighting_options;
+		let dithering = options.dithering
+			&& (options.brightness < 100 || self.software_effect.is_some());
+
+		if !dithering && self.software_effect.is_none() && self.transition.is_none() && self.live_theme.is_none()
+		{
+			return
+		}
+
+		self.frame_timer += self.tick;
+
+		if self.frame_timer < Self::FRAME_INTERVAL
+		{
+			return
+		}
+
+		self.frame_timer = 0;
+		self.dither_frame = self.dither_frame.wrapping_add(1);
+
+		if let Some((effect, started)) = &self.software_effect
+		{
+			self.effect_brightness = effect.brightness_at(started.elapsed());
+		}
+
+		// live themes only change when they're refreshed, so don't rewrite the
+		// keys every frame unless something else needs it
+		if !self.update_live_theme() && !dithering && self.software_effect.is_none() && self.transition.is_none()
+		{
+			return
+		}
+
+		let transition_frame = self.transition_frame();
+
+		if let CurrentLightingState::Custom(assignments) = &self.lighting_state
+		{
+			let assignments = transition_frame.unwrap_or_else(|| assignments.clone());
+			self.apply_assignments(&assignments);
+			self.apply_overrides();
+
+			if let Err(error) = self.device.commit()
+			{
+				self.fall_back_to_hardware_effect(error);
+			}
+		}
+	}
+
+	/// Redraws the live theme's layer over its background if its interval has
+	/// passed, resampling the system metrics, key statistics or screen. Returns
+	/// whether the lighting changed.
+	fn update_live_theme(&mut self) -> bool
+	{
+		let live_theme = match &mut self.live_theme
+		{
+			Some(live_theme) => live_theme,
+			None => return false
+		};
+
+		live_theme.timer += Self::FRAME_INTERVAL;
+
+		if live_theme.timer < live_theme.interval
+		{
+			return false
+		}
+
+		live_theme.timer = 0;
+		let key_colors: Vec<(Scancode, Color)> = match &mut live_theme.layer
+		{
+			LiveLayer::Hud(zones, sampler) =>
+			{
+				let metrics = sampler.sample();
+
+				zones
+					.iter()
+					.flat_map(|(zone, scancodes)| zone.key_colors(scancodes, &metrics))
+					.collect()
+			},
+			LiveLayer::Heatmap(heatmap, scancodes) =>
+				heatmap.key_colors(scancodes, &self.state.key_statistics.read().unwrap()),
+			LiveLayer::Screen(screen, scancodes, sampler, shown) => match sampler.sample(ScreenSync::COLUMNS)
+			{
+				Some(sampled) =>
+				{
+					*shown = screen.smooth(shown, &sampled);
+					screen.key_colors(scancodes, shown)
+				},
+				// the theme's own colors are left showing
+				None => return false
+			}
+		};
+
+		// the layer replaces the background for its keys
+		let mut assignments: ScancodeAssignments = live_theme.background
+			.iter()
+			.map(|(color, scancodes)| (*color, scancodes
+				.iter()
+				.filter(|scancode| !key_colors.iter().any(|(zone_scancode, _color)| zone_scancode == *scancode))
+				.copied()
+				.collect()))
+			.collect();
+
+		for (scancode, color) in key_colors
+		{
+			match assignments.iter_mut().find(|(group_color, _scancodes)| *group_color == color)
+			{
+				Some((_color, scancodes)) => scancodes.push(scancode),
+				None => assignments.push((color, vec![scancode]))
+			}
+		}
+
+		self.lighting_state = CurrentLightingState::Custom(assignments);
+		true
+	}
+
+	fn fall_back_to_hardware_effect(&mut self, error: CommandError)
+	{
+		if let Some((effect, _started)) = self.software_effect.take()
+		{
+			let config = self.state.config.read().unwrap();
+			let profile = self.active_profile(&config);
+			let colors = match self.theme(&config, &ProfileResolver::new(&config, &profile, self.active_mode))
+			{
+				Theme::Animated { colors, .. } => colors.as_slice(),
+				_ => &[]
+			};
+
+			let fallback = effect.hardware_fallback(colors);
+
+			warn!("device can't keep up with software effect ({:?}), falling back to {:?}",
+				error,
+				&fallback);
+
+			self.effect_brightness = 1_f64;
+			self.device.set_effect(EffectGroup::Keys, &fallback);
+			self.lighting_state = CurrentLightingState::Effect(fallback);
+		}
+	}
+
+	fn handle_event(&mut self, event: &DeviceEvent)
+	{
+		debug!(target: crate::tui::EVENTS, "keyboard {}: {:?}", &self.serial_number, event);
+
+		match event
+		{
+			DeviceEvent::KeyDown(KeyType::GKey, number) => self.macro_keydown(*number),
+			DeviceEvent::KeyUp(KeyType::GKey, number) => self.macro_keyup(*number),
+
+			DeviceEvent::BrightnessLevelChanged(brightness) => self.brightness_changed(*brightness),
+
+			DeviceEvent::KeyUp(KeyType::MacroRecord, _) =>
+			{
+				let new_state = !self.state.macro_recording.load(Ordering::Relaxed);
+				self.state.macro_recording.store(new_state, Ordering::Relaxed);
+				self.device.set_macro_recording(new_state);
+				self.main_thread_tx.send(MainThreadSignal::Hook(HookEvent::RecordingToggled { recording: new_state }));
+			},
+
+			DeviceEvent::KeyDown(KeyType::GameMode, _) => self.set_game_mode(GameModeCommand::Toggle),
+
+			DeviceEvent::KeyDown(KeyType::Mode, mode) =>
+			{
+				let mode_for_key = self.mode_for_key(*mode);
+				self.switch_mode(mode_for_key);
+				self.mode_key_held = Some((*mode, Instant::now(), false));
+			},
+
+			DeviceEvent::KeyUp(KeyType::Mode, mode) =>
+			{
+				if let Some((held_mode, _pressed, long_press)) = self.mode_key_held
+				{
+					if held_mode == *mode
+					{
+						self.mode_key_held = None;
+
+						// a short press unlocks the profile
+						if !long_press && self.state.profile_locked.load(Ordering::Relaxed)
+						{
+							self.main_thread_tx.send(MainThreadSignal::SetProfileLock(false));
+						}
+					}
+				}
+			},
+
+			DeviceEvent::MediaKeyDown(key) => self.media_keydown(*key),
+			DeviceEvent::MediaKeyUp(key) => self.media_keyup(*key),
+
+			_ => ()
+		}
+	}
+
+	/// The keyboard doesn't report the light key itself, only the brightness
+	/// changing because of it. When the light key's presses are bound to
+	/// something else the old brightness is put back before handling the press.
+	/// Otherwise the brightness changes as normal, and any action bound to the
+	/// new level is run.
+	fn brightness_changed(&mut self, brightness: u8)
+	{
+		// the keyboard reports brightness it was told to set as well
+		if self.brightness == Some(brightness)
+		{
+			return
+		}
+
+		let bindings = self.state.config.read().unwrap().light_key.clone();
+		let presses_bound = bindings.press.is_some() || bindings.double_press.is_some();
+
+		match (presses_bound, self.brightness)
+		{
+			(true, Some(previous)) =>
+			{
+				if let Err(error) = self.device.set_brightness(previous)
+				{
+					warn!("unable to restore brightness after light key press: {:?}", error);
+				}
+			},
+			// when the brightness is unknown it can't be restored, but the press
+			// still counts
+			(_, _previous) =>
+			{
+				info!("brightness level was changed to {}%", brightness);
+				self.brightness = Some(brightness);
+
+				if let Some(action) = bindings.levels.get(&brightness)
+				{
+					self.run_light_key_action(action.clone());
+				}
+
+				if !presses_bound
+				{
+					return
+				}
+			}
+		}
+
+		// a single press has to wait to see if it's the start of a double press
+		match (self.light_key_pressed_at.take(), bindings.double_press, bindings.press)
+		{
+			(Some(_pressed_at), Some(action), _press) => self.run_light_key_action(action),
+			(None, Some(_double_press), _press) => self.light_key_pressed_at = Some(Instant::now()),
+			(_pressed_at, None, Some(action)) => self.run_light_key_action(action),
+			(_pressed_at, None, None) => ()
+		}
+	}
+
+	/// Runs the light key's single press action once it's too late for the
+	/// press to become a double press
+	fn update_light_key(&mut self)
+	{
+		let expired = self.light_key_pressed_at
+			.map(|pressed_at| pressed_at.elapsed() >= Duration::from_millis(Self::DOUBLE_PRESS_INTERVAL))
+			.unwrap_or(false);
+
+		if expired
+		{
+			self.light_key_pressed_at = None;
+
+			let action = self.state.config.read().unwrap().light_key.press.clone();
+
+			if let Some(action) = action
+			{
+				self.run_light_key_action(action);
+			}
+		}
+	}
+
+	fn run_light_key_action(&mut self, action: LightKeyAction)
+	{
+		debug!("running light key action: {:?}", &action);
+
+		match action
+		{
+			LightKeyAction::CycleThemes(theme_names) =>
+			{
+				// the profile's own theme comes after the last theme in the list
+				self.theme_override = match &self.theme_override
+				{
+					Some(current) => theme_names
+						.iter()
+						.skip_while(|theme_name| *theme_name != current)
+						.nth(1)
+						.cloned(),
+					None => theme_names.first().cloned()
+				};
+
+				self.apply_profile();
+				self.apply_overrides();
+				self.device.commit();
+			},
+			LightKeyAction::ToggleLighting =>
+			{
+				self.lighting_enabled = !self.lighting_enabled;
+				info!("lighting {}", if self.lighting_enabled { "enabled" } else { "disabled" });
+
+				self.apply_profile();
+				self.apply_overrides();
+				self.device.commit();
+			},
+			LightKeyAction::ToggleFocusMode =>
+			{
+				let enabled = self.state.focus_mode.load(Ordering::Relaxed);
+				self.main_thread_tx.send(MainThreadSignal::SetFocusMode(!enabled));
+			},
+			LightKeyAction::SwitchProfile(profile_name) =>
+			{
+				self.main_thread_tx.send(MainThreadSignal::SwitchProfile(profile_name));
+			},
+			LightKeyAction::Macro(assignment) =>
+			{
+				if let Some(macro_state) = &self.light_key_macro
+				{
+					if macros::signal_running(macro_state)
+					{
+						return
+					}
+				}
+
+				let macro_ = assignment
+					.expand(&self.state.config.read().unwrap())
+					.map(|macro_| macro_.into_owned());
+
+				match macro_
+				{
+					Some(macro_) => self.light_key_macro = Some(self.start_macro(macro_, "light key".into())),
+					None => warn!("the light key's macro doesn't exist")
+				}
+			}
+		}
+	}
+
+	fn virtual_modes(&self) -> u8
+	{
+		self.state.config.read().unwrap().virtual_modes
+	}
+
+	/// The mode a mode key switches to. Pressing the last mode key again steps
+	/// through the virtual modes, then back round to its own mode.
+	fn mode_for_key(&self, key: u8) -> u8
+	{
+		let last_mode = self.mode_count + self.virtual_modes();
+
+		match key == self.mode_count && self.active_mode >= key && self.active_mode < last_mode
+		{
+			true => self.active_mode + 1,
+			false => key
+		}
+	}
+
+	fn switch_mode(&mut self, mode: u8)
+	{
+		debug!("mode changed to: {}", mode);
+		self.cancel_confirmations();
+		self.pending_gestures.clear();
+		self.held_gkeys.clear();
+		self.active_mode = mode;
+		self.blink_states = None;
+		self.stop_all_hold_to_repeat_macros();
+		let profile_name = self.state.active_profile_name.read().unwrap().clone();
+		self.state.persisted.update(|persisted| persisted.profile_modes.insert(profile_name.clone(), mode));
+
+		// virtual modes share the last mode's slot on the keyboard
+		self.device.set_mode(mode.min(self.mode_count));
+
+		self.main_thread_tx.send(MainThreadSignal::Hook(HookEvent::ModeChanged
+		{
+			mode,
+			profile: profile_name,
+			serial_number: self.serial_number.clone()
+		}));
+	}
+
+	/// Switches to the mode last used in the active profile (the first mode if
+	/// it hasn't been used yet), so its gkeys and theme apply again
+	fn restore_profile_mode(&mut self)
+	{
+		let mode = self.state.persisted
+			.read()
+			.profile_modes
+			.get(&*self.state.active_profile_name.read().unwrap())
+			.copied()
+			.unwrap_or(1);
+
+		if mode != self.active_mode && mode <= self.mode_count + self.virtual_modes()
+		{
+			debug!("restoring mode {} for the active profile", mode);
+			self.active_mode = mode;
+			self.blink_states = None;
+			self.device.set_mode(mode.min(self.mode_count));
+		}
+	}
+
+	/// Locks the profile once a mode key has been held down long enough, rather
+	/// than waiting for it to be released
+	fn update_mode_key_hold(&mut self)
+	{
+		if let Some((mode, pressed, false)) = self.mode_key_held
+		{
+			if pressed.elapsed() >= Duration::from_millis(Self::MODE_KEY_LONG_PRESS)
+			{
+				debug!("mode key {} long pressed, locking profile", mode);
+				self.mode_key_held = Some((mode, pressed, true));
+				self.main_thread_tx.send(MainThreadSignal::SetProfileLock(true));
+			}
+		}
+	}
+
+	/// Sets overrides on the function row to draw the current sink volume as a bar,
+	/// lit in red instead of white if the sink is muted
+	fn show_volume_indicator(&mut self)
+	{
+		let media_state = self.state.media_state.read().unwrap().clone();
+		let key_count = Self::VOLUME_INDICATOR_KEYS.len();
+		let lit_keys = (media_state.volume.min(100) as usize * key_count + 50) / 100;
+		let indicators = self.state.config.read().unwrap().indicators;
+		let lit_color = match media_state.muted
+		{
+			true => indicators.volume_muted,
+			false => indicators.volume
+		};
+
+		for (i, scancode) in Self::VOLUME_INDICATOR_KEYS.iter().enumerate()
+		{
+			self.set_override(*scancode, if i < lit_keys { lit_color } else { Color::black().into() });
+		}
+	}
+
+	/// Counts down the time left to show the volume bar for, and restores the
+	/// theme once it has expired
+	fn update_volume_indicator(&mut self)
+	{
+		if self.volume_indicator_timer == 0
+		{
+			return
+		}
+
+		self.volume_indicator_timer = self.volume_indicator_timer
+			.saturating_sub(self.tick);
+
+		if self.volume_indicator_timer == 0
+		{
+			for scancode in Self::VOLUME_INDICATOR_KEYS.iter()
+			{
+				self.set_override(*scancode, None);
+			}
+
+			self.apply_profile();
+			self.apply_overrides();
+			self.device.commit();
+		}
+	}
+
+	/// The color of a blinking indicator on a key, whilst it's on or off
+	fn blink_color(&self, color: IndicatorColor, scancode: Scancode, on: bool) -> Color
+	{
+		self.output_color(match on
+		{
+			true => self.indicator_color(color, scancode),
+			false => Color::black()
+		})
+	}
+
+	/// Lights the audio focus keys in the color of the application playing audio,
+	/// or leaves them to the theme if none of the configured applications are
+	fn update_audio_focus(&mut self)
+	{
+		let state = Arc::clone(&self.state);
+		let config = state.config.read().unwrap();
+		let media_state = state.media_state.read().unwrap();
+
+		let (scancodes, color) = match &config.audio_focus
+		{
+			Some(audio_focus) => (
+				audio_focus.scancodes(&config.keygroups),
+				audio_focus.color(&media_state.audio_streams)),
+			None => (Vec::new(), None)
+		};
+
+		for scancode in std::mem::take(&mut self.audio_focus_keys)
+		{
+			self.set_override(scancode, None);
+		}
+
+		if let Some(color) = color
+		{
+			for scancode in &scancodes
+			{
+				self.set_override(*scancode, IndicatorColor::from(color));
+			}
+
+			self.audio_focus_keys = scancodes;
+		}
+	}
+
+	/// Lights one of the profile's notification counter keys for each pending
+	/// notification
+	fn update_notification_counter(&mut self)
+	{
+		let pending = self.state.pending_notifications.load(Ordering::Relaxed);
+
+		let counter =
+		{
+			let config = self.state.config.read().unwrap();
+			let profile = self.active_profile(&config);
+
+			ProfileResolver::new(&config, &profile, self.active_mode)
+				.notifications()
+				.map(|notifications| (notifications.counter_scancodes(&config.keygroups), notifications.color))
+		};
+
+		for scancode in std::mem::take(&mut self.notification_keys)
+		{
+			self.set_override(scancode, None);
+		}
+
+		if let Some((scancodes, color)) = counter
+		{
+			self.notification_keys = scancodes.into_iter().take(pending).collect();
+
+			for scancode in self.notification_keys.clone()
+			{
+				self.set_override(scancode, IndicatorColor::from(color));
+			}
+		}
+	}
+
+	/// Turns game mode on or off, disabling the profile's game mode keys only
+	/// whilst it's on
+	fn set_game_mode(&mut self, command: GameModeCommand)
+	{
+		let enabled = command.enabled(self.state.game_mode.load(Ordering::Relaxed));
+
+		if enabled == self.state.game_mode.load(Ordering::Relaxed)
+		{
+			return
+		}
+
+		self.state.game_mode.store(enabled, Ordering::Relaxed);
+		self.dbus_tx.send(DBusSignal::GameModeChanged(enabled));
+		info!("game mode {}", if enabled { "enabled" } else { "disabled" });
+
+		let game_mode_keys =
+		{
+			let config = self.state.config.read().unwrap();
+			let profile = self.active_profile(&config);
+			ProfileResolver::new(&config, &profile, self.active_mode).game_mode_keys().to_vec()
+		};
+
+		self.program_game_mode_keys(&game_mode_keys);
+
+		if enabled
+		{
+			self.show_game_mode_indicator();
+		}
+	}
+
+	/// Programs the profile's game mode keys into the device if game mode is on,
+	/// or clears them if it's off
+	fn program_game_mode_keys(&mut self, profile_keys: &[Scancode])
+	{
+		let game_mode_keys = match self.state.game_mode.load(Ordering::Relaxed)
+		{
+			true => profile_keys.to_vec(),
+			false => Vec::new()
+		};
+
+		// only reprogram game mode if the keys have actually changed, as profile
+		// changes would otherwise cause a reset/add cycle every time
+		if self.game_mode_keys.as_ref() != Some(&game_mode_keys)
+		{
+			debug!("game mode keys changed, reprogramming: {:?}", &game_mode_keys);
+			self.device.reset_game_mode_keys();
+			self.device.add_game_mode_keys(&game_mode_keys);
+			*self.state.game_mode_keys.write().unwrap() = game_mode_keys.clone();
+			self.game_mode_keys = Some(game_mode_keys);
+		}
+	}
+
+	/// Sets the profile's report rate, or puts back the device's own one for
+	/// profiles without one
+	fn apply_report_rate(&mut self, profile_rate: Option<u16>)
+	{
+		if profile_rate == self.profile_report_rate
+		{
+			return
+		}
+
+		self.profile_report_rate = profile_rate;
+
+		let initial_rate = match self.initial_report_rate
+		{
+			Some(initial_rate) => initial_rate,
+			None =>
+			{
+				if let Some(rate) = profile_rate
+				{
+					warn!("unable to set the report rate to {}Hz, the device can't change its report rate", rate);
+				}
+				return
+			}
+		};
+
+		let rate = match profile_rate
+		{
+			Some(rate) if !self.device.report_rates().contains(&rate) =>
+			{
+				warn!("unable to set the report rate to {}Hz, the device only supports {:?}",
+					rate, self.device.report_rates());
+				initial_rate
+			},
+			Some(rate) => rate,
+			None => initial_rate
+		};
+
+		if self.report_rate != Some(rate)
+		{
+			debug!("setting the report rate to {}Hz", rate);
+			match self.device.set_report_rate(rate)
+			{
+				Ok(_) => self.report_rate = Some(rate),
+				Err(error) => warn!("unable to set the report rate to {}Hz: {:?}", rate, error)
+			}
+		}
+	}
+
+	/// Briefly lights the keys disabled by game mode
+	fn show_game_mode_indicator(&mut self)
+	{
+		let color = self.state.config.read().unwrap().indicators.game_mode;
+		let scancodes = self.game_mode_keys.clone().unwrap_or_default();
+
+		for scancode in &scancodes
+		{
+			self.set_override(*scancode, color);
+		}
+
+		self.game_mode_indicator = Some((scancodes, Self::GAME_MODE_INDICATOR_DURATION));
+		self.apply_overrides();
+		self.device.commit();
+	}
+
+	/// Counts down the time left to show the game mode keys for, and restores the
+	/// theme once it has expired
+	fn update_game_mode_indicator(&mut self)
+	{
+		let timer = match &mut self.game_mode_indicator
+		{
+			Some((_scancodes, timer)) => timer,
+			None => return
+		};
+
+		*timer = timer.saturating_sub(self.tick);
+
+		if *timer == 0
+		{
+			if let Some((scancodes, _timer)) = self.game_mode_indicator.take()
+			{
+				for scancode in scancodes
+				{
+					self.set_override(scancode, None);
+				}
+			}
+
+			self.apply_profile();
+			self.apply_overrides();
+			self.device.commit();
+		}
+	}
+
+	fn update_macro_indicators(&mut self)
+	{
+		self.blink_clock += self.tick;
+
+		let (indicators, blink_states) =
+		{
+			let config = self.state.config.read().unwrap();
+			(config.indicators, config.blink.states(self.blink_clock))
+		};
+
+		// only redrawn when one of the indicators has blinked
+		if self.blink_states == Some(blink_states)
+		{
+			return
+		}
+
+		self.blink_states = Some(blink_states);
+
+		if self.state.macro_recording.load(Ordering::Relaxed)
+		{
+			self.device.set_macro_recording(blink_states.macro_recording);
+		}
+		let mut gkey_data: Vec<(Scancode, Color)> = Vec::new();
+
+		// TODO proabably re-implement this section when drain_filter is added to HashMap
+
+		let stopped_macro_numbers: HashMap<u8, HashSet<u8>> = self.macro_states
+			.iter()
+			.map(|(mode, mode_states)|
+			{
+				 let stopped_mode_macros = mode_states
+					.iter()
+					.filter_map(|(gkey_number, (_tx, stopped, _activation_type))|
+					{
+						let stopped = stopped.load(Ordering::Relaxed).then(|| *gkey_number);
+
+						// if this is the current mode, and the macro is running or stopped,
+						// override the color of the key as appropriate
+
+						if *mode == self.active_mode
+						{
+							let scancode = Scancode::from_gkey(*gkey_number).unwrap();
+							let set_color = stopped
+								.map(|_gkey_number| self.output_color(
+									self.last_color_for_scancode(scancode)))
+								.unwrap_or_else(|| self.blink_color(indicators.macro_running, scancode, blink_states.macro_running));
+							gkey_data.push((scancode, set_color));
+						}
+
+						stopped
+					})
+					.collect();
+
+				(*mode, stopped_mode_macros)
+			})
+			.collect();
+
+		for (mode, mode_states) in &mut self.macro_states
+		{
+			if let Some(mode_stopped_macros) = stopped_macro_numbers.get(mode)
+			{
+				debug!("clearing stopped macros in mode {}: {:#?}", mode, mode_stopped_macros);
+
+				mode_states.retain(|gkey_number, _state|
+					!mode_stopped_macros.contains(gkey_number));
+			}
+		}
+
+		for gkey_number in self.awaiting_confirmation.keys()
+		{
+			let scancode = Scancode::from_gkey(*gkey_number).unwrap();
+			gkey_data.push((scancode, self.blink_color(indicators.macro_confirm, scancode, blink_states.macro_confirm)));
+		}
+
+		if !gkey_data.is_empty()
+		{
+			self.device.set_4(&gkey_data);
+			self.device.commit();
+		}
+
+		let mut mode_leds = 0;
+		let profile_locked = self.state.profile_locked.load(Ordering::Relaxed);
+
+		// a virtual mode's number (counting from 1 after the last mode key) is
+		// blinked in binary on the mode leds
+		if self.active_mode > self.mode_count
+		{
+			if blink_states.virtual_mode && (!profile_locked || blink_states.profile_locked)
+			{
+				mode_leds = (self.active_mode - self.mode_count) & ((1u16 << self.mode_count) - 1) as u8;
+			}
+
+			self.device.set_mode_leds(mode_leds);
+			return
+		}
+
+		for mode in 1..=self.mode_count
+		{
+			// the active mode's led blinks whilst the profile is locked
+			if mode == self.active_mode && profile_locked && !blink_states.profile_locked
+			{
+				continue
+			}
+
+			if mode != self.active_mode
+			{
+				let mode_has_active_macros = self.macro_states
+					.get(&mode)
+					.map(|mode_macros| !mode_macros.is_empty())
+					.unwrap_or(false);
+
+				if !mode_has_active_macros || !blink_states.background_macros
+				{
+					continue
+				}
+			}
+
+			mode_leds |= 1 << (mode - 1);
+		}
+
+		self.device.set_mode_leds(mode_leds);
+	}
+
+	fn macro_keydown(&mut self, gkey_number: u8)
+	{
+		debug!("gkey down {}", gkey_number);
+
+		if self.gshift_key() == Some(gkey_number)
+		{
+			debug!("gshift on");
+			self.gshift_held = Some(gkey_number);
+			return
+		}
+
+		if self.gshift_held.is_some()
+		{
+			self.shifted_gkeys.insert(gkey_number);
+		}
+		else if self.gesture_keydown(gkey_number)
+		{
+			return
+		}
+		else if self.state.config.read().unwrap().macro_hints.is_some()
+		{
+			self.held_gkeys.insert(gkey_number, Instant::now());
+		}
+
+		if let Entry::Occupied(ref entry) = self.current_mode_macro_states().entry(gkey_number)
+		{
+			if macros::signal_running(entry.get())
+			{
+				return
+			}
+		}
+
+		if let Some(macro_) = self.macro_for_gkey(gkey_number)
+		{
+			if macro_.confirm && self.awaiting_confirmation.remove(&gkey_number).is_none()
+			{
+				debug!("macro on gkey {} needs confirming, waiting for another press", gkey_number);
+				self.awaiting_confirmation.insert(gkey_number, Self::CONFIRM_TIMEOUT);
+				// redraw the indicators straight away rather than on the next blink
+				self.blink_states = None;
+				return
+			}
+
+			let trigger = format!("G{} (mode {})", gkey_number, self.active_mode);
+			let macro_state = self.start_macro(macro_, trigger);
+			self.current_mode_macro_states().insert(gkey_number, macro_state);
+		}
+	}
+
+	/// Starts telling apart the gestures of a gkey with gesture assignments.
+	/// Returns false if the gkey doesn't have any.
+	fn gesture_keydown(&mut self, gkey_number: u8) -> bool
+	{
+		let bound =
+		{
+			let config = self.state.config.read().unwrap();
+			let current_profile = self.active_profile(&config);
+
+			ProfileResolver::new(&config, &current_profile, self.active_mode)
+				.gestures_for_gkey(gkey_number)
+				.map(|gestures| (gestures.double_tap.is_some(), gestures.hold.is_some()))
+		};
+
+		let (double_tap_bound, hold_bound) = match bound
+		{
+			Some(bound) => bound,
+			None => return false
+		};
+
+		match self.pending_gestures.remove(&gkey_number)
+		{
+			// pressed again soon enough after a tap
+			Some(PendingGesture { released_at: Some(_), .. }) => self.run_gesture(gkey_number, Gesture::DoubleTap),
+			_ =>
+			{
+				self.pending_gestures.insert(gkey_number, PendingGesture
+				{
+					pressed_at: Instant::now(),
+					released_at: None,
+					held: false,
+					double_tap_bound,
+					hold_bound
+				});
 			}
 		}
 
-		if let Some(macro_) = self.macro_for_gkey(gkey_number)
+		true
+	}
+
+	fn gesture_keyup(&mut self, gkey_number: u8)
+	{
+		let tapped = match self.pending_gestures.get_mut(&gkey_number)
+		{
+			Some(pending) if pending.released_at.is_none() => match (pending.held, pending.double_tap_bound)
+			{
+				// the hold has already run
+				(true, _) => None,
+				// a tap has to wait to see if it's the start of a double tap
+				(false, true) =>
+				{
+					pending.released_at = Some(Instant::now());
+					return
+				},
+				(false, false) => Some(Gesture::Tap)
+			},
+			_ => return
+		};
+
+		self.pending_gestures.remove(&gkey_number);
+
+		if let Some(gesture) = tapped
+		{
+			self.run_gesture(gkey_number, gesture);
+		}
+	}
+
+	/// Runs holds once keys have been held down long enough, and taps once
+	/// it's too late for them to become double taps
+	fn update_gestures(&mut self)
+	{
+		if self.pending_gestures.is_empty()
+		{
+			return
+		}
+
+		let timings = self.state.config.read().unwrap().gestures;
+		let mut gestures = Vec::new();
+
+		self.pending_gestures.retain(|gkey_number, pending|
+		{
+			match pending.released_at
+			{
+				Some(released_at) if released_at.elapsed() >= Duration::from_millis(timings.double_tap) =>
+				{
+					gestures.push((*gkey_number, Gesture::Tap));
+					false
+				},
+				None if pending.hold_bound && !pending.held
+					&& pending.pressed_at.elapsed() >= Duration::from_millis(timings.hold) =>
+				{
+					pending.held = true;
+					gestures.push((*gkey_number, Gesture::Hold));
+					true
+				},
+				_ => true
+			}
+		});
+
+		for (gkey_number, gesture) in gestures
+		{
+			self.run_gesture(gkey_number, gesture);
+		}
+	}
+
+	/// Shows a hint for gkeys held down long enough, and puts back the keys lit
+	/// by the last one once it's been shown for long enough
+	fn update_macro_hints(&mut self)
+	{
+		if let Some((_scancodes, timer)) = &mut self.macro_hint
+		{
+			*timer = timer.saturating_sub(self.tick);
+
+			if *timer == 0
+			{
+				self.clear_macro_hint();
+				self.apply_profile();
+				self.apply_overrides();
+				self.device.commit();
+			}
+		}
+
+		if self.held_gkeys.is_empty()
+		{
+			return
+		}
+
+		let hints = match self.state.config.read().unwrap().macro_hints
+		{
+			Some(hints) => hints,
+			None =>
+			{
+				self.held_gkeys.clear();
+				return
+			}
+		};
+
+		let held: Vec<u8> = self.held_gkeys
+			.iter()
+			.filter(|(_gkey_number, pressed_at)| pressed_at.elapsed() >= Duration::from_millis(hints.hold))
+			.map(|(gkey_number, _pressed_at)| *gkey_number)
+			.collect();
+
+		for gkey_number in held
+		{
+			self.held_gkeys.remove(&gkey_number);
+			self.show_macro_hint(gkey_number, hints);
+		}
+	}
+
+	/// Names the macro assigned to a held gkey and lights the keys it presses,
+	/// unless it's a hold to repeat macro, which is still repeating
+	fn show_macro_hint(&mut self, gkey_number: u8, hints: MacroHints)
+	{
+		let (hint, color) =
+		{
+			let config = self.state.config.read().unwrap();
+			let current_profile = self.active_profile(&config);
+			let resolver = ProfileResolver::new(&config, &current_profile, self.active_mode);
+
+			let hint = resolver
+				.gkey_sources(gkey_number)
+				.first()
+				.map(|source| source.assignment.name())
+				.zip(resolver.macro_for_gkey(gkey_number))
+				.filter(|(_name, macro_)| macro_.activation_type != ActivationType::HoldToRepeat)
+				.map(|(name, macro_)| (name, macro_.pressed_keys()));
+
+			(hint, config.indicators.macro_hint)
+		};
+
+		let (name, scancodes) = match hint
+		{
+			Some(hint) => hint,
+			None => return
+		};
+
+		info!("G{} (mode {}) runs {}", gkey_number, self.active_mode, name);
+
+		if hints.notify
+		{
+			self.dbus_tx.send(DBusSignal::Notify
+			{
+				summary: format!("G{} (mode {})", gkey_number, self.active_mode),
+				body: name.clone()
+			});
+		}
+
+		self.clear_macro_hint();
+
+		for scancode in &scancodes
+		{
+			self.set_override(*scancode, color);
+		}
+
+		self.macro_hint = Some((scancodes, hints.duration.max(1)));
+		self.apply_overrides();
+		self.device.commit();
+	}
+
+	fn clear_macro_hint(&mut self)
+	{
+		if let Some((scancodes, _timer)) = self.macro_hint.take()
+		{
+			for scancode in scancodes
+			{
+				self.set_override(scancode, None);
+			}
+		}
+	}
+
+	fn run_gesture(&mut self, gkey_number: u8, gesture: Gesture)
+	{
+		debug!("gkey {} gesture: {:?}", gkey_number, gesture);
+
+		if let Entry::Occupied(ref entry) = self.current_mode_macro_states().entry(gkey_number)
+		{
+			if macros::signal_running(entry.get())
+			{
+				return
+			}
+		}
+
+		let macro_ =
+		{
+			let config = self.state.config.read().unwrap();
+			let current_profile = self.active_profile(&config);
+
+			ProfileResolver::new(&config, &current_profile, self.active_mode)
+				.macro_for_gesture(gkey_number, gesture)
+				.map(|macro_| macro_.into_owned())
+		};
+
+		if let Some(macro_) = macro_
+		{
+			let trigger = format!("G{} {:?} (mode {})", gkey_number, gesture, self.active_mode);
+			let macro_state = self.start_macro(macro_, trigger);
+			self.current_mode_macro_states().insert(gkey_number, macro_state);
+		}
+	}
+
+	/// Runs a macro on a thread from the runtime's blocking pool, keeping its
+	/// timeline once it's done. `trigger` describes what started it, for the timeline.
+	fn start_macro(&self, macro_: Macro, trigger: String) -> MacroState
+	{
+		macros::start(
+			macro_,
+			trigger,
+			self.window_system_tx.clone(),
+			self.dbus_tx.clone(),
+			self.main_thread_tx.clone(),
+			Arc::clone(&self.state))
+	}
+
+	fn media_keydown(&mut self, key: MediaKey)
+	{
+		if let Some(macro_state) = self.media_key_macros.get(&key)
+		{
+			if macros::signal_running(macro_state)
+			{
+				return
+			}
+		}
+
+		match self.macro_for_media_key(key)
+		{
+			Some(macro_) =>
+			{
+				let macro_state = self.start_macro(macro_, format!("{:?} media key", key));
+				self.media_key_macros.insert(key, macro_state);
+			},
+			None => self.default_media_key_action(key)
+		}
+	}
+
+	fn media_keyup(&mut self, key: MediaKey)
+	{
+		if let Some((tx, _stopped, ActivationType::HoldToRepeat)) = self.media_key_macros.get(&key)
+		{
+			debug!("stopping hold to repeat media key macro");
+			tx.send(MacroSignal::Stop);
+		}
+	}
+
+	/// What a media key does if it isn't bound to anything in the active profile
+	fn default_media_key_action(&mut self, key: MediaKey)
+	{
+		let has_player = self.state.media_state.read().unwrap().player_service.is_some();
+		let media_command = match key
+		{
+			MediaKey::PlayPause => Some(MediaCommand::PlayPause),
+			MediaKey::Next => Some(MediaCommand::Next),
+			MediaKey::Previous => Some(MediaCommand::Previous),
+			_ => None
+		};
+
+		// player controls go straight to the selected mpris player where
+		// there is one, otherwise fall back to the XF86 keys
+		match media_command.filter(|_command| has_player)
+		{
+			Some(command) => self.dbus_tx
+				.send(DBusSignal::MediaControl(command)),
+			None => self.window_system_tx
+				.send(WindowSystemSignal::SendKeyCombo(match key
+				{
+					MediaKey::Mute => "XF86AudioMute",
+					MediaKey::PlayPause => "XF86AudioPlay",
+					MediaKey::Next => "XF86AudioNext",
+					MediaKey::Previous => "XF86AudioPrev",
+					MediaKey::VolumeUp => "XF86AudioRaiseVolume",
+					MediaKey::VolumeDown => "XF86AudioLowerVolume"
+				}.to_string()))
+		}
+
+		// the new volume will arrive shortly after via a MediaStateChanged
+		// signal, which will redraw the bar whilst the timer is running
+		if let MediaKey::VolumeUp | MediaKey::VolumeDown = key
+		{
+			self.volume_indicator_timer = Self::VOLUME_INDICATOR_DURATION;
+			self.show_volume_indicator();
+			self.apply_overrides();
+			self.device.commit();
+		}
+	}
+
+	/// Counts down the time left to confirm any macros that need it, restoring
+	/// the key color of any that weren't confirmed in time
+	fn update_confirmations(&mut self)
+	{
+		if self.awaiting_confirmation.is_empty()
+		{
+			return
+		}
+
+		let mut expired = Vec::new();
+
+		for (gkey_number, time_left) in self.awaiting_confirmation.iter_mut()
 		{
-			debug!("starting macro: {:#?}", &macro_);
+			*time_left = time_left.saturating_sub(self.tick);
 
-			let (macro_tx, macro_rx) = channel();
-			let stopped = Arc::new(AtomicBool::new(false));
-			let macro_thread_stopped = Arc::clone(&stopped);
+			if *time_left == 0
+			{
+				expired.push(*gkey_number);
+			}
+		}
 
-			self.current_mode_macro_states().insert(gkey_number,
-				(macro_tx, stopped, macro_.activation_type));
+		if !expired.is_empty()
+		{
+			debug!("macro confirmation timed out for gkeys: {:?}", &expired);
 
-			self.main_thread_tx.send(MainThreadSignal::RunMacroInPool(Box::new(
+			for gkey_number in &expired
 			{
-				let window_system_tx = self.window_system_tx.clone();
-				let dbus_tx = self.dbus_tx.clone();
-				move || macro_.execute(macro_rx, window_system_tx, dbus_tx, macro_thread_stopped)
-			})));
+				self.awaiting_confirmation.remove(gkey_number);
+			}
+
+			self.restore_gkey_colors(&expired);
+		}
+	}
+
+	/// Abandons any macros waiting to be confirmed
+	fn cancel_confirmations(&mut self)
+	{
+		let gkey_numbers: Vec<u8> = self.awaiting_confirmation
+			.drain()
+			.map(|(gkey_number, _time_left)| gkey_number)
+			.collect();
+
+		if !gkey_numbers.is_empty()
+		{
+			self.restore_gkey_colors(&gkey_numbers);
+		}
+	}
+
+	fn restore_gkey_colors(&mut self, gkey_numbers: &[u8])
+	{
+		if let CurrentLightingState::Custom(_) = &self.lighting_state
+		{
+			let gkey_data: Vec<(Scancode, Color)> = gkey_numbers
+				.iter()
+				.filter_map(|gkey_number| Scancode::from_gkey(*gkey_number))
+				.map(|scancode| (scancode, self.output_color(self.last_color_for_scancode(scancode))))
+				.collect();
+
+			self.device.set_4(&gkey_data);
+			self.device.commit();
 		}
 	}
 
@@ -435,12 +2460,37 @@ impl DeviceThread
 	{
 		debug!("gkey up {}", gkey_number);
 
+		// the gshift key may no longer be one if the profile has changed
+		if self.gshift_held == Some(gkey_number)
+		{
+			debug!("gshift off");
+			self.gshift_held = None;
+			return
+		}
+
+		self.gesture_keyup(gkey_number);
+		self.held_gkeys.remove(&gkey_number);
+
 		if let Some((tx, _stopped, ActivationType::HoldToRepeat)) = self
 			.current_mode_macro_states().get(&gkey_number)
 		{
 			debug!("stopping hold to repeat macro");
 			tx.send(MacroSignal::Stop);
 		}
+
+		// a press still waiting to be confirmed hasn't run, so neither does its
+		// release, and shifted presses don't have one
+		if self.awaiting_confirmation.contains_key(&gkey_number) || self.shifted_gkeys.remove(&gkey_number)
+		{
+			return
+		}
+
+		if let Some(macro_) = self.release_macro_for_gkey(gkey_number)
+		{
+			let trigger = format!("G{} release (mode {})", gkey_number, self.active_mode);
+			let macro_state = self.start_macro(macro_, trigger);
+			self.release_macros.insert(gkey_number, macro_state);
+		}
 	}
 
 	fn stop_all_hold_to_repeat_macros(&self)
@@ -474,5 +2524,159 @@ impl DeviceThread
 						tx.send(MacroSignal::Stop);
 					});
 			});
+
+		self.media_key_macros
+			.drain()
+			.for_each(|(_key, (tx, _stopped, _activation_type))|
+			{
+				tx.send(MacroSignal::Stop);
+			});
+
+		self.release_macros
+			.drain()
+			.for_each(|(_gkey_number, (tx, _stopped, _activation_type))|
+			{
+				tx.send(MacroSignal::Stop);
+			});
+
+		if let Some((tx, _stopped, _activation_type)) = self.light_key_macro.take()
+		{
+			tx.send(MacroSignal::Stop);
+		}
+	}
+}
+
+impl Drop for DeviceThread
+{
+	/// Hands the keyboard back however the thread stops, panics included, so
+	/// it isn't left in software mode with its G-keys doing nothing
+	fn drop(&mut self)
+	{
+		if std::thread::panicking()
+		{
+			warn!("the thread for keyboard {} panicked, handing the keyboard back", &self.serial_number);
+		}
+
+		if let Err(error) = self.device.release_control()
+		{
+			warn!("unable to hand keyboard {} back: {}", &self.serial_number, error);
+		}
+
+		self.state.devices.write().unwrap().retain(|info| info.serial_number != self.serial_number);
+		self.state.device_threads.fetch_sub(1, Ordering::SeqCst);
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+	use crate::persistence::{PersistedState, StateStore};
+	use crate::device::mock::MockDevice;
+
+	const RED: Color = Color::new(0xff, 0, 0);
+	const BLUE: Color = Color::new(0, 0, 0xff);
+
+	const CONFIG: &str = "
+themes:
+  default:
+    - color: ff0000
+      keys:
+        - single: a
+        - single: b
+keygroups: {}
+profiles:
+  default:
+    theme: default
+";
+
+	/// A thread for a mock keyboard, showing the default profile. Anything it
+	/// sends to other threads is dropped.
+	fn device_thread() -> (DeviceThread, MockDevice)
+	{
+		let config = serde_yaml::from_str(CONFIG).expect("test config should parse");
+		let state = Arc::new(SharedState::new(config, StateStore::new(PersistedState::default())));
+		let health = Arc::clone(&state.channel_health);
+		let device = MockDevice::default();
+
+		let thread = DeviceThread::new(
+			Box::new(device.clone()),
+			state,
+			health.monitor("dbus", smol::channel::unbounded().0),
+			health.monitor("window system", smol::channel::unbounded().0),
+			health.monitor("main", smol::channel::unbounded().0));
+
+		(thread, device)
+	}
+
+	#[test]
+	fn the_profile_theme_is_drawn()
+	{
+		let (mut thread, device) = device_thread();
+
+		thread.apply_profile();
+		thread.device.commit().unwrap();
+
+		assert_eq!(device.color(Scancode::A), Some(RED));
+		assert_eq!(device.color(Scancode::B), Some(RED));
+	}
+
+	#[test]
+	fn overrides_are_drawn_over_the_theme_until_removed()
+	{
+		let (mut thread, device) = device_thread();
+
+		thread.apply_profile();
+		thread.device.commit().unwrap();
+
+		thread.set_override(Scancode::A, IndicatorColor::from(BLUE));
+		thread.update_changed_overrides();
+		assert_eq!(device.color(Scancode::A), Some(BLUE));
+		assert_eq!(device.color(Scancode::B), Some(RED));
+
+		// changed overrides are drawn at most once a frame
+		thread.set_override(Scancode::A, None);
+		thread.update_changed_overrides();
+		assert_eq!(device.color(Scancode::A), Some(BLUE));
+
+		thread.tick = DeviceThread::FRAME_INTERVAL;
+		thread.update_changed_overrides();
+		assert_eq!(device.color(Scancode::A), Some(RED));
+	}
+
+	#[test]
+	fn the_mode_led_blinks_whilst_the_profile_is_locked()
+	{
+		let (mut thread, device) = device_thread();
+		thread.state.profile_locked.store(true, Ordering::Relaxed);
+
+		let mut mode_leds = Vec::new();
+
+		// the default blink is 400ms on, 400ms off
+		for _ in 0..4
+		{
+			thread.update_macro_indicators();
+			mode_leds.push(device.state().mode_leds);
+			thread.tick = 400;
+		}
+
+		assert_eq!(mode_leds, vec![0b001, 0b000, 0b001, 0b000]);
+
+		thread.state.profile_locked.store(false, Ordering::Relaxed);
+		thread.update_macro_indicators();
+		assert_eq!(device.state().mode_leds, 0b001);
+	}
+
+	#[test]
+	fn mode_keys_switch_mode_and_remember_it()
+	{
+		let (mut thread, device) = device_thread();
+
+		thread.handle_event(&DeviceEvent::KeyDown(KeyType::Mode, 2));
+		thread.handle_event(&DeviceEvent::KeyUp(KeyType::Mode, 2));
+
+		assert_eq!(thread.active_mode, 2);
+		assert_eq!(device.state().mode_leds, 0b010);
+		assert_eq!(thread.state.persisted.read().profile_modes.get("default"), Some(&2));
 	}
 }
@@ -0,0 +1,138 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use serde_json::{json, Value};
+
+use crate::device::CommandStats;
+
+/// Counters for how the driver has been doing since it started, for keeping an
+/// eye on a flaky usb link: returned by GetStats, and by `GET /metrics` in
+/// prometheus' text format
+pub struct DriverStats
+{
+	#[cfg_attr(not(any(feature = "dbus", feature = "http")), allow(dead_code))]
+	started: Instant,
+	macros_started: AtomicU64,
+	/// keyboards opened after startup, i.e. plugged back in
+	reconnects: AtomicU64,
+	events_processed: AtomicU64,
+	hid_commands: AtomicU64,
+	/// commands that failed, including those that timed out
+	hid_errors: AtomicU64,
+	hid_timeouts: AtomicU64,
+	hid_latency_micros: AtomicU64
+}
+
+impl Default for DriverStats
+{
+	fn default() -> Self
+	{
+		Self
+		{
+			started: Instant::now(),
+			macros_started: AtomicU64::new(0),
+			reconnects: AtomicU64::new(0),
+			events_processed: AtomicU64::new(0),
+			hid_commands: AtomicU64::new(0),
+			hid_errors: AtomicU64::new(0),
+			hid_timeouts: AtomicU64::new(0),
+			hid_latency_micros: AtomicU64::new(0)
+		}
+	}
+}
+
+impl DriverStats
+{
+	pub fn macro_started(&self)
+	{
+		self.macros_started.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn reconnected(&self)
+	{
+		self.reconnects.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn events_processed(&self, count: usize)
+	{
+		self.events_processed.fetch_add(count as u64, Ordering::Relaxed);
+	}
+
+	/// Adds the commands a keyboard has sent since it was last asked
+	pub fn add_commands(&self, stats: &CommandStats)
+	{
+		if stats.commands == 0
+		{
+			return
+		}
+
+		self.hid_commands.fetch_add(stats.commands, Ordering::Relaxed);
+		self.hid_errors.fetch_add(stats.errors, Ordering::Relaxed);
+		self.hid_timeouts.fetch_add(stats.timeouts, Ordering::Relaxed);
+		self.hid_latency_micros.fetch_add(stats.latency.as_micros() as u64, Ordering::Relaxed);
+	}
+
+	#[cfg_attr(not(any(feature = "dbus", feature = "http")), allow(dead_code))]
+	fn counters(&self) -> [(&'static str, &'static str, u64); 7]
+	{
+		[
+			("macros_started", "macros started", self.macros_started.load(Ordering::Relaxed)),
+			("reconnects", "keyboards opened after startup", self.reconnects.load(Ordering::Relaxed)),
+			("events_processed", "key presses and other events read from keyboards", self.events_processed.load(Ordering::Relaxed)),
+			("hid_commands", "commands sent to keyboards", self.hid_commands.load(Ordering::Relaxed)),
+			("hid_errors", "commands that failed or timed out", self.hid_errors.load(Ordering::Relaxed)),
+			("hid_timeouts", "commands keyboards didn't answer in time", self.hid_timeouts.load(Ordering::Relaxed)),
+			("hid_latency_micros", "time spent waiting for keyboards to answer commands", self.hid_latency_micros.load(Ordering::Relaxed))
+		]
+	}
+
+	#[cfg_attr(not(any(feature = "dbus", feature = "http")), allow(dead_code))]
+	pub fn summary(&self) -> Value
+	{
+		let mut summary: serde_json::Map<String, Value> = self.counters()
+			.iter()
+			.map(|(name, _help, value)| (name.to_string(), (*value).into()))
+			.collect();
+
+		summary.insert("uptime_seconds".into(), self.started.elapsed().as_secs().into());
+
+		let commands = self.hid_commands.load(Ordering::Relaxed);
+		let average_latency = match commands
+		{
+			0 => 0.0,
+			commands => self.hid_latency_micros.load(Ordering::Relaxed) as f64 / commands as f64 / 1000.0
+		};
+
+		summary.insert("hid_average_latency_ms".into(), json!(average_latency));
+		Value::Object(summary)
+	}
+
+	/// The counters in prometheus' text exposition format
+	#[cfg(feature = "http")]
+	pub fn prometheus(&self) -> String
+	{
+		use std::fmt::Write;
+
+		let mut text = String::new();
+
+		writeln!(text, "# HELP g815d_uptime_seconds how long the driver has been running");
+		writeln!(text, "# TYPE g815d_uptime_seconds gauge");
+		writeln!(text, "g815d_uptime_seconds {}", self.started.elapsed().as_secs());
+
+		for (name, help, value) in self.counters().iter()
+		{
+			// latency is a running total, which prometheus wants in seconds
+			let (name, value) = match *name
+			{
+				"hid_latency_micros" => ("hid_latency_seconds".to_string(), (*value as f64 / 1_000_000.0).to_string()),
+				name => (name.to_string(), value.to_string())
+			};
+
+			writeln!(text, "# HELP g815d_{}_total {}", name, help);
+			writeln!(text, "# TYPE g815d_{}_total counter", name);
+			writeln!(text, "g815d_{}_total {}", name, value);
+		}
+
+		text
+	}
+}
@@ -0,0 +1,139 @@
+use std::env;
+use std::process::{Command, Stdio};
+
+use log::{debug, warn};
+
+use crate::config::Hooks;
+
+/// Something that happened in the driver, which runs the commands the config's
+/// `hooks` gives for it. What happened is passed to the commands in G815D_
+/// environment variables.
+#[derive(Debug, Clone)]
+pub enum HookEvent
+{
+	ProfileChanged
+	{
+		profile: String,
+		previous_profile: String
+	},
+	ModeChanged
+	{
+		mode: u8,
+		profile: String,
+		serial_number: String
+	},
+	MacroStarted
+	{
+		/// what started the macro, as in its timeline, e.g. "G1 (mode 2)"
+		trigger: String
+	},
+	RecordingToggled
+	{
+		recording: bool
+	},
+	DeviceConnected
+	{
+		serial_number: String
+	}
+}
+
+impl HookEvent
+{
+	pub fn name(&self) -> &'static str
+	{
+		match self
+		{
+			Self::ProfileChanged { .. } => "profile_changed",
+			Self::ModeChanged { .. } => "mode_changed",
+			Self::MacroStarted { .. } => "macro_started",
+			Self::RecordingToggled { .. } => "recording_toggled",
+			Self::DeviceConnected { .. } => "device_connected"
+		}
+	}
+
+	fn commands<'a>(&self, hooks: &'a Hooks) -> &'a [String]
+	{
+		match self
+		{
+			Self::ProfileChanged { .. } => &hooks.profile_changed,
+			Self::ModeChanged { .. } => &hooks.mode_changed,
+			Self::MacroStarted { .. } => &hooks.macro_started,
+			Self::RecordingToggled { .. } => &hooks.recording_toggled,
+			Self::DeviceConnected { .. } => &hooks.device_connected
+		}
+	}
+
+	fn environment(&self) -> Vec<(&'static str, String)>
+	{
+		let mut environment = vec![("G815D_EVENT", self.name().to_string())];
+
+		match self
+		{
+			Self::ProfileChanged { profile, previous_profile } =>
+			{
+				environment.push(("G815D_PROFILE", profile.clone()));
+				environment.push(("G815D_PREVIOUS_PROFILE", previous_profile.clone()));
+			},
+			Self::ModeChanged { mode, profile, serial_number } =>
+			{
+				environment.push(("G815D_MODE", mode.to_string()));
+				environment.push(("G815D_PROFILE", profile.clone()));
+				environment.push(("G815D_SERIAL", serial_number.clone()));
+			},
+			Self::MacroStarted { trigger } => environment.push(("G815D_TRIGGER", trigger.clone())),
+			Self::RecordingToggled { recording } => environment.push(("G815D_RECORDING", match recording
+			{
+				true => "1".to_string(),
+				false => "0".to_string()
+			})),
+			Self::DeviceConnected { serial_number } => environment.push(("G815D_SERIAL", serial_number.clone()))
+		}
+
+		environment
+	}
+}
+
+/// Runs the commands hooked to an event with the user's shell, without waiting
+/// for them to finish. Each is waited for on the runtime's blocking pool, so
+/// finished commands don't linger as zombies.
+pub fn run(hooks: &Hooks, event: &HookEvent)
+{
+	let commands = event.commands(hooks);
+
+	if commands.is_empty()
+	{
+		return
+	}
+
+	debug!("running {} hook: {:?}", event.name(), event);
+	let environment = event.environment();
+
+	for command in commands
+	{
+		let child = Command::new(env::var_os("SHELL").unwrap_or_else(|| "/bin/sh".into()))
+			.arg("-c")
+			.arg(command)
+			.envs(environment.iter().cloned())
+			.stdin(Stdio::null())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn();
+
+		match child
+		{
+			Ok(mut child) =>
+			{
+				let name = event.name();
+				let command = command.clone();
+
+				smol::unblock(move || match child.wait()
+				{
+					Ok(status) if !status.success() => debug!("{} hook '{}' exited with {}", name, command, status),
+					Ok(_) => (),
+					Err(error) => warn!("unable to wait for {} hook '{}': {}", name, command, error)
+				}).detach();
+			},
+			Err(error) => warn!("unable to run {} hook '{}': {}", event.name(), command, error)
+		}
+	}
+}
@@ -0,0 +1,564 @@
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use smol::{Async, Timer};
+use smol::channel::Receiver;
+use smol::future;
+use smol::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+use crate::channels::MonitoredSender;
+use crate::daemon;
+use crate::device::client_colors::KeyColorUpdate;
+use crate::device::color::Color;
+use crate::device::scancode::Scancode;
+use crate::scheduler::PlaylistCommand;
+use crate::{SharedState, MainThreadSignal, parse_key, parse_mode_command};
+
+pub enum HttpSignal
+{
+	Shutdown
+}
+
+struct Response
+{
+	status: u16,
+	content_type: &'static str,
+	body: String
+}
+
+impl Response
+{
+	fn json(status: u16, body: Value) -> Self
+	{
+		Self { status, content_type: "application/json", body: body.to_string() }
+	}
+
+	fn ok() -> Self
+	{
+		Self::json(200, json!({ "ok": true }))
+	}
+
+	fn error(status: u16, message: String) -> Self
+	{
+		Self::json(status, json!({ "error": message }))
+	}
+
+	fn reason(&self) -> &'static str
+	{
+		match self.status
+		{
+			200 => "OK",
+			400 => "Bad Request",
+			401 => "Unauthorized",
+			403 => "Forbidden",
+			404 => "Not Found",
+			405 => "Method Not Allowed",
+			413 => "Payload Too Large",
+			_ => "Error"
+		}
+	}
+}
+
+/// A small http server on localhost, for tools that can't use dbus (i.e. stream
+/// deck plugins and home automation). It mirrors the dbus api: every request
+/// is turned into the same signal to the main thread. Requests are handled one
+/// at a time, which is plenty for the odd button press.
+///
+/// Web pages can send requests to localhost too, so every request needs the
+/// token from `token_path()` (which only the user can read), and requests from
+/// browsers (with an `Origin`, or a `Host` other than localhost, as with dns
+/// rebinding) are turned away before it's checked.
+pub struct HttpServer
+{
+	listener: Async<TcpListener>,
+	port: u16,
+	token: String,
+	token_path: PathBuf,
+	state: Arc<SharedState>,
+	tx: MonitoredSender<MainThreadSignal>
+}
+
+impl HttpServer
+{
+	const READ_TIMEOUT: Duration = Duration::from_secs(2);
+	const MAX_BODY_LENGTH: usize = 64 * 1024;
+
+	pub fn token_path() -> PathBuf
+	{
+		let mut path = daemon::runtime_folder();
+		path.push("g815d.http-token");
+		path
+	}
+
+	pub fn new(port: u16, state: Arc<SharedState>, tx: MonitoredSender<MainThreadSignal>) -> Result<Self, String>
+	{
+		let token = new_token().map_err(|error| format!("unable to make a token: {}", error))?;
+		let token_path = Self::token_path();
+
+		daemon::create_private_file(&token_path)
+			.and_then(|mut file| writeln!(file, "{}", token))
+			.map_err(|error| format!("unable to write the token to {}: {}", token_path.display(), error))?;
+
+		// only ever on localhost
+		let listener = Async::<TcpListener>::bind((Ipv4Addr::LOCALHOST, port))
+			.map_err(|error| format!("unable to listen on port {}: {}", port, error))?;
+
+		info!("listening for http requests on http://localhost:{}, with the token in {}", port, token_path.display());
+		Ok(Self { listener, port, token, token_path, state, tx })
+	}
+
+	pub async fn run(&mut self, rx: Receiver<HttpSignal>)
+	{
+		loop
+		{
+			// waits for the next request, or for the driver to shut down
+			let accepted = future::or(
+				async
+				{
+					match rx.recv().await
+					{
+						Ok(HttpSignal::Shutdown)
+							| Err(_) => None
+					}
+				},
+				async { Some(self.listener.accept().await) }).await;
+
+			match accepted
+			{
+				Some(Ok((stream, _address))) =>
+				{
+					// a client that stops sending part way through is given up on
+					let handled = future::or(self.handle(stream), async
+					{
+						Timer::after(Self::READ_TIMEOUT).await;
+						Err(ErrorKind::TimedOut.into())
+					}).await;
+
+					if let Err(error) = handled
+					{
+						debug!("unable to handle http request: {}", error);
+					}
+				},
+				Some(Err(error)) =>
+				{
+					warn!("stopped listening for http requests: {}", error);
+					break
+				},
+				None => break
+			}
+		}
+	}
+
+	async fn handle(&self, stream: Async<TcpStream>) -> std::io::Result<()>
+	{
+		let mut reader = BufReader::new(&stream);
+		let mut request_line = String::new();
+		reader.read_line(&mut request_line).await?;
+
+		let mut parts = request_line.split_whitespace();
+		let method = parts.next().unwrap_or_default().to_string();
+		let path = parts.next().unwrap_or_default().to_string();
+
+		let mut content_length = 0;
+		let mut host = None;
+		let mut has_origin = false;
+		let mut authorization = None;
+
+		loop
+		{
+			let mut header = String::new();
+
+			if reader.read_line(&mut header).await? == 0 || header.trim().is_empty()
+			{
+				break
+			}
+
+			if let Some((name, value)) = header.split_once(':')
+			{
+				let value = value.trim();
+
+				match name.trim().to_ascii_lowercase().as_str()
+				{
+					"content-length" => content_length = value.parse().unwrap_or(0),
+					"host" => host = Some(value.to_string()),
+					"origin" => has_origin = true,
+					"authorization" => authorization = Some(value.to_string()),
+					_ => ()
+				}
+			}
+		}
+
+		let refusal = self.refusal(host.as_deref(), has_origin, authorization.as_deref());
+
+		let response = match (refusal, content_length > Self::MAX_BODY_LENGTH)
+		{
+			(Some(refusal), _) => refusal,
+			(None, true) => Response::error(413, "the request body is too long".into()),
+			(None, false) =>
+			{
+				let mut body = vec![0; content_length];
+				reader.read_exact(&mut body).await?;
+
+				debug!("http request: {} {}", method, path);
+				self.route(&method, &path, &body)
+			}
+		};
+
+		let head = format!("HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+			response.status, response.reason(), response.content_type, response.body.len());
+
+		let mut writer = &stream;
+		writer.write_all(head.as_bytes()).await?;
+		writer.write_all(response.body.as_bytes()).await?;
+		writer.flush().await
+	}
+
+	/// Why a request can't be handled, if it can't: it's from a browser, or
+	/// doesn't have the token
+	fn refusal(&self, host: Option<&str>, has_origin: bool, authorization: Option<&str>) -> Option<Response>
+	{
+		match (is_local_host(host, self.port), has_origin, is_authorized(authorization, &self.token))
+		{
+			(false, _, _) => Some(Response::error(403, "requests have to be made to localhost".into())),
+			(_, true, _) => Some(Response::error(403, "requests from web pages aren't allowed".into())),
+			(_, _, false) => Some(Response::error(401,
+				format!("requests need the token in {} as a bearer token", self.token_path.display()))),
+			(true, false, true) => None
+		}
+	}
+
+	fn route(&self, method: &str, path: &str, body: &[u8]) -> Response
+	{
+		let path = path.split('?').next().unwrap_or_default();
+		let segments: Vec<String> = path
+			.split('/')
+			.filter(|segment| !segment.is_empty())
+			.map(percent_decode)
+			.collect();
+		let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+		match (method, segments.as_slice())
+		{
+			("GET", ["state"]) => Response::json(200, self.state.summary()),
+			("GET", ["stats"]) => Response::json(200, self.state.driver_stats.summary()),
+			("GET", ["devices"]) => Response::json(200, json!(*self.state.devices.read().unwrap())),
+			// for prometheus to scrape
+			("GET", ["metrics"]) => Response
+			{
+				status: 200,
+				content_type: "text/plain; version=0.0.4",
+				body: self.state.driver_stats.prometheus()
+			},
+			("POST", ["profiles", name]) => self.switch_profile(name),
+			("POST", ["profile-overrides", name]) => self.push_profile(name, body),
+			("DELETE", ["profile-overrides"]) => self.pop_profile(),
+			("POST", ["macros", name]) => self.run_macro(name),
+			("POST", ["keys", client]) => self.set_key_colors(client, body),
+			("DELETE", ["keys", client]) => self.send(MainThreadSignal::ClearKeyColors(client.to_string())),
+			("POST", ["logo"]) => self.set_logo_color(body),
+			("DELETE", ["logo"]) => self.send(MainThreadSignal::SetLogoColor(None)),
+			("POST", ["effect"]) => self.set_effect(body),
+			("DELETE", ["effect"]) => self.send(MainThreadSignal::SetEffect(None)),
+			("POST", ["mode", mode]) => self.set_mode(mode),
+			("POST", ["game-mode", command]) => match parse_mode_command(command)
+			{
+				Ok(command) => self.send(MainThreadSignal::SetGameMode(command)),
+				Err(error) => Response::error(404, error)
+			},
+			("POST", ["focus-mode", command]) => match parse_mode_command(command)
+			{
+				Ok(command) => self.send(MainThreadSignal::SetFocusMode(command.enabled(self.state.focus_mode.load(Ordering::Relaxed)))),
+				Err(error) => Response::error(404, error)
+			},
+			("POST", ["low-power-mode", command]) => match parse_mode_command(command)
+			{
+				Ok(command) => self.send(MainThreadSignal::SetLowPowerMode(command.enabled(self.state.low_power.load(Ordering::Relaxed)))),
+				Err(error) => Response::error(404, error)
+			},
+			("POST", ["playlist", command]) => match *command
+			{
+				"skip" => self.send(MainThreadSignal::Playlist(PlaylistCommand::Skip)),
+				"pause" => self.send(MainThreadSignal::Playlist(PlaylistCommand::Pause)),
+				"resume" => self.send(MainThreadSignal::Playlist(PlaylistCommand::Resume)),
+				_ => Self::unknown_command(command)
+			},
+			(_, ["state"])
+				| (_, ["stats"])
+				| (_, ["metrics"])
+				| (_, ["logo"])
+				| (_, ["effect"])
+				| (_, ["profiles", _])
+				| (_, ["profile-overrides"])
+				| (_, ["profile-overrides", _])
+				| (_, ["macros", _])
+				| (_, ["keys", _])
+				| (_, ["mode", _])
+				| (_, ["game-mode", _])
+				| (_, ["focus-mode", _])
+				| (_, ["low-power-mode", _])
+				| (_, ["playlist", _]) => Response::error(405, format!("{} isn't supported for {}", method, path)),
+			_ => Response::error(404, format!("there's nothing at {}", path))
+		}
+	}
+
+	fn send(&self, signal: MainThreadSignal) -> Response
+	{
+		self.tx.send(signal);
+		Response::ok()
+	}
+
+	fn unknown_command(command: &str) -> Response
+	{
+		Response::error(404, format!("'{}' isn't a command", command))
+	}
+
+	fn switch_profile(&self, name: &str) -> Response
+	{
+		match self.state.config.read().unwrap().profiles.contains_key(name)
+		{
+			true => self.send(MainThreadSignal::SwitchProfile(name.to_string())),
+			false => Response::error(404, format!("there is no profile called '{}'", name))
+		}
+	}
+
+	/// The body is optional, `{"ttl": 60000}` pops the profile after a minute
+	fn push_profile(&self, name: &str, body: &[u8]) -> Response
+	{
+		if !self.state.config.read().unwrap().profiles.contains_key(name)
+		{
+			return Response::error(404, format!("there is no profile called '{}'", name))
+		}
+
+		let ttl = match body.is_empty()
+		{
+			true => 0,
+			false => match serde_json::from_slice::<Value>(body)
+			{
+				Ok(request) => request["ttl"].as_u64().unwrap_or(0),
+				Err(error) => return Response::error(400, format!("the body isn't json: {}", error))
+			}
+		};
+
+		self.send(MainThreadSignal::PushProfile(name.to_string(), (ttl > 0).then(|| Duration::from_millis(ttl))))
+	}
+
+	fn pop_profile(&self) -> Response
+	{
+		match self.state.profile_overrides.read().unwrap().is_empty()
+		{
+			true => Response::error(404, "no profile has been pushed".into()),
+			false => self.send(MainThreadSignal::PopProfile)
+		}
+	}
+
+	fn set_mode(&self, mode: &str) -> Response
+	{
+		let modes = self.state.config.read().unwrap().modes();
+
+		match mode.parse::<u8>().ok().filter(|mode| modes.contains(mode))
+		{
+			Some(mode) => self.send(MainThreadSignal::SetMode(mode)),
+			None => Response::error(404, format!("there is no mode {}, only {} to {}", mode, modes.start(), modes.end()))
+		}
+	}
+
+	fn run_macro(&self, name: &str) -> Response
+	{
+		let exists = self.state.config
+			.read()
+			.unwrap()
+			.macros
+			.as_ref()
+			.map(|macros| macros.contains_key(name))
+			.unwrap_or(false);
+
+		match exists
+		{
+			true => self.send(MainThreadSignal::RunMacro(name.to_string())),
+			false => Response::error(404, format!("there is no macro called '{}'", name))
+		}
+	}
+
+	/// The body is `{"colors": {"w": "ff0000", "a": 65280}, "ttl": 2000}`, as
+	/// with SetKeyColors
+	fn set_key_colors(&self, client: &str, body: &[u8]) -> Response
+	{
+		let request: Value = match serde_json::from_slice(body)
+		{
+			Ok(request) => request,
+			Err(error) => return Response::error(400, format!("the body isn't json: {}", error))
+		};
+
+		let colors = match request["colors"].as_object()
+		{
+			Some(colors) => colors
+				.iter()
+				.map(|(key, color)| Ok((parse_key(key)?, parse_color(key, color)?)))
+				.collect::<Result<Vec<(Scancode, Color)>, String>>(),
+			None => Err("colors must be an object of key names to colors".into())
+		};
+
+		let ttl = request["ttl"].as_u64().unwrap_or(0);
+
+		match colors
+		{
+			Ok(colors) => self.send(MainThreadSignal::SetKeyColors(KeyColorUpdate
+			{
+				client: client.to_string(),
+				colors,
+				replace: false,
+				ttl: (ttl > 0).then(|| ttl)
+			})),
+			Err(error) => Response::error(400, error)
+		}
+	}
+
+	/// The body is `{"color": "ff0000"}`, as with SetLogoColor
+	fn set_logo_color(&self, body: &[u8]) -> Response
+	{
+		let request: Value = match serde_json::from_slice(body)
+		{
+			Ok(request) => request,
+			Err(error) => return Response::error(400, format!("the body isn't json: {}", error))
+		};
+
+		match parse_color("color", &request["color"])
+		{
+			Ok(color) => self.send(MainThreadSignal::SetLogoColor(Some(color))),
+			Err(error) => Response::error(400, error)
+		}
+	}
+
+	/// The body is an effect as in the config, i.e. `{"type": "cycle",
+	/// "duration": 5000, "brightness": 100}`, as with SetEffect
+	fn set_effect(&self, body: &[u8]) -> Response
+	{
+		match serde_json::from_slice(body)
+		{
+			Ok(effect) => self.send(MainThreadSignal::SetEffect(Some(effect))),
+			Err(error) => Response::error(400, format!("the body isn't an effect: {}", error))
+		}
+	}
+}
+
+impl Drop for HttpServer
+{
+	fn drop(&mut self)
+	{
+		let _ = fs::remove_file(&self.token_path);
+	}
+}
+
+/// Whether a request was sent to localhost on the server's port, rather than
+/// to a name that only resolves to localhost (as with dns rebinding)
+fn is_local_host(host: Option<&str>, port: u16) -> bool
+{
+	let port = format!(":{}", port);
+
+	host
+		.and_then(|host| host.strip_suffix(port.as_str()))
+		.map(|host| matches!(host, "localhost" | "127.0.0.1" | "[::1]"))
+		.unwrap_or(false)
+}
+
+fn is_authorized(authorization: Option<&str>, token: &str) -> bool
+{
+	match authorization.and_then(|authorization| authorization.strip_prefix("Bearer "))
+	{
+		// compared in full, so how long it takes doesn't give away how much
+		// of the token was right
+		Some(given) => given.len() == token.len() && given
+			.bytes()
+			.zip(token.bytes())
+			.fold(0, |difference, (a, b)| difference | (a ^ b)) == 0,
+		None => false
+	}
+}
+
+/// 32 random hex digits
+fn new_token() -> std::io::Result<String>
+{
+	let mut bytes = [0_u8; 16];
+	fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+
+	Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn parse_color(key: &str, color: &Value) -> Result<Color, String>
+{
+	match color
+	{
+		Value::Number(color) => color
+			.as_u64()
+			.filter(|color| *color <= 0xffffff)
+			.map(|color| Color::from(color as u32))
+			.ok_or_else(|| format!("{}: {} isn't a color", key, color)),
+		Value::String(color) => Color::parse(color).map_err(|error| format!("{}: {}", key, error)),
+		_ => Err(format!("{}: colors must be a string or an unsigned integer", key))
+	}
+}
+
+/// Decodes the %xx escapes in a path segment, i.e. a profile name with a space
+fn percent_decode(segment: &str) -> String
+{
+	let bytes = segment.as_bytes();
+	let mut decoded = Vec::with_capacity(bytes.len());
+	let mut index = 0;
+
+	while index < bytes.len()
+	{
+		let escaped = match bytes[index] == b'%' && index + 2 < bytes.len()
+		{
+			true => std::str::from_utf8(&bytes[index + 1..index + 3])
+				.ok()
+				.and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+			false => None
+		};
+
+		match escaped
+		{
+			Some(byte) =>
+			{
+				decoded.push(byte);
+				index += 3;
+			},
+			None =>
+			{
+				decoded.push(bytes[index]);
+				index += 1;
+			}
+		}
+	}
+
+	String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn only_local_requests_with_the_token_are_allowed()
+	{
+		assert!(is_local_host(Some("localhost:8815"), 8815));
+		assert!(is_local_host(Some("127.0.0.1:8815"), 8815));
+		assert!(is_local_host(Some("[::1]:8815"), 8815));
+		assert!(!is_local_host(Some("localhost:8816"), 8815));
+		assert!(!is_local_host(Some("rebound.example.com:8815"), 8815));
+		assert!(!is_local_host(None, 8815));
+
+		assert!(is_authorized(Some("Bearer 0123abcd"), "0123abcd"));
+		assert!(!is_authorized(Some("Bearer 0123abce"), "0123abcd"));
+		assert!(!is_authorized(Some("Bearer 0123"), "0123abcd"));
+		assert!(!is_authorized(Some("0123abcd"), "0123abcd"));
+		assert!(!is_authorized(None, "0123abcd"));
+	}
+}
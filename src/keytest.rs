@@ -0,0 +1,97 @@
+use crate::config::{Configuration, LayoutOverrides};
+use crate::device::Device;
+use crate::device::color::Color;
+use crate::device::scancode::Scancode;
+
+/// Lights one key at a time, so the rgb id of every key can be checked against
+/// the keyboard's layout. Keys that light up the wrong key can be corrected,
+/// and the corrections are saved to the layout overrides file. Keys are lit
+/// with the layout overrides already in the config.
+pub fn test_keys(kb: &mut Box<dyn Device>, config: &Configuration)
+{
+	let scancodes: Vec<Scancode> = Scancode::iter_variants().collect();
+	let mut overrides = config.layout_file.clone();
+	let mut corrected = false;
+	let mut i = 0;
+
+	ncurses::initscr();
+	ncurses::noecho();
+
+	kb.take_control();
+
+	loop
+	{
+		let scancode = scancodes[i];
+		let layout = overrides.merge(&config.layout);
+		let rgb_id = layout.rgb_ids.get(&scancode).copied().unwrap_or_else(|| scancode.rgb_id());
+
+		kb.set_rgb_ids(layout.rgb_ids);
+		kb.set_all(Color::black());
+		kb.set_4(&[(scancode, Color::new(0xff, 0xff, 0xff))]);
+		kb.commit();
+
+		ncurses::clear();
+		ncurses::addstr("you're in key test mode, one key at a time is lit in white.\n");
+		ncurses::addstr("Press l/h for the next/previous key, w if the wrong key (or no key) is lit, \
+			q to quit.\n\n");
+		ncurses::addstr(&format!("key {}/{}: {} (scancode {:#04x}, rgb id {:#04x})\n",
+			i + 1,
+			scancodes.len(),
+			scancode.config_name(),
+			scancode as u8,
+			rgb_id));
+
+		match ncurses::getch() as u8 as char
+		{
+			'l' | ' ' => i = (i + 1) % scancodes.len(),
+			'h' => i = (i + scancodes.len() - 1) % scancodes.len(),
+			'w' =>
+			{
+				ncurses::addstr("\nname of the key that lit up (as used in the config), \
+					or nothing if none did: ");
+				ncurses::echo();
+
+				let mut name = String::new();
+				ncurses::getstr(&mut name);
+				ncurses::noecho();
+
+				let name = name.trim();
+
+				if name.is_empty()
+				{
+					log::info!("{} doesn't light any key (rgb id {:#04x})", scancode.config_name(), rgb_id);
+					continue
+				}
+
+				match serde_yaml::from_str::<Scancode>(name)
+				{
+					// lighting this key's rgb id lit the named key, so that's the named key's id
+					Ok(lit_scancode) =>
+					{
+						overrides.rgb_ids.insert(lit_scancode, rgb_id);
+						corrected = true;
+					},
+					Err(_error) =>
+					{
+						ncurses::addstr(&format!("\nthere's no key called '{}', press any key", name));
+						ncurses::getch();
+					}
+				}
+			},
+			'q' => break,
+			_ => ()
+		};
+	}
+
+	ncurses::endwin();
+	kb.release_control();
+
+	if corrected
+	{
+		match overrides.save()
+		{
+			Ok(()) => println!("corrections saved to {}", LayoutOverrides::file_path().display()),
+			Err(config_error) => log::error!("unable to save corrections: {}", config_error)
+		}
+	}
+}
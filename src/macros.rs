@@ -1,14 +1,49 @@
-use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::process::{Command, Stdio};
 use std::env;
 
+use log::{debug, warn};
 use serde::{Serialize, Deserialize};
 
+use crate::channels::MonitoredSender;
+use crate::{SharedState, MainThreadSignal, DBusSignal};
 use crate::windowsystem::{MouseButton, WindowSystemSignal};
-use crate::dbus::DBusSignal;
+use crate::hooks::HookEvent;
+use crate::media::MediaCommand;
+use crate::device::client_colors::{DrawnSignal, KeyColorUpdate};
+use crate::device::color::Color;
+use crate::device::rgb::KeySelection;
+use crate::device::scancode::Scancode;
+use crate::config::MacroTiming;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GameModeCommand
+{
+	On,
+	Off,
+	Toggle
+}
+
+impl GameModeCommand
+{
+	/// Whether game mode should be on after this command, given whether it is now
+	pub fn enabled(&self, currently_enabled: bool) -> bool
+	{
+		match self
+		{
+			Self::On => true,
+			Self::Off => false,
+			Self::Toggle => !currently_enabled
+		}
+	}
+}
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -26,9 +61,23 @@ pub enum Action
 {
 	MouseClick(MouseButton),
 	KeyPress(String),
+	/// holds keys down until a key_up step releases them
+	KeyDown(String),
+	KeyUp(String),
+	TypeUnicode(String),
 	RunCommand(String),
 	Delay,
 	DebugPrint(String),
+	MediaControl(MediaCommand),
+	GameMode(GameModeCommand),
+	/// a rhai script, either inline or the path to a `.rhai` file (relative to
+	/// the config folder), see script.rs
+	Script(String),
+	/// runs another named macro's steps, then carries on
+	RunMacro(String),
+	/// switches to the next of the profiles, pinned whatever the active window
+	/// until it's cycled again, see MainThreadSignal::CycleProfiles
+	CycleProfiles(Vec<String>),
 	DbusMethodCall
 	{
 		destination: String,
@@ -39,17 +88,183 @@ pub enum Action
 	}
 }
 
+impl Macro
+{
+	/// how deep macros can run other macros, so one that (indirectly) runs
+	/// itself can't run forever
+	const MAX_DEPTH: usize = 8;
+
+	/// Runs the steps of a macro called by another macro's run_macro step. Its
+	/// activation type still says how many times to run, but macros that would
+	/// run until stopped (toggle and hold to repeat) run once.
+	fn execute_nested(
+		&self,
+		window_system: &MonitoredSender<WindowSystemSignal>,
+		dbus: &MonitoredSender<DBusSignal>,
+		main_thread: &MonitoredSender<MainThreadSignal>,
+		state: &Arc<SharedState>,
+		signals: &Arc<MacroSignals>,
+		depth: usize,
+		timing: MacroTiming)
+	{
+		for _ in 0..self.execution_count().unwrap_or(1)
+		{
+			for step in &self.steps
+			{
+				step.execute(window_system, dbus, main_thread, state, signals, depth, timing);
+			}
+		}
+	}
+}
+
+impl Action
+{
+	/// Replaces each `{{name}}` in the action's text with the argument's value
+	fn with_arguments(&self, arguments: &HashMap<String, String>) -> Self
+	{
+		let substitute = |text: &String| arguments
+			.iter()
+			.fold(text.clone(), |text, (name, value)| text.replace(&format!("{{{{{}}}}}", name), value));
+
+		match self
+		{
+			Self::KeyPress(keys) => Self::KeyPress(substitute(keys)),
+			Self::KeyDown(keys) => Self::KeyDown(substitute(keys)),
+			Self::KeyUp(keys) => Self::KeyUp(substitute(keys)),
+			Self::TypeUnicode(text) => Self::TypeUnicode(substitute(text)),
+			Self::RunCommand(command) => Self::RunCommand(substitute(command)),
+			Self::DebugPrint(message) => Self::DebugPrint(substitute(message)),
+			Self::Script(script) => Self::Script(substitute(script)),
+			Self::DbusMethodCall { destination, path, interface, method, arguments: call_arguments } =>
+				Self::DbusMethodCall
+				{
+					destination: substitute(destination),
+					path: substitute(path),
+					interface: substitute(interface),
+					method: substitute(method),
+					arguments: call_arguments
+						.as_ref()
+						.map(|call_arguments| call_arguments.iter().map(substitute).collect())
+				},
+			action => action.clone()
+		}
+	}
+}
+
 pub enum MacroSignal
 {
 	Stop,
 	ResetCount
 }
 
+/// The signals sent to a running macro, shared with its script steps so they
+/// can end as soon as the macro is stopped rather than once they've finished
+pub struct MacroSignals
+{
+	rx: Mutex<Receiver<MacroSignal>>,
+	stopping: AtomicBool,
+	reset_count: AtomicBool
+}
+
+impl MacroSignals
+{
+	fn new(rx: Receiver<MacroSignal>) -> Self
+	{
+		Self { rx: Mutex::new(rx), stopping: AtomicBool::new(false), reset_count: AtomicBool::new(false) }
+	}
+
+	/// Whether the macro has been stopped, or whatever started it has gone
+	/// away (i.e. the driver is shutting down)
+	pub fn stopping(&self) -> bool
+	{
+		self.wait(Duration::default())
+	}
+
+	/// Sleeps for `duration`, ending early if the macro is stopped. Returns
+	/// whether it has been.
+	pub fn wait(&self, duration: Duration) -> bool
+	{
+		let deadline = Instant::now() + duration;
+		let rx = self.rx.lock().unwrap();
+
+		while !self.stopping.load(Ordering::Relaxed)
+		{
+			match rx.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+			{
+				Ok(MacroSignal::ResetCount) => self.reset_count.store(true, Ordering::Relaxed),
+				Ok(MacroSignal::Stop)
+					| Err(RecvTimeoutError::Disconnected) => self.stopping.store(true, Ordering::Relaxed),
+				Err(RecvTimeoutError::Timeout) => break
+			}
+		}
+
+		self.stopping.load(Ordering::Relaxed)
+	}
+
+	/// Whether the macro has been triggered again since this was last asked,
+	/// so a repeat macro starts its count again
+	fn take_reset_count(&self) -> bool
+	{
+		self.reset_count.swap(false, Ordering::Relaxed)
+	}
+}
+
+/// Why a macro run ended
+#[derive(Copy, Clone, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason
+{
+	/// it ran as many times as its activation type says
+	Finished,
+	/// stopped by its key (or a profile change) before finishing
+	Stopped
+}
+
+/// When a step of a macro run started, and how long it took, in milliseconds
+#[derive(Clone, Debug, Serialize)]
+pub struct StepTiming
+{
+	pub action: Action,
+	pub started: f64,
+	pub duration: f64
+}
+
+/// The timeline of a single execution of a macro, for working out timing
+/// problems. Only the first `MAX_TIMED_STEPS` steps are kept.
+#[derive(Clone, Debug, Serialize)]
+pub struct MacroRun
+{
+	/// what started the macro, e.g. "G1 (mode 2)"
+	pub trigger: String,
+	/// milliseconds since the unix epoch
+	pub started_at: u64,
+	/// milliseconds
+	pub duration: f64,
+	pub iterations: u32,
+	pub steps: Vec<StepTiming>,
+	pub steps_not_timed: usize,
+	pub stop_reason: StopReason
+}
+
+impl MacroRun
+{
+	pub const MAX_TIMED_STEPS: usize = 1000;
+	/// how many runs are kept in the shared state
+	pub const HISTORY_LENGTH: usize = 50;
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Macro
 {
 	pub activation_type: ActivationType,
 	pub theme: Option<String>,
+	/// require a second press of the gkey before the macro will run
+	#[serde(default)]
+	pub confirm: bool,
+	/// names the steps can use as `{{name}}`, given a value by each gkey the
+	/// macro is assigned to
+	#[serde(default)]
+	pub parameters: Vec<String>,
 	pub steps: Vec<Step>
 }
 
@@ -62,14 +277,95 @@ impl Macro
 		{
 			activation_type: ActivationType::Singular,
 			theme: None,
+			confirm: false,
+			parameters: Vec::new(),
 			steps: vec![Step
 			{
 				action,
-				duration: 5 // TODO actually think about what is sensible here
+				duration: 5, // TODO actually think about what is sensible here
+				jitter: None,
+				lighting: None
 			}]
 		}
 	}
 
+	/// A copy of the macro with its parameters replaced by the given values
+	pub fn with_arguments(&self, arguments: &HashMap<String, String>) -> Self
+	{
+		Self
+		{
+			steps: self.steps
+				.iter()
+				.map(|step| Step
+				{
+					action: step.action.with_arguments(arguments),
+					duration: step.duration,
+					jitter: step.jitter,
+					lighting: step.lighting.clone()
+				})
+				.collect(),
+			..self.clone()
+		}
+	}
+
+	/// The macros this one runs with run_macro steps
+	pub fn called_macros(&self) -> impl Iterator<Item = &str>
+	{
+		self.steps.iter().filter_map(|step| match &step.action
+		{
+			Action::RunMacro(name) => Some(name.as_str()),
+			_ => None
+		})
+	}
+
+	/// The profiles the macro's cycle_profiles steps switch between
+	pub fn cycled_profiles(&self) -> impl Iterator<Item = &str>
+	{
+		self.steps
+			.iter()
+			.filter_map(|step| match &step.action
+			{
+				Action::CycleProfiles(profiles) => Some(profiles),
+				_ => None
+			})
+			.flatten()
+			.map(String::as_str)
+	}
+
+	/// The keys lit by the macro's steps
+	pub fn lit_keys(&self) -> impl Iterator<Item = &KeySelection>
+	{
+		self.steps
+			.iter()
+			.flat_map(|step| step.lighting.iter())
+			.flat_map(|lighting| lighting.keys.iter())
+	}
+
+	/// The keys on the keyboard pressed by the macro's key_press, key_down and
+	/// key_up steps, in the order they're first pressed
+	pub fn pressed_keys(&self) -> Vec<Scancode>
+	{
+		let mut scancodes = Vec::new();
+
+		let combos = self.steps.iter().filter_map(|step| match &step.action
+		{
+			Action::KeyPress(combo)
+				| Action::KeyDown(combo)
+				| Action::KeyUp(combo) => Some(combo),
+			_ => None
+		});
+
+		for scancode in combos.flat_map(|combo| combo.split('+')).filter_map(combo_key)
+		{
+			if !scancodes.contains(&scancode)
+			{
+				scancodes.push(scancode);
+			}
+		}
+
+		scancodes
+	}
+
 	/// Gets the number of times this macro should run (None for unlimited)
 	pub fn execution_count(&self) -> Option<u32>
 	{
@@ -85,35 +381,167 @@ impl Macro
 	/// Executes the macro by running all of it's steps in turn.
 	///
 	/// The macro will run until it's configured `execution_count()` is reached
-	/// at which point is_finished will be set to true.
+	/// at which point is_finished will be set to true. Returns the timeline of
+	/// the run, which is also logged at debug level.
 	pub fn execute(
 		&self,
+		trigger: String,
 		rx: Receiver<MacroSignal>,
-		window_system: Sender<WindowSystemSignal>,
-		dbus: Sender<DBusSignal>,
-		is_finished: Arc<AtomicBool>)
+		window_system: MonitoredSender<WindowSystemSignal>,
+		dbus: MonitoredSender<DBusSignal>,
+		main_thread: MonitoredSender<MainThreadSignal>,
+		state: &Arc<SharedState>,
+		is_finished: Arc<AtomicBool>) -> MacroRun
 	{
+		let started = Instant::now();
+		let milliseconds = |instant: Instant| instant.duration_since(started).as_secs_f64() * 1000_f64;
+
+		let mut run = MacroRun
+		{
+			trigger,
+			started_at: SystemTime::now()
+				.duration_since(UNIX_EPOCH)
+				.map(|since_epoch| since_epoch.as_millis() as u64)
+				.unwrap_or(0),
+			duration: 0_f64,
+			iterations: 0,
+			steps: Vec::new(),
+			steps_not_timed: 0,
+			stop_reason: StopReason::Finished
+		};
+
+		debug!("macro run started by {}", &run.trigger);
+
 		let mut count = self.execution_count();
-		let mut i = 0;
+		let signals = Arc::new(MacroSignals::new(rx));
 
-		while count.is_none() || i < count.unwrap()
+		while count.is_none() || run.iterations < count.unwrap()
 		{
-			i += 1;
+			run.iterations += 1;
 
-			self.steps
-				.iter()
-				.for_each(|step| step.execute(&window_system, &dbus));
+			// picked up again each time round, so a change reaches macros that
+			// are still running
+			let timing = state.macro_timing();
 
-			match rx.try_recv()
+			for step in &self.steps
 			{
-				Ok(MacroSignal::ResetCount) => count = self.execution_count(),
-				Ok(MacroSignal::Stop)
-					| Err(TryRecvError::Disconnected) => break,
-				Err(TryRecvError::Empty) => ()
+				let step_started = Instant::now();
+				step.execute(&window_system, &dbus, &main_thread, state, &signals, 0, timing);
+
+				let timing = StepTiming
+				{
+					action: step.action.clone(),
+					started: milliseconds(step_started),
+					duration: milliseconds(Instant::now()) - milliseconds(step_started)
+				};
+
+				debug!("macro run started by {}: {:?} at {:.1}ms took {:.1}ms",
+					&run.trigger, &timing.action, timing.started, timing.duration);
+
+				match run.steps.len() < MacroRun::MAX_TIMED_STEPS
+				{
+					true => run.steps.push(timing),
+					false => run.steps_not_timed += 1
+				}
+			}
+
+			if signals.stopping()
+			{
+				run.stop_reason = StopReason::Stopped;
+				break
+			}
+
+			if signals.take_reset_count()
+			{
+				count = self.execution_count();
 			}
 		}
 
 		is_finished.store(true, Ordering::Relaxed);
+
+		run.duration = milliseconds(Instant::now());
+		debug!("macro run started by {} ended after {:.1}ms ({} iterations): {:?}",
+			&run.trigger, run.duration, run.iterations, run.stop_reason);
+
+		run
+	}
+}
+
+/// A running macro: a way to signal it, whether it has stopped, and how it was
+/// activated
+pub type MacroState = (Sender<MacroSignal>, Arc<AtomicBool>, ActivationType);
+
+/// Runs a macro on a thread from the runtime's blocking pool, keeping its
+/// timeline once it's done. `trigger` describes what started it, for the
+/// timeline. It's detached, so a long delay doesn't hold up shutdown.
+pub fn start(
+	macro_: Macro,
+	trigger: String,
+	window_system: MonitoredSender<WindowSystemSignal>,
+	dbus: MonitoredSender<DBusSignal>,
+	main_thread: MonitoredSender<MainThreadSignal>,
+	state: Arc<SharedState>) -> MacroState
+{
+	debug!("starting macro: {:#?}", &macro_);
+
+	let (macro_tx, macro_rx) = channel();
+	let stopped = Arc::new(AtomicBool::new(false));
+	let macro_thread_stopped = Arc::clone(&stopped);
+	let activation_type = macro_.activation_type;
+
+	state.driver_stats.macro_started();
+	main_thread.send(MainThreadSignal::Hook(HookEvent::MacroStarted { trigger: trigger.clone() }));
+	smol::unblock(
+	{
+		let main_thread = main_thread.clone();
+
+		move ||
+		{
+			let run = macro_.execute(
+				trigger,
+				macro_rx,
+				window_system,
+				dbus,
+				main_thread,
+				&state,
+				macro_thread_stopped);
+
+			let mut macro_runs = state.macro_runs.write().unwrap();
+			macro_runs.push_front(run);
+			macro_runs.truncate(MacroRun::HISTORY_LENGTH);
+		}
+	}).detach();
+
+	(macro_tx, stopped, activation_type)
+}
+
+/// Handles a repeated trigger of a macro that's still running, stopping toggle
+/// macros and resetting the count of repeat macros. Returns true if it was
+/// handled, false if a new run should be started instead.
+pub fn signal_running((tx, stopped, activation_type): &MacroState) -> bool
+{
+	if stopped.load(Ordering::Relaxed)
+	{
+		return false
+	}
+
+	debug!("macro slot is already active, activationtype: {:#?}", &activation_type);
+
+	match activation_type
+	{
+		ActivationType::Toggle =>
+		{
+			debug!("stopping toggle macro");
+			tx.send(MacroSignal::Stop);
+			true
+		},
+		ActivationType::Repeat(_count) =>
+		{
+			debug!("resetting count on repeat macro");
+			tx.send(MacroSignal::ResetCount);
+			true
+		},
+		_ => false
 	}
 }
 
@@ -121,27 +549,173 @@ impl Macro
 pub struct Step
 {
 	action: Action,
-	duration: u64
+	// can be left out of steps with jitter
+	#[serde(default)]
+	duration: u64,
+	/// picks the duration between a min and max each time the step runs, in
+	/// place of `duration`
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	jitter: Option<Jitter>,
+	/// keys lit in the same frame as the action happens
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	lighting: Option<StepLighting>
+}
+
+/// The shortest and longest a step with jitter can take, in ms
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Jitter
+{
+	pub min: u64,
+	pub max: u64
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StepLighting
+{
+	pub keys: Vec<KeySelection>,
+	pub color: Color,
+	/// how long the keys stay lit, in ms
+	#[serde(default = "StepLighting::default_duration")]
+	pub duration: u64
+}
+
+impl StepLighting
+{
+	/// the client name step lighting is drawn under, see client_colors.rs
+	const CLIENT_NAME: &'static str = "macro lighting";
+	/// how long to wait for the keyboard to draw the keys, so a missing or
+	/// busy keyboard doesn't hold the macro up
+	const DRAW_TIMEOUT: Duration = Duration::from_millis(100);
+
+	fn default_duration() -> u64
+	{
+		150
+	}
+
+	/// Lights the keys, waiting until they've been drawn
+	fn apply(&self, main_thread: &MonitoredSender<MainThreadSignal>, state: &Arc<SharedState>)
+	{
+		let colors =
+		{
+			let config = state.config.read().unwrap();
+
+			self.keys
+				.iter()
+				.flat_map(|selection| selection.scancodes(&config.keygroups))
+				.map(|scancode| (scancode, self.color))
+				.collect()
+		};
+
+		let (drawn, drawn_rx) = DrawnSignal::new();
+
+		main_thread.send(MainThreadSignal::SetKeyColorsNow(KeyColorUpdate
+		{
+			client: Self::CLIENT_NAME.into(),
+			colors,
+			replace: false,
+			ttl: Some(self.duration)
+		}, drawn));
+
+		if drawn_rx.recv_timeout(Self::DRAW_TIMEOUT).is_err()
+		{
+			debug!("the keyboard didn't draw a macro step's lighting in time");
+		}
+	}
 }
 
 impl Step
 {
-	fn execute(&self, window_system: &Sender<WindowSystemSignal>, dbus: &Sender<DBusSignal>)
+	pub fn jitter(&self) -> Option<Jitter>
 	{
+		self.jitter
+	}
+
+	/// How long the step takes this time: somewhere in its jitter (or its
+	/// duration), scaled, then varied by up to `humanize` of that either way
+	fn duration(&self, timing: MacroTiming) -> u64
+	{
+		let duration = match self.jitter
+		{
+			Some(Jitter { min, max }) => min as f64 + max.saturating_sub(min) as f64 * random_fraction(),
+			None => self.duration as f64
+		};
+
+		let variation = timing.humanize * (random_fraction() * 2_f64 - 1_f64);
+		(duration * timing.scale * (1_f64 + variation)).round().max(0_f64) as u64
+	}
+
+	fn execute(
+		&self,
+		window_system: &MonitoredSender<WindowSystemSignal>,
+		dbus: &MonitoredSender<DBusSignal>,
+		main_thread: &MonitoredSender<MainThreadSignal>,
+		state: &Arc<SharedState>,
+		signals: &Arc<MacroSignals>,
+		depth: usize,
+		timing: MacroTiming)
+	{
+		if let Some(lighting) = &self.lighting
+		{
+			lighting.apply(main_thread, state);
+		}
+
 		match &self.action
 		{
-			Action::Delay => std::thread::sleep(Duration::from_millis(self.duration)),
+			Action::Delay => std::thread::sleep(Duration::from_millis(self.duration(timing))),
 
 			Action::MouseClick(button) => window_system
-				.send(WindowSystemSignal::SendClick(*button))
-				.unwrap_or(()),
+				.send(WindowSystemSignal::SendClick(*button)),
 
 			Action::KeyPress(keysequence) => window_system
-				.send(WindowSystemSignal::SendKeyCombo(keysequence.clone()))
-				.unwrap_or(()),
+				.send(WindowSystemSignal::SendKeyCombo(keysequence.clone())),
+
+			Action::KeyDown(keysequence) => window_system
+				.send(WindowSystemSignal::SetKeyComboPressed(keysequence.clone(), true)),
+
+			Action::KeyUp(keysequence) => window_system
+				.send(WindowSystemSignal::SetKeyComboPressed(keysequence.clone(), false)),
+
+			Action::TypeUnicode(text) => window_system
+				.send(WindowSystemSignal::TypeUnicode(text.clone())),
 
 			Action::DebugPrint(message) => println!("{}", message),
 
+			Action::MediaControl(command) => dbus
+				.send(DBusSignal::MediaControl(*command)),
+
+			Action::GameMode(command) => main_thread
+				.send(MainThreadSignal::SetGameMode(*command)),
+
+			Action::CycleProfiles(profiles) => main_thread
+				.send(MainThreadSignal::CycleProfiles(profiles.clone())),
+
+			Action::Script(script) => crate::script::run(script, window_system, main_thread, state, signals),
+
+			Action::RunMacro(name) if depth >= Macro::MAX_DEPTH =>
+				warn!("not running macro {}, macros can only run other macros {} deep", name, Macro::MAX_DEPTH),
+
+			Action::RunMacro(name) =>
+			{
+				// cloned so the config isn't locked whilst it runs
+				let macro_ = state.config
+					.read()
+					.unwrap()
+					.macros
+					.as_ref()
+					.and_then(|macros| macros.get(name))
+					.cloned();
+
+				match macro_
+				{
+					Some(macro_) =>
+					{
+						debug!("running macro {} from another macro", name);
+						macro_.execute_nested(window_system, dbus, main_thread, state, signals, depth + 1, timing);
+					},
+					None => warn!("can't run macro {}, it doesn't exist", name)
+				}
+			},
+
 			Action::RunCommand(command) =>
 			{
 				Command::new(env::var_os("SHELL").unwrap_or_else(|| "/bin/sh".into()))
@@ -155,17 +729,152 @@ impl Step
 
 			Action::DbusMethodCall { destination, path, interface, method, arguments } =>
 			{
-				if let Ok(message) = zbus::Message::method(
-					None,
-					Some(destination),
-					path,
-					Some(interface),
-					method,
-					arguments)
+				dbus.send(DBusSignal::CallMethod
 				{
-					dbus.send(DBusSignal::SendMessage(message));
-				}
+					destination: destination.clone(),
+					path: path.clone(),
+					interface: interface.clone(),
+					method: method.clone(),
+					arguments: arguments.clone()
+				});
 			}
 		};
 	}
 }
+
+/// A number from 0 up to 1, different each time. Step durations only need to
+/// vary, not be unpredictable, so hashing the time with the standard library's
+/// randomly keyed hasher is enough.
+fn random_fraction() -> f64
+{
+	let mut hasher = RandomState::new().build_hasher();
+	hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|since_epoch| since_epoch.as_nanos()).unwrap_or(0));
+
+	// the top 53 bits, as many as an f64 holds exactly
+	(hasher.finish() >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// The key on the keyboard for a key in a key combo, which are X keysym names
+/// (i.e. `ctrl+Return`). Most of them are the key's name in the config with
+/// a different case, the rest are looked up. Keys that aren't on the keyboard
+/// (or only on the numpad with num lock on, i.e. `KP_Home`) are None.
+fn combo_key(key: &str) -> Option<Scancode>
+{
+	let key = key.trim().to_lowercase();
+
+	let name = match key.as_str()
+	{
+		"ctrl" | "control" | "control_l" => "left_control",
+		"control_r" => "right_control",
+		"shift" | "shift_l" => "left_shift",
+		"shift_r" => "right_shift",
+		"alt" | "alt_l" => "left_alt",
+		"alt_r" | "iso_level3_shift" => "right_alt",
+		"meta" | "meta_l" | "super" | "super_l" | "win" => "left_meta",
+		"meta_r" | "super_r" => "right_meta",
+		"return" => "enter",
+		"equal" => "equals",
+		"bracketleft" => "left_bracket",
+		"bracketright" => "right_bracket",
+		"period" => "dot",
+		"print" => "print_screen",
+		"prior" => "page_up",
+		"next" => "page_down",
+		"menu" => "context_menu",
+		"kp_divide" => "numpad_divide",
+		"kp_multiply" => "numpad_multiply",
+		"kp_subtract" => "numpad_minus",
+		"kp_add" => "numpad_plus",
+		"kp_enter" => "numpad_enter",
+		"kp_decimal" => "numpad_dot",
+		key => match (key.strip_prefix("kp_"), key.len())
+		{
+			(Some(digit), _) => return serde_yaml::from_str(&format!("numpad{}", digit)).ok(),
+			(None, 1) if key.chars().all(|character| character.is_ascii_digit()) =>
+				return serde_yaml::from_str(&format!("n{}", key)).ok(),
+			_ => key
+		}
+	};
+
+	serde_yaml::from_str(name).ok()
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	#[test]
+	fn combo_keys_are_found_on_the_keyboard()
+	{
+		let macro_: Macro = serde_yaml::from_str("
+activation_type: singular
+steps:
+  - action:
+      key_press: ctrl+Shift_R+t
+    duration: 5
+  - action:
+      key_down: ctrl+Return
+    duration: 5
+  - action:
+      key_up: KP_1+Page_Up+XF86AudioMute+5
+    duration: 5
+").unwrap();
+
+		assert_eq!(macro_.pressed_keys(), vec![
+			Scancode::LeftControl, Scancode::RightShift, Scancode::T, Scancode::Enter,
+			Scancode::Numpad1, Scancode::PageUp, Scancode::N5
+		]);
+	}
+
+	#[test]
+	fn step_durations_vary_within_their_jitter()
+	{
+		let step: Step = serde_yaml::from_str("{action: delay, jitter: {min: 40, max: 60}}").unwrap();
+		let timing = MacroTiming::default();
+		let durations: Vec<u64> = (0..100).map(|_| step.duration(timing)).collect();
+
+		assert!(durations.iter().all(|duration| (40..=60).contains(duration)));
+		assert!(durations.iter().any(|duration| *duration != durations[0]));
+
+		let step: Step = serde_yaml::from_str("{action: delay, duration: 100}").unwrap();
+
+		assert_eq!(step.duration(timing), 100);
+		assert_eq!(step.duration(MacroTiming { scale: 0.5, ..timing }), 50);
+		assert!((0..100)
+			.map(|_| step.duration(MacroTiming { humanize: 0.2, ..timing }))
+			.all(|duration| (80..=120).contains(&duration)));
+	}
+
+	#[test]
+	fn cycled_profiles_are_listed()
+	{
+		let action: Action = serde_yaml::from_str("cycle_profiles: [coding, gaming]").unwrap();
+		let macro_ = Macro::from_action(action);
+
+		assert_eq!(macro_.cycled_profiles().collect::<Vec<&str>>(), vec!["coding", "gaming"]);
+	}
+
+	#[test]
+	fn waits_end_when_the_macro_is_stopped()
+	{
+		let (tx, rx) = channel();
+		let signals = MacroSignals::new(rx);
+
+		tx.send(MacroSignal::ResetCount).unwrap();
+		assert!(!signals.wait(Duration::from_millis(10)));
+		assert!(signals.take_reset_count());
+		assert!(!signals.take_reset_count());
+
+		tx.send(MacroSignal::Stop).unwrap();
+		let started = Instant::now();
+		assert!(signals.wait(Duration::from_secs(60)));
+		assert!(started.elapsed() < Duration::from_secs(1));
+
+		// shutting down drops the macro's sender
+		let (tx, rx) = channel();
+		let signals = MacroSignals::new(rx);
+		drop(tx);
+		assert!(signals.stopping());
+	}
+}
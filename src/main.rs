@@ -2,78 +2,696 @@
 #![recursion_limit="512"]
 #![allow(clippy::suspicious_else_formatting)]
 
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::{Arc, RwLock};
 use std::sync::mpsc::channel;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
-use std::thread;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 
 use hidapi::HidApi;
-use threadpool::ThreadPool;
-use log::{error, info, trace};
-use crossbeam::channel::unbounded;
+use log::{error, warn, info, debug, trace};
 use clap::{Arg, App};
 
 use config::Configuration;
 use device::thread::DeviceSignal;
+use device::scancode::Scancode;
+use subsystem::Subsystem;
+use channels::RawSender;
 
 mod windowsystem;
+#[cfg(feature = "dbus")]
 mod dbus;
 mod device;
 mod config;
 mod macros;
 mod media;
+mod metrics;
+mod stats;
+mod persistence;
+mod keytest;
+mod notifications;
+mod subsystem;
+mod channels;
+mod daemon;
+mod scheduler;
+mod script;
+mod hooks;
+mod health;
+mod tui;
+mod onboard;
+mod command_pipe;
+#[cfg(feature = "http")]
+mod http;
 
 pub struct SharedState
 {
 	config: RwLock<Configuration>,
 	macro_recording: AtomicBool,
+	game_mode: AtomicBool,
+	// caps how brightly keys are lit, see LightingOptions::low_power_limit
+	low_power: AtomicBool,
+	// lights just the important keys, see config::FocusMode
+	focus_mode: AtomicBool,
+	// the last config reload failed, shown on the logo
+	config_error: AtomicBool,
+	// a color for the logo set over dbus, shown in place of the profile's
+	logo_color: RwLock<Option<device::color::Color>>,
+	// the color temperature night mode has warmed the lighting to, if it's night
+	color_temperature: RwLock<Option<u16>>,
+	// an effect set over dbus, shown on the keys in place of the profile's theme
+	effect: RwLock<Option<device::rgb::EffectConfiguration>>,
+	// set over dbus, scales macro step durations in place of the config's scale
+	macro_timing_scale: RwLock<Option<f64>>,
+	// stops the active window (and media state) from changing the profile
+	profile_locked: AtomicBool,
+	// profiles pushed over dbus, the last one shown in place of the window's
+	profile_overrides: RwLock<Vec<ProfileOverride>>,
 	active_profile: RwLock<config::Profile>,
-	media_state: RwLock<media::MediaState>
+	active_profile_name: RwLock<String>,
+	// the window last reported by the window system, read by macro scripts
+	active_window: RwLock<Option<windowsystem::ActiveWindowInfo>>,
+	// notifications shown since the user was last active, and not dismissed
+	pending_notifications: AtomicUsize,
+	// state kept between runs, see persistence::PersistedState
+	persisted: persistence::StateStore,
+	// messages that couldn't be sent to threads that have stopped
+	channel_health: Arc<channels::ChannelHealth>,
+	// counters for GetStats, see health::DriverStats
+	driver_stats: health::DriverStats,
+	media_state: RwLock<media::MediaState>,
+	palette: RwLock<Option<hsl::HSL>>,
+	game_mode_keys: RwLock<Vec<device::scancode::Scancode>>,
+	pinned_player: RwLock<Option<regex::Regex>>,
+	key_statistics: RwLock<stats::KeyCounts>,
+	// the keys pressed since StartGameModeLearning, if it's on
+	game_mode_learning: RwLock<Option<stats::GameModeLearning>>,
+	// the theme the active profile's playlist is showing, if it has one
+	playlist: RwLock<scheduler::PlaylistStatus>,
+	// the timelines of the most recent macro runs, newest first
+	macro_runs: RwLock<VecDeque<macros::MacroRun>>,
+	// keyboards whose threads haven't stopped yet, see PanicGuard
+	device_threads: AtomicUsize,
+	// what each of those keyboards reported about itself, for GetDeviceInfo
+	devices: RwLock<Vec<device::DeviceInfo>>,
+	// shows the first keyboard's lighting on the others, see LightingOptions::mirror
+	mirror: Arc<device::mirror::LightingMirror>
+}
+
+impl SharedState
+{
+	fn new(config: Configuration, persisted: persistence::StateStore) -> Self
+	{
+		let initial_profile = config.default_profile().clone();
+
+		Self
+		{
+			macro_recording: AtomicBool::new(false),
+			game_mode: AtomicBool::new(false),
+			low_power: AtomicBool::new(config.lighting.low_power),
+			focus_mode: AtomicBool::new(false),
+			config_error: AtomicBool::new(false),
+			logo_color: RwLock::new(None),
+			color_temperature: RwLock::new(None),
+			effect: RwLock::new(None),
+			macro_timing_scale: RwLock::new(None),
+			profile_locked: AtomicBool::new(false),
+			profile_overrides: RwLock::new(Vec::new()),
+			config: RwLock::new(config),
+			active_profile: RwLock::new(initial_profile),
+			active_profile_name: RwLock::new(String::from("default")),
+			active_window: RwLock::new(None),
+			persisted,
+			channel_health: Arc::new(channels::ChannelHealth::default()),
+			driver_stats: health::DriverStats::default(),
+			pending_notifications: AtomicUsize::new(0),
+			media_state: RwLock::new(media::MediaState::default()),
+			palette: RwLock::new(None),
+			game_mode_keys: RwLock::new(Vec::new()),
+			pinned_player: RwLock::new(None),
+			key_statistics: RwLock::new(stats::KeyCounts::new()),
+			game_mode_learning: RwLock::new(None),
+			playlist: RwLock::new(scheduler::PlaylistStatus::default()),
+			macro_runs: RwLock::new(VecDeque::new()),
+			device_threads: AtomicUsize::new(0),
+			devices: RwLock::new(Vec::new()),
+			mirror: Arc::new(device::mirror::LightingMirror::default())
+		}
+	}
+
+	fn subsystem_enabled(&self, subsystem: Subsystem) -> bool
+	{
+		!self.persisted.read().disabled_subsystems.contains(&subsystem)
+	}
+
+	/// The config's macro timing, with the scale set over dbus if there is one
+	fn macro_timing(&self) -> config::MacroTiming
+	{
+		let mut timing = self.config.read().unwrap().macro_timing;
+
+		if let Some(scale) = *self.macro_timing_scale.read().unwrap()
+		{
+			timing.scale = scale;
+		}
+
+		timing
+	}
+
+	/// The driver's current state, as returned by GetState: the active profile,
+	/// the modes it's in, which subsystems are switched on, and whether it's
+	/// degraded (a thread has stopped, with the number of messages it's missed)
+	#[cfg_attr(not(any(feature = "dbus", feature = "http")), allow(dead_code))]
+	fn summary(&self) -> serde_json::Value
+	{
+		let subsystems: serde_json::Map<String, serde_json::Value> = Subsystem::ALL
+			.iter()
+			.map(|subsystem| (subsystem.name().to_string(), self.subsystem_enabled(*subsystem).into()))
+			.collect();
+
+		serde_json::json!({
+			"active_profile": *self.active_profile_name.read().unwrap(),
+			"profile_locked": self.profile_locked.load(Ordering::Relaxed),
+			"profile_overrides": self.profile_overrides
+				.read()
+				.unwrap()
+				.iter()
+				.map(|profile_override| profile_override.name.as_str())
+				.collect::<Vec<&str>>(),
+			"game_mode": self.game_mode.load(Ordering::Relaxed),
+			"low_power_mode": self.low_power.load(Ordering::Relaxed),
+			"focus_mode": self.focus_mode.load(Ordering::Relaxed),
+			"pending_notifications": self.pending_notifications.load(Ordering::Relaxed),
+			"subsystems": subsystems,
+			"playlist": *self.playlist.read().unwrap(),
+			"macro_timing_scale": self.macro_timing().scale,
+			"degraded": self.channel_health.degraded(),
+			"failed_sends": self.channel_health.failed_sends()
+		})
+	}
+}
+
+/// A profile pushed by PushProfile, which stops the active window (and media
+/// state) from changing the profile until it's popped or it expires
+pub struct ProfileOverride
+{
+	name: String,
+	expires: Option<Instant>
+}
+
+/// Stops the keyboards' threads if the main thread panics, and waits for them
+/// to hand the keyboards back, as the process exits as soon as it's unwound
+struct PanicGuard
+{
+	state: Arc<SharedState>,
+	device_threads: channels::Broadcaster<DeviceSignal>
+}
+
+impl PanicGuard
+{
+	const TIMEOUT: Duration = Duration::from_secs(2);
+}
+
+impl Drop for PanicGuard
+{
+	fn drop(&mut self)
+	{
+		if !std::thread::panicking()
+		{
+			return
+		}
+
+		error!("the main thread panicked, handing the keyboards back");
+		self.device_threads.send_raw(DeviceSignal::Shutdown);
+
+		let started = Instant::now();
+
+		while self.state.device_threads.load(Ordering::SeqCst) > 0 && started.elapsed() < Self::TIMEOUT
+		{
+			std::thread::sleep(Duration::from_millis(10));
+		}
+	}
 }
 
 pub enum MainThreadSignal
 {
 	ActiveWindowChanged(Option<windowsystem::ActiveWindowInfo>),
-	RunMacroInPool(Box<dyn FnOnce() + Send>),
-	MediaStateChanged(media::MediaState)
+	MediaStateChanged(media::MediaState),
+	PaletteChanged(Option<hsl::HSL>),
+	PinMediaPlayer(Option<regex::Regex>),
+	SetGameMode(macros::GameModeCommand),
+	SetLowPowerMode(bool),
+	SetFocusMode(bool),
+	EnableSubsystem(Subsystem, bool),
+	Playlist(scheduler::PlaylistCommand),
+	SetLogoColor(Option<device::color::Color>),
+	/// shows a firmware effect on the keys whatever the profile, until cleared
+	SetEffect(Option<device::rgb::EffectConfiguration>),
+	/// scales macro step durations in place of the config's scale, until cleared
+	SetMacroTimingScale(Option<f64>),
+	SetKeyColors(device::client_colors::KeyColorUpdate),
+	SetKeyColorsNow(device::client_colors::KeyColorUpdate, device::client_colors::DrawnSignal),
+	ClearKeyColors(String),
+	SwitchProfile(String),
+	/// shows a profile until it's popped, or its ttl is up
+	PushProfile(String, Option<Duration>),
+	PopProfile,
+	SetProfileLock(bool),
+	/// switches to the profile after the active one in a list (or the first,
+	/// if it isn't pinned to one of them), locking it until it's cycled again,
+	/// then after the last goes back to following the active window
+	CycleProfiles(Vec<String>),
+	SetMode(u8),
+	/// runs a named macro from the config, as if a gkey assigned it was pressed
+	RunMacro(String),
+	NotificationReceived(notifications::Notification),
+	/// flashes the keys for a notification sent over dbus, without counting
+	/// it as pending
+	FlashNotification(notifications::Notification),
+	NotificationDismissed,
+	/// runs the config's hooks for something that happened on another thread
+	Hook(hooks::HookEvent),
+	/// game mode learning was started or stopped, so key presses may need
+	/// counting (or not) from now on
+	KeyCountingChanged,
+	/// passed on from the watcher on the config's folders
+	ConfigFileChanged(std::path::PathBuf),
+	/// passed on from the watcher on /dev
+	DeviceNodeChanged(std::path::PathBuf),
+	/// from ctrl+c, or quitting the tui
+	Shutdown
 }
 
-fn main()
+/// What the other threads have the dbus thread send. Without the dbus feature
+/// there's nowhere to send them, so they're dropped.
+pub enum DBusSignal
+{
+	Shutdown,
+	/// shows a desktop notification from the driver
+	Notify { summary: String, body: String },
+	/// calls a method on another program, for the `dbus_method_call` action
+	CallMethod { destination: String, path: String, interface: String, method: String, arguments: Option<Vec<String>> },
+	MediaControl(media::MediaCommand),
+	GameModeChanged(bool),
+	LowPowerModeChanged(bool),
+	FocusModeChanged(bool)
+}
+
+/// Reads a key by its name in the config, in any case, for the dbus api, the
+/// http server and the command pipe
+pub fn parse_key(name: &str) -> Result<Scancode, String>
+{
+	serde_yaml::from_str(&name.to_lowercase()).map_err(|_| format!("there is no key called '{}'", name))
+}
+
+/// Renders `Scancode::describe_all()` as either `yaml` or `json`, for
+/// `--list-keys` and the dbus api
+pub fn key_list(keygroups: &config::Keygroups, format: &str) -> Result<String, String>
+{
+	let keys = Scancode::describe_all(keygroups);
+
+	match format
+	{
+		"yaml" => serde_yaml::to_string(&keys).map_err(|e| e.to_string()),
+		"json" => serde_json::to_string_pretty(&keys).map_err(|e| e.to_string()),
+		_ => Err(format!("unknown key list format: {} (expected yaml or json)", format))
+	}
+}
+
+/// Reads the `on`, `off` or `toggle` the http server and command pipe take for
+/// game mode, focus mode and low power mode
+pub fn parse_mode_command(command: &str) -> Result<macros::GameModeCommand, String>
+{
+	match command
+	{
+		"on" => Ok(macros::GameModeCommand::On),
+		"off" => Ok(macros::GameModeCommand::Off),
+		"toggle" => Ok(macros::GameModeCommand::Toggle),
+		_ => Err(format!("'{}' isn't on, off or toggle", command))
+	}
+}
+
+#[cfg(feature = "dbus")]
+fn resolved_state() -> Result<String, String>
 {
-	pretty_env_logger::init();
+	dbus::Server::resolved_state().map_err(|error| error.to_string())
+}
+
+#[cfg(not(feature = "dbus"))]
+fn resolved_state() -> Result<String, String>
+{
+	Err("the driver was built without the dbus feature".into())
+}
+
+#[cfg(feature = "dbus")]
+fn already_running() -> bool
+{
+	dbus::Server::already_running()
+}
 
+/// without dbus the only way to tell is the pid file, if one is used
+#[cfg(not(feature = "dbus"))]
+fn already_running() -> bool
+{
+	false
+}
+
+fn main()
+{
 	let args = App::new("g815-driver")
 		.version(env!("CARGO_PKG_VERSION"))
 		.author(env!("CARGO_PKG_AUTHORS"))
 		.about(env!("CARGO_PKG_DESCRIPTION"))
 		.arg(Arg::with_name("palette")
 			 .short("p"))
+		.arg(Arg::with_name("restore-backup")
+			 .long("restore-backup")
+			 .value_name("BACKUP")
+			 .takes_value(true)
+			 .min_values(0)
+			 .help("Restores a config backup (the newest if no name is given) and exits"))
+		.arg(Arg::with_name("list-keys")
+			 .long("list-keys")
+			 .value_name("FORMAT")
+			 .takes_value(true)
+			 .min_values(0)
+			 .possible_values(&["yaml", "json"])
+			 .help("Prints all supported key names and the keygroups they're in, then exits"))
+		.arg(Arg::with_name("test-keys")
+			 .long("test-keys")
+			 .help("Lights each key in turn to check the layout, recording any corrections"))
+		.arg(Arg::with_name("print-default-config")
+			 .long("print-default-config")
+			 .help("Prints the commented example config, then exits"))
+		.arg(Arg::with_name("daemonize")
+			 .long("daemonize")
+			 .help("Runs in the background, logging to g815d.log in $XDG_RUNTIME_DIR"))
+		.arg(Arg::with_name("hidraw-helper")
+			 .long("hidraw-helper")
+			 .value_name("SOCKET")
+			 .takes_value(true)
+			 .min_values(0)
+			 .help("Has g815d-hidraw-helper open the keyboards, for when there's no udev rule giving access to them"))
+		.arg(Arg::with_name("pid-file")
+			 .long("pid-file")
+			 .value_name("FILE")
+			 .takes_value(true)
+			 .help("Writes the driver's pid to a file, removed when it exits"))
+		.arg(Arg::with_name("tui")
+			 .long("tui")
+			 .conflicts_with_all(&["daemonize", "palette", "test-keys"])
+			 .help("Shows the driver's state, events and errors as it runs, with keys to switch profiles and run macros"))
+		.arg(Arg::with_name("simulate")
+			 .long("simulate")
+			 .conflicts_with_all(&["daemonize", "tui"])
+			 .help("Draws a keyboard in the terminal in place of any real ones, to try out themes without one"))
+		.arg(Arg::with_name("info")
+			 .long("info")
+			 .help("Prints what each keyboard reports about itself (firmware versions, capabilities and so on) as json, then exits"))
+		.arg(Arg::with_name("read-onboard-profile")
+			 .long("read-onboard-profile")
+			 .value_name("PROFILE")
+			 .takes_value(true)
+			 .min_values(0)
+			 .validator(onboard::validate_profile)
+			 .help("Prints the lighting stored in one of the keyboard's onboard profiles (the first if no number is given), then exits"))
+		.arg(Arg::with_name("write-onboard-profile")
+			 .long("write-onboard-profile")
+			 .value_name("PROFILE")
+			 .takes_value(true)
+			 .min_values(0)
+			 .validator(onboard::validate_profile)
+			 .conflicts_with("read-onboard-profile")
+			 .help("Stores the default profile's lighting in one of the keyboard's onboard profiles (the first if no number is given), \
+				which it shows whilst the driver isn't running, then exits"))
+		.arg(Arg::with_name("capture")
+			 .long("capture")
+			 .value_name("FILE")
+			 .takes_value(true)
+			 .conflicts_with("simulate")
+			 .help("Records every report sent to and from the keyboards to a file, for reporting firmware quirks"))
+		.arg(Arg::with_name("resolved-state")
+			 .long("resolved-state")
+			 .help("Prints what the running driver resolved for the active window (the profile and why, its theme, \
+				gkey assignments and key colors) as json, then exits"))
+		.arg(Arg::with_name("replay")
+			 .long("replay")
+			 .value_name("FILE")
+			 .takes_value(true)
+			 .help("Prints the events a --capture file would have produced, then exits"))
 		.get_matches();
 
+	// the tui is drawn over anything logged to the terminal, so it shows the log itself
+	let tui_log = match args.is_present("tui")
+	{
+		true => Some(tui::init_logger()),
+		false =>
+		{
+			pretty_env_logger::init();
+			None
+		}
+	};
+
+	if args.is_present("daemonize")
+	{
+		match daemon::daemonize(args.value_of("pid-file"))
+		{
+			Ok(pid) => info!("running in the background with pid {}", pid),
+			Err(error) => error!("unable to run in the background: {}", error)
+		}
+
+		return
+	}
+
+	if args.is_present("print-default-config")
+	{
+		print!("{}", Configuration::DEFAULT);
+		return
+	}
+
+	match Configuration::write_default_if_missing()
+	{
+		Ok(true) => info!("no config file found, so the default config was written to {}",
+			Configuration::file_path().display()),
+		Ok(false) => (),
+		Err(config_error) => error!("unable to write the default config: {}", config_error)
+	}
+
+	if args.is_present("list-keys")
+	{
+		// still list the keys without keygroups if the config can't be loaded
+		let keygroups = Configuration::load()
+			.map(|config| config.keygroups)
+			.unwrap_or_else(|config_error|
+			{
+				warn!("unable to load config, keygroups won't be listed: {}", config_error);
+				Default::default()
+			});
+
+		match key_list(&keygroups, args.value_of("list-keys").unwrap_or("yaml"))
+		{
+			Ok(list) => println!("{}", list),
+			Err(error) => error!("unable to list keys: {}", error)
+		}
+
+		return
+	}
+
+	if let Some(path) = args.value_of("replay")
+	{
+		match device::capture::load(std::path::Path::new(path))
+		{
+			Ok(records) => device::capture::replay(records),
+			Err(error) => error!("unable to read the capture {}: {}", path, error)
+		}
+
+		return
+	}
+
+	if args.is_present("resolved-state")
+	{
+		match resolved_state()
+		{
+			Ok(resolved_state) => println!("{}", resolved_state),
+			Err(error) => error!("unable to ask the driver (is it running?): {}", error)
+		}
+
+		return
+	}
+
+	if args.is_present("restore-backup")
+	{
+		match Configuration::restore_backup(args.value_of("restore-backup"))
+		{
+			Ok(name) => info!("restored config backup {}", name),
+			Err(config_error) => error!("unable to restore config backup: {}", config_error)
+		}
+
+		return
+	}
+
+	// only one instance can control the keyboard at a time
+	if already_running()
+	{
+		error!("another instance of the driver is already running");
+		std::process::exit(1);
+	}
+
+	let _pid_file = args
+		.value_of("pid-file")
+		.map(|path| daemon::PidFile::create(path.into()).unwrap_or_else(|error|
+		{
+			error!("{}", error);
+			std::process::exit(1);
+		}));
+
 	let config = Configuration::load().unwrap();
-	// shouldnt ever need more than 20 threads, as that can handle all
-	// 15 possible simultaneous macros + the device/watcher threads
-	let pool = ThreadPool::new(20);
-	let hidapi = HidApi::new().unwrap();
-	let devices = device::find_devices(hidapi);
-	let initial_profile = config.default_profile().clone();
-
-	let state = Arc::new(SharedState
-	{
-		macro_recording: AtomicBool::new(false),
-		config: RwLock::new(config),
-		active_profile: RwLock::new(initial_profile),
-		media_state: RwLock::new(media::MediaState::default())
-	});
+	// the subsystems waiting on sockets and files run as tasks on the main
+	// thread, whilst those using blocking libraries get their own thread from
+	// smol's blocking pool. Shutdown waits for all of them, but not for macros.
+	let executor = smol::LocalExecutor::new();
+	let mut tasks: Vec<smol::Task<()>> = Vec::new();
+	let mut hidapi = HidApi::new().unwrap();
+	let mut opened_devices = HashSet::new();
+	let hidraw_helper = match args.is_present("hidraw-helper")
+	{
+		true => Some(std::path::PathBuf::from(args.value_of("hidraw-helper").unwrap_or(device::hidraw::DEFAULT_SOCKET))),
+		false => None
+	};
+	let capture = args.value_of("capture").map(|path| device::capture::Capture::create(std::path::Path::new(path)).unwrap_or_else(|error|
+	{
+		error!("unable to create the capture {}: {}", path, error);
+		std::process::exit(1);
+	}));
+	let simulate = args.is_present("simulate");
+	let devices = match simulate
+	{
+		true => vec![Box::new(device::simulated::SimulatedKeyboard::new()) as Box<dyn device::Device>],
+		false => device::find_devices(&hidapi, hidraw_helper.as_deref(), capture.as_ref(), &mut opened_devices)
+	};
+	let initial_profile_playlist = config.default_profile().playlist.clone();
+
+	if args.is_present("test-keys")
+	{
+		let mut devices = devices;
+
+		match devices.pop()
+		{
+			Some(mut kb) => keytest::test_keys(&mut kb, &config),
+			None => error!("no keyboard found to test")
+		}
+
+		return
+	}
+
+	if args.is_present("info")
+	{
+		let info: Vec<device::DeviceInfo> = devices
+			.into_iter()
+			.map(|mut device| device.info())
+			.collect();
+
+		println!("{}", serde_json::to_string_pretty(&info).unwrap());
+		return
+	}
+
+	for (arg, write) in [("read-onboard-profile", false), ("write-onboard-profile", true)].iter()
+	{
+		if !args.is_present(arg)
+		{
+			continue
+		}
+
+		// fine to unwrap, the validator has checked it
+		let profile = args.value_of(arg).map(|profile| profile.parse().unwrap()).unwrap_or(1);
+		let mut devices = devices;
+
+		match devices.pop()
+		{
+			Some(mut kb) =>
+			{
+				let result = match write
+				{
+					true => onboard::write(&mut kb, &config, profile),
+					false => onboard::read(&mut kb, profile)
+				};
+
+				if let Err(error) = result
+				{
+					error!("unable to {} onboard profile {}: {}", match write { true => "write", false => "read" }, profile, error);
+				}
+			},
+			None => error!("no keyboard found")
+		}
+
+		return
+	}
+
+	let state = Arc::new(SharedState::new(config, persistence::StateStore::load()));
+
+	// the initial profile's playlist is picked before any keyboard shows it
+	let mut playlists = scheduler::PlaylistScheduler::new();
+	playlists.set_enabled(state.subsystem_enabled(Subsystem::Scheduler));
+	playlists.set_playlist(&state.config.read().unwrap(), initial_profile_playlist.as_deref(), false);
+	*state.playlist.write().unwrap() = playlists.status();
+
+	// night mode fades a little at a time, so checking once a minute is plenty
+	const NIGHT_MODE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+	let mut last_night_mode_check: Option<Instant> = None;
+
+	// profiles with schedule conditions are checked again whenever one of
+	// their schedules starts or ends
+	const SCHEDULE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+	let mut last_schedule_check = Instant::now();
+	let mut scheduled_profiles = state.config.read().unwrap().scheduled_profiles(config::LocalTime::now());
 
 	let should_exit = Arc::new(AtomicBool::new(false));
-	let (main_thread_tx, main_thread_rx) = channel();
-	let (device_thread_tx, device_thread_rx) = unbounded();
-	let (dbus_thread_tx, dbus_thread_rx) = channel();
-	let (ww_thread_tx, ww_thread_rx) = channel();
+	let (main_thread_tx, main_thread_rx) = smol::channel::unbounded();
+	// every keyboard's thread gets its own copy of each signal
+	let device_threads = channels::Broadcaster::default();
+	let _panic_guard = PanicGuard { state: Arc::clone(&state), device_threads: device_threads.clone() };
+	let (dbus_thread_tx, dbus_thread_rx) = smol::channel::unbounded();
+	let (ww_thread_tx, ww_thread_rx) = smol::channel::unbounded();
+	// notify only sends to std channels
 	let (config_watcher_tx, config_watcher_rx) = channel();
-	let (media_watcher_tx, media_watcher_rx) = channel();
+	let (media_watcher_tx, media_watcher_rx) = smol::channel::unbounded();
+	let (key_statistics_tx, key_statistics_rx) = smol::channel::unbounded();
+	let (notification_watcher_tx, notification_watcher_rx) = smol::channel::unbounded();
+	#[cfg(feature = "http")]
+	let (http_tx, http_rx) = smol::channel::unbounded();
+	let (command_pipe_tx, command_pipe_rx) = smol::channel::unbounded();
+
+	let main_thread_tx = state.channel_health.monitor("main", main_thread_tx);
+	let device_thread_tx = state.channel_health.monitor("device", device_threads.clone());
+	let dbus_thread_tx = state.channel_health.monitor("dbus", dbus_thread_tx);
+	let ww_thread_tx = state.channel_health.monitor("window system", ww_thread_tx);
+	let media_watcher_tx = state.channel_health.monitor("media watcher", media_watcher_tx);
+	let key_statistics_tx = state.channel_health.monitor("key statistics", key_statistics_tx);
+	let notification_watcher_tx = state.channel_health.monitor("notification watcher", notification_watcher_tx);
+	#[cfg(feature = "http")]
+	let http_tx = state.channel_health.monitor("http", http_tx);
+	let command_pipe_tx = state.channel_health.monitor("command pipe", command_pipe_tx);
+
+	// tells whichever thread runs a subsystem that it's been switched on or off
+	let set_subsystem_enabled = |subsystem: Subsystem, enabled: bool| match subsystem
+	{
+		#[cfg(feature = "media")]
+		Subsystem::Media => { media_watcher_tx.send(media::MediaWatcherSignal::SetWatching(enabled)); },
+		// there's no media watcher to tell
+		#[cfg(not(feature = "media"))]
+		Subsystem::Media => (),
+		Subsystem::Windows => { ww_thread_tx.send(windowsystem::WindowSystemSignal::SetWatching(enabled)); },
+		Subsystem::ReactiveLighting
+			| Subsystem::Widgets => { device_thread_tx.send(DeviceSignal::SubsystemsChanged); },
+		// the main loop checks this itself
+		Subsystem::Scheduler => ()
+	};
+
+	// subsystems switched off in a previous run stay off, the threads pick this
+	// up before they start watching
+	for subsystem in Subsystem::ALL.iter().filter(|subsystem| !state.subsystem_enabled(**subsystem))
+	{
+		info!("the {} subsystem is switched off", subsystem.name());
+		set_subsystem_enabled(*subsystem, false);
+	}
 
 	let mut config_watcher = notify::watcher(config_watcher_tx, Duration::from_secs(3)).unwrap();
 	let mut config_file = Configuration::file_path();
@@ -84,12 +702,76 @@ fn main()
 	use notify::Watcher;
 	config_watcher.watch(config_file, notify::RecursiveMode::NonRecursive).unwrap();
 
+	// include folders are watched too, if they exist yet
+	for folder in config::includes::folders().iter().filter(|folder| folder.is_dir())
+	{
+		if let Err(error) = config_watcher.watch(folder, notify::RecursiveMode::NonRecursive)
+		{
+			warn!("unable to watch {} for changes: {}", folder.display(), error);
+		}
+	}
+
+	// devices that can't be opened yet (i.e. before udev has given us access)
+	// are retried whenever a hidraw node appears or its permissions change
+	let (device_watcher_tx, device_watcher_rx) = channel();
+	let mut device_watcher = notify::watcher(device_watcher_tx, Duration::from_secs(1)).unwrap();
+	device_watcher.watch("/dev", notify::RecursiveMode::NonRecursive).unwrap();
+
+	// the watchers' events are passed on to the main loop, so it only wakes up
+	// for the ones it handles. The config is saved by renaming a temporary file
+	// over it, so renames need to be picked up as well as writes.
+	smol::unblock(
+	{
+		let main_thread_tx = main_thread_tx.clone();
+		move || while let Ok(event) = config_watcher_rx.recv()
+		{
+			if let notify::DebouncedEvent::Create(path)
+				| notify::DebouncedEvent::NoticeWrite(path)
+				| notify::DebouncedEvent::Rename(_, path) = event
+			{
+				main_thread_tx.send(MainThreadSignal::ConfigFileChanged(path));
+			}
+		}
+	}).detach();
+
+	smol::unblock(
+	{
+		let main_thread_tx = main_thread_tx.clone();
+		move || while let Ok(event) = device_watcher_rx.recv()
+		{
+			if let notify::DebouncedEvent::Create(path)
+				| notify::DebouncedEvent::Chmod(path) = event
+			{
+				main_thread_tx.send(MainThreadSignal::DeviceNodeChanged(path));
+			}
+		}
+	}).detach();
+
 	ctrlc::set_handler(
 	{
-		let should_exit = should_exit.clone();
-		move || should_exit.store(true, Ordering::Relaxed)
+		let main_thread_tx = main_thread_tx.clone();
+		move || main_thread_tx.send(MainThreadSignal::Shutdown)
 	});
 
+	let spawn_device_thread = |device: Box<dyn device::Device>|
+	{
+		smol::unblock(
+		{
+			let state = Arc::clone(&state);
+			let main_thread_tx = main_thread_tx.clone();
+			let device_thread_rx = device_threads.subscribe();
+			let dbus_thread_tx = dbus_thread_tx.clone();
+			let ww_thread_tx = ww_thread_tx.clone();
+			move || device::thread::DeviceThread::new(
+				device,
+				state,
+				dbus_thread_tx,
+				ww_thread_tx,
+				main_thread_tx)
+				.event_loop(device_thread_rx)
+		})
+	};
+
 	if args.is_present("palette")
 	{
 		let mut current = hsl::HSL { h: 0_f64, s: 1_f64, l: 0.5_f64 };
@@ -128,89 +810,561 @@ fn main()
 	}
 	else
 	{
-		pool.execute(
+		#[cfg(feature = "dbus")]
+		tasks.push(executor.spawn(
 		{
+			let state = Arc::clone(&state);
 			let main_thread_tx = main_thread_tx.clone();
-			move || dbus::Server::new(dbus_thread_rx, main_thread_tx).run()
-		});
+			async move { dbus::Server::new(dbus_thread_rx, main_thread_tx, state).run().await }
+		}));
 
-		pool.execute(
+		// signals for dbus are still sent from all over the driver, so they're
+		// received and dropped until shutdown rather than counted as failures
+		#[cfg(not(feature = "dbus"))]
+		tasks.push(executor.spawn(async move
+		{
+			while let Ok(signal) = dbus_thread_rx.recv().await
+			{
+				if let DBusSignal::Shutdown = signal
+				{
+					break
+				}
+			}
+		}));
+
+		tasks.push(smol::unblock(
 		{
 			let main_thread_tx = main_thread_tx.clone();
-			move || windowsystem::WindowSystem::new().unwrap().run(ww_thread_rx, main_thread_tx)
-		});
+			move ||
+			{
+				let window_system = windowsystem::WindowSystem::new().unwrap_or_else(|error|
+				{
+					warn!("{}, so profiles won't switch by window and macros can't press keys", error);
+					Box::new(windowsystem::NullWindowSystem)
+				});
+
+				window_system.run(ww_thread_rx, main_thread_tx)
+			}
+		}));
 
-		pool.execute(
+		#[cfg(feature = "media")]
+		tasks.push(smol::unblock(
 		{
+			let state = Arc::clone(&state);
 			let main_thread_tx = main_thread_tx.clone();
-			move || media::MediaWatcher::new().unwrap().run(media_watcher_rx, main_thread_tx)
-		});
+			move || media::MediaWatcher::new(state).unwrap().run(media_watcher_rx, main_thread_tx)
+		}));
 
-		for device in devices
+		#[cfg(not(feature = "media"))]
+		drop(media_watcher_rx);
+
+		tasks.push(executor.spawn(
+		{
+			let state = Arc::clone(&state);
+			async move { stats::KeyStatistics::new(state).run(key_statistics_rx).await }
+		}));
+
+		#[cfg(feature = "dbus")]
+		tasks.push(executor.spawn(
+		{
+			let main_thread_tx = main_thread_tx.clone();
+			async move
+			{
+				match notifications::NotificationWatcher::new()
+				{
+					Ok(mut watcher) => watcher.run(notification_watcher_rx, main_thread_tx).await,
+					Err(error) => warn!("unable to watch for notifications: {}", error)
+				}
+			}
+		}));
+
+		#[cfg(not(feature = "dbus"))]
+		drop(notification_watcher_rx);
+
+		let http_options = state.config.read().unwrap().http.clone();
+
+		#[cfg(feature = "http")]
+		match http_options
 		{
-			pool.execute(
+			Some(options) => tasks.push(executor.spawn(
 			{
 				let state = Arc::clone(&state);
 				let main_thread_tx = main_thread_tx.clone();
-				let device_thread_rx = device_thread_rx.clone();
-				let dbus_thread_tx = dbus_thread_tx.clone();
-				let ww_thread_tx = ww_thread_tx.clone();
-				move || device::thread::DeviceThread::new(
-					device,
-					state,
-					dbus_thread_tx,
-					ww_thread_tx,
-					main_thread_tx)
-					.event_loop(device_thread_rx)
-			});
+				async move
+				{
+					match http::HttpServer::new(options.port, state, main_thread_tx)
+					{
+						Ok(mut server) => server.run(http_rx).await,
+						Err(error) => warn!("unable to start the http server: {}", error)
+					}
+				}
+			})),
+			None => drop(http_rx)
+		}
+
+		#[cfg(not(feature = "http"))]
+		if http_options.is_some()
+		{
+			warn!("the config has an http section, but the driver was built without the http feature");
+		}
+
+		match state.config.read().unwrap().command_pipe
+		{
+			true => tasks.push(executor.spawn(
+			{
+				let state = Arc::clone(&state);
+				let main_thread_tx = main_thread_tx.clone();
+				async move
+				{
+					match command_pipe::CommandPipe::new(state, main_thread_tx)
+					{
+						Ok(mut pipe) => pipe.run(command_pipe_rx).await,
+						Err(error) => warn!("unable to read commands from a pipe: {}", error)
+					}
+				}
+			})),
+			false => drop(command_pipe_rx)
+		}
+
+		if let Some(log) = tui_log
+		{
+			tasks.push(smol::unblock(
+			{
+				let state = Arc::clone(&state);
+				let main_thread_tx = main_thread_tx.clone();
+				let should_exit = should_exit.clone();
+				move || tui::Tui::new(state, log, main_thread_tx).run(should_exit)
+			}));
+		}
+
+		for device in devices
+		{
+			tasks.push(spawn_device_thread(device));
 		}
 	}
 
 	info!("ready!");
+	daemon::notify_systemd("READY=1");
 	trace!("startup complete, now in main event loop");
 
 	let mut last_active_window = None;
 
+	// the profile last applied, as profile changes are signalled for every
+	// window change. Profiles only set game mode (and run hooks) when they
+	// become active, so game mode can still be toggled by hand whilst they are
+	let mut last_profile = String::new();
+
+	let top_override = |state: &SharedState| state.profile_overrides
+		.read()
+		.unwrap()
+		.last()
+		.map(|profile_override| profile_override.name.clone());
+
+	let mut last_state_save = Instant::now();
+
+	// macros started over dbus (or http), by name
+	let mut remote_macros: HashMap<String, macros::MacroState> = HashMap::new();
+
+	// switching windows counts as the user having seen their notifications
+	let clear_pending_notifications = ||
+	{
+		if state.pending_notifications.swap(0, Ordering::Relaxed) > 0
+		{
+			device_thread_tx.send(DeviceSignal::NotificationsChanged);
+		}
+	};
+
+	// lights the keys of every flash matching a new notification, as client
+	// colors that expire on their own
+	let flash_notification = |notification: &notifications::Notification|
+	{
+		let config = state.config.read().unwrap();
+
+		for flash in config.notification_flashes.iter().filter(|flash| flash.matches(notification))
+		{
+			debug!("flashing keys for the notification from {}", notification.application);
+
+			device_thread_tx.send(DeviceSignal::SetKeyColors(device::client_colors::KeyColorUpdate
+			{
+				client: "notifications".into(),
+				colors: flash.scancodes(&config.keygroups)
+					.into_iter()
+					.map(|scancode| (scancode, flash.color))
+					.collect(),
+				replace: false,
+				ttl: Some(flash.duration)
+			}));
+		}
+	};
+
 	while !should_exit.load(Ordering::Relaxed)
 	{
-		thread::sleep(Duration::from_millis(10));
+		// devices are told about these once the playlist has caught up with
+		// them, so they don't briefly show the wrong theme
+		let mut config_reloaded = false;
+		let mut profile_changed = false;
+		let mut playlist_changed = false;
+
+		let last_top_override = top_override(&state);
 
-		if let Ok(notify::DebouncedEvent::Create(path))
-			| Ok(notify::DebouncedEvent::NoticeWrite(path)) = config_watcher_rx.try_recv()
+		if last_state_save.elapsed() >= Duration::from_secs(persistence::StateStore::SAVE_INTERVAL)
 		{
-			if path.file_name() == Some(Configuration::config_filename().as_ref())
+			state.persisted.save();
+			last_state_save = Instant::now();
+		}
+
+		// sleeps until the next signal, or until one of the timers below is
+		// due, running the tasks on this thread in the meantime
+		let deadline = [
+			Some(last_state_save + Duration::from_secs(persistence::StateStore::SAVE_INTERVAL)),
+			Some(last_schedule_check + SCHEDULE_CHECK_INTERVAL),
+			Some(last_night_mode_check.map_or_else(Instant::now, |checked| checked + NIGHT_MODE_CHECK_INTERVAL)),
+			playlists.next_update(),
+			state.profile_overrides
+				.read()
+				.unwrap()
+				.iter()
+				.filter_map(|profile_override| profile_override.expires)
+				.min()
+		];
+		let deadline = deadline.iter().flatten().min().copied().unwrap_or_else(Instant::now);
+
+		match smol::block_on(executor.run(channels::recv_until(&main_thread_rx, deadline)))
+		{
+			Ok(MainThreadSignal::Shutdown) => should_exit.store(true, Ordering::Relaxed),
+			Ok(MainThreadSignal::KeyCountingChanged) =>
 			{
-				info!("configuration file has been changed, will reload");
+				key_statistics_tx.send(stats::KeyStatisticsSignal::SettingsChanged);
+			},
+			Ok(MainThreadSignal::ConfigFileChanged(path)) =>
+			{
+				let file_name = path.file_name();
+
+				// an include folder made whilst running needs watching from now on
+				let new_include_folder = config::includes::folders().contains(&path) && path.is_dir();
 
-				match Configuration::load()
+				if new_include_folder
 				{
-					Ok(new_config) =>
+					if let Err(error) = config_watcher.watch(&path, notify::RecursiveMode::NonRecursive)
 					{
-						info!("new config loaded OK, notifying devices");
-						*(state.config.write().unwrap()) = new_config;
-						device_thread_tx.send(DeviceSignal::ConfigurationReloaded);
-						main_thread_tx.send(MainThreadSignal::ActiveWindowChanged(
-							last_active_window.clone()));
-					},
-					Err(config_error) => error!(
-						"changed configuration cannot be loaded: {}",
-						&config_error)
+						warn!("unable to watch {} for changes: {}", path.display(), error);
+					}
 				}
-			}
-		}
 
-		match main_thread_rx.try_recv()
-		{
-			Ok(MainThreadSignal::RunMacroInPool(closure)) => pool.execute(closure),
+				if file_name == Some(Configuration::config_filename().as_ref())
+					|| file_name == Some(config::LayoutOverrides::filename().as_ref())
+					|| config::includes::is_included_file(&path)
+					|| new_include_folder
+				{
+					info!("configuration file has been changed, will reload");
+
+					match Configuration::load()
+					{
+						Ok(new_config) =>
+						{
+							info!("new config loaded OK, notifying devices");
+							*(state.config.write().unwrap()) = new_config;
+							state.config_error.store(false, Ordering::Relaxed);
+							config_reloaded = true;
+							key_statistics_tx.send(stats::KeyStatisticsSignal::SettingsChanged);
+							main_thread_tx.send(MainThreadSignal::ActiveWindowChanged(
+								last_active_window.clone()));
+						},
+						Err(config_error) =>
+						{
+							error!("changed configuration cannot be loaded: {}", &config_error);
+							state.config_error.store(true, Ordering::Relaxed);
+						}
+					}
+				}
+			},
+			Ok(MainThreadSignal::DeviceNodeChanged(path)) =>
+			{
+				let is_hidraw = path
+					.file_name()
+					.and_then(|name| name.to_str())
+					.map(|name| name.starts_with("hidraw"))
+					.unwrap_or(false);
+
+				// real keyboards are left alone whilst simulating one
+				if is_hidraw && !simulate
+				{
+					trace!("{} changed, looking for devices that can now be opened", path.display());
+
+					match hidapi.refresh_devices()
+					{
+						Ok(()) => device::find_devices(&hidapi, hidraw_helper.as_deref(), capture.as_ref(), &mut opened_devices)
+							.into_iter()
+							.for_each(|device|
+							{
+								state.driver_stats.reconnected();
+								tasks.push(spawn_device_thread(device));
+							}),
+						Err(error) => warn!("unable to refresh the device list: {}", error)
+					}
+				}
+			},
 			Ok(MainThreadSignal::MediaStateChanged(new)) =>
 			{
 				*state.media_state.write().unwrap() = new;
 				device_thread_tx.send(DeviceSignal::MediaStateChanged);
+
+				// profiles can have media player conditions, but only switch if the
+				// matched profile actually changes, as switching stops running macros
+				let config = state.config.read().unwrap();
+				let media_state = state.media_state.read().unwrap();
+				let (name, profile) = config.profile_for_active_window(
+					&last_active_window,
+					&media_state);
+
+				let profile_fixed = state.profile_locked.load(Ordering::Relaxed)
+					|| !state.profile_overrides.read().unwrap().is_empty();
+
+				if name != *state.active_profile_name.read().unwrap() && !profile_fixed
+				{
+					info!("media state has changed, applying profile: {}", &name);
+					*(state.active_profile.write().unwrap()) = profile.clone();
+					*state.active_profile_name.write().unwrap() = name.to_string();
+					profile_changed = true;
+				}
+			},
+			Ok(MainThreadSignal::PaletteChanged(palette)) =>
+			{
+				*state.palette.write().unwrap() = palette;
+				device_thread_tx.send(DeviceSignal::PaletteChanged);
+			},
+			Ok(MainThreadSignal::PinMediaPlayer(pattern)) =>
+			{
+				info!("pinned media player changed to: {:?}", &pattern);
+				*state.pinned_player.write().unwrap() = pattern;
+			},
+			Ok(MainThreadSignal::SetGameMode(command)) =>
+			{
+				device_thread_tx.send(DeviceSignal::SetGameMode(command));
+			},
+			Ok(MainThreadSignal::SetMode(mode)) =>
+			{
+				device_thread_tx.send(DeviceSignal::SetMode(mode));
+			},
+			Ok(MainThreadSignal::SetKeyColors(update)) =>
+			{
+				device_thread_tx.send(DeviceSignal::SetKeyColors(update));
+			},
+			Ok(MainThreadSignal::SetKeyColorsNow(update, drawn)) =>
+			{
+				device_thread_tx.send(DeviceSignal::SetKeyColorsNow(update, drawn));
+			},
+			Ok(MainThreadSignal::ClearKeyColors(client)) =>
+			{
+				device_thread_tx.send(DeviceSignal::ClearKeyColors(client));
+			},
+			Ok(MainThreadSignal::SetLowPowerMode(enabled)) =>
+			{
+				if state.low_power.swap(enabled, Ordering::Relaxed) != enabled
+				{
+					info!("low power mode {}", if enabled { "enabled" } else { "disabled" });
+					device_thread_tx.send(DeviceSignal::LowPowerChanged);
+					dbus_thread_tx.send(DBusSignal::LowPowerModeChanged(enabled));
+				}
+			},
+			Ok(MainThreadSignal::SetFocusMode(enabled)) =>
+			{
+				if state.focus_mode.swap(enabled, Ordering::Relaxed) != enabled
+				{
+					info!("focus mode {}", if enabled { "enabled" } else { "disabled" });
+					device_thread_tx.send(DeviceSignal::FocusModeChanged);
+					dbus_thread_tx.send(DBusSignal::FocusModeChanged(enabled));
+				}
+			},
+			Ok(MainThreadSignal::EnableSubsystem(subsystem, enabled)) =>
+			{
+				if state.subsystem_enabled(subsystem) != enabled
+				{
+					info!("{} subsystem {}", subsystem.name(), if enabled { "enabled" } else { "disabled" });
+
+					state.persisted.update(|persisted| match enabled
+					{
+						true => persisted.disabled_subsystems.remove(&subsystem),
+						false => persisted.disabled_subsystems.insert(subsystem)
+					});
+
+					set_subsystem_enabled(subsystem, enabled);
+				}
+			},
+			// device threads pick this up with the rest of the logo's status
+			Ok(MainThreadSignal::SetLogoColor(color)) =>
+			{
+				*state.logo_color.write().unwrap() = color;
+			},
+			Ok(MainThreadSignal::SetEffect(effect)) =>
+			{
+				info!("effect override changed to: {:?}", &effect);
+				*state.effect.write().unwrap() = effect;
+				device_thread_tx.send(DeviceSignal::EffectChanged);
+			},
+			// read by each macro as it starts its next repetition
+			Ok(MainThreadSignal::SetMacroTimingScale(scale)) =>
+			{
+				info!("macro timing scale changed to: {:?}", &scale);
+				*state.macro_timing_scale.write().unwrap() = scale;
+			},
+			Ok(MainThreadSignal::Playlist(command)) =>
+			{
+				playlist_changed = playlists.command(command);
+			},
+			Ok(MainThreadSignal::SwitchProfile(name)) =>
+			{
+				let config = state.config.read().unwrap();
+
+				match config.profiles.get(&name)
+				{
+					// kept until the active window next changes
+					Some(profile) =>
+					{
+						info!("switching to profile: {}", &name);
+						*(state.active_profile.write().unwrap()) = profile.clone();
+						*state.active_profile_name.write().unwrap() = name;
+						profile_changed = true;
+					},
+					None => warn!("can't switch to profile {}, it doesn't exist", &name)
+				}
+			},
+			Ok(MainThreadSignal::RunMacro(name)) =>
+			{
+				let signalled = remote_macros
+					.get(&name)
+					.map(macros::signal_running)
+					.unwrap_or(false);
+
+				let macro_ = state.config
+					.read()
+					.unwrap()
+					.macros
+					.as_ref()
+					.and_then(|macros| macros.get(&name))
+					.cloned();
+
+				match (signalled, macro_)
+				{
+					(true, _) => (),
+					(false, Some(macro_)) =>
+					{
+						let macro_state = macros::start(
+							macro_,
+							format!("remote ({})", name),
+							ww_thread_tx.clone(),
+							dbus_thread_tx.clone(),
+							main_thread_tx.clone(),
+							Arc::clone(&state));
+
+						remote_macros.insert(name, macro_state);
+					},
+					(false, None) => warn!("can't run macro {}, it doesn't exist", &name)
+				}
+			},
+			Ok(MainThreadSignal::PushProfile(name, ttl)) =>
+			{
+				info!("pushing profile override: {}", &name);
+				state.profile_overrides.write().unwrap().push(ProfileOverride
+				{
+					name,
+					expires: ttl.map(|ttl| Instant::now() + ttl)
+				});
+			},
+			Ok(MainThreadSignal::PopProfile) =>
+			{
+				if let Some(profile_override) = state.profile_overrides.write().unwrap().pop()
+				{
+					info!("popped profile override: {}", &profile_override.name);
+				}
+			},
+			Ok(MainThreadSignal::SetProfileLock(locked)) =>
+			{
+				info!("profile {} {}", state.active_profile_name.read().unwrap(), if locked { "locked" } else { "unlocked" });
+				state.profile_locked.store(locked, Ordering::Relaxed);
+
+				// catch up with any window changes whilst it was locked
+				if !locked
+				{
+					main_thread_tx.send(MainThreadSignal::ActiveWindowChanged(last_active_window.clone()));
+				}
+			},
+			Ok(MainThreadSignal::CycleProfiles(names)) =>
+			{
+				let current = state.active_profile_name.read().unwrap().clone();
+				let pinned = state.profile_locked.load(Ordering::Relaxed);
+
+				let next = match names.iter().position(|name| *name == current).filter(|_index| pinned)
+				{
+					Some(index) => names.get(index + 1),
+					None => names.first()
+				};
+
+				let config = state.config.read().unwrap();
+
+				match next.map(|name| (name, config.profiles.get(name)))
+				{
+					Some((name, Some(profile))) =>
+					{
+						info!("cycling to profile: {}, pinned until it's cycled again", name);
+						*(state.active_profile.write().unwrap()) = profile.clone();
+						*state.active_profile_name.write().unwrap() = name.clone();
+						state.profile_locked.store(true, Ordering::Relaxed);
+						profile_changed = true;
+					},
+					Some((name, None)) => warn!("can't cycle to profile {}, it doesn't exist", name),
+					None =>
+					{
+						info!("cycled past the last profile, following the active window again");
+						main_thread_tx.send(MainThreadSignal::SetProfileLock(false));
+					}
+				}
+			},
+			Ok(MainThreadSignal::NotificationReceived(notification)) =>
+			{
+				state.pending_notifications.fetch_add(1, Ordering::Relaxed);
+				device_thread_tx.send(DeviceSignal::NotificationsChanged);
+				flash_notification(&notification);
+			},
+			Ok(MainThreadSignal::FlashNotification(notification)) => flash_notification(&notification),
+			Ok(MainThreadSignal::Hook(event)) =>
+			{
+				debug!(target: tui::EVENTS, "{:?}", &event);
+				hooks::run(&state.config.read().unwrap().hooks, &event);
+			},
+			Ok(MainThreadSignal::NotificationDismissed) =>
+			{
+				let pending = state.pending_notifications.load(Ordering::Relaxed);
+
+				if pending > 0
+				{
+					state.pending_notifications.store(pending - 1, Ordering::Relaxed);
+					device_thread_tx.send(DeviceSignal::NotificationsChanged);
+				}
+			},
+			Ok(MainThreadSignal::ActiveWindowChanged(active_window))
+				if state.profile_locked.load(Ordering::Relaxed)
+					|| !state.profile_overrides.read().unwrap().is_empty() =>
+			{
+				// the locked (or overriding) profile stays, but is refreshed in case the config was reloaded
+				if let Some(profile) = state.config.read().unwrap().profiles.get(&*state.active_profile_name.read().unwrap())
+				{
+					*(state.active_profile.write().unwrap()) = profile.clone();
+				}
+
+				if active_window != last_active_window
+				{
+					clear_pending_notifications();
+				}
+
+				*state.active_window.write().unwrap() = active_window.clone();
+				last_active_window = active_window;
 			},
 			Ok(MainThreadSignal::ActiveWindowChanged(active_window)) =>
 			{
 				let config = state.config.read().unwrap();
-				let (name, profile) = config.profile_for_active_window(&active_window);
+				let media_state = state.media_state.read().unwrap();
+				let (name, profile) = config.profile_for_active_window(&active_window, &media_state);
 
 				info!("active window has changed\n\twindow: {}\n\tapplying profile: {}",
 					  active_window
@@ -220,20 +1374,171 @@ fn main()
 					  &name);
 
 				*(state.active_profile.write().unwrap()) = profile.clone();
-				device_thread_tx.send(DeviceSignal::ProfileChanged);
+				*state.active_profile_name.write().unwrap() = name.to_string();
+				profile_changed = true;
+
+				if active_window != last_active_window
+				{
+					clear_pending_notifications();
+				}
+
+				*state.active_window.write().unwrap() = active_window.clone();
 				last_active_window = active_window;
 			},
 			Err(_) => ()
 		}
+
+		playlist_changed |= playlists.set_enabled(state.subsystem_enabled(Subsystem::Scheduler));
+		playlist_changed |= playlists.set_playlist(
+			&state.config.read().unwrap(),
+			state.active_profile.read().unwrap().playlist.as_deref(),
+			config_reloaded);
+		playlist_changed |= playlists.update();
+
+		if playlist_changed
+		{
+			*state.playlist.write().unwrap() = playlists.status();
+		}
+
+		// expired overrides are dropped wherever they are in the stack, but only
+		// a change to the top one changes the profile
+		let now = Instant::now();
+		state.profile_overrides
+			.write()
+			.unwrap()
+			.retain(|profile_override| profile_override.expires.map(|expires| expires > now).unwrap_or(true));
+
+		let new_top_override = top_override(&state);
+
+		if new_top_override != last_top_override
+		{
+			match new_top_override
+			{
+				Some(name) =>
+				{
+					let config = state.config.read().unwrap();
+
+					match config.profiles.get(&name)
+					{
+						Some(profile) =>
+						{
+							info!("applying profile override: {}", &name);
+							*(state.active_profile.write().unwrap()) = profile.clone();
+							*state.active_profile_name.write().unwrap() = name;
+							profile_changed = true;
+						},
+						None => warn!("can't apply profile override {}, it doesn't exist", &name)
+					}
+				},
+				// back to the active window's profile
+				None => main_thread_tx.send(MainThreadSignal::ActiveWindowChanged(last_active_window.clone()))
+			}
+		}
+
+		if last_schedule_check.elapsed() >= SCHEDULE_CHECK_INTERVAL
+		{
+			last_schedule_check = Instant::now();
+
+			let config = state.config.read().unwrap();
+			let now_scheduled = config.scheduled_profiles(config::LocalTime::now());
+
+			// only switch if the matched profile actually changes, like media
+			// player conditions, and leave profiles switched to by hand alone
+			// until a schedule does change
+			if now_scheduled != scheduled_profiles
+			{
+				scheduled_profiles = now_scheduled;
+
+				let (name, profile) = config.profile_for_active_window(
+					&last_active_window,
+					&state.media_state.read().unwrap());
+
+				let profile_fixed = state.profile_locked.load(Ordering::Relaxed)
+					|| !state.profile_overrides.read().unwrap().is_empty();
+
+				if name != *state.active_profile_name.read().unwrap() && !profile_fixed
+				{
+					info!("a profile's schedule has started or ended, applying profile: {}", &name);
+					*(state.active_profile.write().unwrap()) = profile.clone();
+					*state.active_profile_name.write().unwrap() = name.to_string();
+					profile_changed = true;
+				}
+			}
+		}
+
+		if profile_changed && *state.active_profile_name.read().unwrap() != last_profile
+		{
+			let profile = state.active_profile_name.read().unwrap().clone();
+			let previous_profile = std::mem::replace(&mut last_profile, profile.clone());
+			hooks::run(&state.config.read().unwrap().hooks, &hooks::HookEvent::ProfileChanged { profile, previous_profile });
+
+			if let Some(enabled) = state.active_profile.read().unwrap().game_mode
+			{
+				device_thread_tx.send(DeviceSignal::SetGameMode(match enabled
+				{
+					true => macros::GameModeCommand::On,
+					false => macros::GameModeCommand::Off
+				}));
+			}
+		}
+
+		if config_reloaded || last_night_mode_check.map(|checked| checked.elapsed() >= NIGHT_MODE_CHECK_INTERVAL).unwrap_or(true)
+		{
+			last_night_mode_check = Some(Instant::now());
+
+			let temperature = state.config
+				.read()
+				.unwrap()
+				.night_mode
+				.as_ref()
+				.and_then(scheduler::night_temperature);
+
+			if temperature != *state.color_temperature.read().unwrap()
+			{
+				debug!("night mode color temperature changed to {:?}", temperature);
+				*state.color_temperature.write().unwrap() = temperature;
+				device_thread_tx.send(DeviceSignal::NightModeChanged);
+			}
+		}
+
+		// reloads and profile changes apply the whole profile anyway
+		match (config_reloaded, profile_changed, playlist_changed)
+		{
+			(true, _, _) => device_thread_tx.send(DeviceSignal::ConfigurationReloaded),
+			(false, true, _) => device_thread_tx.send(DeviceSignal::ProfileChanged),
+			(false, false, true) => device_thread_tx.send(DeviceSignal::PlaylistChanged),
+			_ => ()
+		}
 	}
 
-	trace!("notifying threads of shutdown");
+	trace!("notifying tasks of shutdown");
+
+	daemon::notify_systemd("STOPPING=1");
+
+	// threads that have already stopped don't need telling
+	device_thread_tx.send_if_running(DeviceSignal::Shutdown);
+	ww_thread_tx.send_if_running(windowsystem::WindowSystemSignal::Shutdown);
+	dbus_thread_tx.send_if_running(DBusSignal::Shutdown);
+	media_watcher_tx.send_if_running(media::MediaWatcherSignal::Shutdown);
+	key_statistics_tx.send_if_running(stats::KeyStatisticsSignal::Shutdown);
+	notification_watcher_tx.send_if_running(notifications::NotificationWatcherSignal::Shutdown);
+	#[cfg(feature = "http")]
+	http_tx.send_if_running(http::HttpSignal::Shutdown);
+	command_pipe_tx.send_if_running(command_pipe::CommandPipeSignal::Shutdown);
+
+	// saved first, in case the driver is killed whilst the tasks stop
+	state.persisted.save();
+
+	smol::block_on(executor.run(async move
+	{
+		for task in tasks
+		{
+			task.await;
+		}
+	}));
 
-	device_thread_tx.send(DeviceSignal::Shutdown);
-	ww_thread_tx.send(windowsystem::WindowSystemSignal::Shutdown);
-	dbus_thread_tx.send(dbus::DBusSignal::Shutdown);
-	media_watcher_tx.send(media::MediaWatcherSignal::Shutdown);
-	pool.join();
+	// only writes anything if a keyboard changed something whilst stopping
+	state.persisted.save();
 
-	trace!("threadpool shutdown");
+	trace!("all tasks finished");
 }
@@ -0,0 +1,201 @@
+use std::convert::TryFrom;
+
+use serde::{Serialize, Deserialize};
+
+#[cfg(feature = "media")]
+use log::debug;
+
+#[cfg(feature = "pulse")]
+mod pulse;
+#[cfg(feature = "media")]
+mod pipewire;
+#[cfg(feature = "media")]
+mod watcher;
+
+#[cfg(feature = "media")]
+pub use watcher::MediaWatcher;
+
+#[cfg(feature = "dbus")]
+const MPRIS_PATH: &str = "/org/mpris/MediaPlayer2";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlayerStatus
+{
+	Playing,
+	Paused,
+	NoMedia
+}
+
+impl TryFrom<String> for PlayerStatus
+{
+	type Error = String;
+
+	fn try_from(status: String) -> Result<Self, Self::Error>
+	{
+		match status.as_str()
+		{
+			"Playing" => Ok(Self::Playing),
+			"Paused" => Ok(Self::Paused),
+			"Stopped" => Ok(Self::NoMedia),
+			_ => Err(format!("Unknown PlayerStatus value '{}'", status))
+		}
+	}
+}
+
+/// Commands that can be sent to the selected mpris player
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaCommand
+{
+	PlayPause,
+	Play,
+	Pause,
+	Stop,
+	Next,
+	Previous
+}
+
+impl MediaCommand
+{
+	/// Builds the org.mpris.MediaPlayer2.Player method call for this command
+	#[cfg(feature = "dbus")]
+	pub fn message(&self, player_service: &str) -> Result<zbus::Message, zbus::MessageError>
+	{
+		let method = match self
+		{
+			Self::PlayPause => "PlayPause",
+			Self::Play => "Play",
+			Self::Pause => "Pause",
+			Self::Stop => "Stop",
+			Self::Next => "Next",
+			Self::Previous => "Previous"
+		};
+
+		zbus::Message::method(
+			None,
+			Some(player_service),
+			MPRIS_PATH,
+			Some("org.mpris.MediaPlayer2.Player"),
+			method,
+			&())
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaState
+{
+	pub muted: bool,
+	/// average volume of the default sink as a percentage (can exceed 100)
+	pub volume: u8,
+	pub player_status: PlayerStatus,
+	/// the dbus name of the player the status was read from
+	pub player_service: Option<String>,
+	/// the mpris `Identity` of the player the status was read from
+	pub player: Option<String>,
+	pub artist: Option<String>,
+	pub title: Option<String>,
+	/// the applications currently playing audio, oldest stream first
+	pub audio_streams: Vec<AudioStream>
+}
+
+impl Default for MediaState
+{
+	fn default() -> Self
+	{
+		Self
+		{
+			muted: false,
+			volume: 0,
+			player_status: PlayerStatus::NoMedia,
+			player_service: None,
+			player: None,
+			artist: None,
+			title: None,
+			audio_streams: Vec::new()
+		}
+	}
+}
+
+#[cfg_attr(not(feature = "media"), allow(dead_code))]
+pub enum MediaWatcherSignal
+{
+	Shutdown,
+	/// start or stop watching media players and the sink
+	SetWatching(bool)
+}
+
+/// Mute and volume state of the default audio sink
+#[cfg(feature = "media")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SinkState
+{
+	pub muted: bool,
+	/// average volume as a percentage (can exceed 100)
+	pub volume: u8
+}
+
+/// An application playing audio through the sound server
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AudioStream
+{
+	/// the name the application gives its streams, e.g. "Firefox"
+	pub application: String,
+	/// the name of the application's executable, if known
+	pub binary: Option<String>
+}
+
+/// A sound server the default sink state can be read from
+#[cfg(feature = "media")]
+pub trait MediaBackend
+{
+	fn name(&self) -> &'static str;
+
+	/// Polls the sound server for the default sink state. Returns None if
+	/// there is no new state available yet.
+	fn sink_state(&mut self) -> Option<SinkState>;
+
+	/// Polls the sound server for the applications playing audio. Returns None
+	/// if there's no new list available yet, or the backend can't list them.
+	fn audio_streams(&mut self) -> Option<Vec<AudioStream>>
+	{
+		None
+	}
+
+	fn shutdown(&mut self)
+	{
+	}
+}
+
+#[cfg(feature = "media")]
+impl dyn MediaBackend
+{
+	/// Connects to the first available sound server, trying pulse (including
+	/// pipewire-pulse) first, then native pipewire
+	pub fn new() -> Result<Box<dyn MediaBackend>, String>
+	{
+		Self::pulse()
+			.or_else(|pulse_error|
+			{
+				debug!("pulse backend unavailable ({}), trying pipewire", pulse_error);
+
+				pipewire::PipeWireBackend::new()
+					.map(|backend| Box::new(backend) as Box<dyn MediaBackend>)
+					.map_err(|pipewire_error| format!(
+						"no sound server available (pulse: {}, pipewire: {})",
+						pulse_error,
+						pipewire_error))
+			})
+	}
+
+	#[cfg(feature = "pulse")]
+	fn pulse() -> Result<Box<dyn MediaBackend>, String>
+	{
+		pulse::PulseBackend::new().map(|backend| Box::new(backend) as Box<dyn MediaBackend>)
+	}
+
+	#[cfg(not(feature = "pulse"))]
+	fn pulse() -> Result<Box<dyn MediaBackend>, String>
+	{
+		Err("the driver was built without the pulse feature".into())
+	}
+}
@@ -0,0 +1,72 @@
+use std::process::{Command, Stdio};
+
+use super::{MediaBackend, SinkState};
+
+/// Reads the default sink state from pipewire via wireplumber's `wpctl`, for
+/// systems without the pulse compatibility daemon
+pub struct PipeWireBackend;
+
+impl PipeWireBackend
+{
+	const DEFAULT_SINK: &'static str = "@DEFAULT_AUDIO_SINK@";
+
+	pub fn new() -> Result<Self, String>
+	{
+		let backend = Self;
+
+		// make sure wpctl is installed and can actually reach pipewire
+		backend.read_sink_state()?;
+		Ok(backend)
+	}
+
+	fn read_sink_state(&self) -> Result<SinkState, String>
+	{
+		let output = Command::new("wpctl")
+			.arg("get-volume")
+			.arg(Self::DEFAULT_SINK)
+			.stdin(Stdio::null())
+			.stderr(Stdio::null())
+			.output()
+			.map_err(|e| format!("unable to run wpctl: {}", e))?;
+
+		if !output.status.success()
+		{
+			return Err(format!("wpctl exited with {}", output.status))
+		}
+
+		Self::parse_volume(&String::from_utf8_lossy(&output.stdout))
+	}
+
+	/// Parses the output of `wpctl get-volume`, which looks like
+	/// `Volume: 0.45` or `Volume: 0.45 [MUTED]`
+	fn parse_volume(output: &str) -> Result<SinkState, String>
+	{
+		let volume = output
+			.trim()
+			.strip_prefix("Volume:")
+			.and_then(|rest| rest.split_whitespace().next())
+			.and_then(|volume| volume.parse::<f64>().ok())
+			.ok_or_else(|| format!("unexpected output from wpctl: {}", output.trim()))?;
+
+		Ok(SinkState
+		{
+			muted: output.contains("[MUTED]"),
+			volume: (volume * 100_f64).round().max(0_f64).min(u8::MAX as f64) as u8
+		})
+	}
+}
+
+impl MediaBackend for PipeWireBackend
+{
+	fn name(&self) -> &'static str
+	{
+		"pipewire"
+	}
+
+	fn sink_state(&mut self) -> Option<SinkState>
+	{
+		self.read_sink_state()
+			.map_err(|error| log::debug!("failed to read pipewire sink state: {}", error))
+			.ok()
+	}
+}
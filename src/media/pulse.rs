@@ -0,0 +1,208 @@
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+use log::{trace, debug};
+use pulse::operation::{Operation, State as OpState};
+use pulse::callbacks::ListResult;
+use pulse::context::introspect::{ServerInfo, SinkInfo, SinkInputInfo};
+use pulse::proplist::properties::{APPLICATION_NAME, APPLICATION_PROCESS_BINARY};
+
+use super::{MediaBackend, SinkState, AudioStream};
+
+enum PulseReply
+{
+	DefaultSinkName(Option<String>),
+	SinkState(SinkState),
+	AudioStreams(Vec<AudioStream>)
+}
+
+/// Reads the default sink state using the PulseAudio client api (which also
+/// works with pipewire-pulse)
+pub struct PulseBackend
+{
+	context: pulse::context::Context,
+	mainloop: pulse::mainloop::standard::Mainloop,
+	introspecter: pulse::context::introspect::Introspector,
+	callback_tx: Sender<PulseReply>,
+	callback_rx: Receiver<PulseReply>,
+	default_sink: Option<String>,
+	// the last list of audio streams received, until it's been polled for
+	audio_streams: Option<Vec<AudioStream>>,
+	server_info_op: Option<Operation<dyn FnMut(&ServerInfo)>>,
+	sink_info_op: Option<Operation<dyn FnMut(ListResult<&SinkInfo>)>>,
+	sink_input_info_op: Option<Operation<dyn FnMut(ListResult<&SinkInputInfo>)>>
+}
+
+impl PulseBackend
+{
+	pub fn new() -> Result<Self, String>
+	{
+		let mainloop = pulse::mainloop::standard::Mainloop::new()
+			.ok_or("failed to allocate pulse mainloop struct")?;
+		let context = pulse::context::Context::new(&mainloop, env!("CARGO_PKG_NAME"))
+			.ok_or("failed to allocate pulse context struct")?;
+		let introspecter = context.introspect();
+		let (callback_tx, callback_rx) = channel();
+
+		let mut backend = Self
+		{
+			context,
+			mainloop,
+			introspecter,
+			callback_tx,
+			callback_rx,
+			default_sink: None,
+			audio_streams: None,
+			server_info_op: None,
+			sink_info_op: None,
+			sink_input_info_op: None
+		};
+
+		backend.connect()?;
+		Ok(backend)
+	}
+
+	/// Attempts to connect (or re-connect) to the pulse daemon indefinitely until
+	/// a ready or error condition is returned
+	fn connect(&mut self) -> Result<(), String>
+	{
+		use pulse::context::{State, FlagSet};
+
+		trace!("connecting to pulse");
+
+		self.context.connect(None, FlagSet::NOFLAGS, None)
+			.map_err(|e| e.to_string().unwrap_or_else(|| "unknown error".to_string()))?;
+
+		loop
+		{
+			self.mainloop.iterate(true);
+			let state = self.context.get_state();
+
+			trace!("waiting for pulse to connect, state = {:?}", &state);
+
+			match state
+			{
+				State::Ready => return Ok(()),
+				State::Failed => return Err("pulse connection failed".to_string()),
+				State::Terminated => return Err("pulse connection terminated".to_string()),
+				_ => ()
+			}
+		}
+	}
+}
+
+impl MediaBackend for PulseBackend
+{
+	fn name(&self) -> &'static str
+	{
+		"pulseaudio"
+	}
+
+	fn sink_state(&mut self) -> Option<SinkState>
+	{
+		let mut sink_state = None;
+
+		loop
+		{
+			// iterate each time because we most likely have both replies
+			// waiting from pulse in the time the thread has been sleeping for
+			self.mainloop.iterate(false);
+
+			match self.callback_rx.try_recv()
+			{
+				Ok(PulseReply::DefaultSinkName(name)) if name != self.default_sink =>
+				{
+					debug!("pulse default sink has changed: {:?} => {:?}", &self.default_sink, &name);
+					self.default_sink = name;
+				},
+				Ok(PulseReply::SinkState(state)) => sink_state = Some(state),
+				Ok(PulseReply::AudioStreams(streams)) => self.audio_streams = Some(streams),
+				Ok(_) => (),
+				Err(_) => break
+			}
+		}
+
+		// make sure we only send another server_info request if we've already
+		// had the result of the last one back so we don't get out-of-order replies
+		// (same for get_sink_info)
+
+		if self.server_info_op.as_ref().map(|op| op.get_state() != OpState::Running).unwrap_or(true)
+		{
+			self.server_info_op = Some(self.introspecter.get_server_info(
+			{
+				let callback_tx = self.callback_tx.clone();
+				move |server_info| callback_tx
+					.send(PulseReply::DefaultSinkName(server_info
+						.default_sink_name
+						.as_deref()
+						.map(|name| name.to_owned())))
+					.unwrap_or(())
+			}));
+		}
+
+		if let Some(ref sink_name) = self.default_sink
+		{
+			if self.sink_info_op.as_ref().map(|op| op.get_state() != OpState::Running).unwrap_or(true)
+			{
+				self.sink_info_op = Some(self.introspecter.get_sink_info_by_name(sink_name,
+				{
+					let callback_tx = self.callback_tx.clone();
+					move |response| if let ListResult::Item(sink_info) = response
+					{
+						let volume = sink_info.volume.avg().0 as u64 * 100
+							/ pulse::volume::Volume::NORMAL.0 as u64;
+
+						callback_tx.send(PulseReply::SinkState(SinkState
+						{
+							muted: sink_info.mute,
+							volume: volume.min(u8::MAX as u64) as u8
+						}));
+					}
+				}));
+			}
+		}
+
+		sink_state
+	}
+
+	/// Lists the applications with a sink input that's actually playing (not
+	/// corked). The replies are received along with the sink state.
+	fn audio_streams(&mut self) -> Option<Vec<AudioStream>>
+	{
+		if self.sink_input_info_op.as_ref().map(|op| op.get_state() != OpState::Running).unwrap_or(true)
+		{
+			self.sink_input_info_op = Some(self.introspecter.get_sink_input_info_list(
+			{
+				let callback_tx = self.callback_tx.clone();
+				let mut streams = Vec::new();
+
+				move |response| match response
+				{
+					ListResult::Item(sink_input_info) if !sink_input_info.corked =>
+					{
+						let proplist = &sink_input_info.proplist;
+
+						if let Some(application) = proplist.get_str(APPLICATION_NAME)
+						{
+							streams.push(AudioStream
+							{
+								application,
+								binary: proplist.get_str(APPLICATION_PROCESS_BINARY)
+							});
+						}
+					},
+					ListResult::End => callback_tx
+						.send(PulseReply::AudioStreams(std::mem::take(&mut streams)))
+						.unwrap_or(()),
+					_ => ()
+				}
+			}));
+		}
+
+		self.audio_streams.take()
+	}
+
+	fn shutdown(&mut self)
+	{
+		self.context.disconnect();
+	}
+}
@@ -0,0 +1,266 @@
+use std::sync::Arc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::time::Duration;
+use std::convert::TryFrom;
+
+use zbus::dbus_proxy;
+use log::{trace, debug, info, warn};
+use smol::channel::Receiver;
+
+use crate::channels::{self, MonitoredSender};
+use crate::{SharedState, MainThreadSignal};
+use super::{MediaBackend, MediaState, MediaWatcherSignal, PlayerStatus, SinkState, MPRIS_PATH};
+
+const MPRIS_PREFIX: &str = "org.mpris.MediaPlayer2.";
+
+#[dbus_proxy(interface = "org.freedesktop.DBus")]
+trait FreeDesktopDBus
+{
+	fn list_names(&self) -> zbus::Result<Vec<String>>;
+}
+
+#[dbus_proxy(interface = "org.mpris.MediaPlayer2")]
+trait MediaPlayer2
+{
+	#[dbus_proxy(property)]
+	fn identity(&self) -> zbus::Result<String>;
+}
+
+#[dbus_proxy(interface = "org.mpris.MediaPlayer2.Player")]
+trait MediaPlayer2Player
+{
+	#[dbus_proxy(property)]
+	fn playback_status(&self) -> zbus::Result<String>;
+
+	#[dbus_proxy(property)]
+	fn metadata(&self) -> zbus::Result<zvariant::OwnedValue>;
+}
+
+/// The parts of an mpris player's state that make up a MediaState
+struct PlayerState
+{
+	status: PlayerStatus,
+	service: Option<String>,
+	identity: Option<String>,
+	artist: Option<String>,
+	title: Option<String>
+}
+
+/// Converts an mpris metadata value into a string. Lists of strings (such as
+/// `xesam:artist`) are joined with commas.
+fn metadata_string(value: &zvariant::Value) -> Option<String>
+{
+	match value
+	{
+		zvariant::Value::Str(string) => Some(string.as_str().to_owned()),
+		zvariant::Value::Value(inner) => metadata_string(inner),
+		zvariant::Value::Array(array) =>
+		{
+			let strings: Vec<String> = array
+				.get()
+				.iter()
+				.filter_map(metadata_string)
+				.collect();
+
+			(!strings.is_empty()).then(|| strings.join(", "))
+		},
+		_ => None
+	}
+}
+pub struct MediaWatcher
+{
+	state: Arc<SharedState>,
+	backend: Option<Box<dyn MediaBackend>>,
+	dbus: zbus::Connection,
+	fd_proxy: FreeDesktopDBusProxy<'static>
+}
+
+impl MediaWatcher
+{
+	pub fn new(state: Arc<SharedState>) -> Result<Self, String>
+	{
+		let dbus = zbus::Connection::new_session().map_err(|e| e.to_string())?;
+		let fd_proxy = FreeDesktopDBusProxy::new(&dbus).map_err(|e| e.to_string())?;
+
+		// carry on without mute/volume state if there's no sound server, as the
+		// mpris player state is still useful on its own
+		let backend = <dyn MediaBackend>::new()
+			.map(|backend|
+			{
+				info!("media watcher using the {} backend", backend.name());
+				backend
+			})
+			.map_err(|error| warn!("mute and volume state unavailable: {}", error))
+			.ok();
+
+		trace!("media watcher starting up, dbus ok");
+
+		Ok(Self
+		{
+			state,
+			backend,
+			dbus,
+			fd_proxy
+		})
+	}
+
+	/// Lists the dbus names of all mpris players along with their identities
+	fn players(&self) -> Result<Vec<(String, Option<String>)>, String>
+	{
+		Ok(self.fd_proxy
+			.list_names()
+			.map_err(|e| e.to_string())?
+			.into_iter()
+			.filter(|service_name| service_name.starts_with(MPRIS_PREFIX))
+			.map(|service_name|
+			{
+				let identity = MediaPlayer2Proxy::new_for(&self.dbus, &service_name, MPRIS_PATH)
+					.and_then(|proxy| proxy.identity())
+					.ok();
+
+				(service_name, identity)
+			})
+			.collect())
+	}
+
+	/// Selects which mpris player to track. A player pinned over dbus takes priority,
+	/// then the `media.players` patterns from the config in order, otherwise the
+	/// first player on the bus is used. Patterns are matched against the player's
+	/// identity, and its dbus name without the org.mpris.MediaPlayer2. prefix.
+	fn select_player(&self) -> Result<(String, Option<String>), String>
+	{
+		let players = self.players()?;
+		let pinned = self.state.pinned_player.read().unwrap().clone();
+		let config = self.state.config.read().unwrap();
+
+		let find_player = |pattern: &regex::Regex| players
+			.iter()
+			.find(|(service_name, identity)|
+				pattern.is_match(service_name.trim_start_matches(MPRIS_PREFIX))
+					|| identity.as_ref().map(|identity| pattern.is_match(identity)).unwrap_or(false))
+			.cloned();
+
+		pinned
+			.iter()
+			.chain(config.media.players.iter())
+			.find_map(find_player)
+			.or_else(|| players.first().cloned())
+			.ok_or_else(|| "no loaded media players found on dbus".to_string())
+	}
+
+	/// Reads the `PlaybackStatus` and `Metadata` properties of the selected player.
+	/// Metadata and identity are optional, as not all players provide them.
+	fn player_state(&self) -> Result<PlayerState, String>
+	{
+		let (player_service, identity) = self.select_player()?;
+
+		let player_proxy = MediaPlayer2PlayerProxy::new_for(&self.dbus, &player_service, MPRIS_PATH)
+			.map_err(|e| e.to_string())?;
+
+		let status = player_proxy
+			.playback_status()
+			.map_err(|e| e.to_string())
+			.and_then(PlayerStatus::try_from)?;
+
+		// metadata is an a{sv} dict, keyed by xesam/mpris names
+		let metadata = player_proxy.metadata().ok();
+		let metadata_value = |key: &str| match metadata.as_deref()
+		{
+			Some(zvariant::Value::Dict(dict)) => dict
+				.get::<str, zvariant::Value>(key)
+				.ok()
+				.flatten()
+				.and_then(metadata_string),
+			_ => None
+		};
+
+		Ok(PlayerState
+		{
+			status,
+			service: Some(player_service),
+			identity,
+			artist: metadata_value("xesam:artist"),
+			title: metadata_value("xesam:title")
+		})
+	}
+
+	/// Runs the main loop for the media watcher, watching for changes to mpris
+	/// player status and metadata, and checking the mute state and volume of the
+	/// current default sink.
+	pub fn run(&mut self, rx: Receiver<MediaWatcherSignal>, tx: MonitoredSender<MainThreadSignal>)
+	{
+		let mut media_state = MediaState::default();
+		let mut watching = true;
+
+		loop
+		{
+			match channels::recv_timeout(&rx, Duration::from_millis(250))
+			{
+				Ok(MediaWatcherSignal::Shutdown)
+					| Err(RecvTimeoutError::Disconnected) => break,
+				Err(RecvTimeoutError::Timeout) => (),
+
+				// nothing is known about media whilst not watching it
+				Ok(MediaWatcherSignal::SetWatching(enabled)) =>
+				{
+					watching = enabled;
+
+					if !watching && media_state != MediaState::default()
+					{
+						media_state = MediaState::default();
+						tx.send(MainThreadSignal::MediaStateChanged(media_state.clone()));
+					}
+				}
+			}
+
+			if !watching
+			{
+				continue
+			}
+
+			let player_state = self.player_state().unwrap_or(PlayerState
+			{
+				status: PlayerStatus::NoMedia,
+				service: None,
+				identity: None,
+				artist: None,
+				title: None
+			});
+
+			// default to the last sink state if the backend hasn't replied in time
+			let sink_state = self.backend
+				.as_mut()
+				.and_then(|backend| backend.sink_state())
+				.unwrap_or(SinkState { muted: media_state.muted, volume: media_state.volume });
+
+			let audio_streams = self.backend
+				.as_mut()
+				.and_then(|backend| backend.audio_streams())
+				.unwrap_or_else(|| media_state.audio_streams.clone());
+
+			let current_state = MediaState
+			{
+				player_status: player_state.status,
+				player_service: player_state.service,
+				player: player_state.identity,
+				artist: player_state.artist,
+				title: player_state.title,
+				muted: sink_state.muted,
+				volume: sink_state.volume,
+				audio_streams
+			};
+
+			if media_state != current_state
+			{
+				debug!("media state has changed: {:?} => {:?}", &media_state, &current_state);
+				media_state = current_state.clone();
+				tx.send(MainThreadSignal::MediaStateChanged(current_state));
+			}
+		}
+
+		if let Some(backend) = self.backend.as_mut()
+		{
+			backend.shutdown();
+		}
+	}
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::fs;
+use std::time::Instant;
+
+/// A snapshot of how busy the system is, see `MetricsSampler`
+#[derive(Clone, Debug, Default)]
+pub struct SystemMetrics
+{
+	/// usage of each cpu core (0.0 - 1.0)
+	pub cpu_cores: Vec<f64>,
+	/// usage across all cores (0.0 - 1.0)
+	pub cpu: f64,
+	/// fraction of memory in use
+	pub memory: f64,
+	/// bytes per second received and sent across all interfaces except loopback
+	pub network: f64,
+	/// fraction of time the busiest disk spent doing I/O
+	pub disk: f64
+}
+
+struct Counters
+{
+	// (busy, total) jiffies for all cores, then each core
+	cpu: Vec<(u64, u64)>,
+	network_bytes: u64,
+	// milliseconds spent doing I/O, for each disk
+	disk_ticks: HashMap<String, u64>,
+	read_at: Instant
+}
+
+/// Reads system metrics from /proc. Most of them are rates, so they're worked
+/// out from the difference between consecutive samples: the first sample only
+/// has the memory usage.
+pub struct MetricsSampler
+{
+	previous: Option<Counters>
+}
+
+impl MetricsSampler
+{
+	pub fn new() -> Self
+	{
+		Self { previous: None }
+	}
+
+	pub fn sample(&mut self) -> SystemMetrics
+	{
+		let counters = Self::read_counters();
+		let mut metrics = SystemMetrics
+		{
+			memory: Self::read_memory().unwrap_or(0_f64),
+			..Default::default()
+		};
+
+		if let Some(previous) = &self.previous
+		{
+			let elapsed = counters.read_at.duration_since(previous.read_at).as_secs_f64();
+
+			let cpu_usage: Vec<f64> = counters.cpu
+				.iter()
+				.zip(previous.cpu.iter())
+				.map(|((busy, total), (previous_busy, previous_total))|
+				{
+					match total.saturating_sub(*previous_total)
+					{
+						0 => 0_f64,
+						total => busy.saturating_sub(*previous_busy) as f64 / total as f64
+					}
+				})
+				.collect();
+
+			if let Some((cpu, cores)) = cpu_usage.split_first()
+			{
+				metrics.cpu = *cpu;
+				metrics.cpu_cores = cores.to_vec();
+			}
+
+			if elapsed > 0_f64
+			{
+				metrics.network = counters.network_bytes
+					.saturating_sub(previous.network_bytes) as f64 / elapsed;
+
+				metrics.disk = counters.disk_ticks
+					.iter()
+					.filter_map(|(disk, ticks)| previous.disk_ticks
+						.get(disk)
+						.map(|previous_ticks| ticks.saturating_sub(*previous_ticks)))
+					.map(|ticks| ticks as f64 / 1000_f64 / elapsed)
+					.fold(0_f64, f64::max)
+					.min(1_f64);
+			}
+		}
+
+		self.previous = Some(counters);
+		metrics
+	}
+
+	fn read_counters() -> Counters
+	{
+		Counters
+		{
+			cpu: Self::read_cpu(),
+			network_bytes: Self::read_network(),
+			disk_ticks: Self::read_disk(),
+			read_at: Instant::now()
+		}
+	}
+
+	/// Reads the `cpu` and `cpuN` lines from /proc/stat
+	fn read_cpu() -> Vec<(u64, u64)>
+	{
+		fs::read_to_string("/proc/stat")
+			.unwrap_or_default()
+			.lines()
+			.filter(|line| line.starts_with("cpu"))
+			.map(|line|
+			{
+				let jiffies: Vec<u64> = line
+					.split_whitespace()
+					.skip(1)
+					.filter_map(|value| value.parse().ok())
+					.collect();
+
+				// idle and iowait are the 4th and 5th columns
+				let total: u64 = jiffies.iter().sum();
+				let idle = jiffies.get(3).copied().unwrap_or(0) + jiffies.get(4).copied().unwrap_or(0);
+				(total - idle, total)
+			})
+			.collect()
+	}
+
+	fn read_memory() -> Option<f64>
+	{
+		let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+		let value = |name: &str| meminfo
+			.lines()
+			.find(|line| line.starts_with(name))
+			.and_then(|line| line.split_whitespace().nth(1))
+			.and_then(|value| value.parse::<f64>().ok());
+
+		let total = value("MemTotal:")?;
+		let available = value("MemAvailable:")?;
+
+		Some(match total > 0_f64
+		{
+			true => 1_f64 - available / total,
+			false => 0_f64
+		})
+	}
+
+	/// Totals the received and sent bytes in /proc/net/dev
+	fn read_network() -> u64
+	{
+		fs::read_to_string("/proc/net/dev")
+			.unwrap_or_default()
+			.lines()
+			.skip(2)
+			.filter_map(|line| line.split_once(':'))
+			.filter(|(interface, _counters)| interface.trim() != "lo")
+			.map(|(_interface, counters)|
+			{
+				let counters: Vec<u64> = counters
+					.split_whitespace()
+					.filter_map(|value| value.parse().ok())
+					.collect();
+
+				counters.get(0).copied().unwrap_or(0) + counters.get(8).copied().unwrap_or(0)
+			})
+			.sum()
+	}
+
+	/// Reads the time spent doing I/O for each disk in /proc/diskstats
+	fn read_disk() -> HashMap<String, u64>
+	{
+		fs::read_to_string("/proc/diskstats")
+			.unwrap_or_default()
+			.lines()
+			.filter_map(|line|
+			{
+				let columns: Vec<&str> = line.split_whitespace().collect();
+				let name = columns.get(2)?;
+
+				if name.starts_with("loop") || name.starts_with("ram")
+				{
+					return None
+				}
+
+				columns
+					.get(12)
+					.and_then(|ticks| ticks.parse().ok())
+					.map(|ticks| (name.to_string(), ticks))
+			})
+			.collect()
+	}
+}
@@ -0,0 +1,26 @@
+#[cfg(feature = "dbus")]
+mod watcher;
+
+#[cfg(feature = "dbus")]
+pub use watcher::NotificationWatcher;
+
+pub enum NotificationWatcherSignal
+{
+	Shutdown
+}
+
+/// A new desktop notification, or one sent straight to the driver over dbus
+#[derive(Debug, Clone)]
+pub struct Notification
+{
+	pub application: String,
+	pub summary: String,
+	pub body: String
+}
+
+impl Notification
+{
+	/// the application the driver's own notifications are sent as, which aren't
+	/// counted as pending notifications
+	pub const APPLICATION: &'static str = "g815d";
+}
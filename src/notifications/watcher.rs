@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+
+use log::{debug, info, warn};
+use smol::channel::Receiver;
+use smol::future;
+use zbus::{Connection, Message, MessageType};
+use zbus::fdo::DBusProxy;
+
+use crate::channels::MonitoredSender;
+use crate::daemon;
+use crate::MainThreadSignal;
+use super::{Notification, NotificationWatcherSignal};
+
+/// The arguments of org.freedesktop.Notifications.Notify
+type NotifyArguments = (String, u32, String, String, String, Vec<String>,
+	HashMap<String, zvariant::OwnedValue>, i32);
+
+/// Watches the session bus for notifications being shown and dismissed, using
+/// a monitoring connection so it works with any notification server. The count
+/// of pending notifications itself is kept by the main thread.
+pub struct NotificationWatcher
+{
+	connection: Connection
+}
+
+impl NotificationWatcher
+{
+	const MATCH_RULES: [&'static str; 2] = [
+		"type='method_call',interface='org.freedesktop.Notifications',member='Notify'",
+		"type='signal',interface='org.freedesktop.Notifications',member='NotificationClosed'"
+	];
+
+	// the reason given in NotificationClosed when the user dismissed it
+	const DISMISSED_BY_USER: u32 = 2;
+
+	pub fn new() -> Result<Self, String>
+	{
+		// non-blocking, so the task can wait for messages alongside shutting down
+		let handshake = zbus::handshake::ClientHandshake::new_session_nonblock().map_err(|e| e.to_string())?;
+		let authenticated_socket = handshake.blocking_finish().map_err(|e| e.to_string())?;
+		let connection = Connection::new_authenticated_unix(authenticated_socket);
+
+		let proxy = DBusProxy::new(&connection).map_err(|e| e.to_string())?;
+		let name = proxy.hello().map_err(|e| e.to_string())?;
+
+		connection.set_unique_name(name)?;
+		connection
+			.call_method(
+				Some("org.freedesktop.DBus"),
+				"/org/freedesktop/DBus",
+				Some("org.freedesktop.DBus.Monitoring"),
+				"BecomeMonitor",
+				&(Self::MATCH_RULES.to_vec(), 0_u32))
+			.map_err(|e| e.to_string())?;
+
+		info!("watching for notifications");
+		Ok(Self { connection })
+	}
+
+	pub async fn run(&mut self, rx: Receiver<NotificationWatcherSignal>, tx: MonitoredSender<MainThreadSignal>)
+	{
+		let bus = match daemon::Readable::new(self.connection.as_raw_fd())
+		{
+			Ok(bus) => bus,
+			Err(error) => return warn!("unable to wait for notifications: {}", error)
+		};
+
+		loop
+		{
+			match self.connection.receive_message()
+			{
+				Ok(message) =>
+				{
+					if let Some(signal) = Self::parse(&message)
+					{
+						tx.send(signal);
+					}
+				},
+				// sleeps until the next message, or until the driver shuts down
+				Err(zbus::Error::Io(io_error)) if io_error.kind() == std::io::ErrorKind::WouldBlock =>
+				{
+					let woken = future::or(
+						async
+						{
+							match rx.recv().await
+							{
+								Ok(NotificationWatcherSignal::Shutdown)
+									| Err(_) => None
+							}
+						},
+						async { Some(bus.wait().await) }).await;
+
+					match woken
+					{
+						Some(Ok(())) => (),
+						Some(Err(error)) =>
+						{
+							warn!("stopped watching for notifications: {}", error);
+							break
+						},
+						None => break
+					}
+				},
+				Err(error) =>
+				{
+					warn!("stopped watching for notifications: {}", error);
+					break
+				}
+			}
+		}
+	}
+
+	/// Turns a monitored message into a change to the pending notification count
+	fn parse(message: &Message) -> Option<MainThreadSignal>
+	{
+		let header = message.header().ok()?;
+
+		match (header.message_type().ok()?, header.member().ok()??)
+		{
+			(MessageType::MethodCall, "Notify") =>
+			{
+				let (application, replaces_id, _icon, summary, body, ..) = message.body::<NotifyArguments>().ok()?;
+				debug!("notification from {}", application);
+
+				// a notification replacing another one isn't a new notification
+				(replaces_id == 0 && application != Notification::APPLICATION).then(|| MainThreadSignal::NotificationReceived(Notification
+				{
+					application,
+					summary,
+					body
+				}))
+			},
+			(MessageType::Signal, "NotificationClosed") =>
+			{
+				let (_id, reason) = message.body::<(u32, u32)>().ok()?;
+				(reason == Self::DISMISSED_BY_USER).then(|| MainThreadSignal::NotificationDismissed)
+			},
+			_ => None
+		}
+	}
+}
@@ -0,0 +1,75 @@
+use std::borrow::Cow;
+
+use crate::config::{Configuration, ProfileResolver};
+use crate::device::{CommandResult, Device, OnboardLighting};
+use crate::device::rgb::{EffectConfiguration, RenderCapabilities, Theme};
+
+/// Checks a profile number given on the command line
+pub fn validate_profile(profile: String) -> Result<(), String>
+{
+	match profile.parse::<u8>()
+	{
+		Ok(profile) if profile > 0 => Ok(()),
+		_ => Err("onboard profiles are numbered from 1".to_string())
+	}
+}
+
+/// What an onboard profile can show of a theme: firmware effects are stored as
+/// they are (software ones as their nearest firmware effect), but the profile
+/// has no room for each key's color, so other themes are stored as their first
+/// color
+fn effect(theme: &Theme, config: &Configuration) -> EffectConfiguration
+{
+	let theme = theme.negotiate(&RenderCapabilities { software_effects: false });
+
+	match theme
+	{
+		Cow::Borrowed(Theme::Effect(effect)) => effect.clone(),
+		Cow::Owned(Theme::Effect(effect)) => effect,
+		theme => EffectConfiguration::Static
+		{
+			color: theme
+				.scancode_assignments(&config.keygroups)
+				.and_then(|assignments| assignments.first().map(|(color, _scancodes)| *color))
+				.unwrap_or_default()
+		}
+	}
+}
+
+/// The lighting of a keyboard's default profile (in its first mode), as an
+/// onboard profile would show it
+pub fn default_lighting(config: &Configuration, serial_number: &str) -> OnboardLighting
+{
+	let profile = config.default_profile_for_device(serial_number);
+	let resolver = ProfileResolver::new(config, profile, 1);
+	let keys = effect(resolver.theme(), config);
+
+	OnboardLighting
+	{
+		logo: resolver
+			.logo_theme()
+			.map(|logo_theme| effect(logo_theme, config))
+			.unwrap_or_else(|| keys.clone()),
+		keys
+	}
+}
+
+/// Prints the lighting stored in an onboard profile (--read-onboard-profile)
+pub fn read(device: &mut Box<dyn Device>, profile: u8) -> CommandResult<()>
+{
+	let lighting = device.onboard_lighting(profile)?;
+
+	println!("onboard profile {}\nkeys: {:?}\nlogo: {:?}", profile, lighting.keys, lighting.logo);
+	Ok(())
+}
+
+/// Stores the default profile's lighting in an onboard profile
+/// (--write-onboard-profile), so the keyboard shows it without the driver
+pub fn write(device: &mut Box<dyn Device>, config: &Configuration, profile: u8) -> CommandResult<()>
+{
+	let lighting = default_lighting(config, device.serial_number());
+	device.set_onboard_lighting(profile, &lighting)?;
+
+	println!("onboard profile {} now shows {:?} on the keys and {:?} on the logo", profile, lighting.keys, lighting.logo);
+	Ok(())
+}
@@ -0,0 +1,186 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::{RwLock, RwLockReadGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use serde::{Serialize, Deserialize};
+
+use crate::config::Configuration;
+use crate::subsystem::Subsystem;
+
+/// Runtime state that's kept between runs of the driver. Anything added here
+/// needs a default, so older state files can still be read.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PersistedState
+{
+	/// the mode last used in each profile
+	pub profile_modes: HashMap<String, u8>,
+	/// subsystems switched off over dbus
+	pub disabled_subsystems: HashSet<Subsystem>
+}
+
+/// The state file as it's written to disk
+#[derive(Serialize, Deserialize)]
+struct StateFile
+{
+	version: u32,
+	state: PersistedState
+}
+
+/// Keeps the persisted state in memory, and writes it to state.yml (next to
+/// the config) when it has changed. Subsystems read it with `read()` and change
+/// it with `update()`, rather than writing files of their own.
+///
+/// A state file that can't be read (or was written by a newer version of the
+/// driver) is moved aside, and the driver starts from the default state.
+pub struct StateStore
+{
+	state: RwLock<PersistedState>,
+	changed: AtomicBool
+}
+
+impl StateStore
+{
+	/// Increase this when a change to `PersistedState` can't be handled by
+	/// serde defaults, and convert older files in `migrate()`
+	const VERSION: u32 = 1;
+	/// how often the main loop saves the state (if it's changed), in seconds
+	pub const SAVE_INTERVAL: u64 = 30;
+
+	pub fn file_path() -> PathBuf
+	{
+		let mut path = Configuration::config_folder();
+		path.push("state.yml");
+		path
+	}
+
+	pub fn load() -> Self
+	{
+		let state = match fs::read_to_string(Self::file_path())
+		{
+			Ok(yaml_string) => Self::parse(&yaml_string).unwrap_or_else(|reason|
+			{
+				warn!("unable to read the driver's saved state, starting afresh: {}", reason);
+				Self::move_aside();
+				PersistedState::default()
+			}),
+			Err(error) if error.kind() == ErrorKind::NotFound => PersistedState::default(),
+			Err(error) =>
+			{
+				warn!("unable to open the driver's saved state, starting afresh: {}", error);
+				PersistedState::default()
+			}
+		};
+
+		Self::new(state)
+	}
+
+	/// Keeps a state in memory, without reading the state file
+	pub fn new(state: PersistedState) -> Self
+	{
+		Self
+		{
+			state: RwLock::new(state),
+			changed: AtomicBool::new(false)
+		}
+	}
+
+	fn parse(yaml_string: &str) -> Result<PersistedState, String>
+	{
+		let mut value: serde_yaml::Value = serde_yaml::from_str(yaml_string)
+			.map_err(|error| error.to_string())?;
+
+		let version = value
+			.get("version")
+			.and_then(|version| version.as_u64())
+			.ok_or_else(|| "the state file has no version".to_string())? as u32;
+
+		if version > Self::VERSION
+		{
+			return Err(format!("the state file is version {}, but only versions up to {} are supported",
+				version, Self::VERSION))
+		}
+
+		Self::migrate(&mut value, version)?;
+
+		serde_yaml::from_value::<StateFile>(value)
+			.map(|state_file| state_file.state)
+			.map_err(|error| error.to_string())
+	}
+
+	/// Converts a state file from an older version into the current one
+	fn migrate(_value: &mut serde_yaml::Value, version: u32) -> Result<(), String>
+	{
+		match version
+		{
+			Self::VERSION => Ok(()),
+			version => Err(format!("unknown state file version {}", version))
+		}
+	}
+
+	/// Renames an unreadable state file, so it isn't overwritten and can be
+	/// looked at later
+	fn move_aside()
+	{
+		let timestamp = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|duration| duration.as_millis())
+			.unwrap_or(0);
+
+		let path = Self::file_path();
+		let mut unreadable_path = path.clone();
+		unreadable_path.set_file_name(format!("state.yml.unreadable.{}", timestamp));
+
+		match fs::rename(&path, &unreadable_path)
+		{
+			Ok(()) => info!("moved the unreadable state file to {}", unreadable_path.display()),
+			Err(error) => warn!("unable to move the unreadable state file: {}", error)
+		}
+	}
+
+	pub fn read(&self) -> RwLockReadGuard<'_, PersistedState>
+	{
+		self.state.read().unwrap()
+	}
+
+	/// Changes the state, which will be written out on the next `save()`
+	pub fn update<F, R>(&self, update: F) -> R
+	where
+		F: FnOnce(&mut PersistedState) -> R
+	{
+		let result = update(&mut self.state.write().unwrap());
+		self.changed.store(true, Ordering::Relaxed);
+		result
+	}
+
+	/// Writes the state to disk, if it has changed since it was last written
+	pub fn save(&self)
+	{
+		if !self.changed.swap(false, Ordering::Relaxed)
+		{
+			return
+		}
+
+		let state_file = StateFile
+		{
+			version: Self::VERSION,
+			state: self.read().clone()
+		};
+
+		let result = serde_yaml::to_string(&state_file)
+			.map_err(|error| error.to_string())
+			.and_then(|yaml_string| Configuration::write_atomically(&Self::file_path(), yaml_string.as_bytes())
+				.map_err(|error| error.to_string()));
+
+		if let Err(reason) = result
+		{
+			warn!("unable to save the driver's state: {}", reason);
+			self.changed.store(true, Ordering::Relaxed);
+		}
+	}
+}
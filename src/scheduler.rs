@@ -0,0 +1,280 @@
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use serde::Serialize;
+
+use crate::config::{Configuration, NightMode, PlaylistEntry};
+
+#[derive(Debug, Clone, Copy)]
+pub enum PlaylistCommand
+{
+	Skip,
+	Pause,
+	Resume
+}
+
+/// What the active profile's playlist is showing
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PlaylistStatus
+{
+	pub playlist: Option<String>,
+	/// None when none of the playlist's themes are in their time window, so
+	/// the profile's own theme is shown
+	pub theme: Option<String>,
+	pub paused: bool
+}
+
+/// Rotates through the active profile's playlist, moving on to the next theme
+/// once the current one's duration is up, and skipping themes outside their
+/// time window. Runs on the main thread, so every keyboard shows the same theme.
+pub struct PlaylistScheduler
+{
+	playlist: Option<String>,
+	entries: Vec<PlaylistEntry>,
+	index: Option<usize>,
+	// when the current theme was shown (or the rotation resumed), and how long
+	// it had left to go at that point
+	started: Instant,
+	time_left: Duration,
+	paused: bool,
+	enabled: bool,
+	last_window_check: Instant
+}
+
+impl PlaylistScheduler
+{
+	/// how often to check whether time windows have opened or closed
+	const WINDOW_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+	pub fn new() -> Self
+	{
+		Self
+		{
+			playlist: None,
+			entries: Vec::new(),
+			index: None,
+			started: Instant::now(),
+			time_left: Duration::default(),
+			paused: false,
+			enabled: true,
+			last_window_check: Instant::now()
+		}
+	}
+
+	/// Plays a profile's playlist from the start, unless it's already playing
+	/// (i.e. when switching between profiles with the same playlist). A reload
+	/// always starts again, as the playlist may have changed. Returns whether
+	/// the status has changed.
+	pub fn set_playlist(&mut self, config: &Configuration, playlist: Option<&str>, reloaded: bool) -> bool
+	{
+		if self.playlist.as_deref() == playlist && !reloaded
+		{
+			return false
+		}
+
+		let previous = self.status();
+
+		self.playlist = playlist.map(String::from);
+		self.entries = playlist
+			.and_then(|playlist| config.playlists.get(playlist))
+			.cloned()
+			.unwrap_or_default();
+		self.index = None;
+		self.paused = false;
+
+		if let Some(playlist) = &self.playlist
+		{
+			debug!("playing playlist {}", playlist);
+		}
+
+		self.advance();
+		self.status() != previous
+	}
+
+	/// Switches the rotation on or off (with the scheduler subsystem). Whilst
+	/// it's off, profiles show their own theme.
+	pub fn set_enabled(&mut self, enabled: bool) -> bool
+	{
+		if enabled == self.enabled
+		{
+			return false
+		}
+
+		self.enabled = enabled;
+		self.index = None;
+
+		if enabled
+		{
+			self.advance();
+		}
+
+		true
+	}
+
+	pub fn command(&mut self, command: PlaylistCommand) -> bool
+	{
+		if self.playlist.is_none() || !self.enabled
+		{
+			debug!("ignoring {:?}, there's no playlist playing", command);
+			return false
+		}
+
+		match command
+		{
+			PlaylistCommand::Skip =>
+			{
+				self.paused = false;
+				self.advance();
+				info!("skipped to theme {:?}", self.theme());
+			},
+			PlaylistCommand::Pause if !self.paused =>
+			{
+				self.time_left = self.time_left.saturating_sub(self.started.elapsed());
+				self.paused = true;
+				info!("playlist paused");
+			},
+			PlaylistCommand::Resume if self.paused =>
+			{
+				self.started = Instant::now();
+				self.paused = false;
+				info!("playlist resumed");
+			},
+			_ => return false
+		}
+
+		true
+	}
+
+	/// Moves on to the next theme once the current one's time is up, or its
+	/// time window has closed. Returns whether the theme has changed.
+	pub fn update(&mut self) -> bool
+	{
+		if !self.enabled || self.paused || self.entries.is_empty()
+		{
+			return false
+		}
+
+		let check_windows = self.last_window_check.elapsed() >= Self::WINDOW_CHECK_INTERVAL;
+
+		if check_windows
+		{
+			self.last_window_check = Instant::now();
+		}
+
+		let move_on = match self.index
+		{
+			Some(index) => self.started.elapsed() >= self.time_left
+				|| (check_windows && !Self::in_window(&self.entries[index], local_minutes())),
+			// waiting for a time window to open
+			None => check_windows
+		};
+
+		match move_on
+		{
+			true =>
+			{
+				let previous = self.theme();
+				self.advance();
+				self.theme() != previous
+			},
+			false => false
+		}
+	}
+
+	/// When `update` next has something to check, so the main loop can sleep
+	/// until then. None whilst there's nothing playing.
+	pub fn next_update(&self) -> Option<Instant>
+	{
+		if !self.enabled || self.paused || self.entries.is_empty()
+		{
+			return None
+		}
+
+		let window_check = self.last_window_check + Self::WINDOW_CHECK_INTERVAL;
+
+		Some(match self.index
+		{
+			Some(_) => window_check.min(self.started + self.time_left),
+			None => window_check
+		})
+	}
+
+	pub fn status(&self) -> PlaylistStatus
+	{
+		PlaylistStatus
+		{
+			playlist: self.playlist.clone(),
+			theme: self.theme(),
+			paused: self.paused
+		}
+	}
+
+	fn theme(&self) -> Option<String>
+	{
+		self.index.map(|index| self.entries[index].theme.clone())
+	}
+
+	/// Shows the next theme whose time window is open, or none if they're all
+	/// closed
+	fn advance(&mut self)
+	{
+		let now = local_minutes();
+		let count = self.entries.len();
+		let start = self.index.map(|index| index + 1).unwrap_or(0);
+
+		self.index = match self.enabled
+		{
+			true => (0..count)
+				.map(|offset| (start + offset) % count)
+				.find(|index| Self::in_window(&self.entries[*index], now)),
+			false => None
+		};
+
+		self.started = Instant::now();
+		self.time_left = self.index
+			.map(|index| Duration::from_secs(self.entries[index].duration))
+			.unwrap_or_default();
+	}
+
+	fn in_window(entry: &PlaylistEntry, minutes: u16) -> bool
+	{
+		entry.between
+			.map(|window| window.contains(minutes))
+			.unwrap_or(true)
+	}
+}
+
+/// The local time, using the system's timezone (or $TZ)
+pub fn local_time() -> Option<libc::tm>
+{
+	let mut time: libc::tm = unsafe { std::mem::zeroed() };
+
+	let local = unsafe
+	{
+		let now = libc::time(std::ptr::null_mut());
+		libc::localtime_r(&now, &mut time)
+	};
+
+	match local.is_null()
+	{
+		true => None,
+		false => Some(time)
+	}
+}
+
+/// Minutes since midnight in local time
+fn local_minutes() -> u16
+{
+	local_time()
+		.map(|time| (time.tm_hour * 60 + time.tm_min) as u16)
+		.unwrap_or(0)
+}
+
+/// The color temperature night mode calls for now, or None whilst it's day
+pub fn night_temperature(night_mode: &NightMode) -> Option<u16>
+{
+	let time = local_time()?;
+	let night = night_mode.night(time.tm_yday as u16, (time.tm_gmtoff / 60) as i32);
+
+	night_mode.temperature_at(night, (time.tm_hour * 60 + time.tm_min) as u16)
+}
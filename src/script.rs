@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use log::warn;
+
+use crate::channels::MonitoredSender;
+use crate::macros::MacroSignals;
+use crate::windowsystem::WindowSystemSignal;
+use crate::{SharedState, MainThreadSignal};
+
+/// The client name colors set by scripts are drawn under, see client_colors.rs
+#[cfg(feature = "scripting")]
+const CLIENT_NAME: &str = "macro script";
+
+/// Runs a macro's script step with rhai, for logic the step list can't express.
+/// `script` is either the script itself, or the path to a `.rhai` file relative
+/// to the config folder. Scripts can call:
+///
+/// * `send_key(combo)` - press a key combination, as with a key_press step
+/// * `click(button)` - click `left`, `middle` or `right`
+/// * `set_key_color(key, color)` / `clear_key_colors()` - light keys until
+///   they're cleared
+/// * `get_active_window()` - a map of the active window's title, executable,
+///   class, class_name, workspace, monitor and fullscreen, or () when there
+///   isn't one
+/// * `sleep(ms)`
+///
+/// Errors in the script are logged, and end the step. Stopping the macro (or
+/// the driver) ends the script too, even part way through a loop or a sleep.
+#[cfg(feature = "scripting")]
+pub fn run(
+	script: &str,
+	window_system: &MonitoredSender<WindowSystemSignal>,
+	main_thread: &MonitoredSender<MainThreadSignal>,
+	state: &Arc<SharedState>,
+	signals: &Arc<MacroSignals>)
+{
+	use std::time::Duration;
+
+	use log::debug;
+	use rhai::{Dynamic, Engine, EvalAltResult, Map};
+
+	use crate::config::Configuration;
+	use crate::device::client_colors::KeyColorUpdate;
+	use crate::device::color::Color;
+	use crate::device::scancode::Scancode;
+	use crate::windowsystem::MouseButton;
+
+	let mut engine = Engine::new();
+
+	{
+		let window_system = window_system.clone();
+		engine.register_fn("send_key", move |combo: &str|
+			window_system.send(WindowSystemSignal::SendKeyCombo(combo.to_string())));
+	}
+
+	{
+		let window_system = window_system.clone();
+		engine.register_fn("click", move |button: &str| -> Result<(), Box<EvalAltResult>>
+		{
+			let button: MouseButton = serde_yaml::from_str(button)
+				.map_err(|_| format!("'{}' isn't a mouse button", button))?;
+
+			window_system.send(WindowSystemSignal::SendClick(button));
+			Ok(())
+		});
+	}
+
+	{
+		let main_thread = main_thread.clone();
+		engine.register_fn("set_key_color", move |key: &str, color: &str| -> Result<(), Box<EvalAltResult>>
+		{
+			let scancode: Scancode = serde_yaml::from_str(key)
+				.map_err(|_| format!("there is no key called '{}'", key))?;
+			let color = Color::parse(color)?;
+
+			main_thread.send(MainThreadSignal::SetKeyColors(KeyColorUpdate
+			{
+				client: CLIENT_NAME.into(),
+				colors: vec![(scancode, color)],
+				replace: false,
+				ttl: None
+			}));
+			Ok(())
+		});
+	}
+
+	{
+		let main_thread = main_thread.clone();
+		engine.register_fn("clear_key_colors", move ||
+			main_thread.send(MainThreadSignal::ClearKeyColors(CLIENT_NAME.into())));
+	}
+
+	{
+		let state = Arc::clone(state);
+		engine.register_fn("get_active_window", move || match &*state.active_window.read().unwrap()
+		{
+			Some(window) =>
+			{
+				let mut map = Map::new();
+				map.insert("title".into(), window.title.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT));
+				map.insert("executable".into(), window.executable.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT));
+				map.insert("class".into(), window.class.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT));
+				map.insert("class_name".into(), window.class_name.clone().map(Dynamic::from).unwrap_or(Dynamic::UNIT));
+				map.insert("workspace".into(), window.workspace.map(|workspace| Dynamic::from(workspace as i64)).unwrap_or(Dynamic::UNIT));
+				map.insert("monitor".into(), window.monitor.map(|monitor| Dynamic::from(monitor as i64)).unwrap_or(Dynamic::UNIT));
+				map.insert("fullscreen".into(), Dynamic::from(window.fullscreen));
+				Dynamic::from(map)
+			},
+			None => Dynamic::UNIT
+		});
+	}
+
+	{
+		let signals = Arc::clone(signals);
+		engine.register_fn("sleep", move |ms: i64| { signals.wait(Duration::from_millis(ms.max(0) as u64)); });
+	}
+
+	{
+		// checked every so many operations, as each check locks the macro's signals
+		let signals = Arc::clone(signals);
+		engine.on_progress(move |operations| match operations % 1000 == 0 && signals.stopping()
+		{
+			true => Some(Dynamic::UNIT),
+			false => None
+		});
+	}
+
+	let result = match script.trim_end().ends_with(".rhai")
+	{
+		true =>
+		{
+			let mut path = Configuration::config_folder();
+			path.push(script.trim());
+			engine.run_file(path)
+		},
+		false => engine.run(script)
+	};
+
+	match result.map_err(|error| *error)
+	{
+		Ok(()) => (),
+		Err(EvalAltResult::ErrorTerminated(..)) => debug!("macro script ended, as its macro was stopped"),
+		Err(error) => warn!("macro script failed: {}", error)
+	}
+}
+
+#[cfg(not(feature = "scripting"))]
+pub fn run(
+	_script: &str,
+	_window_system: &MonitoredSender<WindowSystemSignal>,
+	_main_thread: &MonitoredSender<MainThreadSignal>,
+	_state: &Arc<SharedState>,
+	_signals: &Arc<MacroSignals>)
+{
+	warn!("skipping a macro script step, the driver was built without the scripting feature");
+}
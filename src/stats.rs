@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use smol::{Async, Timer};
+use smol::channel::Receiver;
+use smol::future;
+
+use crate::SharedState;
+use crate::device::scancode::Scancode;
+
+pub type KeyCounts = HashMap<Scancode, u64>;
+
+pub enum KeyStatisticsSignal
+{
+	/// `key_statistics` or game mode learning was turned on or off
+	SettingsChanged,
+	Shutdown
+}
+
+/// The keys pressed in a profile since game mode learning was started (over
+/// dbus), so game mode can be offered the keys that weren't
+#[derive(Debug, Default)]
+pub struct GameModeLearning
+{
+	/// presses are only counted whilst this profile is active
+	pub profile: String,
+	pub pressed: KeyCounts
+}
+
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+impl GameModeLearning
+{
+	/// keys that get in the way of games, by opening menus or switching windows
+	const CANDIDATES: [Scancode; 3] = [Scancode::LeftMeta, Scancode::RightMeta, Scancode::ContextMenu];
+
+	pub fn new(profile: String) -> Self
+	{
+		Self { profile, pressed: KeyCounts::new() }
+	}
+
+	/// The keys that get in the way of games which weren't pressed whilst
+	/// learning, leaving out any game mode already disables
+	pub fn suggested_keys(&self, game_mode_keys: &[Scancode]) -> Vec<Scancode>
+	{
+		Self::CANDIDATES
+			.iter()
+			.filter(|scancode| !self.pressed.contains_key(scancode) && !game_mode_keys.contains(scancode))
+			.copied()
+			.collect()
+	}
+}
+
+/// Counts how many times each key is pressed, by reading the keyboard's evdev
+/// device. Only the counts are kept (in memory), never the order keys are
+/// pressed in. Collection only happens whilst `key_statistics` is enabled in
+/// the config, or game mode learning is on.
+pub struct KeyStatistics
+{
+	state: Arc<SharedState>,
+	device: Option<Async<File>>
+}
+
+impl KeyStatistics
+{
+	const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+	const INPUT_EVENT_SIZE: usize = 24;
+	const EV_KEY: u16 = 0x01;
+	const O_NONBLOCK: i32 = 0o4000;
+
+	pub fn new(state: Arc<SharedState>) -> Self
+	{
+		Self { state, device: None }
+	}
+
+	pub async fn run(&mut self, rx: Receiver<KeyStatisticsSignal>)
+	{
+		loop
+		{
+			let enabled = self.state.config.read().unwrap().key_statistics
+				|| self.state.game_mode_learning.read().unwrap().is_some();
+
+			match (enabled, self.device.is_some())
+			{
+				(false, true) =>
+				{
+					info!("key statistics and game mode learning disabled, closing keyboard input device");
+					self.device = None;
+				},
+				(true, false) => self.open_device(),
+				_ => ()
+			}
+
+			// Ok(None) when there are key presses to read, or it's time to try
+			// opening the device again
+			let woken = future::or(
+				async { rx.recv().await.map(Some) },
+				async
+				{
+					match (self.device.as_ref(), enabled)
+					{
+						(Some(device), _) => { let _ = device.readable().await; },
+						(None, true) => { Timer::after(Self::RETRY_INTERVAL).await; },
+						(None, false) => future::pending().await
+					}
+
+					Ok(None)
+				}).await;
+
+			match woken
+			{
+				Ok(None) => self.read_events(),
+				Ok(Some(KeyStatisticsSignal::SettingsChanged)) => (),
+				Ok(Some(KeyStatisticsSignal::Shutdown))
+					| Err(_) => break
+			}
+		}
+	}
+
+	/// Finds the evdev device for the keyboard's normal keys
+	fn find_device() -> Option<PathBuf>
+	{
+		fs::read_dir("/dev/input/by-id")
+			.ok()?
+			.filter_map(|entry| entry.ok())
+			.map(|entry| entry.path())
+			.find(|path| path
+				.file_name()
+				.and_then(|name| name.to_str())
+				.map(|name| name.contains("G815") && name.ends_with("-event-kbd"))
+				.unwrap_or(false))
+	}
+
+	fn open_device(&mut self)
+	{
+		let path = match Self::find_device()
+		{
+			Some(path) => path,
+			None => return debug!("unable to find the keyboard's input device for key statistics")
+		};
+
+		match OpenOptions::new().read(true).custom_flags(Self::O_NONBLOCK).open(&path).and_then(Async::new)
+		{
+			Ok(device) =>
+			{
+				info!("collecting key statistics from {}", path.display());
+				self.device = Some(device);
+			},
+			Err(error) => warn!("unable to open {} for key statistics: {}", path.display(), error)
+		}
+	}
+
+	/// Counts key presses from every input_event waiting on the device. Each event
+	/// is a timeval (16 bytes), then the type (u16), code (u16) and value (i32).
+	fn read_events(&mut self)
+	{
+		let mut buffer = [0_u8; Self::INPUT_EVENT_SIZE * 64];
+
+		let bytes_read = match self.device.as_ref().map(|device| device.get_ref().read(&mut buffer))
+		{
+			Some(Ok(bytes_read)) => bytes_read,
+			Some(Err(error)) if error.kind() == ErrorKind::WouldBlock => return,
+			Some(Err(error)) =>
+			{
+				warn!("lost the keyboard input device for key statistics: {}", error);
+				self.device = None;
+				return
+			},
+			None => return
+		};
+
+		let counting = self.state.config.read().unwrap().key_statistics;
+		let mut counts = self.state.key_statistics.write().unwrap();
+		let mut learning = self.state.game_mode_learning.write().unwrap();
+		let active_profile = self.state.active_profile_name.read().unwrap();
+		let mut learning = learning
+			.as_mut()
+			.filter(|learning| learning.profile == *active_profile);
+
+		for event in buffer[..bytes_read].chunks_exact(Self::INPUT_EVENT_SIZE)
+		{
+			let event_type = u16::from_ne_bytes([event[16], event[17]]);
+			let code = u16::from_ne_bytes([event[18], event[19]]);
+			let value = i32::from_ne_bytes([event[20], event[21], event[22], event[23]]);
+
+			// value is 1 for a press, 0 for a release and 2 for autorepeat
+			if event_type == Self::EV_KEY && value == 1
+			{
+				if let Some(scancode) = Self::scancode(code)
+				{
+					if counting
+					{
+						*counts.entry(scancode).or_insert(0) += 1;
+					}
+
+					if let Some(learning) = learning.as_mut()
+					{
+						*learning.pressed.entry(scancode).or_insert(0) += 1;
+					}
+				}
+			}
+		}
+	}
+
+	/// Converts a linux input key code into a scancode
+	fn scancode(code: u16) -> Option<Scancode>
+	{
+		use Scancode::*;
+
+		Some(match code
+		{
+			1 => Escape,
+			2 => N1, 3 => N2, 4 => N3, 5 => N4, 6 => N5, 7 => N6, 8 => N7, 9 => N8, 10 => N9, 11 => N0,
+			12 => Minus, 13 => Equals, 14 => Backspace, 15 => Tab,
+			16 => Q, 17 => W, 18 => E, 19 => R, 20 => T, 21 => Y, 22 => U, 23 => I, 24 => O, 25 => P,
+			26 => LeftBracket, 27 => RightBracket, 28 => Enter, 29 => LeftControl,
+			30 => A, 31 => S, 32 => D, 33 => F, 34 => G, 35 => H, 36 => J, 37 => K, 38 => L,
+			39 => Semicolon, 40 => Apostrophe, 41 => Grave, 42 => LeftShift, 43 => UsBackslash,
+			44 => Z, 45 => X, 46 => C, 47 => V, 48 => B, 49 => N, 50 => M,
+			51 => Comma, 52 => Dot, 53 => Slash, 54 => RightShift, 55 => NumpadMultiply,
+			56 => LeftAlt, 57 => Space, 58 => CapsLock,
+			59 => F1, 60 => F2, 61 => F3, 62 => F4, 63 => F5, 64 => F6, 65 => F7, 66 => F8, 67 => F9, 68 => F10,
+			69 => NumLock, 70 => ScrollLock,
+			71 => Numpad7, 72 => Numpad8, 73 => Numpad9, 74 => NumpadMinus,
+			75 => Numpad4, 76 => Numpad5, 77 => Numpad6, 78 => NumpadPlus,
+			79 => Numpad1, 80 => Numpad2, 81 => Numpad3, 82 => Numpad0, 83 => NumpadDot,
+			86 => Backslash, 87 => F11, 88 => F12,
+			89 => Ro, 92 => Henkan, 93 => KatakanaHiragana, 94 => Muhenkan, 124 => Yen,
+			96 => NumpadEnter, 97 => RightControl, 98 => NumpadDivide, 99 => PrintScreen, 100 => RightAlt,
+			102 => Home, 103 => Up, 104 => PageUp, 105 => Left, 106 => Right,
+			107 => End, 108 => Down, 109 => PageDown, 110 => Insert, 111 => Delete,
+			113 => Mute, 119 => Pause, 125 => LeftMeta, 126 => RightMeta, 127 => ContextMenu,
+			163 => MediaNext, 164 => MediaPlayPause, 165 => MediaPrevious,
+			_ => return None
+		})
+	}
+}
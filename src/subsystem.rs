@@ -0,0 +1,61 @@
+use serde::{Serialize, Deserialize};
+
+/// Parts of the driver that can be switched off whilst it's running (over
+/// dbus), to save cpu or to stop them getting in the way. Which ones are off
+/// is kept between runs.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Subsystem
+{
+	/// watching media players, and the volume and mute state
+	Media,
+	/// switching profiles when the active window changes
+	Windows,
+	/// indicators drawn over themes (mute, volume, audio focus, notifications,
+	/// game mode and macros)
+	ReactiveLighting,
+	/// the live parts of hud, heatmap and screen themes, which otherwise
+	/// just show their colors
+	Widgets,
+	/// rotating through playlists
+	Scheduler
+}
+
+impl Subsystem
+{
+	pub const ALL: [Subsystem; 5] = [
+		Subsystem::Media,
+		Subsystem::Windows,
+		Subsystem::ReactiveLighting,
+		Subsystem::Widgets,
+		Subsystem::Scheduler
+	];
+
+	pub fn name(self) -> &'static str
+	{
+		match self
+		{
+			Subsystem::Media => "media",
+			Subsystem::Windows => "windows",
+			Subsystem::ReactiveLighting => "reactive_lighting",
+			Subsystem::Widgets => "widgets",
+			Subsystem::Scheduler => "scheduler"
+		}
+	}
+
+	/// Whether the subsystem runs on each keyboard, so it can be switched off
+	/// for just one of them. The others are shared by every keyboard.
+	pub fn per_device(self) -> bool
+	{
+		match self
+		{
+			Subsystem::ReactiveLighting | Subsystem::Widgets => true,
+			Subsystem::Media | Subsystem::Windows | Subsystem::Scheduler => false
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<Self>
+	{
+		Self::ALL.iter().copied().find(|subsystem| subsystem.name() == name)
+	}
+}
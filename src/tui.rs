@@ -0,0 +1,306 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+use crate::channels::MonitoredSender;
+use crate::{SharedState, MainThreadSignal};
+
+/// The log target keyboard events (and hooks) are logged to, at debug level,
+/// which --tui shows as they happen
+pub const EVENTS: &str = "g815d::events";
+
+/// The events and problems logged whilst the TUI has been running, newest last
+pub struct TuiLog
+{
+	started: Instant,
+	events: Mutex<VecDeque<String>>,
+	errors: Mutex<VecDeque<String>>
+}
+
+impl TuiLog
+{
+	/// how many lines of each are kept
+	const HISTORY_LENGTH: usize = 200;
+
+	fn push(&self, lines: &Mutex<VecDeque<String>>, line: String)
+	{
+		let mut lines = lines.lock().unwrap();
+
+		if lines.len() == Self::HISTORY_LENGTH
+		{
+			lines.pop_front();
+		}
+
+		lines.push_back(format!("{:>8.1}s  {}", self.started.elapsed().as_secs_f64(), line));
+	}
+
+	/// The last `count` lines, oldest first
+	fn last(lines: &Mutex<VecDeque<String>>, count: usize) -> Vec<String>
+	{
+		let lines = lines.lock().unwrap();
+		lines.iter().skip(lines.len().saturating_sub(count)).cloned().collect()
+	}
+}
+
+/// Collects the log into a TuiLog, as anything written to the terminal would
+/// just be drawn over
+struct TuiLogger(Arc<TuiLog>);
+
+impl Log for TuiLogger
+{
+	fn enabled(&self, metadata: &Metadata) -> bool
+	{
+		metadata.target() == EVENTS || metadata.level() <= Level::Warn
+	}
+
+	fn log(&self, record: &Record)
+	{
+		let log = &self.0;
+
+		match record.target() == EVENTS
+		{
+			true => log.push(&log.events, record.args().to_string()),
+			false if record.level() <= Level::Warn =>
+				log.push(&log.errors, format!("{} {}: {}", record.level(), record.target(), record.args())),
+			false => ()
+		}
+	}
+
+	fn flush(&self) {}
+}
+
+/// Logs to a TuiLog from now on, in place of pretty_env_logger
+pub fn init_logger() -> Arc<TuiLog>
+{
+	let log = Arc::new(TuiLog
+	{
+		started: Instant::now(),
+		events: Mutex::new(VecDeque::new()),
+		errors: Mutex::new(VecDeque::new())
+	});
+
+	log::set_boxed_logger(Box::new(TuiLogger(Arc::clone(&log))))
+		.expect("a logger was already set");
+	log::set_max_level(LevelFilter::Debug);
+
+	log
+}
+
+/// Shows the driver's state whilst it runs in the foreground (--tui): the
+/// active profile and mode, media, recent macro runs and what's been logged,
+/// with keys to switch profiles and modes and to run macros
+pub struct Tui
+{
+	state: Arc<SharedState>,
+	log: Arc<TuiLog>,
+	tx: MonitoredSender<MainThreadSignal>,
+	// index into the config's macros, sorted by name
+	selected_macro: usize
+}
+
+impl Tui
+{
+	// milliseconds
+	const REDRAW_INTERVAL: i32 = 250;
+	const HELP: &'static str = "q quit | p/P next/previous profile | l lock profile | 1-9 mode | \
+		j/k choose macro, enter runs it";
+	const MACRO_RUNS_SHOWN: usize = 5;
+	const ERRORS_SHOWN: usize = 6;
+
+	pub fn new(state: Arc<SharedState>, log: Arc<TuiLog>, tx: MonitoredSender<MainThreadSignal>) -> Self
+	{
+		Self { state, log, tx, selected_macro: 0 }
+	}
+
+	pub fn run(&mut self, should_exit: Arc<AtomicBool>)
+	{
+		ncurses::initscr();
+		ncurses::noecho();
+		ncurses::cbreak();
+		ncurses::curs_set(ncurses::CURSOR_VISIBILITY::CURSOR_INVISIBLE);
+		ncurses::timeout(Self::REDRAW_INTERVAL);
+
+		while !should_exit.load(Ordering::Relaxed)
+		{
+			self.draw();
+
+			let key = ncurses::getch();
+
+			if key == ncurses::ERR
+			{
+				continue
+			}
+
+			match key as u8 as char
+			{
+				// the main loop sets should_exit once it's shutting down
+				'q' => self.tx.send(MainThreadSignal::Shutdown),
+				'p' => self.switch_profile(1),
+				'P' => self.switch_profile(-1),
+				'l' =>
+				{
+					let locked = self.state.profile_locked.load(Ordering::Relaxed);
+					self.tx.send(MainThreadSignal::SetProfileLock(!locked));
+				},
+				mode @ '1'..='9' => self.tx.send(MainThreadSignal::SetMode(mode as u8 - b'0')),
+				'j' => self.selected_macro += 1,
+				'k' => self.selected_macro = self.selected_macro.saturating_sub(1),
+				'\n' =>
+				{
+					if let Some(name) = self.macro_names().get(self.selected_macro)
+					{
+						self.tx.send(MainThreadSignal::RunMacro(name.clone()));
+					}
+				},
+				_ => ()
+			}
+		}
+
+		ncurses::endwin();
+	}
+
+	fn macro_names(&self) -> Vec<String>
+	{
+		let mut names: Vec<String> = self.state.config
+			.read()
+			.unwrap()
+			.macros
+			.as_ref()
+			.map(|macros| macros.keys().cloned().collect())
+			.unwrap_or_default();
+
+		names.sort();
+		names
+	}
+
+	/// Switches to the profile `offset` places from the active one, in name order
+	fn switch_profile(&self, offset: isize)
+	{
+		let mut names: Vec<String> = self.state.config.read().unwrap().profiles.keys().cloned().collect();
+
+		if names.is_empty()
+		{
+			return
+		}
+
+		names.sort();
+
+		let active = self.state.active_profile_name.read().unwrap().clone();
+		let index = names.iter().position(|name| *name == active).unwrap_or(0) as isize;
+		let next = (index + offset).rem_euclid(names.len() as isize) as usize;
+
+		self.tx.send(MainThreadSignal::SwitchProfile(names[next].clone()));
+	}
+
+	fn draw(&mut self)
+	{
+		let (mut rows, mut columns) = (0, 0);
+		ncurses::getmaxyx(ncurses::stdscr(), &mut rows, &mut columns);
+
+		let mut lines = vec![Self::HELP.to_string(), String::new()];
+		lines.extend(self.status_lines());
+
+		let macros = self.macro_names();
+		self.selected_macro = self.selected_macro.min(macros.len().saturating_sub(1));
+
+		lines.push(String::new());
+		lines.push(match macros.get(self.selected_macro)
+		{
+			Some(name) => format!("macro: {} ({}/{})", name, self.selected_macro + 1, macros.len()),
+			None => "macro: none in the config".to_string()
+		});
+
+		lines.push(String::new());
+		lines.push("recent macro runs:".to_string());
+		lines.extend(self.state.macro_runs
+			.read()
+			.unwrap()
+			.iter()
+			.take(Self::MACRO_RUNS_SHOWN)
+			.map(|run| format!("  {} - {:.0}ms, {:?}", run.trigger, run.duration, run.stop_reason)));
+
+		let errors = TuiLog::last(&self.log.errors, Self::ERRORS_SHOWN);
+
+		// the events get whatever room is left
+		let room = (rows as usize).saturating_sub(lines.len() + errors.len() + 4);
+
+		lines.push(String::new());
+		lines.push("events:".to_string());
+		lines.extend(TuiLog::last(&self.log.events, room).into_iter().map(|line| format!("  {}", line)));
+
+		lines.push(String::new());
+		lines.push("warnings and errors:".to_string());
+		lines.extend(errors.into_iter().map(|line| format!("  {}", line)));
+
+		ncurses::erase();
+
+		for (row, line) in lines.iter().take(rows as usize).enumerate()
+		{
+			let line: String = line.chars().take(columns as usize).collect();
+			ncurses::mvaddstr(row as i32, 0, &line);
+		}
+
+		ncurses::refresh();
+	}
+
+	fn status_lines(&self) -> Vec<String>
+	{
+		let state = &self.state;
+		let profile = state.active_profile_name.read().unwrap().clone();
+		let mode = state.persisted.read().profile_modes.get(&profile).copied().unwrap_or(1);
+		let overrides: Vec<String> = state.profile_overrides
+			.read()
+			.unwrap()
+			.iter()
+			.map(|profile_override| profile_override.name.clone())
+			.collect();
+
+		let on_off = |flag: &AtomicBool| match flag.load(Ordering::Relaxed)
+		{
+			true => "on",
+			false => "off"
+		};
+
+		let media = state.media_state.read().unwrap().clone();
+		let playing = match (&media.artist, &media.title)
+		{
+			(Some(artist), Some(title)) => format!(" - {} - {}", artist, title),
+			(None, Some(title)) => format!(" - {}", title),
+			_ => String::new()
+		};
+
+		vec![
+			format!("profile: {}{}, mode {}",
+				profile,
+				match state.profile_locked.load(Ordering::Relaxed)
+				{
+					true => " (locked)",
+					false => ""
+				},
+				mode),
+			format!("profile overrides: {}", match overrides.is_empty()
+			{
+				true => "none".to_string(),
+				false => overrides.join(", ")
+			}),
+			format!("game mode: {}, low power: {}, focus mode: {}, recording: {}",
+				on_off(&state.game_mode),
+				on_off(&state.low_power),
+				on_off(&state.focus_mode),
+				on_off(&state.macro_recording)),
+			format!("media: {:?}{} ({}), volume {}%{}",
+				media.player_status,
+				playing,
+				media.player.as_deref().unwrap_or("no player"),
+				media.volume,
+				match media.muted
+				{
+					true => " (muted)",
+					false => ""
+				})
+		]
+	}
+}
@@ -1,17 +1,25 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::env;
-use std::sync::mpsc::{Sender, Receiver, TryRecvError};
+use std::sync::mpsc::RecvTimeoutError;
 use std::fmt;
 
 use serde::{Serialize, Deserialize};
 use log::debug;
+use smol::channel::Receiver;
 
+use crate::channels::{self, MonitoredSender};
 use crate::MainThreadSignal;
 use crate::config::ActiveWindowConditions;
 
+#[cfg(feature = "x11")]
 mod x11;
+mod null;
+mod screen;
 // TODO support wayland?
 
+pub use null::NullWindowSystem;
+pub use screen::ScreenSampler;
+
 #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 pub enum MouseButton
@@ -25,25 +33,51 @@ pub enum MouseButton
 pub enum WindowSystemError
 {
 	NotSupported,
-	NotDetected
+	NotDetected,
+	/// there's an X display, but the x11 feature wasn't built
+	#[cfg(not(feature = "x11"))]
+	NotBuilt
+}
+
+impl fmt::Display for WindowSystemError
+{
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result
+	{
+		match self
+		{
+			WindowSystemError::NotSupported => write!(f, "wayland isn't supported yet"),
+			WindowSystemError::NotDetected => write!(f, "no X display was found"),
+			#[cfg(not(feature = "x11"))]
+			WindowSystemError::NotBuilt => write!(f, "the driver was built without the x11 feature")
+		}
+	}
 }
 
 pub enum WindowSystemSignal
 {
 	Shutdown,
+	/// start or stop watching the active window
+	SetWatching(bool),
 	SendClick(MouseButton),
-	SendKeyCombo(String)
+	SendKeyCombo(String),
+	/// presses or releases a key combo, without the other half
+	SetKeyComboPressed(String, bool),
+	TypeUnicode(String)
 }
 
 pub trait WindowSystem where Self: Send
 {
 	fn send_key_combo(&self, key_combo: &str, pressed: bool, delay: Duration);
 	fn send_mouse_button(&self, button: MouseButton, pressed: bool);
+	fn type_unicode(&self, text: &str);
 	fn active_window_info(&self) -> Option<ActiveWindowInfo>;
 }
 
 impl dyn WindowSystem where Self: Send
 {
+	/// how often the active window is checked for changes
+	const WINDOW_CHECK_INTERVAL: Duration = Duration::from_millis(400);
+
 	pub fn new() -> Result<Box<dyn WindowSystem>, WindowSystemError>
 	{
 		if env::var("WAYLAND_DISPLAY").is_ok()
@@ -52,7 +86,7 @@ impl dyn WindowSystem where Self: Send
 		}
 		else if env::var("DISPLAY").is_ok()
 		{
-			Ok(Box::new(x11::X11Interface::new()))
+			Self::x11()
 		}
 		else
 		{
@@ -60,6 +94,18 @@ impl dyn WindowSystem where Self: Send
 		}
 	}
 
+	#[cfg(feature = "x11")]
+	fn x11() -> Result<Box<dyn WindowSystem>, WindowSystemError>
+	{
+		x11::X11Interface::new().map(|x11| Box::new(x11) as Box<dyn WindowSystem>)
+	}
+
+	#[cfg(not(feature = "x11"))]
+	fn x11() -> Result<Box<dyn WindowSystem>, WindowSystemError>
+	{
+		Err(WindowSystemError::NotBuilt)
+	}
+
 	pub fn send_key_combo_press(&self, key_combo: &str)
 	{
 		let duration = Duration::from_millis(6);
@@ -76,27 +122,52 @@ impl dyn WindowSystem where Self: Send
 	pub fn run(
 		&self,
 		rx: Receiver<WindowSystemSignal>,
-		tx: Sender<MainThreadSignal>)
+		tx: MonitoredSender<MainThreadSignal>)
 	{
 		let mut last_active_window = None;
+		let mut watching = true;
+		let mut last_window_check = Instant::now();
 
-		// receiving anything should be interpreted as a shutdown event
 		loop
 		{
-			match rx.try_recv()
+			// waits for signals rather than sleeping, so macros' input isn't held
+			// up until the next time the active window is checked
+			let wait = Self::WINDOW_CHECK_INTERVAL.saturating_sub(last_window_check.elapsed());
+
+			match channels::recv_timeout(&rx, wait)
 			{
 				Ok(WindowSystemSignal::Shutdown)
-					| Err(TryRecvError::Disconnected) => break,
+					| Err(RecvTimeoutError::Disconnected) => break,
 
-				Err(TryRecvError::Empty) => (),
+				Err(RecvTimeoutError::Timeout) => (),
 
+				// forgetting the last window means it's sent again once watching restarts
+				Ok(WindowSystemSignal::SetWatching(enabled)) =>
+				{
+					watching = enabled;
+					last_active_window = None;
+				},
 				Ok(WindowSystemSignal::SendClick(button)) => self.send_mouse_click(button),
-				Ok(WindowSystemSignal::SendKeyCombo(combo)) => self.send_key_combo_press(&combo)
+				Ok(WindowSystemSignal::SendKeyCombo(combo)) => self.send_key_combo_press(&combo),
+				Ok(WindowSystemSignal::SetKeyComboPressed(combo, pressed)) =>
+					self.send_key_combo(&combo, pressed, Duration::from_millis(6)),
+				Ok(WindowSystemSignal::TypeUnicode(text)) => self.type_unicode(&text)
 			}
 
-			let active_window = self.active_window_info();
+			if last_window_check.elapsed() < Self::WINDOW_CHECK_INTERVAL
+			{
+				continue
+			}
+
+			last_window_check = Instant::now();
+
+			let active_window = match watching
+			{
+				true => self.active_window_info(),
+				false => None
+			};
 
-			if last_active_window != active_window
+			if watching && last_active_window != active_window
 			{
 				debug!(
 					"active window has changed: {:?} => {:?}",
@@ -106,8 +177,6 @@ impl dyn WindowSystem where Self: Send
 				tx.send(MainThreadSignal::ActiveWindowChanged(active_window.clone()));
 				last_active_window = active_window;
 			}
-
-			std::thread::sleep(Duration::from_millis(400));
 		}
 	}
 }
@@ -118,18 +187,22 @@ pub struct ActiveWindowInfo
 	pub title: Option<String>,
 	pub executable: Option<String>,
 	pub class: Option<String>,
-	pub class_name: Option<String>
+	pub class_name: Option<String>,
+	/// the current workspace, numbered from 1
+	pub workspace: Option<u32>,
+	pub workspace_name: Option<String>,
+	/// the monitor the middle of the window is on, numbered from 1
+	pub monitor: Option<u32>,
+	/// the monitor's output name, i.e. DP-1
+	pub monitor_name: Option<String>,
+	pub fullscreen: bool
 }
 
 impl ActiveWindowInfo
 {
 	pub fn matches_conditions(&self, conditions: &ActiveWindowConditions) -> bool
 	{
-		if conditions.title.as_ref()
-			.or_else(|| conditions.executable.as_ref())
-			.or_else(|| conditions.class.as_ref())
-			.or_else(|| conditions.class_name.as_ref())
-			.is_none()
+		if !conditions.has_window_conditions()
 		{
 			return false
 		}
@@ -168,6 +241,37 @@ impl ActiveWindowInfo
 				.unwrap_or(false)
 		}
 
+		if let Some(workspace) = conditions.workspace
+		{
+			matches = matches && self.workspace == Some(workspace)
+		}
+
+		if let Some(ref regex) = conditions.workspace_name
+		{
+			matches = matches && self.workspace_name
+				.as_ref()
+				.map(|workspace_name| regex.is_match(workspace_name))
+				.unwrap_or(false)
+		}
+
+		if let Some(monitor) = conditions.monitor
+		{
+			matches = matches && self.monitor == Some(monitor)
+		}
+
+		if let Some(ref regex) = conditions.monitor_name
+		{
+			matches = matches && self.monitor_name
+				.as_ref()
+				.map(|monitor_name| regex.is_match(monitor_name))
+				.unwrap_or(false)
+		}
+
+		if let Some(fullscreen) = conditions.fullscreen
+		{
+			matches = matches && self.fullscreen == fullscreen
+		}
+
 		matches
 	}
 }
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use log::debug;
+
+use super::{WindowSystem, ActiveWindowInfo, MouseButton};
+
+/// Used when there's no supported window system (i.e. headless, or under
+/// wayland). There's never an active window, so profiles don't switch by
+/// window, and macros can't press keys or click. Everything else (lighting,
+/// media keys, commands run by macros) still works.
+pub struct NullWindowSystem;
+
+impl WindowSystem for NullWindowSystem
+{
+	fn send_key_combo(&self, key_combo: &str, pressed: bool, _delay: Duration)
+	{
+		if pressed
+		{
+			debug!("no window system, so {} can't be pressed", key_combo);
+		}
+	}
+
+	fn send_mouse_button(&self, button: MouseButton, pressed: bool)
+	{
+		if pressed
+		{
+			debug!("no window system, so {:?} can't be clicked", button);
+		}
+	}
+
+	fn type_unicode(&self, text: &str)
+	{
+		debug!("no window system, so {:?} can't be typed", text);
+	}
+
+	fn active_window_info(&self) -> Option<ActiveWindowInfo>
+	{
+		None
+	}
+}
@@ -0,0 +1,404 @@
+use log::warn;
+
+use crate::device::color::Color;
+
+/// Samples the colors along the screen for screen themes, from the monitor the
+/// active window is on (the whole screen when there isn't one). Each sample is
+/// split into vertical strips, and a strip's color is the average of its
+/// pixels with colorful pixels counting for more, so a picture's colors aren't
+/// washed out by the dark or grey parts around it.
+pub struct ScreenSampler
+{
+	#[cfg(feature = "x11")]
+	capture: Option<capture::ScreenCapture>,
+	// set once the screen can't be read, so it's only logged once
+	failed: bool
+}
+
+impl ScreenSampler
+{
+	/// how far apart (in pixels, both ways) the pixels that are averaged are,
+	/// reading every one would take far too long on a big monitor
+	#[cfg_attr(not(feature = "x11"), allow(dead_code))]
+	const STEP: usize = 8;
+
+	pub fn new() -> Self
+	{
+		Self
+		{
+			#[cfg(feature = "x11")]
+			capture: None,
+			failed: false
+		}
+	}
+
+	/// The color of each of `columns` strips across the screen, left to right,
+	/// or None if the screen can't be read
+	pub fn sample(&mut self, columns: usize) -> Option<Vec<Color>>
+	{
+		match self.read(columns)
+		{
+			Ok(colors) => Some(colors),
+			Err(error) =>
+			{
+				if !self.failed
+				{
+					warn!("unable to read the screen for a screen theme: {}", error);
+					self.failed = true;
+				}
+
+				None
+			}
+		}
+	}
+
+	#[cfg(feature = "x11")]
+	fn read(&mut self, columns: usize) -> Result<Vec<Color>, String>
+	{
+		// there's no point trying to connect over and over once it's failed
+		if self.capture.is_none()
+		{
+			if self.failed
+			{
+				return Err("the X display couldn't be opened".to_string())
+			}
+
+			self.capture = Some(capture::ScreenCapture::new()?);
+		}
+
+		self.capture
+			.as_mut()
+			.unwrap()
+			.grab(|pixels, width, height, stride| column_colors(pixels, width, height, stride, columns))
+	}
+
+	#[cfg(not(feature = "x11"))]
+	fn read(&mut self, _columns: usize) -> Result<Vec<Color>, String>
+	{
+		Err("the driver was built without the x11 feature".to_string())
+	}
+}
+
+/// The color of each of `columns` vertical strips of an image of 32 bit
+/// pixels (blue, green, red then unused, as X gives them on little endian
+/// machines), with rows `stride` bytes apart
+#[cfg_attr(not(feature = "x11"), allow(dead_code))]
+fn column_colors(pixels: &[u8], width: usize, height: usize, stride: usize, columns: usize) -> Vec<Color>
+{
+	(0..columns)
+		.map(|column|
+		{
+			let start = column * width / columns;
+			let end = (column + 1) * width / columns;
+
+			let mut totals = [0_f64; 3];
+			let mut weights = 0_f64;
+
+			for y in (0..height).step_by(ScreenSampler::STEP)
+			{
+				for x in (start..end).step_by(ScreenSampler::STEP)
+				{
+					let offset = y * stride + x * 4;
+
+					if let Some(&[blue, green, red]) = pixels.get(offset..offset + 3)
+					{
+						let chroma = red.max(green).max(blue) - red.min(green).min(blue);
+						let weight = 1_f64 + 4_f64 * chroma as f64 / 255_f64;
+
+						totals[0] += red as f64 * weight;
+						totals[1] += green as f64 * weight;
+						totals[2] += blue as f64 * weight;
+						weights += weight;
+					}
+				}
+			}
+
+			match weights > 0_f64
+			{
+				true => Color::new(
+					(totals[0] / weights).round() as u8,
+					(totals[1] / weights).round() as u8,
+					(totals[2] / weights).round() as u8),
+				false => Color::black()
+			}
+		})
+		.collect()
+}
+
+#[cfg(feature = "x11")]
+mod capture
+{
+	use std::os::raw::{c_char, c_int, c_uint, c_ulong};
+	use std::ptr;
+	use std::sync::atomic::{AtomicBool, Ordering};
+
+	use x11::xlib::{self, Bool, Display, Drawable, Visual, XImage, XErrorEvent};
+
+	use crate::windowsystem::x11::X11Interface;
+
+	/// XShmSegmentInfo, from X11/extensions/XShm.h
+	#[repr(C)]
+	struct SegmentInfo
+	{
+		shmseg: c_ulong,
+		shmid: c_int,
+		shmaddr: *mut c_char,
+		read_only: Bool
+	}
+
+	// the x11 crate doesn't have bindings for the shared memory extension
+	extern "C"
+	{
+		fn XShmQueryExtension(display: *mut Display) -> Bool;
+		fn XShmCreateImage(display: *mut Display, visual: *mut Visual, depth: c_uint, format: c_int,
+			data: *mut c_char, shminfo: *mut SegmentInfo, width: c_uint, height: c_uint) -> *mut XImage;
+		fn XShmAttach(display: *mut Display, shminfo: *mut SegmentInfo) -> Bool;
+		fn XShmDetach(display: *mut Display, shminfo: *mut SegmentInfo) -> Bool;
+		fn XShmGetImage(display: *mut Display, drawable: Drawable, image: *mut XImage, x: c_int, y: c_int,
+			plane_mask: c_ulong) -> Bool;
+	}
+
+	static ATTACH_FAILED: AtomicBool = AtomicBool::new(false);
+
+	unsafe extern "C" fn attach_error(_display: *mut Display, _event: *mut XErrorEvent) -> c_int
+	{
+		ATTACH_FAILED.store(true, Ordering::Relaxed);
+		0
+	}
+
+	/// An image in memory shared with the X server, so grabbing the screen
+	/// doesn't have to send every pixel over the X connection
+	struct SharedImage
+	{
+		display: *mut Display,
+		image: *mut XImage,
+		// the image points to this, so it mustn't move
+		segment: Box<SegmentInfo>,
+		width: u32,
+		height: u32
+	}
+
+	impl SharedImage
+	{
+		unsafe fn new(display: *mut Display, width: u32, height: u32) -> Result<Self, String>
+		{
+			let screen = xlib::XDefaultScreen(display);
+			let mut segment = Box::new(SegmentInfo { shmseg: 0, shmid: -1, shmaddr: ptr::null_mut(), read_only: xlib::False });
+
+			let image = XShmCreateImage(display, xlib::XDefaultVisual(display, screen),
+				xlib::XDefaultDepth(display, screen) as c_uint, xlib::ZPixmap, ptr::null_mut(),
+				&mut *segment, width, height);
+
+			if image.is_null()
+			{
+				return Err("unable to create a shared memory image".to_string())
+			}
+
+			let size = (*image).bytes_per_line as usize * (*image).height as usize;
+			segment.shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+
+			if segment.shmid < 0
+			{
+				xlib::XDestroyImage(image);
+				return Err(format!("unable to create shared memory: {}", std::io::Error::last_os_error()))
+			}
+
+			let address = libc::shmat(segment.shmid, ptr::null(), 0);
+
+			// removed once both sides have let go of it, even if the driver is killed
+			libc::shmctl(segment.shmid, libc::IPC_RMID, ptr::null_mut());
+
+			if address as isize == -1
+			{
+				xlib::XDestroyImage(image);
+				return Err(format!("unable to attach shared memory: {}", std::io::Error::last_os_error()))
+			}
+
+			segment.shmaddr = address as *mut c_char;
+			(*image).data = segment.shmaddr;
+
+			// the server can't attach to it if it's on another machine, which is an
+			// X error rather than a return value
+			ATTACH_FAILED.store(false, Ordering::Relaxed);
+			let previous_handler = xlib::XSetErrorHandler(Some(attach_error));
+			let attached = XShmAttach(display, &mut *segment);
+			xlib::XSync(display, xlib::False);
+			xlib::XSetErrorHandler(previous_handler);
+
+			if attached == 0 || ATTACH_FAILED.load(Ordering::Relaxed)
+			{
+				xlib::XDestroyImage(image);
+				libc::shmdt(address);
+				return Err("the X server couldn't attach to shared memory".to_string())
+			}
+
+			Ok(Self { display, image, segment, width, height })
+		}
+	}
+
+	impl Drop for SharedImage
+	{
+		fn drop(&mut self)
+		{
+			unsafe
+			{
+				XShmDetach(self.display, &mut *self.segment);
+				xlib::XSync(self.display, xlib::False);
+				xlib::XDestroyImage(self.image);
+				libc::shmdt(self.segment.shmaddr as *const libc::c_void);
+			}
+		}
+	}
+
+	/// Grabs the monitor the active window is on, with the shared memory
+	/// extension if the X server has it, otherwise the slow way
+	pub struct ScreenCapture
+	{
+		// dropped before the connection it was made on
+		shared: Option<SharedImage>,
+		use_shared_memory: bool,
+		x11: X11Interface
+	}
+
+	// the display connection is only used from the device thread that sampled it
+	unsafe impl Send for ScreenCapture {}
+
+	impl ScreenCapture
+	{
+		pub fn new() -> Result<Self, String>
+		{
+			let x11 = X11Interface::new().map_err(|error| error.to_string())?;
+			let use_shared_memory = unsafe { XShmQueryExtension(x11.display()) != 0 };
+
+			Ok(Self { shared: None, use_shared_memory, x11 })
+		}
+
+		/// Grabs the active monitor, and reads its pixels with `read` (given
+		/// the pixels, the width, height and the bytes per row)
+		pub fn grab<T, F>(&mut self, read: F) -> Result<T, String>
+			where F: FnOnce(&[u8], usize, usize, usize) -> T
+		{
+			let display = self.x11.display();
+
+			unsafe
+			{
+				let screen = xlib::XDefaultScreen(display);
+				let root_window = xlib::XDefaultRootWindow(display);
+
+				let (x, y, width, height) = self.x11
+					.get_active_window()
+					.and_then(|window| self.x11.get_window_monitor_area(window))
+					.unwrap_or((0, 0,
+						xlib::XDisplayWidth(display, screen) as u32,
+						xlib::XDisplayHeight(display, screen) as u32));
+
+				if width == 0 || height == 0
+				{
+					return Err("the monitor has no size".to_string())
+				}
+
+				if !self.use_shared_memory
+				{
+					let image = xlib::XGetImage(display, root_window, x, y, width, height, xlib::XAllPlanes(), xlib::ZPixmap);
+
+					if image.is_null()
+					{
+						return Err("unable to grab the screen".to_string())
+					}
+
+					let result = read_image(image, read);
+					xlib::XDestroyImage(image);
+					return result
+				}
+
+				// monitors can change size, or the active window can move to another
+				let stale = self.shared
+					.as_ref()
+					.map(|shared| shared.width != width || shared.height != height)
+					.unwrap_or(true);
+
+				if stale
+				{
+					self.shared = None;
+
+					match SharedImage::new(display, width, height)
+					{
+						Ok(shared) => self.shared = Some(shared),
+						Err(error) =>
+						{
+							self.use_shared_memory = false;
+							return Err(error)
+						}
+					}
+				}
+
+				let shared = self.shared.as_ref().unwrap();
+
+				if XShmGetImage(display, root_window, shared.image, x, y, xlib::XAllPlanes()) == 0
+				{
+					return Err("unable to grab the screen".to_string())
+				}
+
+				read_image(shared.image, read)
+			}
+		}
+	}
+
+	unsafe fn read_image<T, F>(image: *mut XImage, read: F) -> Result<T, String>
+		where F: FnOnce(&[u8], usize, usize, usize) -> T
+	{
+		let image = &*image;
+
+		if image.bits_per_pixel != 32 || image.byte_order != xlib::LSBFirst
+			|| image.red_mask != 0xff0000 || image.blue_mask != 0xff
+		{
+			return Err(format!("only 24 bit color screens can be read, not {} bit", image.depth))
+		}
+
+		let pixels = std::slice::from_raw_parts(image.data as *const u8,
+			image.bytes_per_line as usize * image.height as usize);
+
+		Ok(read(pixels, image.width as usize, image.height as usize, image.bytes_per_line as usize))
+	}
+}
+
+#[cfg(test)]
+mod tests
+{
+	use super::*;
+
+	fn image(width: usize, height: usize, pixel: impl Fn(usize, usize) -> [u8; 3]) -> Vec<u8>
+	{
+		(0..height)
+			.flat_map(|y| (0..width).map(move |x| (x, y)))
+			.flat_map(|(x, y)|
+			{
+				let [red, green, blue] = pixel(x, y);
+				vec![blue, green, red, 0]
+			})
+			.collect()
+	}
+
+	#[test]
+	fn strips_take_their_most_colorful_pixels_color()
+	{
+		// red on the left, blue on the right
+		let pixels = image(64, 16, |x, _y| match x < 32
+		{
+			true => [0xff, 0, 0],
+			false => [0, 0, 0xff]
+		});
+
+		assert_eq!(column_colors(&pixels, 64, 16, 64 * 4, 2), vec![Color::new(0xff, 0, 0), Color::new(0, 0, 0xff)]);
+
+		// a quarter of the strip is green, on grey
+		let pixels = image(64, 64, |_x, y| match y < 16
+		{
+			true => [0, 0xff, 0],
+			false => [0x40, 0x40, 0x40]
+		});
+
+		let colors = column_colors(&pixels, 64, 64, 64 * 4, 1);
+		assert!(colors[0].g > 0x80 && colors[0].r < 0x30);
+	}
+}
@@ -4,10 +4,10 @@ use std::ffi::{CStr, CString};
 use std::ptr;
 use std::time::Duration;
 
-use x11::{xlib, xtest};
+use x11::{xlib, xrandr, xtest};
 use x11::xlib::{Display, Window, KeyCode, XFree};
 
-use crate::windowsystem::{ActiveWindowInfo, WindowSystem, MouseButton};
+use crate::windowsystem::{ActiveWindowInfo, WindowSystem, WindowSystemError, MouseButton};
 
 #[derive(Debug)]
 pub enum GetWindowPropertyError
@@ -35,24 +35,29 @@ unsafe impl Send for X11Interface {}
 
 impl X11Interface
 {
-	pub fn new() -> Self
+	pub fn new() -> Result<Self, WindowSystemError>
 	{
 		unsafe
 		{
 			let display = xlib::XOpenDisplay(ptr::null());
 
+			if display.is_null()
+			{
+				return Err(WindowSystemError::NotDetected)
+			}
+
 			let mut min_keycode = 0;
 			let mut max_keycode = 0;
 			xlib::XDisplayKeycodes(display, &mut min_keycode, &mut max_keycode);
 
-			X11Interface
+			Ok(X11Interface
 			{
 				display,
 				// the X11 spec says these are never outside 8..255 so this
 				// cast should be fine
 				min_keycode: min_keycode as KeyCode,
 				max_keycode: max_keycode as KeyCode
-			}
+			})
 		}
 	}
 
@@ -139,8 +144,156 @@ impl X11Interface
 		}
 	}
 
+	/// Gets the current workspace number (0 based) and its name, if the window
+	/// manager supports them
+	pub fn get_current_workspace(&self) -> (Option<u32>, Option<String>)
+	{
+		unsafe
+		{
+			let root_window = xlib::XDefaultRootWindow(self.display);
+
+			let number = self.get_window_property(root_window, "_NET_CURRENT_DESKTOP")
+				.ok()
+				.and_then(|property| property.map(|data|
+				{
+					let number = *(data as *mut c_long) as u32;
+					XFree(data as *mut c_void);
+					number
+				}));
+
+			// _NET_DESKTOP_NAMES is a list of null terminated strings, one per workspace
+			let name = number.and_then(|number| self
+				.get_window_property_with_count(root_window, "_NET_DESKTOP_NAMES")
+				.ok()
+				.flatten()
+				.and_then(|(data, item_count)|
+				{
+					let bytes = std::slice::from_raw_parts(data, item_count as usize);
+					let name = bytes
+						.split(|byte| *byte == 0)
+						.nth(number as usize)
+						.map(|name| String::from_utf8_lossy(name).into_owned());
+					XFree(data as *mut c_void);
+					name
+				}));
+
+			(number, name)
+		}
+	}
+
+	/// Gets the monitor (0 based, in xrandr's order) the middle of a window is
+	/// on, and its output name
+	pub fn get_window_monitor(&self, window: Window) -> Option<(u32, String)>
+	{
+		self.find_window_monitor(window, |number, monitor| unsafe
+		{
+			let name = xlib::XGetAtomName(self.display, monitor.name);
+			let monitor_name = match name.is_null()
+			{
+				true => String::new(),
+				false =>
+				{
+					let monitor_name = CStr::from_ptr(name).to_string_lossy().into_owned();
+					XFree(name as *mut c_void);
+					monitor_name
+				}
+			};
+
+			(number as u32, monitor_name)
+		})
+	}
+
+	/// Gets the area of the root window (x, y, width, height) covered by the
+	/// monitor the middle of a window is on
+	pub fn get_window_monitor_area(&self, window: Window) -> Option<(i32, i32, u32, u32)>
+	{
+		self.find_window_monitor(window, |_number, monitor|
+			(monitor.x, monitor.y, monitor.width.max(0) as u32, monitor.height.max(0) as u32))
+	}
+
+	pub(super) fn display(&self) -> *mut Display
+	{
+		self.display
+	}
+
+	fn find_window_monitor<T, F>(&self, window: Window, describe: F) -> Option<T>
+		where F: FnOnce(usize, &xrandr::XRRMonitorInfo) -> T
+	{
+		unsafe
+		{
+			let root_window = xlib::XDefaultRootWindow(self.display);
+
+			let mut root = 0;
+			let (mut x, mut y, mut width, mut height, mut border, mut depth) = (0, 0, 0, 0, 0, 0);
+
+			if xlib::XGetGeometry(self.display, window, &mut root, &mut x, &mut y,
+				&mut width, &mut height, &mut border, &mut depth) == 0
+			{
+				return None
+			}
+
+			// the geometry is relative to the window's parent (usually the
+			// window manager's frame), so find where it is on the root window
+			let mut child = 0;
+
+			if xlib::XTranslateCoordinates(self.display, window, root_window,
+				(width / 2) as c_int, (height / 2) as c_int, &mut x, &mut y, &mut child) == 0
+			{
+				return None
+			}
+
+			let mut monitor_count = 0;
+			let monitors = xrandr::XRRGetMonitors(self.display, root_window, xlib::True, &mut monitor_count);
+
+			if monitors.is_null()
+			{
+				return None
+			}
+
+			let monitor = std::slice::from_raw_parts(monitors, monitor_count.max(0) as usize)
+				.iter()
+				.enumerate()
+				.find(|(_number, monitor)| x >= monitor.x && x < monitor.x + monitor.width
+					&& y >= monitor.y && y < monitor.y + monitor.height)
+				.map(|(number, monitor)| describe(number, monitor));
+
+			xrandr::XRRFreeMonitors(monitors);
+			monitor
+		}
+	}
+
+	/// Whether the window manager has made a window fullscreen
+	pub fn is_window_fullscreen(&self, window: Window) -> bool
+	{
+		unsafe
+		{
+			let fullscreen = CString::new("_NET_WM_STATE_FULLSCREEN").unwrap();
+			let fullscreen = xlib::XInternAtom(self.display, fullscreen.as_ptr(), xlib::False);
+
+			// _NET_WM_STATE is a list of atoms, which xlib hands back as longs
+			self.get_window_property_with_count(window, "_NET_WM_STATE")
+				.ok()
+				.flatten()
+				.map(|(data, item_count)|
+				{
+					let is_fullscreen = std::slice::from_raw_parts(data as *const c_ulong, item_count as usize)
+						.contains(&fullscreen);
+					XFree(data as *mut c_void);
+					is_fullscreen
+				})
+				.unwrap_or(false)
+		}
+	}
+
 	unsafe fn get_window_property(&self, window: Window, property: &str)
 		-> Result<Option<*mut c_uchar>, GetWindowPropertyError>
+	{
+		self.get_window_property_with_count(window, property)
+			.map(|property| property.map(|(data, _item_count)| data))
+	}
+
+	unsafe fn get_window_property_with_count(&self, window: Window, property: &str)
+		-> Result<Option<(*mut c_uchar, c_ulong)>, GetWindowPropertyError>
 	{
 		let mut property_type = 0 as c_ulong;
 		let mut format = 0 as c_int;
@@ -173,7 +326,7 @@ impl X11Interface
 			x11::xlib::Success => Ok(match item_count
 			{
 				0 => None,
-				_ => Some(result_pointer)
+				_ => Some((result_pointer, item_count))
 			}),
 			status => panic!("status from XGetWindowProperty unknown: {}", status)
 		}
@@ -287,6 +440,59 @@ impl X11Interface
 			}
 		}
 	}
+
+	// time for clients to pick up keyboard mapping changes before the key is used
+	const REMAP_DELAY: Duration = Duration::from_millis(10);
+
+	/// Converts a character into the keysym that types it. Latin-1 characters
+	/// have keysyms equal to their codepoint, everything else uses the
+	/// 0x01000000 unicode keysym range.
+	fn char_to_keysym(character: char) -> c_ulong
+	{
+		match character
+		{
+			'\n' => x11::keysym::XK_Return as c_ulong,
+			'\t' => x11::keysym::XK_Tab as c_ulong,
+			' '..='~' | '\u{a0}'..='\u{ff}' => character as c_ulong,
+			_ => 0x0100_0000 | character as c_ulong
+		}
+	}
+
+	/// Types each character by mapping its keysym onto an unused keycode and
+	/// pressing that, so the result doesn't depend on the current layout or
+	/// modifier state
+	fn type_unicode_chars(&self, text: &str)
+	{
+		let keycode = match self.find_unused_keycode()
+		{
+			Some(keycode) => keycode,
+			None =>
+			{
+				log::warn!("no unused keycode available to type unicode text with");
+				return
+			}
+		};
+
+		unsafe
+		{
+			for character in text.chars()
+			{
+				let mut symbol = Self::char_to_keysym(character);
+				xlib::XChangeKeyboardMapping(self.display, keycode as i32, 1, &mut symbol, 1);
+				xlib::XSync(self.display, xlib::False);
+				std::thread::sleep(Self::REMAP_DELAY);
+
+				xtest::XTestFakeKeyEvent(self.display, keycode as u32, xlib::True, xlib::CurrentTime);
+				xtest::XTestFakeKeyEvent(self.display, keycode as u32, xlib::False, xlib::CurrentTime);
+				xlib::XSync(self.display, xlib::False);
+				std::thread::sleep(Self::REMAP_DELAY);
+			}
+
+			let mut symbol = 0;
+			xlib::XChangeKeyboardMapping(self.display, keycode as i32, 1, &mut symbol, 1);
+			xlib::XFlush(self.display);
+		}
+	}
 }
 
 impl Drop for X11Interface
@@ -295,7 +501,7 @@ impl Drop for X11Interface
 	{
 		unsafe
 		{
-			XFree(self.display as *mut c_void);
+			xlib::XCloseDisplay(self.display);
 		}
 	}
 }
@@ -308,6 +514,8 @@ impl WindowSystem for X11Interface
 		{
 			let pid = self.get_window_pid(window).unwrap_or(None);
 			let class_hint = self.get_window_class_hint(window).ok();
+			let (workspace, workspace_name) = self.get_current_workspace();
+			let monitor = self.get_window_monitor(window);
 
 			ActiveWindowInfo
 			{
@@ -316,7 +524,13 @@ impl WindowSystem for X11Interface
 					.and_then(|pid| std::fs::read_link(format!("/proc/{}/exe", pid)).ok())
 					.map(|exe_path| exe_path.to_string_lossy().into()),
 				class: class_hint.as_ref().map(|hint| hint.class.clone()),
-				class_name: class_hint.as_ref().map(|hint| hint.name.clone())
+				class_name: class_hint.as_ref().map(|hint| hint.name.clone()),
+				// numbered from 1 for config friendliness
+				workspace: workspace.map(|number| number + 1),
+				workspace_name,
+				monitor: monitor.as_ref().map(|(number, _name)| number + 1),
+				monitor_name: monitor.map(|(_number, name)| name),
+				fullscreen: self.is_window_fullscreen(window)
 			}
 		})
 	}
@@ -338,6 +552,11 @@ impl WindowSystem for X11Interface
 		}
 	}
 
+	fn type_unicode(&self, text: &str)
+	{
+		self.type_unicode_chars(text);
+	}
+
 	fn send_key_combo(&self, key_combo: &str, pressed: bool, delay: Duration)
 	{
 		if let Some(ref sequence) = self.key_combo_to_keysym_sequence(key_combo)